@@ -0,0 +1,118 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue, AUTHORIZATION};
+use reqwest::Url;
+
+use crate::backend::BackendError;
+use crate::local;
+
+/// The future returned by [`AuthProvider::credentials`].
+pub type Authorized<'a> = Pin<Box<dyn Future<Output = Result<Credentials, BackendError>> + 'a>>;
+
+/// A source of credentials which can change during a download, such as an OAuth access token
+/// which expires and is refreshed, or a pre-signed URL which is signed again.  It is set with
+/// [`AsyncDownloadBuilder::auth_provider`](crate::AsyncDownloadBuilder::auth_provider).
+///
+/// The provider is asked for credentials before every request the download makes, including
+/// retries, range requests and each redirect, so it should keep them until they expire rather
+/// than fetch new ones every time.  If a request is rejected with `401 Unauthorized`, the
+/// provider is asked again with `rejected` set, so that it renews them, and the request is sent
+/// once more.  A second `401` fails the download.  An error returned by the provider is a
+/// transient failure, which is retried if retries are enabled.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::cell::RefCell;
+/// use tokio_dl_stream_to_disk::auth::{AuthProvider, Authorized, Credentials};
+/// use reqwest::Url;
+///
+/// /// Sends a bearer token, fetching a new one when it is rejected.
+/// struct Token(RefCell<String>);
+///
+/// impl AuthProvider for Token {
+///     fn credentials(&self, _url: &Url, rejected: bool) -> Authorized<'_> {
+///         Box::pin(async move {
+///             if rejected {
+///                 let token = reqwest::get("https://auth.example.com/token").await?.text().await?;
+///                 *self.0.borrow_mut() = token;
+///             }
+///             Ok(Credentials::bearer(&self.0.borrow())?)
+///         })
+///     }
+/// }
+/// ```
+pub trait AuthProvider {
+    /// Returns the credentials to send a request for `url` with.  `rejected` is `true` if the
+    /// last credentials sent with it were rejected with `401 Unauthorized`.
+    fn credentials(&self, url: &Url, rejected: bool) -> Authorized<'_>;
+}
+
+/// What an [`AuthProvider`] adds to a request: headers, such as `Authorization`, and the URL
+/// to send it to instead, such as a pre-signed URL.
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    headers: HeaderMap,
+    url: Option<Url>,
+}
+
+impl Credentials {
+    /// Returns credentials which leave the request as it is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns credentials which send `token` as a bearer token, as for OAuth 2.0.  Fails if the
+    /// token is not a valid header value.
+    pub fn bearer(token: &str) -> Result<Self, InvalidHeaderValue> {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+        value.set_sensitive(true);
+        Ok(Self::new().header(AUTHORIZATION, value))
+    }
+
+    /// Returns credentials which send `username` and `password` with Basic authentication.
+    pub fn basic(username: &str, password: &str) -> Self {
+        let credentials = local::encode_base64(format!("{}:{}", username, password).as_bytes());
+        let mut value = HeaderValue::from_str(&format!("Basic {}", credentials))
+            .expect("base64 is a valid header value");
+        value.set_sensitive(true);
+        Self::new().header(AUTHORIZATION, value)
+    }
+
+    /// Set a header on the request, replacing any it has by that name.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Send the request to `url` instead, such as a pre-signed URL which was signed again because
+    /// the last one expired.  The download URL is left as it is, so the provider is asked for
+    /// the URL of every request.  The URL must be allowed by the policy.
+    pub fn url(mut self, url: Url) -> Self {
+        self.url = Some(url);
+        self
+    }
+
+    /// Applies the credentials to `request`.
+    pub(crate) fn apply(self, request: &mut reqwest::Request) {
+        for (name, value) in self.headers.iter() {
+            request.headers_mut().insert(name, value.clone());
+        }
+        if let Some(url) = self.url {
+            *request.url_mut() = url;
+        }
+    }
+}
+
+/// The auth provider of a download, if any.
+#[derive(Clone)]
+pub(crate) struct SharedAuth(pub(crate) Arc<dyn AuthProvider>);
+
+impl fmt::Debug for SharedAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AuthProvider")
+    }
+}
@@ -0,0 +1,132 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures_util::stream::Stream;
+use reqwest::{ResponseBuilderExt, Url};
+
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::{local, AsyncDownload, Failure};
+
+pub use bytes::Bytes;
+pub use http::{Request, Response};
+
+/// The error an [`HttpBackend`] fails with.
+pub type BackendError = Box<dyn StdError + Send + Sync>;
+
+/// The body of a response returned by an [`HttpBackend`].
+pub type Body = Pin<Box<dyn Stream<Item = Result<Bytes, BackendError>> + Send>>;
+
+/// The future returned by [`HttpBackend::execute`].
+pub type Executed<'a> = Pin<Box<dyn Future<Output = Result<Response<Body>, BackendError>> + 'a>>;
+
+/// The transport requests are sent with, in place of the `reqwest::Client`, such as a client
+/// built on hyper directly, isahc or curl, or a mock which serves canned responses in tests.
+/// It is set with [`AsyncDownloadBuilder::backend`](crate::AsyncDownloadBuilder::backend).
+///
+/// A backend only sends each request it is given: the download still follows redirects,
+/// applies its policy, and decides what to retry.  An error returned by a backend is a transient
+/// failure, like a connection error, while a response with an error status fails the download
+/// as it would have from the `reqwest::Client`.  The request timeout applies until the head of
+/// the response arrives, but client options such as proxies cannot be combined with a backend.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use futures_util::stream;
+/// use tokio_dl_stream_to_disk::backend::{Body, Bytes, Executed, HttpBackend, Request, Response};
+///
+/// /// Serves the same body for every request.
+/// struct Mock(Bytes);
+///
+/// impl HttpBackend for Mock {
+///     fn execute(&self, _request: Request<Bytes>) -> Executed<'_> {
+///         let body: Body = Box::pin(stream::iter([Ok(self.0.clone())]));
+///         Box::pin(async move { Ok(Response::new(body)) })
+///     }
+/// }
+/// ```
+pub trait HttpBackend {
+    /// Sends `request`, returning the response once its head has arrived, with the body still to
+    /// be streamed.  A redirect is returned rather than followed.
+    fn execute(&self, request: Request<Bytes>) -> Executed<'_>;
+}
+
+/// The backend a download sends its requests with, if any.
+#[derive(Clone)]
+pub(crate) struct SharedBackend(pub(crate) Arc<dyn HttpBackend>);
+
+impl fmt::Debug for SharedBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HttpBackend")
+    }
+}
+
+impl AsyncDownload {
+    /// Sends a single request with the configured backend, or otherwise with `client`, unless it
+    /// is for a local `file` or `data` URL, or an `sftp` or `scp` URL read over SSH.  A request for an
+    /// `s3`, `gs` or `az` URL is sent to its object store, and one for the magnet link of a torrent
+    /// to its peers.  With a cache directory, a `GET` request may be answered from the cache
+    /// instead.
+    pub(crate) async fn execute(&self, client: &reqwest::Client, request: reqwest::Request) -> Result<reqwest::Response, Failure> {
+        match request.url().scheme() {
+            "file" | "data" => return local::execute(&request).await,
+            #[cfg(feature="object-store")]
+            "s3" | "gs" | "az" => return crate::cloud::execute(&self.config.stores, &request).await,
+            #[cfg(feature="sftp")]
+            "sftp" | "scp" => return crate::sftp::execute(&request).await,
+            #[cfg(feature="torrent")]
+            "magnet" => return self.execute_swarm(&request).await,
+            _ => (),
+        }
+        match self.config.cache {
+            Some(ref dir) => self.execute_cached(dir, client, request).await,
+            None => self.transmit(client, request).await,
+        }
+    }
+
+    /// Sends a single HTTP request with the configured backend, or otherwise with `client`.
+    pub(crate) async fn transmit(&self, client: &reqwest::Client, request: reqwest::Request) -> Result<reqwest::Response, Failure> {
+        let Some(SharedBackend(ref backend)) = self.config.backend else {
+            return Ok(client.execute(request).await?);
+        };
+        let url = request.url().clone();
+        let timeout = request.timeout().copied();
+        let body = request.body()
+            .and_then(reqwest::Body::as_bytes)
+            .map(Bytes::copy_from_slice)
+            .unwrap_or_default();
+        let mut http_request = Request::builder()
+            .method(request.method().clone())
+            .uri(url.as_str())
+            .version(request.version())
+            .body(body)
+            .map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))?;
+        *http_request.headers_mut() = request.headers().clone();
+        let response = backend.execute(http_request);
+        let response = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, response).await
+                .map_err(|_| Failure::transient(TDSTDError::new(TDSTDErrorKind::Timeout)))?,
+            None => response.await,
+        };
+        let response = response.map_err(|err| Failure::transient(TDSTDError::new(TDSTDErrorKind::Other(err))))?;
+        into_response(url, response)
+    }
+}
+
+/// Converts a response to a request for `url` into the `reqwest::Response` it would have been.
+pub(crate) fn into_response(url: Url, response: Response<Body>) -> Result<reqwest::Response, Failure> {
+    let (parts, body) = response.into_parts();
+    let mut builder = Response::builder()
+        .status(parts.status)
+        .version(parts.version)
+        .url(url);
+    if let Some(headers) = builder.headers_mut() {
+        *headers = parts.headers;
+    }
+    let response = builder.body(reqwest::Body::wrap_stream(body))
+        .map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))?;
+    Ok(reqwest::Response::from(response))
+}
@@ -0,0 +1,153 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+
+use blake3::hazmat::{left_subtree_len, merge_subtrees_non_root, merge_subtrees_root, HasherExt, Mode};
+use blake3::{Hash, Hasher, CHUNK_LEN};
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::transform::{AuthenticationFailed, Transform, Transformed};
+
+/// The length of the header holding the length of the content.
+const HEADER_LEN: usize = 8;
+
+/// The length of a parent node, the chaining values of its two children.
+const PARENT_LEN: usize = 64;
+
+/// A [`Transform`] which decodes a body in the combined [bao](https://github.com/oconnor663/bao)
+/// encoding, verifying it against its BLAKE3 root hash as it streams, so that corruption is
+/// detected at the first chunk of 1 KiB which does not match rather than once the whole file
+/// has been written.  The body is the 8-byte little-endian length of the content followed by
+/// the BLAKE3 tree of the content in pre-order, each parent node before its children, as
+/// produced by `bao encode`.  Only the verified content is written, so the file is as large as
+/// the content, while progress refers to the encoded body.
+///
+/// A chunk or parent node which does not match, or a body which ends early or carries more than
+/// the content, fails the download with `AuthenticationFailed` and the file it was written to is
+/// removed.  Chunks which were verified have already been written by then, so a download into a
+/// writer should not be trusted until it completes.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use tokio_dl_stream_to_disk::bao::BaoDecoder;
+/// use tokio_dl_stream_to_disk::AsyncDownload;
+///
+/// # fn run(root_hash: &[u8; 32]) -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+/// let download = AsyncDownload::builder()
+///     .url("https://example.com/disk.img.bao")
+///     .dst_dir("/tmp")
+///     .filename("disk.img")
+///     .transform(BaoDecoder::new(root_hash))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct BaoDecoder {
+    root_hash: Hash,
+    buf: BytesMut,
+    header_read: bool,
+    /// The subtrees still to be read, the next one last.
+    subtrees: Vec<Subtree>,
+}
+
+/// A subtree of the content, with the hash its chunks must add up to.
+struct Subtree {
+    expected: Hash,
+    offset: u64,
+    len: u64,
+}
+
+impl BaoDecoder {
+    /// Returns a decoder for content with the BLAKE3 hash `root_hash`.
+    pub fn new(root_hash: &[u8; 32]) -> Self {
+        Self {
+            root_hash: Hash::from(*root_hash),
+            buf: BytesMut::new(),
+            header_read: false,
+            subtrees: Vec::new(),
+        }
+    }
+
+    /// Verifies every parent node and chunk buffered, returning the content of the chunks.
+    fn decode(&mut self) -> Result<Bytes, IOError> {
+        if !self.header_read {
+            if self.buf.len() < HEADER_LEN {
+                return Ok(Bytes::new());
+            }
+            let len = self.buf.get_u64_le();
+            self.header_read = true;
+            self.subtrees.push(Subtree { expected: self.root_hash, offset: 0, len });
+        }
+        let mut output = BytesMut::new();
+        while let Some(subtree) = self.subtrees.last() {
+            // Any other subtree at the start of the content is a left child, whose sibling is
+            // still to be read.
+            let is_root = subtree.offset == 0 && self.subtrees.len() == 1;
+            if subtree.len > CHUNK_LEN as u64 {
+                if self.buf.len() < PARENT_LEN {
+                    break;
+                }
+                let parent = self.buf.split_to(PARENT_LEN);
+                let left: [u8; 32] = parent[..32].try_into().unwrap();
+                let right: [u8; 32] = parent[32..].try_into().unwrap();
+                let actual = if is_root {
+                    merge_subtrees_root(&left, &right, Mode::Hash)
+                } else {
+                    Hash::from(merge_subtrees_non_root(&left, &right, Mode::Hash))
+                };
+                if actual != subtree.expected {
+                    return Err(unverified());
+                }
+                let Subtree { offset, len, .. } = self.subtrees.pop().unwrap();
+                let left_len = left_subtree_len(len);
+                self.subtrees.push(Subtree { expected: Hash::from(right), offset: offset + left_len, len: len - left_len });
+                self.subtrees.push(Subtree { expected: Hash::from(left), offset, len: left_len });
+            } else {
+                if self.buf.len() < subtree.len as usize {
+                    break;
+                }
+                let chunk = self.buf.split_to(subtree.len as usize);
+                let mut hasher = Hasher::new();
+                let actual = if is_root {
+                    hasher.update(&chunk).finalize()
+                } else {
+                    Hash::from(hasher.set_input_offset(subtree.offset).update(&chunk).finalize_non_root())
+                };
+                if actual != subtree.expected {
+                    return Err(unverified());
+                }
+                self.subtrees.pop();
+                output.extend_from_slice(&chunk);
+            }
+        }
+        if self.subtrees.is_empty() && !self.buf.is_empty() {
+            return Err(unverified());
+        }
+        Ok(output.freeze())
+    }
+}
+
+impl Transform for BaoDecoder {
+    fn transform(&mut self, chunk: Bytes) -> Transformed<'_> {
+        self.buf.extend_from_slice(&chunk);
+        Box::pin(std::future::ready(self.decode()))
+    }
+
+    fn finish(&mut self) -> Transformed<'_> {
+        let result = if self.header_read && self.subtrees.is_empty() {
+            Ok(Bytes::new())
+        } else {
+            Err(unverified())
+        };
+        Box::pin(std::future::ready(result))
+    }
+
+    fn reset(&mut self) {
+        self.buf.clear();
+        self.header_read = false;
+        self.subtrees.clear();
+    }
+}
+
+fn unverified() -> IOError {
+    IOError::new(IOErrorKind::InvalidData, AuthenticationFailed)
+}
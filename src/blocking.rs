@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::ffi::OsStr;
+use std::io::{Error as IOError, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use tokio::io::AsyncWrite;
+use tokio::runtime::Runtime;
+
+use crate::error::Error as TDSTDError;
+use crate::{AsyncDownload, DownloadResult, IntoUrl, ProgressControl, ResponseMetadata};
+
+/// A download which blocks the calling thread rather than being awaited, for programs such as
+/// build scripts and command line tools which are not async.  It wraps an `AsyncDownload`, so
+/// the same retry, verification and atomic write options apply, and runs it on a
+/// single-threaded runtime of its own.  Like `reqwest::blocking`, it must not be used from
+/// within an async runtime, where its methods panic.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use tokio_dl_stream_to_disk::blocking::Download;
+/// use tokio_dl_stream_to_disk::AsyncDownload;
+///
+/// # fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+/// let download = AsyncDownload::builder()
+///     .url("https://example.com/file.bin")
+///     .dst_dir("/tmp")
+///     .retries(3)
+///     .build()?;
+/// let mut download = Download::from_async(download)?;
+/// download.download(&None)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Download {
+    download: AsyncDownload,
+    runtime: Runtime,
+}
+
+impl Download {
+    /// Returns a Download like [`AsyncDownload::new`].
+    pub fn new<U: IntoUrl, P: AsRef<Path>, F: AsRef<OsStr>>(url: U, dst_path: P, fname: F) -> Result<Self, TDSTDError> {
+        Self::from_async(AsyncDownload::new(url, dst_path, fname)?)
+    }
+
+    /// Returns a Download like [`AsyncDownload::to_path`].
+    pub fn to_path<U: IntoUrl, P: AsRef<Path>>(url: U, path: P) -> Result<Self, TDSTDError> {
+        Self::from_async(AsyncDownload::to_path(url, path)?)
+    }
+
+    /// Returns a Download which runs `download`, e.g. one configured with
+    /// [`AsyncDownload::builder`].  Fails if the runtime cannot be started.
+    pub fn from_async(download: AsyncDownload) -> Result<Self, TDSTDError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self { download, runtime })
+    }
+
+    /// Returns the `AsyncDownload` this wraps.
+    pub fn into_async(self) -> AsyncDownload {
+        self.download
+    }
+
+    /// Returns the length of the download in bytes, as for [`AsyncDownload::length`].
+    pub fn length(&self) -> Option<u64> {
+        self.download.length()
+    }
+
+    /// Returns the metadata of the most recent response, as for [`AsyncDownload::metadata`].
+    pub fn metadata(&self) -> Option<&ResponseMetadata> {
+        self.download.metadata()
+    }
+
+    /// Change the filename of the download, as for [`AsyncDownload::set_filename`].
+    pub fn set_filename<F: AsRef<OsStr>>(&mut self, fname: F) {
+        self.download.set_filename(fname);
+    }
+
+    /// Returns the path the download is written to, as for [`AsyncDownload::path`].
+    pub fn path(&self) -> PathBuf {
+        self.download.path()
+    }
+
+    /// Get the download URL, but do not download it, as for [`AsyncDownload::get`].
+    pub fn get(self) -> Result<Self, Box<dyn Error>> {
+        let Self { download, runtime } = self;
+        let download = runtime.block_on(download.get())?;
+        Ok(Self { download, runtime })
+    }
+
+    /// Download to the destination, as for [`AsyncDownload::download`].
+    pub fn download(&mut self, cb: &Option<Box<dyn Fn(u64)>>) -> Result<DownloadResult, TDSTDError> {
+        self.runtime.block_on(self.download.download(cb))
+    }
+
+    /// Download to the destination, calling `cb` with the position and total length as it
+    /// progresses, as for [`AsyncDownload::download_with_callback`].
+    pub fn download_with_callback<R: ProgressControl>(&mut self, cb: impl FnMut(u64, Option<u64>) -> R) -> Result<DownloadResult, TDSTDError> {
+        self.runtime.block_on(self.download.download_with_callback(cb))
+    }
+
+    /// Resume a previous download, as for [`AsyncDownload::resume`].
+    pub fn resume(&mut self, cb: &Option<Box<dyn Fn(u64)>>) -> Result<DownloadResult, TDSTDError> {
+        self.runtime.block_on(self.download.resume(cb))
+    }
+
+    /// Stream the download into `writer` rather than a file, as for
+    /// [`AsyncDownload::download_to_writer`].  The writer blocks the runtime while it writes,
+    /// which is the calling thread.
+    pub fn download_to_writer<W: Write>(&mut self, writer: &mut W, cb: &Option<Box<dyn Fn(u64)>>) -> Result<DownloadResult, TDSTDError> {
+        self.runtime.block_on(self.download.download_to_writer(&mut BlockingWriter(writer), cb))
+    }
+
+    /// Download into memory, as for [`AsyncDownload::download_to_memory`].
+    pub fn download_to_memory(&mut self, max_size: u64, cb: &Option<Box<dyn Fn(u64)>>) -> Result<Bytes, TDSTDError> {
+        self.runtime.block_on(self.download.download_to_memory(max_size, cb))
+    }
+
+    #[cfg(feature="sha256sum")]
+    /// Download and return the sha256sum of its contents, as for
+    /// [`AsyncDownload::download_and_return_sha256sum`].
+    pub fn download_and_return_sha256sum(&mut self, cb: &Option<Box<dyn Fn(u64)>>) -> Result<Vec<u8>, TDSTDError> {
+        self.runtime.block_on(self.download.download_and_return_sha256sum(cb))
+    }
+
+    /// Check whether the file at the destination already matches, as for
+    /// [`AsyncDownload::verify_existing`].
+    pub fn verify_existing(&mut self) -> Result<bool, TDSTDError> {
+        self.runtime.block_on(self.download.verify_existing())
+    }
+}
+
+/// Writes to a blocking writer from within the runtime, which is the thread it belongs to.
+struct BlockingWriter<'a, W>(&'a mut W);
+
+impl<W: Write> AsyncWrite for BlockingWriter<'_, W> {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, IOError>> {
+        Poll::Ready(self.get_mut().0.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IOError>> {
+        Poll::Ready(self.get_mut().0.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IOError>> {
+        self.poll_flush(cx)
+    }
+}
@@ -0,0 +1,1604 @@
+#[cfg(feature="tor")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature="tor")]
+use std::hash::{BuildHasher, Hasher};
+use std::ffi::{OsStr, OsString};
+#[cfg(feature="tor")]
+use std::net::SocketAddr;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::stream::Stream;
+#[cfg(feature="digest")]
+use digest::DynDigest;
+use reqwest::dns::Resolve;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::tls::Version as TlsVersion;
+use reqwest::{Identity, Method, Proxy};
+use tokio_util::sync::CancellationToken;
+
+use crate::{filename, AsyncDownload, IntoUrl};
+use crate::auth::{AuthProvider, SharedAuth};
+use crate::backend::{HttpBackend, SharedBackend};
+use crate::conditional::Validators;
+#[cfg(feature="compress")]
+use crate::compression::Compress;
+#[cfg(any(feature="compress", feature="decompress"))]
+use crate::compression::CompressionFormat;
+use crate::handle::Control;
+use crate::intercept::{Interceptor, Interceptors};
+#[cfg(feature="metalink")]
+use crate::metalink::MetalinkSource;
+use crate::metrics::{Metrics, SharedMetrics};
+use crate::netrc::Netrc;
+#[cfg(feature="sri")]
+use crate::integrity::Integrity;
+#[cfg(feature="pinning")]
+use crate::pinning::Pin;
+#[cfg(feature="digest")]
+use crate::pieces::PieceHashes;
+use crate::policy::Policy;
+use crate::redirect::Redirects;
+use crate::resolve::Resolver;
+use crate::retry::Backoff;
+#[cfg(any(feature="minisign", feature="openpgp"))]
+use crate::signature::SigningKey;
+#[cfg(feature="sha256sum")]
+use crate::sidecar::Manifest;
+use crate::supplied::Supplied;
+use crate::template::FilenameTemplate;
+use crate::throttle::{Budget, RateLimiter, Share};
+use crate::transform::{Transform, Transforms};
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+
+/// What to do when the destination file already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverwriteBehavior {
+    /// Fail with `FileExists`.
+    #[default]
+    Error,
+    /// Truncate the existing file and download over it.
+    Overwrite,
+    /// Leave the existing file alone and report success without downloading.
+    Skip,
+    /// Leave the existing file alone and report success without downloading if it already
+    /// matches, as checked by [`AsyncDownload::verify_existing`], and download over it otherwise.
+    SkipIfValid,
+    /// Download to the first free filename of the form `<fname>.1`, `<fname>.2`, etc.
+    RenameWithSuffix,
+}
+
+/// What to do with a partially written file when a download does not complete.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CleanupPolicy {
+    /// Keep the partial file, so that the download can be resumed later.
+    #[default]
+    Keep,
+    /// Remove the partial file if the download is cancelled, but keep it on other failures.
+    RemoveOnCancel,
+    /// Remove the partial file on any failure, or if the download is dropped before it finishes.
+    RemoveOnError,
+}
+
+/// How hard to try to make a completed download durable before reporting success.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave it to the operating system to write the file to disk.
+    #[default]
+    None,
+    /// Sync the contents of the file, but not necessarily its metadata.
+    Data,
+    /// Sync the contents and metadata of the file, and on Unix also the directory containing it,
+    /// so that its name survives a crash once it has been created or renamed.
+    Full,
+}
+
+/// Where to record the source of a completed download, so that later tooling can tell where it
+/// came from and revalidate it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SourceMetadata {
+    /// Do not record the source.
+    #[default]
+    None,
+    /// Record it in extended attributes of the file, such as `user.xdg.origin.url`.  Only Linux
+    /// and macOS support this, and the filesystem must too.
+    ExtendedAttributes,
+    /// Record it as JSON in a `<fname>.meta.json` file alongside the download.
+    Sidecar,
+}
+
+/// Options which apply to a download, shared between `AsyncDownload` and its builder.
+#[derive(Debug, Default)]
+pub(crate) struct Config {
+    pub(crate) client: Option<reqwest::Client>,
+    pub(crate) backend: Option<SharedBackend>,
+    pub(crate) overwrite: OverwriteBehavior,
+    pub(crate) create_dirs: bool,
+    pub(crate) portable_filenames: bool,
+    pub(crate) filename_template: Option<FilenameTemplate>,
+    pub(crate) sync: SyncPolicy,
+    #[cfg(unix)]
+    pub(crate) mode: Option<u32>,
+    #[cfg(unix)]
+    pub(crate) uid: Option<u32>,
+    #[cfg(unix)]
+    pub(crate) gid: Option<u32>,
+    pub(crate) timeout: Option<Duration>,
+    /// The HTTP version to send requests with, if not negotiated.
+    pub(crate) version: Option<reqwest::Version>,
+    pub(crate) headers: HeaderMap,
+    pub(crate) netrc: Option<Netrc>,
+    pub(crate) auth: Option<SharedAuth>,
+    pub(crate) interceptors: Interceptors,
+    pub(crate) method: Method,
+    pub(crate) body: Option<Bytes>,
+    /// The slice of the remote file to download, from its start up to its end, exclusive.
+    pub(crate) range: Option<(u64, u64)>,
+    pub(crate) retries: u32,
+    pub(crate) mismatch_retries: u32,
+    pub(crate) quarantine: bool,
+    pub(crate) backoff: Backoff,
+    pub(crate) max_retry_after: Option<Duration>,
+    pub(crate) atomic: bool,
+    pub(crate) temp_dir: Option<PathBuf>,
+    pub(crate) cancellation_token: Option<CancellationToken>,
+    pub(crate) control: Option<Arc<Control>>,
+    pub(crate) cleanup: CleanupPolicy,
+    pub(crate) segments: usize,
+    pub(crate) preallocate: bool,
+    pub(crate) buffer_size: Option<usize>,
+    pub(crate) offload_hashing: bool,
+    pub(crate) max_buffered: Option<usize>,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    pub(crate) budgets: Vec<Share>,
+    pub(crate) total_timeout: Option<Duration>,
+    pub(crate) stall_timeout: Option<Duration>,
+    pub(crate) minimum_speed: Option<(u64, Duration)>,
+    pub(crate) progress_interval: Option<Duration>,
+    pub(crate) progress_bytes: Option<u64>,
+    pub(crate) metrics: Option<SharedMetrics>,
+    pub(crate) validators: Validators,
+    pub(crate) store_validators: bool,
+    pub(crate) remote_time: bool,
+    pub(crate) cache: Option<PathBuf>,
+    pub(crate) source_metadata: SourceMetadata,
+    #[cfg(target_os="macos")]
+    pub(crate) quarantine_agent: Option<String>,
+    pub(crate) mirrors: Vec<String>,
+    pub(crate) redirects: Option<Redirects>,
+    pub(crate) policy: Option<Policy>,
+    pub(crate) race_bytes: Option<u64>,
+    #[cfg(feature="metalink")]
+    pub(crate) metalink: Option<MetalinkSource>,
+    #[cfg(feature="torrent")]
+    pub(crate) torrent: Option<String>,
+    /// The peers of the torrent, once it has been resolved and has any.
+    #[cfg(feature="torrent")]
+    pub(crate) swarm: Option<Arc<crate::peers::Swarm>>,
+    /// Whether requests are sent through a proxy, which connections to peers would bypass.
+    pub(crate) proxied: bool,
+    /// The object stores of `s3`, `gs` and `az` URLs, once requests have been made to them.
+    #[cfg(feature="object-store")]
+    pub(crate) stores: crate::cloud::Stores,
+    #[cfg(feature="sha256sum")]
+    pub(crate) expected_sha256: Option<[u8; 32]>,
+    #[cfg(feature="sha256sum")]
+    pub(crate) sha256_manifest: Option<Manifest>,
+    #[cfg(feature="sri")]
+    pub(crate) expected_integrity: Option<Integrity>,
+    #[cfg(any(feature="minisign", feature="openpgp"))]
+    pub(crate) signing_key: Option<SigningKey>,
+    #[cfg(any(feature="minisign", feature="openpgp"))]
+    pub(crate) signature_url: Option<String>,
+    #[cfg(feature="digest")]
+    pub(crate) piece_hashes: Option<Arc<PieceHashes>>,
+    #[cfg(feature="pinning")]
+    pub(crate) pins: Vec<Pin>,
+    #[cfg(feature="decompress")]
+    pub(crate) decompress: Option<CompressionFormat>,
+    #[cfg(feature="compress")]
+    pub(crate) store_compressed: Option<CompressionFormat>,
+    pub(crate) transforms: Transforms,
+    pub(crate) hash_transformed: bool,
+    /// Whether the client may decode the response, so that it no longer matches its digest.
+    pub(crate) content_decoded: bool,
+    #[cfg(feature="content-digest")]
+    pub(crate) ignore_content_digest: bool,
+    #[cfg(feature="state")]
+    pub(crate) state_file: bool,
+}
+
+impl Config {
+    /// Returns `true` if downloads to a file are decompressed as they are written.
+    pub(crate) fn decompresses(&self) -> bool {
+        #[cfg(feature="decompress")]
+        return self.decompress.is_some();
+        #[cfg(not(feature="decompress"))]
+        false
+    }
+
+    /// Returns where the download is described, such as the URL of a Metalink or a magnet link,
+    /// which stands in for the download URL until the description is fetched.
+    fn described_by(&self) -> Option<&str> {
+        #[cfg(feature="metalink")]
+        if let Some(MetalinkSource::Url(ref url)) = self.metalink {
+            return Some(url);
+        }
+        #[cfg(feature="torrent")]
+        if let Some(ref source) = self.torrent {
+            return Some(source);
+        }
+        None
+    }
+
+    /// Returns `true` if URLs to download from are fetched from a description of the download.
+    fn is_described(&self) -> bool {
+        #[cfg(feature="metalink")]
+        if self.metalink.is_some() {
+            return true;
+        }
+        self.described_by().is_some()
+    }
+}
+
+/// The AsyncDownloadBuilder struct allows you to configure an `AsyncDownload` before it is
+/// started.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use tokio_dl_stream_to_disk::AsyncDownload;
+///
+/// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+/// let mut download = AsyncDownload::builder()
+///     .url("https://bit.ly/3yWXSOW")
+///     .dst_dir("/tmp")
+///     .filename("5mb_test.bin")
+///     .overwrite(true)
+///     .timeout(Duration::from_secs(60))
+///     .build()?;
+/// download.download(&None).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct AsyncDownloadBuilder {
+    url: Option<String>,
+    supplied: Option<Supplied>,
+    dst_path: Option<PathBuf>,
+    fname: Option<OsString>,
+    config: Config,
+    client_builder: Option<reqwest::ClientBuilder>,
+    proxies: Vec<Proxy>,
+    decode_content: bool,
+    resolver: Resolver,
+    no_system_proxy: bool,
+    https_only: bool,
+    error: Option<TDSTDError>,
+}
+
+impl AsyncDownloadBuilder {
+    /// Returns an empty AsyncDownloadBuilder.  The url, or a stream or response, and the
+    /// destination directory must be set before calling [`build`](AsyncDownloadBuilder::build).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the URL you want to download the contents of, as a string or a `reqwest::Url`.  A URL
+    /// which is not an absolute HTTP or HTTPS URL, or a `file` or `data` URL, causes `build` to
+    /// fail with `InvalidUrl`.
+    pub fn url<U: IntoUrl>(mut self, url: U) -> Self {
+        match url.into_url() {
+            Ok(url) => self.url = Some(url.to_string()),
+            Err(err) => self.error = Some(err),
+        }
+        self
+    }
+
+    /// Write `stream` instead of the body of a request, in place of a URL.  `length_hint` is the
+    /// length of the body, if known, which progress is reported against.  Since the body cannot
+    /// be requested again, the download is neither retried, resumed, nor requested in segments
+    /// or from mirrors, and `build` fails with `InvalidConfig` if any of these are set.  See
+    /// [`AsyncDownload::from_stream`].
+    pub fn stream<St, E>(mut self, stream: St, length_hint: Option<u64>) -> Self
+    where
+        St: Stream<Item = Result<Bytes, E>> + 'static,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        self.supplied = Some(Supplied::stream(stream, length_hint));
+        self
+    }
+
+    /// Write the body of `response`, made with a client of your own, in place of a URL.  As
+    /// with [`stream`](AsyncDownloadBuilder::stream), it cannot be requested again, and a
+    /// response which was not successful makes `build` fail with its HTTP status.  See
+    /// [`AsyncDownload::from_response`].
+    pub fn response(mut self, response: reqwest::Response) -> Self {
+        match Supplied::response(response) {
+            Ok(supplied) => self.supplied = Some(supplied),
+            Err(err) => self.error = Some(err),
+        }
+        self
+    }
+
+    /// Set the directory the download will be written to.
+    pub fn dst_dir<P: AsRef<Path>>(mut self, dst_path: P) -> Self {
+        self.dst_path = Some(dst_path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the filename of the download within the destination directory.  If no filename is set,
+    /// it is derived from the `Content-Disposition` header of the response, or failing that the
+    /// last segment of the final URL, and sanitized so that it cannot escape the destination
+    /// directory.  A filename which is not a single path component, such as one containing a
+    /// path separator or `..`, causes the download to fail with `InvalidFilename`.  The chosen
+    /// path is available from [`AsyncDownload::path`] once the response has been received.  The
+    /// filename need not be UTF-8, although it must be to be made
+    /// [`portable`](AsyncDownloadBuilder::portable_filenames) without being altered.
+    pub fn filename<F: AsRef<OsStr>>(mut self, fname: F) -> Self {
+        self.fname = Some(fname.as_ref().to_os_string());
+        self
+    }
+
+    /// Set the path the download will be written to, as its destination directory and filename
+    /// at once.  A path without a directory is written to the current directory, and one which
+    /// does not end in a filename, such as `/` or `..`, causes `build` to fail with
+    /// `InvalidFilename`.  The directory must exist unless
+    /// [`create_dirs`](AsyncDownloadBuilder::create_dirs) is set.
+    pub fn to_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        match filename::split_path(path.as_ref()) {
+            Some((dst_path, fname)) => {
+                self.dst_path = Some(dst_path);
+                self.fname = Some(fname);
+            }
+            None => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidFilename)),
+        }
+        self
+    }
+
+    /// Make the filename valid on Windows as well as the current platform, whether it was set or
+    /// derived from the response: characters Windows reserves are replaced with `_`, trailing
+    /// dots and spaces are removed, and device names such as `CON` or `LPT1` are prefixed with
+    /// `_`.  Defaults to `false`.
+    pub fn portable_filenames(mut self, portable: bool) -> Self {
+        self.config.portable_filenames = portable;
+        self
+    }
+
+    /// Name the download after a template once it has completed, such as `{sha256}` for
+    /// content-addressed storage or `{host}-{date}-{basename}`.  The placeholders are:
+    ///
+    /// * `{basename}` - the filename which was set, or derived from the response
+    /// * `{sha256}` - the sha256sum of the download, as hex, with the `sha256sum` feature.  This
+    ///   covers the same bytes as [`expect_sha256`](AsyncDownloadBuilder::expect_sha256)
+    /// * `{date}` - the date the download completed, in UTC, as `YYYY-MM-DD`
+    /// * `{host}` - the host of the final URL, after any redirects
+    ///
+    /// A literal brace is written `{{` or `}}`, and an unknown placeholder causes `build` to fail
+    /// with `InvalidConfig`.  The download is written to a `.part` file named after the basename,
+    /// as though [`atomic`](AsyncDownloadBuilder::atomic) were set, and renamed once the name is
+    /// known.  The overwrite behavior is applied to the expanded name then, rather than before
+    /// the download starts: `Skip` keeps the file already there and discards the download,
+    /// and `SkipIfValid` replaces it, since the download has been verified.  The expanded name
+    /// is available from [`AsyncDownload::path`] and
+    /// [`DownloadResult::path`](crate::DownloadResult::path) once the download completes.
+    pub fn filename_template(mut self, template: &str) -> Self {
+        match FilenameTemplate::parse(template) {
+            Some(template) => self.config.filename_template = Some(template),
+            None => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig(
+                "filename template has an unknown placeholder or an unmatched brace",
+            ))),
+        }
+        self
+    }
+
+    /// Create the destination directory and any missing parents before writing the download,
+    /// rather than failing with `DirectoryMissing`.  The temporary directory of an atomic
+    /// download is created too.  Defaults to `false`.
+    pub fn create_dirs(mut self, create: bool) -> Self {
+        self.config.create_dirs = create;
+        self
+    }
+
+    /// Use a pre-built `reqwest::Client` for the request, so that connection pools, proxies and TLS
+    /// settings can be shared across many downloads.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.config.client = Some(client);
+        self
+    }
+
+    /// Send requests with `backend` rather than a `reqwest::Client`, e.g. a mock in tests.  It
+    /// cannot be combined with [`client`](AsyncDownloadBuilder::client) or the options which
+    /// configure the client, such as proxies.  See [`HttpBackend`].
+    pub fn backend(mut self, backend: Arc<dyn HttpBackend>) -> Self {
+        self.config.backend = Some(SharedBackend(backend));
+        self
+    }
+
+    /// Overwrite the destination file if it already exists, rather than failing with
+    /// `FileExists`.  Defaults to `false`.  This is shorthand for
+    /// [`overwrite_behavior`](AsyncDownloadBuilder::overwrite_behavior).
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.config.overwrite = if overwrite {
+            OverwriteBehavior::Overwrite
+        } else {
+            OverwriteBehavior::Error
+        };
+        self
+    }
+
+    /// Set what to do when the destination file already exists.  Defaults to
+    /// `OverwriteBehavior::Error`.
+    pub fn overwrite_behavior(mut self, behavior: OverwriteBehavior) -> Self {
+        self.config.overwrite = behavior;
+        self
+    }
+
+    /// Set a timeout for the request, applied from when the request starts connecting until the
+    /// response body has finished.  Each retry gets a fresh timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a timeout for the whole download, including any retries, after which it fails with
+    /// `Timeout`.
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.config.total_timeout = Some(timeout);
+        self
+    }
+
+    /// Restrict what the download may connect to, as described for [`Policy`].  The download URL
+    /// and mirrors are checked by [`build`](AsyncDownloadBuilder::build), which returns
+    /// `PolicyViolation` if they are not allowed.  This configures the client, so it cannot be
+    /// combined with [`client`](AsyncDownloadBuilder::client).
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.config.policy = Some(policy);
+        self
+    }
+
+    /// Set the maximum number of redirects to follow, or `0` to follow none.  A download which is
+    /// redirected more times fails with `Redirect`.  The URLs which redirected to the download
+    /// are available from [`DownloadResult::redirects`].  Redirects are followed by the download
+    /// rather than the client, so this cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).  Defaults to `10`.
+    ///
+    /// [`DownloadResult::redirects`]: crate::DownloadResult::redirects
+    pub fn max_redirects(mut self, max: usize) -> Self {
+        self.config.redirects.get_or_insert_default().limit = max;
+        self
+    }
+
+    /// Only follow redirects to the same host, failing with `Redirect` otherwise.  Like
+    /// [`max_redirects`](AsyncDownloadBuilder::max_redirects), this cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).  Defaults to `false`.
+    pub fn same_host_redirects(mut self, same_host: bool) -> Self {
+        self.config.redirects.get_or_insert_default().same_host = same_host;
+        self
+    }
+
+    /// Refuse redirects from an `https` URL to one which is not, failing with `Redirect`.  Like
+    /// [`max_redirects`](AsyncDownloadBuilder::max_redirects), this cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).  Defaults to `false`.
+    pub fn deny_https_downgrade(mut self, deny: bool) -> Self {
+        self.config.redirects.get_or_insert_default().deny_downgrade = deny;
+        self
+    }
+
+    /// Only download over `https`.  If the download URL or a mirror does not use `https`,
+    /// [`build`](AsyncDownloadBuilder::build) returns `PolicyViolation`, and so does the download
+    /// if it is redirected to a URL which does not, as with [`Policy::https_only`].  This
+    /// configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).  Defaults to `false`.
+    pub fn https_only(mut self, enabled: bool) -> Self {
+        self.https_only = enabled;
+        self.configure_client(|client| client.https_only(enabled))
+    }
+
+    /// Set the minimum TLS version to accept.  A server which cannot negotiate at least this
+    /// version fails to connect with `Connect`.  Whether a version can be required depends on the
+    /// TLS backend of `reqwest`, and if it cannot, [`build`](AsyncDownloadBuilder::build) fails.
+    /// This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn min_tls_version(self, version: TlsVersion) -> Self {
+        self.configure_client(|client| client.min_tls_version(version))
+    }
+
+    /// Authenticate to the server with a client certificate, for servers which require mutual
+    /// TLS.  This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn identity(self, identity: Identity) -> Self {
+        self.configure_client(|client| client.identity(identity))
+    }
+
+    /// Like [`identity`](AsyncDownloadBuilder::identity), but takes the client certificate and its
+    /// private key as a DER-encoded PKCS#12 archive and its password.  If they are not valid,
+    /// [`build`](AsyncDownloadBuilder::build) will return `InvalidConfig`.
+    pub fn identity_pkcs12(mut self, der: &[u8], password: &str) -> Self {
+        match Identity::from_pkcs12_der(der, password) {
+            Ok(identity) => self.identity(identity),
+            Err(_) => {
+                self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("client identity is not valid")));
+                self
+            }
+        }
+    }
+
+    /// Like [`identity`](AsyncDownloadBuilder::identity), but takes a PEM-encoded client
+    /// certificate chain and PEM-encoded PKCS#8 private key.  If they are not valid,
+    /// [`build`](AsyncDownloadBuilder::build) will return `InvalidConfig`.
+    pub fn identity_pem(mut self, cert: &[u8], key: &[u8]) -> Self {
+        match Identity::from_pkcs8_pem(cert, key) {
+            Ok(identity) => self.identity(identity),
+            Err(_) => {
+                self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("client identity is not valid")));
+                self
+            }
+        }
+    }
+
+    /// Send requests through a proxy.  Several proxies can be added, e.g. one for `http` and
+    /// another for `https` URLs, and the first which applies to a URL is used.  Once a proxy has
+    /// been added, proxies configured in the environment are not used.  This configures the
+    /// client, so it cannot be combined with [`client`](AsyncDownloadBuilder::client).
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Like [`proxy`](AsyncDownloadBuilder::proxy), but sends every request through the proxy at
+    /// `url`, such as `http://proxy.example.com:3128` or `socks5h://127.0.0.1:1080`.  A username
+    /// and password in the URL are used to authenticate to the proxy.  With `socks5h`, host names
+    /// are resolved by the proxy rather than locally.  SOCKS proxies require the `socks` feature.
+    /// If the URL is not valid, [`build`](AsyncDownloadBuilder::build) will return
+    /// `InvalidConfig`.
+    pub fn proxy_url(mut self, url: &str) -> Self {
+        match Proxy::all(url) {
+            Ok(proxy) => self.proxy(proxy),
+            Err(_) => {
+                self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("proxy URL is not valid")));
+                self
+            }
+        }
+    }
+
+    #[cfg(feature="tor")]
+    /// Route the download through the SOCKS port of a local Tor daemon, `127.0.0.1:9050`, as with
+    /// [`tor_proxy`](AsyncDownloadBuilder::tor_proxy).
+    pub fn tor(self) -> Self {
+        self.tor_proxy(SocketAddr::from(([127, 0, 0, 1], 9050)))
+    }
+
+    #[cfg(feature="tor")]
+    /// Route the download through the SOCKS port of a Tor daemon at `addr`.  Host names are
+    /// resolved by Tor rather than locally, so they are not leaked to the local resolver.  Each
+    /// download authenticates to Tor with its own random credentials, so that with Tor's default
+    /// `IsolateSOCKSAuth` it uses its own circuit rather than sharing one with other downloads.
+    /// This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn tor_proxy(self, addr: SocketAddr) -> Self {
+        let random = || RandomState::new().build_hasher().finish();
+        let url = format!("socks5h://tdstd-{:016x}:{:016x}@{}", random(), random(), addr);
+        self.proxy_url(&url)
+    }
+
+    /// Do not use the proxies configured in the environment, such as by `HTTPS_PROXY`, or by the
+    /// system.  Proxies added with [`proxy`](AsyncDownloadBuilder::proxy) are still used.  This
+    /// configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn no_system_proxy(mut self) -> Self {
+        self.no_system_proxy = true;
+        self
+    }
+
+    /// Resolve host names with a custom resolver, such as one using DNS-over-HTTPS, rather than
+    /// the system resolver.  This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn dns_resolver<R: Resolve + 'static>(mut self, resolver: Arc<R>) -> Self {
+        self.resolver.custom = Some(resolver);
+        self
+    }
+
+    /// Resolve `host` to `ip` without asking a resolver, e.g. to pin a host to a mirror.  This can
+    /// be called several times for the same host to give it several addresses.  The port is
+    /// taken from the URL as usual.  Addresses given here are still checked by a [`Policy`].
+    /// This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn resolve(mut self, host: &str, ip: IpAddr) -> Self {
+        self.resolver.overrides.entry(host.to_ascii_lowercase()).or_default().push(ip);
+        self
+    }
+
+    /// Try the IPv4 addresses of a host before its IPv6 addresses, e.g. where IPv6 connectivity
+    /// is broken.  The IPv6 addresses are still tried if none of the IPv4 addresses can be
+    /// reached.  This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn prefer_ipv4(mut self) -> Self {
+        self.resolver.prefer_ipv6 = Some(false);
+        self
+    }
+
+    /// Try the IPv6 addresses of a host before its IPv4 addresses, as with
+    /// [`prefer_ipv4`](AsyncDownloadBuilder::prefer_ipv4).
+    pub fn prefer_ipv6(mut self) -> Self {
+        self.resolver.prefer_ipv6 = Some(true);
+        self
+    }
+
+    /// Bind connections to the local address `ip`, so the download leaves from a specific
+    /// interface.  Only hosts with an address of the same family can be reached.  This configures
+    /// the client, so it cannot be combined with [`client`](AsyncDownloadBuilder::client).
+    pub fn local_address(self, ip: IpAddr) -> Self {
+        self.configure_client(|client| client.local_address(ip))
+    }
+
+    /// Ask the server to compress the response with `Content-Encoding`, and decompress it while
+    /// it is written, so the file holds the original content.  The encodings offered are those
+    /// enabled by the `gzip`, `brotli` and `zstd` features, and without any of them this does
+    /// nothing.  The length and progress of a compressed response are in decompressed bytes, so
+    /// its total length is not known in advance, and an expected checksum is checked against the
+    /// decompressed content.  Requests for a range, such as when resuming, do not ask for
+    /// compression, so their offsets match the decompressed content.  This configures the
+    /// client, so it cannot be combined with [`client`](AsyncDownloadBuilder::client).  Defaults
+    /// to `false`, in which case the response is written as it is sent.
+    pub fn decode_content(mut self, decode: bool) -> Self {
+        self.decode_content = decode;
+        self
+    }
+
+    /// Only use HTTP/1.1.  This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    pub fn http1_only(self) -> Self {
+        self.configure_client(|client| client.http1_only())
+    }
+
+    /// Use HTTP/2 without negotiating it first, for servers which are known to support it,
+    /// including over plain `http`.  Otherwise HTTP/2 is used over `https` when the server
+    /// offers it during the TLS handshake, which requires the `http2` feature.  The version used
+    /// is available from [`DownloadResult::version`].  This configures the client, so it cannot
+    /// be combined with [`client`](AsyncDownloadBuilder::client).
+    ///
+    /// [`DownloadResult::version`]: crate::DownloadResult::version
+    pub fn http2_prior_knowledge(self) -> Self {
+        self.configure_client(|client| client.http2_prior_knowledge())
+    }
+
+    /// Use HTTP/3 over QUIC without negotiating it first, for `https` servers which are known to
+    /// support it.  This is experimental: it requires the `http3` feature, which `reqwest` only
+    /// builds with `RUSTFLAGS="--cfg reqwest_unstable"`, and the client uses rustls rather than
+    /// the system's TLS library.  The version used is available from
+    /// [`DownloadResult::version`].  This configures the client, so it cannot be combined with
+    /// [`client`](AsyncDownloadBuilder::client).
+    ///
+    /// [`DownloadResult::version`]: crate::DownloadResult::version
+    #[cfg(feature="http3")]
+    pub fn http3_prior_knowledge(mut self) -> Self {
+        self.config.version = Some(reqwest::Version::HTTP_3);
+        self.configure_client(|client| client.use_rustls_tls().http3_prior_knowledge())
+    }
+
+    /// Set a timeout for only the connect phase of each request.  This configures the client, so
+    /// it cannot be combined with [`client`](AsyncDownloadBuilder::client).
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        self.configure_client(|client| client.connect_timeout(timeout))
+    }
+
+    /// Fail the attempt with `Timeout` if no bytes are received for `timeout` while the response
+    /// body is streaming.  Like other transient failures, this is retried if retries are enabled.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.config.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail the attempt with `TooSlow` if the response body arrives at less than `bytes_per_sec`
+    /// bytes per second on average over `window`, like curl's `--speed-limit` and
+    /// `--speed-time`, so that a connection which only trickles is given up on.  Only time spent
+    /// waiting for the network counts, not time paused, held back by the rate limit or writing, and
+    /// with [`segments`](AsyncDownloadBuilder::segments) each connection is checked on its own.
+    /// Like other transient failures, this is retried if retries are enabled.  A zero window
+    /// makes [`build`](AsyncDownloadBuilder::build) return `InvalidConfig`.
+    pub fn minimum_speed(mut self, bytes_per_sec: u64, window: Duration) -> Self {
+        match window.is_zero() {
+            true => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("minimum speed window must not be zero"))),
+            false => self.config.minimum_speed = Some((bytes_per_sec, window)),
+        }
+        self
+    }
+
+    /// Set headers which will be sent with the request, replacing any previously set.
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.config.headers = headers;
+        self
+    }
+
+    /// Add a header which will be sent with the request, such as `Authorization` or `User-Agent`.
+    /// If the name or value is not valid, [`build`](AsyncDownloadBuilder::build) will return
+    /// `InvalidConfig`.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+    {
+        match (key.try_into(), value.try_into()) {
+            (Ok(key), Ok(value)) => {
+                self.config.headers.append(key, value);
+            }
+            _ => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("invalid header"))),
+        }
+        self
+    }
+
+    /// Send Basic authentication with the login and password that `~/.netrc` lists for the host
+    /// of each `http` or `https` request, or its `default`, as curl's `--netrc` and wget do.  The
+    /// file named by the `NETRC` environment variable is read instead if it is set.  A request
+    /// which already has an `Authorization` header, such as one set with
+    /// [`header`](AsyncDownloadBuilder::header) or from the user of the URL, is sent as it is,
+    /// and a missing file lists no credentials.  Defaults to `false`.
+    pub fn netrc(mut self, enabled: bool) -> Self {
+        self.config.netrc = match enabled {
+            true => Netrc::default_file(),
+            false => None,
+        };
+        self
+    }
+
+    /// Look up credentials as with [`netrc`](AsyncDownloadBuilder::netrc), but in the file at
+    /// `path`, which the download fails to read if it does not exist.
+    pub fn netrc_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.config.netrc = Some(Netrc::file(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Ask `provider` for the credentials of every request, such as an OAuth access token it
+    /// refreshes or a pre-signed URL it signs again when the last one expires, so that a long
+    /// download outlives them.  A request rejected with `401 Unauthorized` is sent once more
+    /// with renewed credentials.  Credentials from [`netrc`](AsyncDownloadBuilder::netrc) are
+    /// only added to requests the provider sends without an `Authorization` header.  See
+    /// [`AuthProvider`].
+    pub fn auth_provider(mut self, provider: Arc<dyn AuthProvider>) -> Self {
+        self.config.auth = Some(SharedAuth(provider));
+        self
+    }
+
+    /// Add a hook which inspects and changes every request before it is sent, including each
+    /// hop of a redirect, after any hooks added before it, e.g. to sign requests or add trace
+    /// headers.  See [`Interceptor`].
+    pub fn interceptor(mut self, interceptor: impl Interceptor + 'static) -> Self {
+        self.config.interceptors.0.push(Box::new(interceptor));
+        self
+    }
+
+    /// Set the HTTP method of the request.  Defaults to `GET`.
+    pub fn method(mut self, method: Method) -> Self {
+        self.config.method = method;
+        self
+    }
+
+    /// Set the body of the request, e.g. for a `POST`.  The body is sent again with every retry
+    /// and range request.
+    pub fn body<B: Into<Bytes>>(mut self, body: B) -> Self {
+        self.config.body = Some(body.into());
+        self
+    }
+
+    /// Download only the bytes of the remote file from offset `start` up to `end`, exclusive,
+    /// which are requested with a `Range` header and written to the start of the file on disk.
+    /// The server must answer with `206 Partial Content` and a `Content-Range` which starts at
+    /// `start`, and the download fails with `InvalidResponse` otherwise, including when the
+    /// server ignores the range and sends the whole file.  If the remote file ends before `end`,
+    /// the download stops at its end.  Retries, resumes and segments request ranges within the
+    /// slice.  An `end` which is not past `start` makes [`build`](AsyncDownloadBuilder::build)
+    /// return `InvalidConfig`.
+    pub fn range(mut self, start: u64, end: u64) -> Self {
+        if end > start {
+            self.config.range = Some((start, end));
+        } else {
+            self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("range must end after it starts")));
+        }
+        self
+    }
+
+    /// Retry the download up to `retries` times on transient failures, such as connection resets,
+    /// timeouts, `429 Too Many Requests` and `5xx` responses.  If the server supports ranges, retries pick up from the
+    /// last byte written.  If every retry fails, the last error is returned wrapped in
+    /// `TooManyRetries`.  Defaults to `0`.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.config.retries = retries;
+        self
+    }
+
+    /// Set the policy used to wait between retries.  Defaults to exponential backoff starting at
+    /// 500 milliseconds.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.config.backoff = backoff;
+        self
+    }
+
+    /// Download the file again from the start, up to `retries` times, if it fails verification
+    /// against an expected checksum, Subresource Integrity string, piece hashes, announced digest
+    /// or signature, or a transform stage finds it is not authentic.  Each failed attempt is
+    /// reported by [`DownloadResult::corrupt_attempts`](crate::DownloadResult::corrupt_attempts).
+    /// This only applies to downloads to a file.  Defaults to `0`.
+    pub fn retry_on_mismatch(mut self, retries: u32) -> Self {
+        self.config.mismatch_retries = retries;
+        self
+    }
+
+    /// Move a download which fails verification to `<filename>.corrupt-<timestamp>` next to the
+    /// destination, where the timestamp is in seconds since the Unix epoch, rather than removing
+    /// it, so that it can be inspected later.  If it cannot be moved, for example because the
+    /// `.part` file is in a temporary directory on another filesystem, it is removed.  Defaults
+    /// to `false`.
+    pub fn quarantine_corrupt(mut self, quarantine: bool) -> Self {
+        self.config.quarantine = quarantine;
+        self
+    }
+
+    /// Set the longest `Retry-After` to wait for.  When a `429` or `503` response has a
+    /// `Retry-After` header, the next retry waits for the duration it gives instead of the
+    /// backoff, and if that is longer than `max`, the download fails without retrying.  Defaults
+    /// to 5 minutes.
+    pub fn max_retry_after(mut self, max: Duration) -> Self {
+        self.config.max_retry_after = Some(max);
+        self
+    }
+
+    /// Write the download to `<fname>.part` and only rename it to its final name once the stream
+    /// has completed and any verification has passed, so a partially written file never has the
+    /// final name.  Resuming picks up from the `.part` file.  Defaults to `false`.
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.config.atomic = atomic;
+        self
+    }
+
+    /// Set how the completed download is synced to disk.  For an atomic download, the `.part`
+    /// file is synced before it is renamed.  Defaults to `SyncPolicy::None`.
+    pub fn sync(mut self, sync: SyncPolicy) -> Self {
+        self.config.sync = sync;
+        self
+    }
+
+    #[cfg(unix)]
+    /// Set the Unix permissions of the written file, such as `0o755` for a downloaded binary.
+    /// They are applied once the download has completed and been verified, before an atomic
+    /// download is renamed, so that the file never has its final name with other permissions.
+    /// Until then, the file has the permissions it was created with.  Defaults to leaving them
+    /// as created.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.config.mode = Some(mode);
+        self
+    }
+
+    #[cfg(unix)]
+    /// Set the owner and group of the written file by user and group ID, leaving either as
+    /// created if `None`.  They are applied like [`mode`](AsyncDownloadBuilder::mode), and
+    /// before it, since changing the owner may clear the setuid and setgid bits.  Changing the
+    /// owner usually needs privileges, and the download fails with `PermissionDenied` without
+    /// them.
+    pub fn owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.config.uid = uid;
+        self.config.gid = gid;
+        self
+    }
+
+    /// Set the directory the `.part` file of an atomic download is written to.  Defaults to the
+    /// destination directory.  Once the download completes, the `.part` file is moved next to
+    /// the destination before it is renamed into place, and if the directory is on another
+    /// filesystem, such as a `tmpfs` mounted on `/tmp`, this copies it and syncs the copy before
+    /// removing the original.
+    pub fn temp_dir<P: AsRef<Path>>(mut self, temp_dir: P) -> Self {
+        self.config.temp_dir = Some(temp_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Cancel the download when the given token is cancelled, in which case it fails with
+    /// `Cancelled`.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.config.cancellation_token = Some(token);
+        self
+    }
+
+    /// Remove the partially written file when the download is cancelled.  Defaults to `false`, so
+    /// that the download can be resumed later.  This is shorthand for
+    /// [`cleanup`](AsyncDownloadBuilder::cleanup).
+    pub fn remove_on_cancel(mut self, remove: bool) -> Self {
+        self.config.cleanup = if remove {
+            CleanupPolicy::RemoveOnCancel
+        } else {
+            CleanupPolicy::Keep
+        };
+        self
+    }
+
+    /// Set what to do with the partially written file when the download does not complete.
+    /// Defaults to `CleanupPolicy::Keep`.
+    pub fn cleanup(mut self, cleanup: CleanupPolicy) -> Self {
+        self.config.cleanup = cleanup;
+        self
+    }
+
+    #[cfg(feature="state")]
+    /// Save the progress of the download as JSON in `<filename>.state` next to it, with its URL,
+    /// `ETag`, `Last-Modified` date, length and the pieces of a segmented download which have
+    /// been written, so that [`resume`](AsyncDownload::resume) or [`AsyncDownload::from_state`]
+    /// picks it up where it left off after the process is restarted.  A segmented download only
+    /// fetches the pieces which are missing, and otherwise the download resumes from the end of
+    /// the partial file.
+    /// Ranges are requested with `If-Range`, so the download starts again from the beginning if
+    /// the file on the server has changed.  The state is saved whenever a response is received,
+    /// at least once a second while segments are written and when an attempt fails, and is
+    /// removed once the download completes.  Defaults to `false`.
+    pub fn state_file(mut self, enabled: bool) -> Self {
+        self.config.state_file = enabled;
+        self
+    }
+
+    /// Split the download into `segments` byte ranges which are fetched concurrently over separate
+    /// connections, if the server advertises `Accept-Ranges: bytes` and a `Content-Length`.
+    /// Each segment is written at its own offset of a file which is given its full length up
+    /// front, sparsely unless [`preallocate`](AsyncDownloadBuilder::preallocate) is set.
+    /// Otherwise the download falls back to a single connection.  With the `io-uring` feature on
+    /// Linux, the segments are written through io_uring, on a thread of its own, unless the kernel
+    /// does not allow it.  Any checksum is computed from the file once all segments have
+    /// completed.  When the pieces left of a resumed download are scattered in more ranges than
+    /// there are connections, each connection asks for several of them in one request, which the
+    /// server answers with a `multipart/byteranges` response, or else they are requested one at a
+    /// time.  Defaults to `1`.
+    pub fn segments(mut self, segments: usize) -> Self {
+        self.config.segments = segments;
+        self
+    }
+
+    /// Reserve disk space for the whole download before streaming it, once its length is known,
+    /// so that running out of space fails straight away and the file is less fragmented.  The
+    /// size of the file is not changed, so an interrupted download can still be resumed.  On
+    /// Linux filesystems which cannot preallocate are written to as usual.  Elsewhere the file is
+    /// grown to its length and truncated back, which fails straight away on filesystems that
+    /// allocate the space to grow a file, but reserves none of it.  Defaults to `false`.
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.config.preallocate = preallocate;
+        self
+    }
+
+    /// Set the size of the buffer in front of the file the download is written to, so that the
+    /// chunks received from the network are written to it in fewer and larger writes.  A zero size makes
+    /// [`build`](AsyncDownloadBuilder::build) return `InvalidConfig`.  Defaults to 256 KiB.
+    pub fn buffer_size(mut self, bytes: usize) -> Self {
+        match bytes {
+            0 => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("buffer size must not be zero"))),
+            bytes => self.config.buffer_size = Some(bytes),
+        }
+        self
+    }
+
+    /// Read the response ahead of writing it, through a queue which holds at most `bytes` bytes,
+    /// and hold no more than `bytes` in any other buffer of the download either: the buffer in
+    /// front of the file and the queue of
+    /// [`offload_hashing`](AsyncDownloadBuilder::offload_hashing).  A download then holds at
+    /// most a few times `bytes` in memory, besides the chunk being transformed and any buffers
+    /// of the client, however fast the network is compared to the disk.  A zero size, or one
+    /// above `tokio::sync::Semaphore::MAX_PERMITS`, makes [`build`](AsyncDownloadBuilder::build)
+    /// return `InvalidConfig`.  By default the response is only read once the previous chunk has
+    /// been written.
+    pub fn max_buffered(mut self, bytes: usize) -> Self {
+        match bytes {
+            0 => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("buffered bytes must not be zero"))),
+            bytes if bytes > tokio::sync::Semaphore::MAX_PERMITS => {
+                self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("too many buffered bytes")));
+            }
+            bytes => self.config.max_buffered = Some(bytes),
+        }
+        self
+    }
+
+    /// Hash the download on a blocking thread rather than on the runtime, while the next chunks
+    /// are read and written, which helps when it is verified against checksums or signatures and
+    /// the network is faster than a single hasher.  At most a few MiB are queued for the thread
+    /// before the download waits for it.  Defaults to `false`.
+    pub fn offload_hashing(mut self, offload: bool) -> Self {
+        self.config.offload_hashing = offload;
+        self
+    }
+
+    /// Limit the download to `bytes_per_sec` bytes per second.
+    pub fn limit_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.config.rate_limiter = Some(RateLimiter::new(bytes_per_sec));
+        self
+    }
+
+    /// Limit the download with a `RateLimiter` which may be shared with other downloads, so that
+    /// their combined rate stays under its limit.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.config.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Share the bandwidth of `budget` fairly with the other downloads in it, such as the rest of
+    /// a group of downloads.  A download may be in several budgets, such as one for its group
+    /// and one for every download, and waits for its share of each.
+    pub fn budget(mut self, budget: &Budget) -> Self {
+        self.config.budgets.push(budget.share());
+        self
+    }
+
+    /// Add a mirror of the download.  When mirrors are added, the download URL and every mirror
+    /// are requested at once, and the download continues from whichever delivers the first
+    /// [`race_bytes`](AsyncDownloadBuilder::race_bytes) soonest, cancelling the others.  Any later
+    /// range requests, such as for retries or segments, go to the same mirror.
+    pub fn mirror(mut self, url: &str) -> Self {
+        self.config.mirrors.push(String::from(url));
+        self
+    }
+
+    /// Set how many bytes each mirror gets to deliver when racing them.  Defaults to 256 KiB.
+    pub fn race_bytes(mut self, bytes: u64) -> Self {
+        self.config.race_bytes = Some(bytes);
+        self
+    }
+
+    #[cfg(feature="metalink")]
+    /// Download the file described by the Metalink at `url`, such as a `.meta4` file in the
+    /// format of RFC 5854 or a `.metalink` file of the older version 3, which is fetched with the
+    /// same options before the download starts.  The highest priority URLs it lists are raced
+    /// as [`mirror`](AsyncDownloadBuilder::mirror)s, after the download URL if one was set, and
+    /// its name is used if no filename was set.  With the `sha256sum` feature, its sha256sum and
+    /// sha256 piece hashes are verified, unless others were set.  If the Metalink lists several
+    /// files, the one named like the download is used.  A document which is not valid, or does
+    /// not list the download, fails with `InvalidMetalink`.  The download URL need not be set.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let mut download = AsyncDownload::builder()
+    ///     .metalink("https://example.com/release.iso.meta4")
+    ///     .dst_dir("/tmp")
+    ///     .build()?;
+    /// download.download(&None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn metalink<U: IntoUrl>(mut self, url: U) -> Self {
+        match url.into_url() {
+            Ok(url) => self.config.metalink = Some(MetalinkSource::Url(url.to_string())),
+            Err(err) => self.error = Some(err),
+        }
+        self
+    }
+
+    #[cfg(feature="metalink")]
+    /// Ask the download URL for a description of the download with a `HEAD` request before it
+    /// starts, as in RFC 6249: the mirrors its `Link` headers list as duplicates are raced as
+    /// with [`metalink`](AsyncDownloadBuilder::metalink), along with those of a Metalink they
+    /// link to, and with the `sha256sum` feature, the sha256sum of its `Digest` header is
+    /// verified.  A server which lists none is downloaded from as usual.  Defaults to `false`.
+    pub fn metalink_links(mut self, enabled: bool) -> Self {
+        match enabled {
+            true => self.config.metalink = Some(MetalinkSource::Links),
+            false => self.config.metalink = None,
+        }
+        self
+    }
+
+    #[cfg(feature="torrent")]
+    /// Download the file a torrent describes from its web seeds and peers, where `source` is the
+    /// URL of a `.torrent` file, of at most 8 MiB, or a magnet link.  The web seeds it lists, as
+    /// in BEP 19, or those of the magnet link's `ws` and `as` parameters, are raced as
+    /// [`mirror`](AsyncDownloadBuilder::mirror)s, after the download URL if one was set, and its
+    /// name is used if no filename was set.  The peers listed by its trackers, from `announce`
+    /// and `announce-list` or the magnet link's `tr` parameters, and those of its `x.pe`
+    /// parameters, are a further source, which pieces are fetched from over the peer wire
+    /// protocol; nothing is uploaded, and the DHT is not used.  A magnet link which does not give
+    /// the URL of its torrent in its `xs` parameter has its info dictionary fetched from the
+    /// peers, as in BEP 9.  Its SHA-1 piece hashes are verified unless others were set.  Peers
+    /// are not used with a [`policy`](AsyncDownloadBuilder::policy) which only allows `https`,
+    /// or when requests go through a proxy.  A torrent which is not valid, lists several files,
+    /// or has no web seeds or peers to download from fails with `InvalidTorrent`.  The download
+    /// URL need not be set.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let mut download = AsyncDownload::builder()
+    ///     .torrent("https://example.com/release.iso.torrent")
+    ///     .dst_dir("/tmp")
+    ///     .build()?;
+    /// download.download(&None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn torrent(mut self, source: &str) -> Self {
+        let source = match source.starts_with("magnet:") {
+            true if crate::torrent::is_valid_magnet(source) => Ok(String::from(source)),
+            true => Err(TDSTDError::new(TDSTDErrorKind::InvalidTorrent)),
+            false => source.into_url().map(|url| url.to_string()),
+        };
+        match source {
+            Ok(source) => self.config.torrent = Some(source),
+            Err(err) => self.error = Some(err),
+        }
+        self
+    }
+
+    /// Report progress at most once per `interval`, rather than for every chunk read from the
+    /// network.  If [`progress_bytes`](AsyncDownloadBuilder::progress_bytes) is also set,
+    /// progress is reported when either is reached.  The final position is always reported.
+    pub fn progress_interval(mut self, interval: Duration) -> Self {
+        self.config.progress_interval = Some(interval);
+        self
+    }
+
+    /// Report progress at most once per `bytes` downloaded, rather than for every chunk read from
+    /// the network.  The final position is always reported.
+    pub fn progress_bytes(mut self, bytes: u64) -> Self {
+        self.config.progress_bytes = Some(bytes);
+        self
+    }
+
+    /// Record the bytes downloaded, retries, and how long the download took and whether it failed
+    /// in `metrics`, which may be shared with other downloads.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.config.metrics = Some(SharedMetrics(metrics));
+        self
+    }
+
+    /// Send `If-None-Match` with the given `ETag` from a previous download.  If the server responds
+    /// with `304 Not Modified`, nothing is downloaded and [`DownloadResult::not_modified`] returns
+    /// `true`.
+    ///
+    /// [`DownloadResult::not_modified`]: crate::DownloadResult::not_modified
+    pub fn if_none_match(mut self, etag: &str) -> Self {
+        self.config.validators.etag = Some(String::from(etag));
+        self
+    }
+
+    /// Send `If-Modified-Since` with the given HTTP date, such as the `Last-Modified` of a previous
+    /// download.  If the server responds with `304 Not Modified`, nothing is downloaded and
+    /// [`DownloadResult::not_modified`] returns `true`.
+    ///
+    /// [`DownloadResult::not_modified`]: crate::DownloadResult::not_modified
+    pub fn if_modified_since(mut self, date: &str) -> Self {
+        self.config.validators.last_modified = Some(String::from(date));
+        self
+    }
+
+    /// Store the `ETag` and `Last-Modified` of each completed download in a `<fname>.validators`
+    /// file alongside it, and send them with later downloads while the file exists, as with
+    /// [`if_none_match`](AsyncDownloadBuilder::if_none_match) and
+    /// [`if_modified_since`](AsyncDownloadBuilder::if_modified_since).  If the server has a newer
+    /// version, it is written according to the overwrite behavior, so this is usually combined
+    /// with `OverwriteBehavior::Overwrite`.  A filename must be set for the stored validators to
+    /// be found before the request is made.  Defaults to `false`.
+    pub fn store_validators(mut self, store: bool) -> Self {
+        self.config.store_validators = store;
+        self
+    }
+
+    /// Set the modification time of the written file to the `Last-Modified` date of the
+    /// response, like `curl -R` or `wget -N`, before it is renamed into place.  While the file
+    /// exists, later downloads then send its modification time as `If-Modified-Since`, unless a
+    /// date was set with [`if_modified_since`](AsyncDownloadBuilder::if_modified_since) or
+    /// stored with [`store_validators`](AsyncDownloadBuilder::store_validators).  A filename
+    /// must be set for the file to be found before the request is made.  The time is left alone
+    /// if the response has no valid `Last-Modified` date.  Defaults to `false`.
+    pub fn remote_time(mut self, enabled: bool) -> Self {
+        self.config.remote_time = enabled;
+        self
+    }
+
+    /// Keep responses in an HTTP cache in `dir`, with the semantics of a private cache under
+    /// RFC 9111, so that downloading the same URL again, even from another process, is served
+    /// from the cache while its `Cache-Control: max-age` or `Expires` says it is fresh, and is
+    /// otherwise revalidated with its `ETag` or `Last-Modified`, so that an unchanged file is not
+    /// sent again.  `Vary` and the `no-store` and `no-cache` directives of requests and
+    /// responses are honored.  A response is only stored once its body has been read in full,
+    /// so one downloaded in segments or resumed part way is not, although one stored before is
+    /// served by segments alike.  The directory is created if needed, and the cache is never
+    /// cleaned up; a cache which cannot be read or written is ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let mut download = AsyncDownload::builder()
+    ///     .url("https://example.com/toolchain.tar.gz")
+    ///     .dst_dir("build")
+    ///     .cache_dir("/var/cache/toolchains")
+    ///     .overwrite(true)
+    ///     .build()?;
+    /// download.download(&None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn cache_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.config.cache = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Record where the completed download came from: its final URL, `Content-Type`, `ETag` and
+    /// `Last-Modified`, the date it was downloaded and, with the `sha256sum` feature, its
+    /// sha256sum.  Extended attributes follow the freedesktop.org convention where there is
+    /// one, as `user.xdg.origin.url` and `user.mime_type`, and are otherwise named
+    /// `user.etag`, `user.last_modified`, `user.downloaded` and `user.checksum.sha256`;
+    /// macOS has no `user.` namespace, so they are set without the prefix there.  They are set
+    /// before an atomic download is renamed, and the download fails if they cannot be.
+    /// Defaults to `SourceMetadata::None`.
+    pub fn source_metadata(mut self, metadata: SourceMetadata) -> Self {
+        self.config.source_metadata = metadata;
+        self
+    }
+
+    #[cfg(target_os="macos")]
+    /// Mark the download as quarantined with the `com.apple.quarantine` attribute, naming `agent`
+    /// as the application which downloaded it, and record its URL as where it came from in
+    /// `com.apple.metadata:kMDItemWhereFroms`, so that Gatekeeper checks it before it is first
+    /// opened, as it does for downloads made by a browser.  The attributes are set before an
+    /// atomic download is renamed, and the download fails if they cannot be.
+    pub fn gatekeeper_quarantine(mut self, agent: &str) -> Self {
+        self.config.quarantine_agent = Some(String::from(agent));
+        self
+    }
+
+    #[cfg(feature="sha256sum")]
+    /// Verify the download against the given sha256sum while streaming it.  If the checksum does
+    /// not match, the file is removed and `ChecksumMismatch` is returned.
+    pub fn expect_sha256(mut self, sha256: &[u8; 32]) -> Self {
+        self.config.expected_sha256 = Some(*sha256);
+        self
+    }
+
+    #[cfg(feature="sha256sum")]
+    /// Like [`expect_sha256`](AsyncDownloadBuilder::expect_sha256), but takes the sha256sum as a
+    /// hex string.  If it is not valid, [`build`](AsyncDownloadBuilder::build) will return
+    /// `InvalidDigest`.
+    pub fn expect_sha256_hex(mut self, sha256: &str) -> Self {
+        match parse_hex(sha256).and_then(|digest| digest.try_into().ok()) {
+            Some(digest) => self.config.expected_sha256 = Some(digest),
+            None => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidDigest)),
+        }
+        self
+    }
+
+    #[cfg(feature="digest")]
+    /// Verify each piece of `piece_len` bytes of the download against its expected hash with
+    /// `D` as it streams, as in torrent piece lists or chunk manifests, so that the download
+    /// fails as soon as a piece does not match rather than once it is complete.  The last piece
+    /// may be shorter.  A piece which does not match, or a download with more or fewer pieces
+    /// than listed, fails with `PieceMismatch`, reporting the first such piece and the bytes it
+    /// covers, and the file is removed.  When resuming, the pieces already on disk are checked
+    /// before the rest is downloaded.  If `piece_len` is zero,
+    /// [`build`](AsyncDownloadBuilder::build) will return `InvalidConfig`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "sha256sum")]
+    /// # fn run(hashes: Vec<Vec<u8>>) -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// let download = AsyncDownload::builder()
+    ///     .url("https://example.com/disk.img")
+    ///     .dst_dir("/tmp")
+    ///     .expect_piece_hashes::<sha2::Sha256>(4 * 1024 * 1024, hashes)
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn expect_piece_hashes<D: DynDigest + Default + Send + 'static>(mut self, piece_len: u64, hashes: Vec<Vec<u8>>) -> Self {
+        if piece_len == 0 {
+            self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("pieces cannot be empty")));
+            return self;
+        }
+        self.config.piece_hashes = Some(Arc::new(PieceHashes::new::<D>(piece_len, hashes)));
+        self
+    }
+
+    #[cfg(feature="sha256sum")]
+    /// Verify the download against the sha256sum in `<url>.sha256`, which is fetched with the
+    /// same options before the download starts, as published next to the files on most mirrors.
+    /// The sidecar may hold only the digest, or list it in the format of `sha256sum` or BSD
+    /// `sha256 -r`.  If it lists several entries, the one whose name matches the last segment of
+    /// the URL or the filename of the download is used, and if there is none,
+    /// `ChecksumNotFound` is returned.  This is ignored if an expected sha256sum was set with
+    /// [`expect_sha256`](AsyncDownloadBuilder::expect_sha256).
+    pub fn sha256_sidecar(mut self) -> Self {
+        self.config.sha256_manifest = Some(Manifest::Sidecar);
+        self
+    }
+
+    #[cfg(feature="sha256sum")]
+    /// Like [`sha256_sidecar`](AsyncDownloadBuilder::sha256_sidecar), but fetches the checksums
+    /// from the manifest at `url`, such as a `SHA256SUMS` file listing every file of a release.
+    /// The entry used must match the name of the download even if it is the only one.
+    pub fn sha256_manifest(mut self, url: &str) -> Self {
+        self.config.sha256_manifest = Some(Manifest::Url(String::from(url)));
+        self
+    }
+
+    #[cfg(feature="minisign")]
+    /// Verify the download against its detached minisign signature once it completes, fetched
+    /// from `<url>.minisig` or the [`signature_url`](AsyncDownloadBuilder::signature_url).  The
+    /// public key is given in base64, or as the contents of a `minisign.pub` file.  Only
+    /// signatures in the default, prehashed mode are accepted.  If the signature is not valid,
+    /// the file is removed and `SignatureInvalid` is returned.  Signatures can only be verified
+    /// for downloads to a file.  If the public key is not valid,
+    /// [`build`](AsyncDownloadBuilder::build) will return `InvalidConfig`.
+    pub fn verify_minisign(mut self, public_key: &str) -> Self {
+        match SigningKey::minisign(public_key) {
+            Some(key) => self.config.signing_key = Some(key),
+            None => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("public key is not valid"))),
+        }
+        self
+    }
+
+    #[cfg(feature="openpgp")]
+    /// Like [`verify_minisign`](AsyncDownloadBuilder::verify_minisign), but verifies a detached
+    /// OpenPGP signature, ASCII-armored or binary, against an ASCII-armored public key.  The
+    /// signature is fetched from `<url>.asc` unless a
+    /// [`signature_url`](AsyncDownloadBuilder::signature_url) is set, e.g. for a `.sig` file.
+    /// It may be made by the primary key or any of its subkeys.
+    pub fn verify_openpgp(mut self, public_key: &str) -> Self {
+        match SigningKey::openpgp(public_key) {
+            Some(key) => self.config.signing_key = Some(key),
+            None => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidConfig("public key is not valid"))),
+        }
+        self
+    }
+
+    #[cfg(any(feature="minisign", feature="openpgp"))]
+    /// Fetch the detached signature of the download from `url`, rather than from next to it.
+    pub fn signature_url(mut self, url: &str) -> Self {
+        self.config.signature_url = Some(String::from(url));
+        self
+    }
+
+    #[cfg(feature="sri")]
+    /// Verify the download against a Subresource Integrity string, such as
+    /// `sha384-<base64 digest>`, as found in npm lockfiles and web manifests, while streaming it.
+    /// If the string lists several digests, those of the strongest algorithm among SHA-256,
+    /// SHA-384 and SHA-512 are used, and the download must match one of them.  If it does not,
+    /// the file is removed and `ChecksumMismatch` is returned.  If the string lists no digest of
+    /// these algorithms, or one of them is not valid, [`build`](AsyncDownloadBuilder::build)
+    /// will return `InvalidDigest`.
+    pub fn expect_integrity(mut self, sri: &str) -> Self {
+        match Integrity::parse(sri) {
+            Some(integrity) => self.config.expected_integrity = Some(integrity),
+            None => self.error = Some(TDSTDError::new(TDSTDErrorKind::InvalidDigest)),
+        }
+        self
+    }
+
+    #[cfg(feature="pinning")]
+    /// Pin the certificate presented by the server to the given sha256 digest of its DER
+    /// encoding.  Several pins can be added, e.g. for a backup certificate, and every response,
+    /// including redirects, must match one of them or the download fails with `PinMismatch`.  The
+    /// certificate is checked once the TLS connection is established and the request has been
+    /// sent, but before any of the response is read.  Only the certificate of the server is
+    /// checked, not the rest of its chain, and a download over plain `http` never matches.
+    ///
+    /// This configures the client to record the certificate.  If a pre-built
+    /// [`client`](AsyncDownloadBuilder::client) is used instead, so that it can be shared with its
+    /// pins, it must be built with `tls_info(true)`, otherwise no response matches.
+    pub fn pin_certificate_sha256(mut self, sha256: &[u8; 32]) -> Self {
+        self.config.pins.push(Pin::Certificate(*sha256));
+        self
+    }
+
+    #[cfg(feature="pinning")]
+    /// Like [`pin_certificate_sha256`](AsyncDownloadBuilder::pin_certificate_sha256), but pins the
+    /// sha256 digest of the DER-encoded `SubjectPublicKeyInfo` of the certificate, which stays
+    /// the same when the certificate is renewed with the same key.
+    pub fn pin_spki_sha256(mut self, sha256: &[u8; 32]) -> Self {
+        self.config.pins.push(Pin::Spki(*sha256));
+        self
+    }
+
+    #[cfg(feature="decompress")]
+    /// Decompress the download from `format` as it is written, so that e.g. `foo.tar.zst` is
+    /// written out as `foo.tar` without a second pass over the disk.  If the filename is derived
+    /// from the response, the extension of the format is removed.  Progress, lengths and
+    /// expected checksums refer to the compressed bytes as they are downloaded.  Since the
+    /// decompressed file cannot be appended to, retries start the download again, and
+    /// [`resume`](AsyncDownload::resume) and segmented downloads are not available.  Downloads
+    /// into a writer or memory are not decompressed, but archives which are unpacked as they are
+    /// downloaded are.  Compressed data which is corrupt or incomplete fails the download with an
+    /// `IO` error.
+    pub fn decompress(mut self, format: CompressionFormat) -> Self {
+        self.config.decompress = Some(format);
+        self
+    }
+
+    /// Add a stage which the response body passes through before it is written, after any
+    /// stages added before it.  Progress and lengths refer to the bytes as they are downloaded,
+    /// and so do expected checksums unless
+    /// [`hash_transformed`](AsyncDownloadBuilder::hash_transformed) is set.  Since the state of
+    /// a stage does not outlive the download, [`resume`](AsyncDownload::resume) and segmented
+    /// downloads are not available.
+    pub fn transform(mut self, stage: impl Transform + 'static) -> Self {
+        self.config.transforms.0.push(Box::new(stage));
+        self
+    }
+
+    #[cfg(feature="compress")]
+    /// Compress the download to `format` as it is written, for storing large but compressible
+    /// downloads.  This adds a [`Compress`] stage with
+    /// [`transform`](AsyncDownloadBuilder::transform), so expected checksums still refer to the
+    /// uncompressed bytes unless [`hash_transformed`](AsyncDownloadBuilder::hash_transformed) is
+    /// set.  If the filename is derived from the response, the extension of the format is
+    /// appended to it, so that `foo.tar` is written as `foo.tar.zst`.
+    pub fn store_compressed(mut self, format: CompressionFormat) -> Self {
+        self.config.store_compressed = Some(format);
+        self.transform(Compress::new(format))
+    }
+
+    /// Verify the expected checksum against the output of the transform stages, rather than the
+    /// bytes as they are downloaded, and likewise for the digests returned by
+    /// [`download_and_return_hash`](AsyncDownload::download_and_return_hash).
+    pub fn hash_transformed(mut self, enabled: bool) -> Self {
+        self.config.hash_transformed = enabled;
+        self
+    }
+
+    #[cfg(feature="content-digest")]
+    /// Verify the download against the digest the server announces in a `Repr-Digest` or
+    /// `Content-Digest` header (RFC 9530), or a legacy `Content-MD5` header, failing with
+    /// `ChecksumMismatch` if it does not match.  SHA-512 is preferred to SHA-256, and other
+    /// algorithms are ignored.  The digest is of the response as it is sent, so it is not checked
+    /// when resuming, when [`decode_content`](AsyncDownloadBuilder::decode_content) is set, when
+    /// a pre-built [`client`](AsyncDownloadBuilder::client), which may decode the response, is
+    /// used, or when [`hash_transformed`](AsyncDownloadBuilder::hash_transformed) is set.
+    /// Defaults to `true`.
+    pub fn verify_content_digest(mut self, enabled: bool) -> Self {
+        self.config.ignore_content_digest = !enabled;
+        self
+    }
+
+    /// Returns the configured `AsyncDownload`, or an error if a required field is missing or an
+    /// option was invalid.
+    pub fn build(mut self) -> Result<AsyncDownload, TDSTDError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let url = match (self.url, &self.supplied) {
+            (Some(_), Some(_)) => return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig(
+                "a url cannot be combined with a stream or response",
+            ))),
+            (None, None) => match self.config.described_by() {
+                Some(url) => Some(url.to_owned()),
+                None => return Err(TDSTDError::new(TDSTDErrorKind::MissingField("url"))),
+            },
+            (url, _) => url,
+        };
+        let dst_path = self.dst_path.ok_or_else(|| TDSTDError::new(TDSTDErrorKind::MissingField("dst_dir")))?;
+        let fname = self.fname.unwrap_or_default();
+        let mut config = self.config;
+        if self.supplied.is_some()
+            && (config.retries > 0 || config.mismatch_retries > 0 || config.segments > 1 || !config.mirrors.is_empty() || config.is_described())
+        {
+            return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig(
+                "a stream or response cannot be retried, or requested in segments or from mirrors",
+            )));
+        }
+        config.atomic |= config.filename_template.is_some();
+        config.content_decoded = self.decode_content || config.client.is_some();
+        let mut client_builder = self.client_builder;
+        if config.client.is_some() {
+            if config.redirects.is_some() {
+                return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig(
+                    "redirect options cannot be combined with a pre-built client",
+                )));
+            }
+        } else {
+            config.redirects.get_or_insert_default();
+        }
+        config.proxied = !self.proxies.is_empty()
+            || (!self.no_system_proxy
+                && ["HTTP_PROXY", "HTTPS_PROXY", "ALL_PROXY", "http_proxy", "https_proxy", "all_proxy"]
+                    .iter()
+                    .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty())));
+        if self.no_system_proxy || !self.proxies.is_empty() {
+            let mut builder = client_builder.unwrap_or_default().no_proxy();
+            for proxy in self.proxies {
+                builder = builder.proxy(proxy);
+            }
+            client_builder = Some(builder);
+        }
+        if self.https_only {
+            config.policy = Some(config.policy.take().unwrap_or_default().https_only(true));
+        }
+        if let Some(ref policy) = config.policy {
+            for url in url.iter().map(String::as_str).chain(config.mirrors.iter().map(String::as_str)).chain(config.described_by()) {
+                if url.starts_with("magnet:") {
+                    continue;
+                }
+                if let Ok(url) = reqwest::Url::parse(url) {
+                    policy.check_url(&url)?;
+                }
+            }
+            self.resolver.public_only = policy.checks_addresses();
+            client_builder = Some(client_builder.unwrap_or_default());
+        }
+        if self.resolver.is_needed() {
+            client_builder = Some(client_builder.unwrap_or_default().dns_resolver(Arc::new(self.resolver)));
+        }
+        if self.decode_content {
+            client_builder = Some(client_builder.unwrap_or_default());
+        }
+        #[cfg(feature="pinning")]
+        if !config.pins.is_empty() && config.client.is_none() {
+            client_builder = Some(client_builder.unwrap_or_default().tls_info(true));
+        }
+        if config.backend.is_some() && (client_builder.is_some() || config.client.is_some()) {
+            return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig(
+                "client options cannot be combined with an HTTP backend",
+            )));
+        }
+        if let Some(client_builder) = client_builder {
+            if config.client.is_some() {
+                return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig(
+                    "client options cannot be combined with a pre-built client",
+                )));
+            }
+            let client = content_decoding(client_builder, self.decode_content)
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .map_err(|err| TDSTDError::from(Box::new(err) as Box<dyn std::error::Error>))?;
+            config.client = Some(client);
+        }
+        let mut download = match url {
+            // Every URL was checked as it was set, and a magnet link stands in for the URL until
+            // its web seeds are known.
+            Some(url) => AsyncDownload::with_url(url, dst_path, fname),
+            None => AsyncDownload::with_url(String::new(), dst_path, fname),
+        };
+        download.config = config;
+        if let Some(supplied) = self.supplied {
+            download.supply(supplied);
+        }
+        Ok(download)
+    }
+
+    /// Applies an option to the `reqwest::ClientBuilder` used to build the client for this
+    /// download.
+    fn configure_client(mut self, f: impl FnOnce(reqwest::ClientBuilder) -> reqwest::ClientBuilder) -> Self {
+        let client_builder = self.client_builder.take().unwrap_or_default();
+        self.client_builder = Some(f(client_builder));
+        self
+    }
+}
+
+/// Enables or disables decoding every `Content-Encoding` the client supports.
+pub(crate) fn content_decoding(client: reqwest::ClientBuilder, enabled: bool) -> reqwest::ClientBuilder {
+    if !enabled {
+        return client.no_gzip().no_brotli().no_zstd();
+    }
+    #[cfg(feature="gzip")]
+    let client = client.gzip(true);
+    #[cfg(feature="brotli")]
+    let client = client.brotli(true);
+    #[cfg(feature="zstd")]
+    let client = client.zstd(true);
+    client
+}
+
+#[cfg(any(feature="sha256sum", feature="state", feature="torrent"))]
+pub(crate) fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
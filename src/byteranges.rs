@@ -0,0 +1,146 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::Stream;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+
+use crate::builder::Config;
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::{parse_content_range, read_body, Failure, S};
+
+/// The most a part may send in its headers before its body.
+const MAX_PART_HEADERS: usize = 8 * 1024;
+
+/// Returns the `Range` header which requests each of `ranges`, from its start up to its end,
+/// after moving them by `offset`.
+pub(crate) fn header(ranges: &[(u64, u64)], offset: u64) -> String {
+    let ranges: Vec<String> = ranges.iter()
+        .map(|(start, end)| format!("{}-{}", start + offset, end + offset - 1))
+        .collect();
+    format!("bytes={}", ranges.join(","))
+}
+
+/// Returns the boundary of a `multipart/byteranges` response, or `None` if the response is of
+/// another type, such as when the server sent a single range.
+pub(crate) fn boundary(headers: &HeaderMap) -> Option<String> {
+    let mut params = headers.get(CONTENT_TYPE)?.to_str().ok()?.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/byteranges") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("boundary").then(|| value.trim().trim_matches('"').to_owned())
+    })
+    .filter(|boundary| !boundary.is_empty())
+}
+
+/// The parts of a `multipart/byteranges` response.  Each part is found with
+/// [`next_part`](Parts::next_part), after which the stream yields its body, and ends where the
+/// part does.
+pub(crate) struct Parts {
+    stream: Box<S>,
+    buf: BytesMut,
+    /// The line break and dashes which lead the boundary, as it appears between parts.
+    delimiter: Vec<u8>,
+    /// Whether the stream is within the body of a part, or the preamble before the first.
+    in_body: bool,
+    /// Whether the stream has failed, so that nothing more is read from it.
+    failed: bool,
+}
+
+impl Parts {
+    pub(crate) fn new(stream: Box<S>, boundary: &str) -> Self {
+        Self {
+            stream,
+            // The first boundary need not follow a line break, so one is put in front of it.
+            buf: BytesMut::from(&b"\r\n"[..]),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            in_body: true,
+            failed: false,
+        }
+    }
+
+    /// Skips the rest of the current part and reads the headers of the next one, returning its
+    /// start offset and the offset of its last byte, or `None` once the last part has been
+    /// read.  A part without a valid `Content-Range` is an `InvalidResponse`.
+    pub(crate) async fn next_part(&mut self, config: &Config) -> Result<Option<(u64, u64)>, Failure> {
+        while read_body(self, config, &mut None).await?.is_some() {}
+        self.buf.advance(self.delimiter.len());
+        let headers = loop {
+            if self.buf.starts_with(b"--") {
+                return Ok(None);
+            }
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                break self.buf.split_to(end + 4).freeze();
+            }
+            if self.buf.len() > MAX_PART_HEADERS {
+                return Err(Failure::fatal(TDSTDErrorKind::InvalidResponse));
+            }
+            self.fill(config).await?;
+        };
+        let (start, last, _) = headers.split(|&b| b == b'\n')
+            .filter_map(|line| std::str::from_utf8(line).ok())
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim().eq_ignore_ascii_case("content-range").then(|| parse_content_range(value.trim()))?
+            })
+            .ok_or_else(|| Failure::fatal(TDSTDErrorKind::InvalidResponse))?;
+        self.in_body = true;
+        Ok(Some((start, last)))
+    }
+
+    /// Reads more of the response into the buffer, failing if it ends.
+    async fn fill(&mut self, config: &Config) -> Result<(), Failure> {
+        match read_body(&mut self.stream, config, &mut None).await? {
+            Some(chunk) => {
+                self.buf.extend_from_slice(&chunk);
+                Ok(())
+            }
+            None => Err(Failure::transient(IOError::from(IOErrorKind::UnexpectedEof).into())),
+        }
+    }
+}
+
+impl Stream for Parts {
+    type Item = Result<Bytes, IOError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if !this.in_body || this.failed {
+                return Poll::Ready(None);
+            }
+            // Everything before the delimiter, or which cannot be the start of one, is the body.
+            let body = match find(&this.buf, &this.delimiter) {
+                Some(0) => {
+                    this.in_body = false;
+                    return Poll::Ready(None);
+                }
+                Some(at) => at,
+                None => this.buf.len().saturating_sub(this.delimiter.len() - 1),
+            };
+            if body > 0 {
+                return Poll::Ready(Some(Ok(this.buf.split_to(body).freeze())));
+            }
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.buf.extend_from_slice(&chunk),
+                Poll::Ready(Some(Err(err))) => {
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Ready(None) => {
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(IOError::from(IOErrorKind::UnexpectedEof))));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Returns the offset of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
@@ -0,0 +1,390 @@
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::stream::{self, StreamExt};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, AGE, AUTHORIZATION, CACHE_CONTROL, CONNECTION,
+    CONTENT_LENGTH, CONTENT_RANGE, DATE, ETAG, EXPIRES, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    IF_RANGE, IF_UNMODIFIED_SINCE, LAST_MODIFIED, PRAGMA, TRANSFER_ENCODING, VARY,
+};
+use reqwest::{Method, StatusCode, Url};
+use tokio::io::AsyncWriteExt;
+
+use crate::backend::{self, BackendError, Body, Response};
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::{date, local, AsyncDownload, Failure};
+
+/// The longest a response without an explicit lifetime is considered fresh for, when its
+/// lifetime is estimated from its `Last-Modified` date.
+const MAX_HEURISTIC_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Headers which describe a single message rather than the stored response.
+const UNSTORED: [HeaderName; 6] = [ACCEPT_RANGES, AGE, CONNECTION, CONTENT_LENGTH, CONTENT_RANGE, TRANSFER_ENCODING];
+
+/// Headers which make a request conditional on what the client already has.
+const CONDITIONAL: [HeaderName; 5] = [IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE];
+
+/// Numbers the temporary files bodies are written to, so that concurrent downloads of the same
+/// URL do not write to the same one.
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// A response stored in the cache, which is read from a `<key>.head` file holding its headers
+/// and a `<key>.body` file holding its body.
+struct Entry {
+    url: String,
+    /// When the response was received, or last revalidated.
+    received: SystemTime,
+    len: u64,
+    /// The values of the request headers named by `Vary`, which a request must match.
+    selecting: HeaderMap,
+    headers: HeaderMap,
+}
+
+impl Entry {
+    fn new(url: &Url, request_headers: &HeaderMap, headers: &HeaderMap) -> Self {
+        let mut entry = Self {
+            url: url.to_string(),
+            received: SystemTime::now(),
+            len: 0,
+            selecting: HeaderMap::new(),
+            headers: HeaderMap::new(),
+        };
+        entry.update(headers);
+        for name in entry.varies_by() {
+            if let Some(value) = request_headers.get(&name) {
+                entry.selecting.insert(name, value.clone());
+            }
+        }
+        entry
+    }
+
+    /// Replaces the stored headers with those of a newer response, such as `304 Not Modified`.
+    fn update(&mut self, headers: &HeaderMap) {
+        for name in headers.keys().filter(|name| !UNSTORED.contains(name)) {
+            self.headers.remove(name);
+            for value in headers.get_all(name) {
+                self.headers.append(name, value.clone());
+            }
+        }
+        self.received = SystemTime::now();
+    }
+
+    /// Returns the request headers named by the `Vary` of the response.
+    fn varies_by(&self) -> Vec<HeaderName> {
+        self.headers.get_all(VARY).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+            .collect()
+    }
+
+    /// Returns `true` if the response may be used for a request with `headers`.
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.varies_by().iter().all(|name| self.selecting.get(name) == headers.get(name))
+    }
+
+    /// Returns how old the response is, as in section 4.2.3 of RFC 9111.
+    fn age(&self, now: SystemTime) -> Duration {
+        let apparent_age = self.date()
+            .and_then(|date| self.received.duration_since(date).ok())
+            .unwrap_or_default();
+        let age = header(&self.headers, AGE)
+            .and_then(|age| age.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        apparent_age.max(age) + now.duration_since(self.received).unwrap_or_default()
+    }
+
+    /// Returns how long the response is fresh for, as in section 4.2.1 of RFC 9111, estimating
+    /// it from its `Last-Modified` date if it has no explicit lifetime.
+    fn lifetime(&self) -> Duration {
+        let directives = directives(&self.headers);
+        if has(&directives, "no-cache") {
+            return Duration::ZERO;
+        }
+        if let Some(max_age) = seconds(&directives, "max-age") {
+            return max_age;
+        }
+        let date = self.date().unwrap_or(self.received);
+        if let Some(expires) = header(&self.headers, EXPIRES) {
+            // An `Expires` date which is not valid is in the past.
+            return date::parse_http_date(expires)
+                .and_then(|expires| expires.duration_since(date).ok())
+                .unwrap_or_default();
+        }
+        header(&self.headers, LAST_MODIFIED)
+            .and_then(date::parse_http_date)
+            .and_then(|modified| date.duration_since(modified).ok())
+            .map_or(Duration::ZERO, |since| (since / 10).min(MAX_HEURISTIC_LIFETIME))
+    }
+
+    fn date(&self) -> Option<SystemTime> {
+        header(&self.headers, DATE).and_then(date::parse_http_date)
+    }
+
+    /// Returns `true` if the response can be revalidated with a conditional request.
+    fn has_validators(&self) -> bool {
+        self.headers.contains_key(ETAG) || self.headers.contains_key(LAST_MODIFIED)
+    }
+
+    /// Adds the `If-None-Match` and `If-Modified-Since` headers which ask whether the response
+    /// is still current.
+    fn validate(&self, headers: &mut HeaderMap) {
+        if let Some(etag) = self.headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(modified) = self.headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, modified.clone());
+        }
+    }
+
+    /// Writes the entry in the form of the response headers it came from, preceded by pseudo
+    /// headers for what the response does not say itself.
+    fn to_text(&self) -> String {
+        let received = self.received.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut text = format!(":url: {}\n:received: {}\n:length: {}\n", self.url, received, self.len);
+        let lines = self.selecting.iter().map(|(name, value)| (format!(":request-{}", name), value))
+            .chain(self.headers.iter().map(|(name, value)| (name.to_string(), value)));
+        for (name, value) in lines {
+            if let Ok(value) = value.to_str() {
+                text.push_str(&format!("{}: {}\n", name, value));
+            }
+        }
+        text
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let mut entry = Self {
+            url: String::new(),
+            received: UNIX_EPOCH,
+            len: 0,
+            selecting: HeaderMap::new(),
+            headers: HeaderMap::new(),
+        };
+        for line in text.lines() {
+            let (name, value) = line.split_once(": ").unwrap_or((line.trim_end_matches(':'), ""));
+            match name {
+                ":url" => entry.url = String::from(value),
+                ":received" => entry.received = UNIX_EPOCH + Duration::from_secs(value.parse().ok()?),
+                ":length" => entry.len = value.parse().ok()?,
+                name => {
+                    let (headers, name) = match name.strip_prefix(":request-") {
+                        Some(name) => (&mut entry.selecting, name),
+                        None => (&mut entry.headers, name),
+                    };
+                    let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+                    headers.append(name, HeaderValue::from_str(value).ok()?);
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+impl AsyncDownload {
+    /// Answers a `GET` request from the cache in `dir` as a private cache would under RFC 9111:
+    /// a fresh response is served from the cache, including any `Range` of it, and a stale one
+    /// is revalidated with a conditional request, so that a server which answers `304 Not
+    /// Modified` sends no body.  A complete `200 OK` response which may be stored is written to
+    /// the cache as its body is read.  A failure to read or write the cache never fails the
+    /// download; the request is sent as if there were none.
+    pub(crate) async fn execute_cached(&self, dir: &Path, client: &reqwest::Client, mut request: reqwest::Request) -> Result<reqwest::Response, Failure> {
+        let request_directives = directives(request.headers());
+        let conditional = CONDITIONAL.iter().any(|name| request.headers().contains_key(name));
+        if request.method() != Method::GET || has(&request_directives, "no-store") || conditional {
+            return self.transmit(client, request).await;
+        }
+        let url = request.url().clone();
+        let request_headers = request.headers().clone();
+        let range = local::range(&request);
+        let no_cache = has(&request_directives, "no-cache")
+            || request.headers().get_all(PRAGMA).iter().any(|v| v.as_bytes().eq_ignore_ascii_case(b"no-cache"));
+        if let Some((mut entry, body)) = load(dir, &url, &request_headers).await {
+            let age = entry.age(SystemTime::now());
+            let fresh = age < entry.lifetime() && seconds(&request_directives, "max-age").is_none_or(|max_age| age <= max_age);
+            if fresh && !no_cache {
+                return serve(&url, &entry, body, range, age).await;
+            }
+            if entry.has_validators() {
+                entry.validate(request.headers_mut());
+                let response = self.transmit(client, request).await?;
+                if response.status() != StatusCode::NOT_MODIFIED {
+                    return store(dir, &request_headers, range, response).await;
+                }
+                entry.update(response.headers());
+                // The stale headers still describe the body if they cannot be replaced.
+                let _ = write_head(dir, &entry).await;
+                return serve(&url, &entry, body, range, Duration::ZERO).await;
+            }
+        }
+        let response = self.transmit(client, request).await?;
+        store(dir, &request_headers, range, response).await
+    }
+}
+
+/// Returns the entry stored for `url` with its body, if there is one a request with `headers`
+/// may use.
+async fn load(dir: &Path, url: &Url, headers: &HeaderMap) -> Option<(Entry, tokio::fs::File)> {
+    let path = dir.join(key(url));
+    let text = tokio::fs::read_to_string(path.with_extension("head")).await.ok()?;
+    let entry = Entry::from_text(&text).filter(|entry| entry.url == url.as_str() && entry.matches(headers))?;
+    let body = tokio::fs::File::open(path.with_extension("body")).await.ok()?;
+    // The body may have been replaced by a newer response whose headers are not written yet.
+    if body.metadata().await.ok()?.len() != entry.len {
+        return None;
+    }
+    Some((entry, body))
+}
+
+/// Returns the response to a request for `range` of a stored response, which is `age` old.
+async fn serve(url: &Url, entry: &Entry, body: tokio::fs::File, range: Option<(u64, Option<u64>)>, age: Duration) -> Result<reqwest::Response, Failure> {
+    let (mut builder, range) = local::partial(range, entry.len);
+    if let Some(headers) = builder.headers_mut() {
+        for (name, value) in &entry.headers {
+            headers.append(name, value.clone());
+        }
+        headers.insert(AGE, HeaderValue::from(age.as_secs()));
+    }
+    let body = local::read(body, range).await?;
+    let response: Response<Body> = builder.body(body).map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))?;
+    backend::into_response(url.clone(), response)
+}
+
+/// Returns `response` with its body written to the cache as it is read, if the response may be
+/// stored: a complete `200 OK` response which a private cache may store and can use again.
+async fn store(dir: &Path, request_headers: &HeaderMap, range: Option<(u64, Option<u64>)>, response: reqwest::Response) -> Result<reqwest::Response, Failure> {
+    let url = response.url().clone();
+    let entry = Entry::new(&url, request_headers, response.headers());
+    let directives = directives(response.headers());
+    // A shared cache could not store a response to a request with credentials unless it says
+    // so, and a private cache is no more careful than that.
+    let authorized = request_headers.contains_key(AUTHORIZATION)
+        && !["public", "must-revalidate", "s-maxage"].iter().any(|directive| has(&directives, directive));
+    let storable = range.is_none()
+        && response.status() == StatusCode::OK
+        && !has(&directives, "no-store")
+        && !authorized
+        && !entry.varies_by().iter().any(|name| name.as_str() == "*")
+        && (entry.lifetime() > Duration::ZERO || entry.has_validators());
+    if !storable || tokio::fs::create_dir_all(dir).await.is_err() {
+        return Ok(response);
+    }
+    let path = dir.join(key(&url));
+    let temp_path = path.with_extension(format!("{}.{}.tmp", std::process::id(), TEMP_FILES.fetch_add(1, Ordering::Relaxed)));
+    let Ok(file) = tokio::fs::File::create(&temp_path).await else {
+        return Ok(response);
+    };
+    let writer = Writer {
+        dir: dir.to_path_buf(),
+        path,
+        temp_path,
+        file,
+        expected_len: response.content_length(),
+        entry,
+    };
+
+    let (status, version, headers) = (response.status(), response.version(), response.headers().clone());
+    let chunks = response.bytes_stream();
+    let body: Body = Box::pin(stream::unfold((chunks, Some(writer)), |(mut chunks, mut writer)| async move {
+        match chunks.next().await {
+            Some(Ok(chunk)) => {
+                if let Some(mut w) = writer.take() {
+                    if w.file.write_all(&chunk).await.is_ok() {
+                        w.entry.len += chunk.len() as u64;
+                        // The body may not be read past its length.
+                        if w.expected_len == Some(w.entry.len) {
+                            let _ = w.commit().await;
+                        } else {
+                            writer = Some(w);
+                        }
+                    }
+                }
+                Some((Ok(chunk), (chunks, writer)))
+            }
+            Some(Err(err)) => Some((Err(Box::new(err) as BackendError), (chunks, None))),
+            None => {
+                if let Some(w) = writer {
+                    if w.expected_len.is_none() {
+                        let _ = w.commit().await;
+                    }
+                }
+                None
+            }
+        }
+    }));
+    let mut stored = Response::new(body);
+    *stored.status_mut() = status;
+    *stored.version_mut() = version;
+    *stored.headers_mut() = headers;
+    backend::into_response(url, stored)
+}
+
+/// Writes the body of a response to a temporary file, which becomes its entry once the body is
+/// read in full, and is removed if it is not.
+struct Writer {
+    dir: PathBuf,
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: tokio::fs::File,
+    expected_len: Option<u64>,
+    entry: Entry,
+}
+
+impl Writer {
+    async fn commit(mut self) -> Result<(), IOError> {
+        self.file.flush().await?;
+        tokio::fs::rename(&self.temp_path, self.path.with_extension("body")).await?;
+        write_head(&self.dir, &self.entry).await
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.temp_path);
+    }
+}
+
+/// Writes the headers of `entry`, replacing those stored before.
+async fn write_head(dir: &Path, entry: &Entry) -> Result<(), IOError> {
+    let path = dir.join(key(&Url::parse(&entry.url).map_err(IOError::other)?));
+    let temp_path = path.with_extension(format!("{}.{}.tmp", std::process::id(), TEMP_FILES.fetch_add(1, Ordering::Relaxed)));
+    tokio::fs::write(&temp_path, entry.to_text()).await?;
+    tokio::fs::rename(&temp_path, path.with_extension("head")).await
+}
+
+/// Returns the name the entry for `url` is stored under, which is its 64-bit FNV-1a hash since
+/// a URL may be too long for a filename.  The URL is stored in the entry, so that URLs which
+/// have the same hash are not confused.
+fn key(url: &Url) -> String {
+    let hash = url.as_str().bytes().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ u64::from(b)).wrapping_mul(0x100000001b3));
+    format!("{:016x}", hash)
+}
+
+/// Returns the directives of the `Cache-Control` headers, with their arguments.
+fn directives(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers.get_all(CACHE_CONTROL).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|directive| match directive.split_once('=') {
+            Some((name, arg)) => (name.trim().to_ascii_lowercase(), Some(arg.trim().trim_matches('"').to_owned())),
+            None => (directive.trim().to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+fn has(directives: &[(String, Option<String>)], name: &str) -> bool {
+    directives.iter().any(|(n, _)| n == name)
+}
+
+fn seconds(directives: &[(String, Option<String>)], name: &str) -> Option<Duration> {
+    directives.iter()
+        .find(|(n, _)| n == name)
+        .and_then(|(_, arg)| arg.as_deref()?.parse().ok())
+        .map(Duration::from_secs)
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
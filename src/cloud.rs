@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures_util::stream::{self, TryStreamExt};
+use http::Response;
+use object_store::aws::{AmazonS3Builder, AmazonS3ConfigKey};
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::{GcpCredential, GoogleCloudStorageBuilder};
+use object_store::path::Path;
+use object_store::{Attribute, ClientOptions, GetOptions, GetRange, ObjectStore, RetryConfig, StaticCredentialProvider};
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE, LAST_MODIFIED};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::backend::{self, Body};
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::{date, local, Failure};
+
+/// The object stores requests have been made to, by scheme and bucket, so that the credentials
+/// each one fetches are kept for the requests which follow.
+#[derive(Debug, Default)]
+pub(crate) struct Stores(Mutex<HashMap<String, Arc<dyn ObjectStore>>>);
+
+/// Answers a request for an `s3`, `gs` or `az` URL as a server would, including a `Range` of the
+/// form downloads request and the conditional headers downloads and the cache send, by getting
+/// the object from its store with `object_store`, so that it is resumed, retried and verified
+/// like any other.  The store is configured from the environment, as its builder's `from_env`
+/// does, and finds credentials where its SDK would:
+///
+/// * `s3://bucket/key` uses the `AWS_*` variables, then the profile `AWS_PROFILE`, or `default`,
+///   of `~/.aws/credentials` and `~/.aws/config`, then a web identity token, then the
+///   credentials of an ECS task or EC2 instance.  `AWS_ENDPOINT_URL_S3` or `AWS_ENDPOINT_URL`
+///   is used in place of AWS, e.g. for MinIO.
+/// * `gs://bucket/key` uses `GOOGLE_OAUTH_ACCESS_TOKEN` as a bearer token if it is set, and
+///   otherwise the service account of `GOOGLE_APPLICATION_CREDENTIALS`, the application
+///   default credentials of `gcloud`, or the metadata server.
+/// * `az://container/blob` is read from the storage account `AZURE_STORAGE_ACCOUNT_NAME` with
+///   its key, a shared access signature, a bearer token, a service principal or workload
+///   identity from the `AZURE_*` variables, or else a managed identity.
+///
+/// Public objects can be read without credentials by setting `AWS_SKIP_SIGNATURE`,
+/// `GOOGLE_SKIP_SIGNATURE` or `AZURE_SKIP_SIGNATURE` to `true`.  The store does not retry
+/// requests itself, as downloads already do.  A version of an object may be asked for with the
+/// `versionId`, `generation` or `versionid` query parameter of its store.
+pub(crate) async fn execute(stores: &Stores, request: &reqwest::Request) -> Result<reqwest::Response, Failure> {
+    let url = request.url();
+    let got = get(stores, url, request);
+    let response = match request.timeout() {
+        Some(&timeout) => tokio::time::timeout(timeout, got).await
+            .map_err(|_| Failure::transient(TDSTDError::new(TDSTDErrorKind::Timeout)))??,
+        None => got.await?,
+    };
+    backend::into_response(url.clone(), response)
+}
+
+async fn get(stores: &Stores, url: &Url, request: &reqwest::Request) -> Result<Response<Body>, Failure> {
+    let store = stores.get(url)?;
+    let path = Path::from_url_path(url.path()).map_err(|_| Failure::fatal(TDSTDErrorKind::InvalidUrl))?;
+    let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok()).map(String::from);
+    let http_date = |name| header(name).and_then(|value| date::parse_http_date(&value));
+    let range = local::range(request);
+    let version = match url.scheme() {
+        "s3" => "versionId",
+        "gs" => "generation",
+        _ => "versionid",
+    };
+    let mut options = GetOptions {
+        if_match: header(IF_MATCH),
+        if_none_match: header(IF_NONE_MATCH),
+        if_modified_since: http_date(IF_MODIFIED_SINCE).map(Into::into),
+        if_unmodified_since: http_date(IF_UNMODIFIED_SINCE).map(Into::into),
+        range: range.map(|range| match range {
+            (start, Some(end)) => GetRange::Bounded(start..end.saturating_add(1)),
+            (start, None) => GetRange::Offset(start),
+        }),
+        version: url.query_pairs().find(|(key, _)| key == version).map(|(_, value)| value.into_owned()),
+        head: request.method() == Method::HEAD,
+        ..GetOptions::default()
+    };
+    // `If-Range` is checked as a precondition of the range, which is left out if it fails.
+    let if_range = range.and(header(IF_RANGE));
+    match if_range {
+        Some(ref etag) if etag.starts_with('"') || etag.starts_with("W/") => options.if_match = Some(etag.clone()),
+        Some(ref modified) => options.if_unmodified_since = date::parse_http_date(modified).map(Into::into),
+        None => (),
+    }
+    let mut ranged = range.is_some();
+    let result = match store.get_opts(&path, options.clone()).await {
+        Err(object_store::Error::Precondition { .. }) if if_range.is_some() => {
+            ranged = false;
+            options.range = None;
+            options.if_match = header(IF_MATCH);
+            options.if_unmodified_since = http_date(IF_UNMODIFIED_SINCE).map(Into::into);
+            store.get_opts(&path, options).await
+        }
+        result => result,
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            let status = match err {
+                object_store::Error::NotFound { .. } => StatusCode::NOT_FOUND,
+                object_store::Error::PermissionDenied { .. } => StatusCode::FORBIDDEN,
+                object_store::Error::Unauthenticated { .. } => StatusCode::UNAUTHORIZED,
+                object_store::Error::Precondition { .. } => StatusCode::PRECONDITION_FAILED,
+                object_store::Error::NotModified { .. } => StatusCode::NOT_MODIFIED,
+                // A range which starts past the end of the object cannot be satisfied.
+                err => match (range, store.head(&path).await) {
+                    (Some((start, _)), Ok(meta)) if start >= meta.size => {
+                        let (builder, _) = local::partial(range, meta.size);
+                        return builder.body(Box::pin(stream::empty()) as Body)
+                            .map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))));
+                    }
+                    _ => return Err(Failure::transient(TDSTDError::new(TDSTDErrorKind::Other(Box::new(err))))),
+                },
+            };
+            return Ok(local::status(status));
+        }
+    };
+    let served = result.range.start..result.range.end;
+    let (mut builder, _) = match ranged {
+        true => local::partial(Some((served.start, served.end.checked_sub(1))), result.meta.size),
+        false => local::partial(None, result.meta.size),
+    };
+    if let Some(ref etag) = result.meta.e_tag {
+        builder = builder.header(ETAG, etag);
+    }
+    builder = builder.header(LAST_MODIFIED, date::format_http_date(SystemTime::from(result.meta.last_modified)));
+    if let Some(content_type) = result.attributes.get(&Attribute::ContentType) {
+        builder = builder.header(CONTENT_TYPE, content_type.as_ref());
+    }
+    let body: Body = match request.method() == Method::HEAD || served.is_empty() {
+        true => Box::pin(stream::empty()),
+        false => Box::pin(result.into_stream().map_err(|err| Box::new(err) as backend::BackendError)),
+    };
+    builder.body(body).map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))
+}
+
+impl Stores {
+    /// Returns the store of the bucket `url` is in, building it the first time.
+    fn get(&self, url: &Url) -> Result<Arc<dyn ObjectStore>, Failure> {
+        let bucket = url.host_str().ok_or_else(|| Failure::fatal(TDSTDErrorKind::InvalidUrl))?;
+        let key = format!("{}://{}", url.scheme(), bucket);
+        let mut stores = self.0.lock().unwrap();
+        if let Some(store) = stores.get(&key) {
+            return Ok(Arc::clone(store));
+        }
+        let store = build(url.scheme(), bucket)
+            .map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))?;
+        stores.insert(key, Arc::clone(&store));
+        Ok(store)
+    }
+}
+
+fn build(scheme: &str, bucket: &str) -> Result<Arc<dyn ObjectStore>, object_store::Error> {
+    // Downloads time out and retry on their own, and a body may take far longer than a request.
+    let client = ClientOptions::new().with_timeout_disabled();
+    let retry = RetryConfig { max_retries: 0, ..RetryConfig::default() };
+    Ok(match scheme {
+        "s3" => {
+            let mut builder = AmazonS3Builder::from_env()
+                .with_bucket_name(bucket)
+                .with_client_options(client)
+                .with_retry(retry);
+            if let Some(endpoint) = var("AWS_ENDPOINT_URL_S3") {
+                builder = builder.with_endpoint(endpoint);
+            }
+            if builder.get_config_value(&AmazonS3ConfigKey::Endpoint).is_some_and(|endpoint| endpoint.starts_with("http://")) {
+                builder = builder.with_allow_http(true);
+            }
+            if var("AWS_ACCESS_KEY_ID").is_none() {
+                let profile = Profile::load();
+                if let (Some(access_key), Some(secret_key)) = (profile.get("aws_access_key_id"), profile.get("aws_secret_access_key")) {
+                    builder = builder.with_access_key_id(access_key).with_secret_access_key(secret_key);
+                    if let Some(token) = profile.get("aws_session_token") {
+                        builder = builder.with_token(token);
+                    }
+                }
+                if let (None, None, Some(region)) = (var("AWS_REGION"), var("AWS_DEFAULT_REGION"), profile.get("region")) {
+                    builder = builder.with_region(region);
+                }
+            }
+            Arc::new(builder.build()?)
+        }
+        "gs" => {
+            let mut builder = GoogleCloudStorageBuilder::from_env()
+                .with_bucket_name(bucket)
+                .with_client_options(client)
+                .with_retry(retry);
+            if let Some(bearer) = var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+                builder = builder.with_credentials(Arc::new(StaticCredentialProvider::new(GcpCredential { bearer })));
+            }
+            Arc::new(builder.build()?)
+        }
+        _ => Arc::new(
+            MicrosoftAzureBuilder::from_env()
+                .with_container_name(bucket)
+                .with_client_options(client)
+                .with_retry(retry)
+                .build()?,
+        ),
+    })
+}
+
+/// The settings of the AWS profile named by `AWS_PROFILE`, or `default`, from the shared
+/// credentials file and then the config file.  Only static keys and the region are read.
+struct Profile(Vec<(String, String)>);
+
+impl Profile {
+    fn load() -> Self {
+        let name = var("AWS_PROFILE").unwrap_or_else(|| String::from("default"));
+        let home = || env::var_os("HOME").filter(|home| !home.is_empty()).map(|home| PathBuf::from(home).join(".aws"));
+        let credentials = var("AWS_SHARED_CREDENTIALS_FILE").map(PathBuf::from).or_else(|| home().map(|dir| dir.join("credentials")));
+        let config = var("AWS_CONFIG_FILE").map(PathBuf::from).or_else(|| home().map(|dir| dir.join("config")));
+        let mut settings = Vec::new();
+        if let Some(data) = credentials.and_then(|path| std::fs::read_to_string(path).ok()) {
+            settings.extend(section(&data, &name));
+        }
+        if let Some(data) = config.and_then(|path| std::fs::read_to_string(path).ok()) {
+            // The config file names its sections `profile <name>`, except for the default.
+            let name = match name.as_str() {
+                "default" => name.clone(),
+                name => format!("profile {}", name),
+            };
+            settings.extend(section(&data, &name));
+        }
+        Self(settings)
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Returns the settings of the section `name` of an INI file.
+fn section(data: &str, name: &str) -> Vec<(String, String)> {
+    let mut settings = Vec::new();
+    let mut current = false;
+    for line in data.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            current = header.trim() == name;
+        } else if let (true, Some((key, value))) = (current, line.split_once('=')) {
+            settings.push((key.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+    }
+    settings
+}
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
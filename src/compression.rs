@@ -0,0 +1,164 @@
+#[cfg(feature="compress")]
+use std::io::Error as IOError;
+
+#[cfg(feature="compress")]
+use async_compression::tokio::write::{GzipEncoder, XzEncoder, ZstdEncoder};
+#[cfg(feature="decompress")]
+use async_compression::tokio::write::{GzipDecoder, XzDecoder, ZstdDecoder};
+#[cfg(feature="compress")]
+use bytes::Bytes;
+#[cfg(all(feature="tar", feature="decompress"))]
+use tokio::io::{AsyncBufRead, AsyncRead};
+#[cfg(any(feature="compress", feature="decompress"))]
+use tokio::io::AsyncWrite;
+#[cfg(feature="compress")]
+use tokio::io::AsyncWriteExt;
+
+#[cfg(feature="compress")]
+use crate::transform::{Transform, Transformed};
+
+/// A compression format which a download can be decompressed from as it is written, with
+/// [`AsyncDownloadBuilder::decompress`](crate::AsyncDownloadBuilder::decompress), or
+/// compressed to, with
+/// [`AsyncDownloadBuilder::store_compressed`](crate::AsyncDownloadBuilder::store_compressed).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionFormat {
+    /// gzip, usually with the extension `.gz`.
+    Gzip,
+    /// xz, usually with the extension `.xz`.
+    Xz,
+    /// Zstandard, usually with the extension `.zst`.
+    Zstd,
+}
+
+impl CompressionFormat {
+    /// Returns the format usually meant by a file extension, such as `"gz"`, if any.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "gz" | "tgz" => Some(CompressionFormat::Gzip),
+            "xz" | "txz" => Some(CompressionFormat::Xz),
+            "zst" | "zstd" | "tzst" => Some(CompressionFormat::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Returns the usual file extension of the format, such as `"gz"`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            CompressionFormat::Gzip => "gz",
+            CompressionFormat::Xz => "xz",
+            CompressionFormat::Zstd => "zst",
+        }
+    }
+
+    #[cfg(feature="decompress")]
+    /// Returns a writer which decompresses everything written to it into `dest`.  It must be shut
+    /// down once everything has been written, which fails if the compressed data is incomplete.
+    pub(crate) fn decoder<'a, W: AsyncWrite + Unpin + 'a>(self, dest: W) -> Box<dyn AsyncWrite + Unpin + 'a> {
+        match self {
+            CompressionFormat::Gzip => Box::new(GzipDecoder::new(dest)),
+            CompressionFormat::Xz => Box::new(XzDecoder::new(dest)),
+            CompressionFormat::Zstd => Box::new(ZstdDecoder::new(dest)),
+        }
+    }
+
+    #[cfg(all(feature="tar", feature="decompress"))]
+    /// Returns a reader which decompresses everything read from `src`.
+    pub(crate) fn reader<'a, R: AsyncBufRead + Unpin + 'a>(self, src: R) -> Box<dyn AsyncRead + Unpin + 'a> {
+        use async_compression::tokio::bufread;
+
+        match self {
+            CompressionFormat::Gzip => Box::new(bufread::GzipDecoder::new(src)),
+            CompressionFormat::Xz => Box::new(bufread::XzDecoder::new(src)),
+            CompressionFormat::Zstd => Box::new(bufread::ZstdDecoder::new(src)),
+        }
+    }
+
+    #[cfg(feature="decompress")]
+    /// Removes the extension of this format from a filename derived from the response, so
+    /// `foo.tar.zst` is written as `foo.tar`.  A `.tgz` style extension becomes `.tar`.
+    pub(crate) fn strip_extension(self, fname: &str) -> String {
+        match fname.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && Self::from_extension(extension) == Some(self) => {
+                match extension.to_ascii_lowercase().as_str() {
+                    "tgz" | "txz" | "tzst" => format!("{}.tar", stem),
+                    _ => String::from(stem),
+                }
+            }
+            _ => String::from(fname),
+        }
+    }
+}
+
+#[cfg(feature="compress")]
+/// A [`Transform`] which compresses the download, for storing large but compressible downloads.
+/// It is usually added with
+/// [`AsyncDownloadBuilder::store_compressed`](crate::AsyncDownloadBuilder::store_compressed).
+pub struct Compress {
+    format: CompressionFormat,
+    encoder: Encoder,
+}
+
+#[cfg(feature="compress")]
+enum Encoder {
+    Gzip(GzipEncoder<Vec<u8>>),
+    Xz(XzEncoder<Vec<u8>>),
+    Zstd(ZstdEncoder<Vec<u8>>),
+}
+
+#[cfg(feature="compress")]
+impl Encoder {
+    fn new(format: CompressionFormat) -> Self {
+        match format {
+            CompressionFormat::Gzip => Encoder::Gzip(GzipEncoder::new(Vec::new())),
+            CompressionFormat::Xz => Encoder::Xz(XzEncoder::new(Vec::new())),
+            CompressionFormat::Zstd => Encoder::Zstd(ZstdEncoder::new(Vec::new())),
+        }
+    }
+
+    /// Writes `data` to the encoder, or shuts it down if there is none, and returns the
+    /// compressed output so far.
+    async fn write(&mut self, data: Option<&[u8]>) -> Result<Bytes, IOError> {
+        let writer: &mut (dyn AsyncWrite + Unpin) = match self {
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Xz(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+        };
+        match data {
+            Some(data) => writer.write_all(data).await?,
+            None => writer.shutdown().await?,
+        }
+        let output = match self {
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Xz(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+}
+
+#[cfg(feature="compress")]
+impl Compress {
+    /// Returns a stage which compresses to `format` at its default level.
+    pub fn new(format: CompressionFormat) -> Self {
+        Self {
+            format,
+            encoder: Encoder::new(format),
+        }
+    }
+}
+
+#[cfg(feature="compress")]
+impl Transform for Compress {
+    fn transform(&mut self, chunk: Bytes) -> Transformed<'_> {
+        Box::pin(async move { self.encoder.write(Some(&chunk)).await })
+    }
+
+    fn finish(&mut self) -> Transformed<'_> {
+        Box::pin(self.encoder.write(None))
+    }
+
+    fn reset(&mut self) {
+        self.encoder = Encoder::new(self.format);
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use reqwest::header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+use crate::error::Error as TDSTDError;
+use crate::{date, filename, AsyncDownload};
+
+/// The `ETag` and `Last-Modified` of a previous download, which are sent back to the server to
+/// ask whether it has changed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+impl Validators {
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(String::from);
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// Adds the `If-None-Match` and `If-Modified-Since` headers to a request.
+    pub(crate) fn apply(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(ref etag) = self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+
+    /// Reads validators stored by `store`, returning `None` if there are none.
+    async fn load(path: &Path) -> Result<Option<Self>, IOError> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == IOErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut validators = Self::default();
+        for line in contents.lines() {
+            match line.split_once(": ") {
+                Some(("ETag", etag)) => validators.etag = Some(String::from(etag)),
+                Some(("Last-Modified", last_modified)) => validators.last_modified = Some(String::from(last_modified)),
+                _ => (),
+            }
+        }
+        Ok(Some(validators))
+    }
+
+    /// Writes the validators to `path` in the form of the response headers they came from, or
+    /// removes it if there are none.
+    async fn store(&self, path: &Path) -> Result<(), IOError> {
+        if self.is_empty() {
+            return match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() != IOErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            };
+        }
+        let mut contents = String::new();
+        if let Some(ref etag) = self.etag {
+            contents.push_str(&format!("ETag: {}\n", etag));
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            contents.push_str(&format!("Last-Modified: {}\n", last_modified));
+        }
+        tokio::fs::write(path, contents).await
+    }
+}
+
+impl AsyncDownload {
+    /// Returns the path of the file the validators of the download are stored in.
+    fn validators_path(&self) -> PathBuf {
+        self.dst_path.join(filename::with_suffix(&self.fname, ".validators"))
+    }
+
+    /// Makes a conditional request with the configured validators, or those stored alongside an
+    /// existing download, and returns `true` if the server reports that the download has not
+    /// been modified.  An unconditional response obtained with `get` is used as it is.
+    pub(crate) async fn check_not_modified(&mut self) -> Result<bool, TDSTDError> {
+        let mut validators = self.config.validators.clone();
+        if (self.config.store_validators || self.config.remote_time) && !self.fname.is_empty() {
+            self.check_fname()?;
+            let path = self.path();
+            if self.config.store_validators && path.is_file() {
+                if let Some(stored) = Validators::load(&self.validators_path()).await? {
+                    validators.etag = validators.etag.or(stored.etag);
+                    validators.last_modified = validators.last_modified.or(stored.last_modified);
+                }
+            }
+            if self.config.remote_time && validators.last_modified.is_none() && path.is_file() {
+                let modified = tokio::fs::metadata(&path).await?.modified()?;
+                validators.last_modified = Some(date::format_http_date(modified));
+            }
+        }
+        if validators.is_empty() || self.response_stream.is_some() {
+            return Ok(false);
+        }
+        Ok(self.get_conditional(&validators).await?)
+    }
+
+    /// Sets the modification time of the file at `path` to the `Last-Modified` date of the last
+    /// response, if enabled and it has a valid one.
+    pub(crate) async fn apply_remote_time(&self, path: &Path) -> Result<(), IOError> {
+        if !self.config.remote_time {
+            return Ok(());
+        }
+        let modified = self.response.as_ref()
+            .and_then(|response| response.last_modified())
+            .and_then(date::parse_http_date);
+        if let Some(modified) = modified {
+            set_modified(path, modified).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores the validators of the last response alongside the download, if enabled.
+    pub(crate) async fn store_validators(&self) -> Result<(), TDSTDError> {
+        if self.config.store_validators {
+            let validators = self.response.as_ref()
+                .map(|response| Validators::from_headers(&response.headers))
+                .unwrap_or_default();
+            validators.store(&self.validators_path()).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn set_modified(path: &Path, modified: SystemTime) -> Result<(), IOError> {
+    let file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    file.into_std().await.set_modified(modified)
+}
@@ -0,0 +1,111 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use md5::Md5;
+use reqwest::header::HeaderMap;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::error::Error as TDSTDError;
+use crate::result::ResponseMetadata;
+use crate::{check_digest, Inspect};
+
+/// Verifies the download against the digest of the response announced in its `Repr-Digest` or
+/// `Content-Digest` header (RFC 9530), or failing that its legacy `Content-MD5` header.  The
+/// strongest algorithm supported is used if there are several.
+pub(crate) struct HeaderDigest {
+    enabled: bool,
+    expected: Option<Vec<u8>>,
+    hasher: Option<Hasher>,
+}
+
+enum Hasher {
+    Sha512(Sha512),
+    Sha256(Sha256),
+    Md5(Md5),
+}
+
+impl HeaderDigest {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            expected: None,
+            hasher: None,
+        }
+    }
+
+    /// Returns `true` if the response the download started from announced a digest.
+    pub(crate) fn is_announced(&self) -> bool {
+        self.expected.is_some()
+    }
+
+    /// Checks the download against the announced digest, if there was one.
+    pub(crate) fn check(self) -> Result<(), TDSTDError> {
+        let (Some(expected), Some(hasher)) = (self.expected, self.hasher) else {
+            return Ok(());
+        };
+        let actual = match hasher {
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+        };
+        check_digest(&expected, &actual)
+    }
+}
+
+impl Inspect for HeaderDigest {
+    fn update(&mut self, chunk: &[u8]) {
+        match self.hasher {
+            Some(Hasher::Sha512(ref mut hasher)) => hasher.update(chunk),
+            Some(Hasher::Sha256(ref mut hasher)) => hasher.update(chunk),
+            Some(Hasher::Md5(ref mut hasher)) => hasher.update(chunk),
+            None => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.expected = None;
+        self.hasher = None;
+    }
+
+    fn response(&mut self, response: &ResponseMetadata) {
+        if !self.enabled {
+            return;
+        }
+        if let Some((hasher, expected)) = announced(&response.headers) {
+            self.hasher = Some(hasher);
+            self.expected = Some(expected);
+        }
+    }
+}
+
+/// Returns a hasher for the strongest digest announced by the headers, and the digest.
+fn announced(headers: &HeaderMap) -> Option<(Hasher, Vec<u8>)> {
+    for name in ["repr-digest", "content-digest"] {
+        let members: Vec<(String, Vec<u8>)> = headers.get_all(name).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(parse_member)
+            .collect();
+        let digest = |algorithm: &str, len: usize| members.iter()
+            .find(|(name, digest)| name == algorithm && digest.len() == len)
+            .map(|(_, digest)| digest.clone());
+        if let Some(digest) = digest("sha-512", 64) {
+            return Some((Hasher::Sha512(Sha512::new()), digest));
+        }
+        if let Some(digest) = digest("sha-256", 32) {
+            return Some((Hasher::Sha256(Sha256::new()), digest));
+        }
+    }
+    let digest = headers.get("content-md5")
+        .and_then(|value| BASE64.decode(value.as_bytes().trim_ascii()).ok())
+        .filter(|digest| digest.len() == 16)?;
+    Some((Hasher::Md5(Md5::new()), digest))
+}
+
+/// Parses a member of a digest dictionary, such as `sha-256=:<base64>:`, ignoring any
+/// parameters.
+fn parse_member(member: &str) -> Option<(String, Vec<u8>)> {
+    let (name, value) = member.split_once('=')?;
+    let value = value.split(';').next()?.trim();
+    let digest = BASE64.decode(value.strip_prefix(':')?.strip_suffix(':')?).ok()?;
+    Some((name.trim().to_ascii_lowercase(), digest))
+}
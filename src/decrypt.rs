@@ -0,0 +1,110 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+
+use aes_gcm::aead::stream::DecryptorBE32;
+use aes_gcm::aead::KeyInit;
+use aes_gcm::{Aes256Gcm, Key};
+use bytes::{Bytes, BytesMut};
+
+use crate::transform::{AuthenticationFailed, Transform, Transformed};
+
+/// The length of the nonce prefix at the start of the body.
+const NONCE_PREFIX_LEN: usize = 7;
+
+/// The length of the tag which follows each encrypted chunk.
+const TAG_LEN: usize = 16;
+
+/// A [`Transform`] which decrypts a body encrypted with AES-256-GCM in the STREAM construction,
+/// so that each chunk is authenticated before it is written.  The body is a 7-byte nonce prefix
+/// followed by the chunks, each of them `chunk_size` bytes of plaintext and a 16-byte tag except
+/// the last, which is at most that long and marked as the last.  This is what
+/// `aead::stream::EncryptorBE32<Aes256Gcm>` produces when every chunk but the last is encrypted
+/// with `encrypt_next` and the last with `encrypt_last`.
+///
+/// A chunk which fails to authenticate, or a body which ends without its last chunk, fails the
+/// download with `AuthenticationFailed` and the file it was written to is removed.  Chunks which
+/// were authenticated have already been written by then, so a download into a writer should not
+/// be trusted until it completes.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use tokio_dl_stream_to_disk::decrypt::Aes256GcmStream;
+/// use tokio_dl_stream_to_disk::AsyncDownload;
+///
+/// # fn run(key: &[u8; 32]) -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+/// let download = AsyncDownload::builder()
+///     .url("https://example.com/backup.bin.enc")
+///     .dst_dir("/tmp")
+///     .filename("backup.bin")
+///     .transform(Aes256GcmStream::new(key, 64 * 1024))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Aes256GcmStream {
+    key: Key<Aes256Gcm>,
+    chunk_size: usize,
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    buf: BytesMut,
+}
+
+impl Aes256GcmStream {
+    /// Returns a decryptor for a body encrypted with `key`, in chunks of `chunk_size` bytes of
+    /// plaintext.
+    pub fn new(key: &[u8; 32], chunk_size: usize) -> Self {
+        Self {
+            key: *Key::<Aes256Gcm>::from_slice(key),
+            chunk_size,
+            decryptor: None,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Decrypts every complete chunk buffered, holding back the last one until it is known
+    /// whether more follow.
+    fn decrypt_chunks(&mut self) -> Result<Bytes, IOError> {
+        if self.decryptor.is_none() {
+            if self.buf.len() < NONCE_PREFIX_LEN {
+                return Ok(Bytes::new());
+            }
+            let nonce = self.buf.split_to(NONCE_PREFIX_LEN);
+            let aead = Aes256Gcm::new(&self.key);
+            self.decryptor = Some(DecryptorBE32::from_aead(aead, nonce.as_ref().into()));
+        }
+        let decryptor = self.decryptor.as_mut().unwrap();
+        let chunk_len = self.chunk_size + TAG_LEN;
+        let mut output = BytesMut::new();
+        while self.buf.len() > chunk_len {
+            let chunk = self.buf.split_to(chunk_len);
+            output.extend_from_slice(&decryptor.decrypt_next(chunk.as_ref()).map_err(|_| unauthenticated())?);
+        }
+        Ok(output.freeze())
+    }
+}
+
+impl Transform for Aes256GcmStream {
+    fn transform(&mut self, chunk: Bytes) -> Transformed<'_> {
+        self.buf.extend_from_slice(&chunk);
+        Box::pin(std::future::ready(self.decrypt_chunks()))
+    }
+
+    fn finish(&mut self) -> Transformed<'_> {
+        let last = self.buf.split();
+        let result = match self.decryptor.take() {
+            Some(decryptor) if last.len() >= TAG_LEN => decryptor.decrypt_last(last.as_ref())
+                .map(Bytes::from)
+                .map_err(|_| unauthenticated()),
+            _ => Err(unauthenticated()),
+        };
+        Box::pin(std::future::ready(result))
+    }
+
+    fn reset(&mut self) {
+        self.decryptor = None;
+        self.buf.clear();
+    }
+}
+
+fn unauthenticated() -> IOError {
+    IOError::new(IOErrorKind::InvalidData, AuthenticationFailed)
+}
@@ -1,13 +1,44 @@
 use std::error::Error as StdError;
 use std::io::{Error as IOError, ErrorKind as IOErrorKind};
 use std::fmt;
+use std::path::PathBuf;
+
+use reqwest::StatusCode;
+
+use crate::policy::Violation;
+use crate::transform::AuthenticationFailed;
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ErrorKind {
     FileExists,
     DirectoryMissing,
+    InvalidFilename,
+    InvalidUrl,
     PermissionDenied,
     InvalidResponse,
+    HttpStatus(StatusCode),
+    Connect,
+    Redirect,
+    MissingField(&'static str),
+    InvalidConfig(&'static str),
+    InvalidDigest,
+    InvalidMetalink,
+    InvalidTorrent,
+    ChecksumMismatch { expected: Vec<u8>, actual: Vec<u8> },
+    ChecksumNotFound,
+    AuthenticationFailed,
+    PieceMismatch { piece: usize, offset: u64, len: u64 },
+    SignatureInvalid,
+    PinMismatch,
+    Cancelled,
+    Timeout,
+    TooSlow { bytes_per_sec: u64 },
+    TooManyRetries { retries: u32, last: Box<Error> },
+    TooLarge { limit: u64 },
+    TruncatedBody { expected: u64, actual: u64 },
+    PolicyViolation(&'static str),
+    UnsafeArchiveEntry(PathBuf),
     IO(IOError),
     Other(Box<dyn StdError>),
 }
@@ -15,12 +46,22 @@ pub enum ErrorKind {
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
+    source: Option<Box<dyn StdError>>,
 }
 
 impl Error {
     pub fn new(k: ErrorKind) -> Error {
         Error {
             kind: k,
+            source: None,
+        }
+    }
+
+    /// Returns an `InvalidUrl` error caused by `err`, such as the error parsing the URL.
+    pub(crate) fn invalid_url(err: Option<Box<dyn StdError>>) -> Error {
+        Error {
+            kind: ErrorKind::InvalidUrl,
+            source: err,
         }
     }
 
@@ -32,8 +73,32 @@ impl Error {
 	match self.kind {
 	    ErrorKind::FileExists => None,
 	    ErrorKind::DirectoryMissing => None,
+	    ErrorKind::InvalidFilename => None,
+	    ErrorKind::InvalidUrl => None,
 	    ErrorKind::PermissionDenied => None,
 	    ErrorKind::InvalidResponse => None,
+	    ErrorKind::HttpStatus(_) => None,
+	    ErrorKind::Connect => None,
+	    ErrorKind::Redirect => None,
+	    ErrorKind::MissingField(_) => None,
+	    ErrorKind::InvalidConfig(_) => None,
+	    ErrorKind::InvalidDigest => None,
+	    ErrorKind::InvalidMetalink => None,
+	    ErrorKind::InvalidTorrent => None,
+	    ErrorKind::ChecksumMismatch { .. } => None,
+	    ErrorKind::ChecksumNotFound => None,
+	    ErrorKind::AuthenticationFailed => None,
+	    ErrorKind::PieceMismatch { .. } => None,
+	    ErrorKind::SignatureInvalid => None,
+	    ErrorKind::PinMismatch => None,
+	    ErrorKind::Cancelled => None,
+	    ErrorKind::Timeout => None,
+	    ErrorKind::TooSlow { .. } => None,
+	    ErrorKind::TooManyRetries { .. } => None,
+	    ErrorKind::TooLarge { .. } => None,
+	    ErrorKind::TruncatedBody { .. } => None,
+	    ErrorKind::PolicyViolation(_) => None,
+	    ErrorKind::UnsafeArchiveEntry(_) => None,
 	    ErrorKind::IO(err) => Some(err),
 	    ErrorKind::Other(_) => None,
 	}
@@ -43,8 +108,32 @@ impl Error {
 	match self.kind {
 	    ErrorKind::FileExists => None,
 	    ErrorKind::DirectoryMissing => None,
+	    ErrorKind::InvalidFilename => None,
+	    ErrorKind::InvalidUrl => None,
 	    ErrorKind::PermissionDenied => None,
 	    ErrorKind::InvalidResponse => None,
+	    ErrorKind::HttpStatus(_) => None,
+	    ErrorKind::Connect => None,
+	    ErrorKind::Redirect => None,
+	    ErrorKind::MissingField(_) => None,
+	    ErrorKind::InvalidConfig(_) => None,
+	    ErrorKind::InvalidDigest => None,
+	    ErrorKind::InvalidMetalink => None,
+	    ErrorKind::InvalidTorrent => None,
+	    ErrorKind::ChecksumMismatch { .. } => None,
+	    ErrorKind::ChecksumNotFound => None,
+	    ErrorKind::AuthenticationFailed => None,
+	    ErrorKind::PieceMismatch { .. } => None,
+	    ErrorKind::SignatureInvalid => None,
+	    ErrorKind::PinMismatch => None,
+	    ErrorKind::Cancelled => None,
+	    ErrorKind::Timeout => None,
+	    ErrorKind::TooSlow { .. } => None,
+	    ErrorKind::TooManyRetries { .. } => None,
+	    ErrorKind::TooLarge { .. } => None,
+	    ErrorKind::TruncatedBody { .. } => None,
+	    ErrorKind::PolicyViolation(_) => None,
+	    ErrorKind::UnsafeArchiveEntry(_) => None,
 	    ErrorKind::IO(_) => None,
 	    ErrorKind::Other(err) => Some(err),
 	}
@@ -53,23 +142,55 @@ impl Error {
 
 impl From<IOError> for Error {
     fn from(err: IOError) -> Error {
-        if err.kind() == IOErrorKind::PermissionDenied {
+        if err.get_ref().is_some_and(|inner| inner.is::<AuthenticationFailed>()) {
             Error {
-                kind: ErrorKind::PermissionDenied,
+                kind: ErrorKind::AuthenticationFailed,
+                source: Some(Box::new(err)),
             }
-        } else {
+        } else if err.kind() == IOErrorKind::PermissionDenied {
             Error {
-                kind: ErrorKind::IO(err),
+                kind: ErrorKind::PermissionDenied,
+                source: Some(Box::new(err)),
             }
+        } else {
+            Error::new(ErrorKind::IO(err))
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        let violation = std::iter::successors(err.source(), |err| (*err).source())
+            .find_map(|err| err.downcast_ref::<Violation>());
+        let kind = if let Some(violation) = violation {
+            ErrorKind::PolicyViolation(violation.0)
+        } else if let Some(status) = err.status() {
+            ErrorKind::HttpStatus(status)
+        } else if err.is_timeout() {
+            ErrorKind::Timeout
+        } else if err.is_connect() {
+            ErrorKind::Connect
+        } else if err.is_redirect() {
+            ErrorKind::Redirect
+        } else {
+            ErrorKind::InvalidResponse
+        };
+        Error {
+            kind,
+            source: Some(Box::new(err)),
         }
     }
 }
 
+impl From<Violation> for Error {
+    fn from(violation: Violation) -> Error {
+        Error::new(ErrorKind::PolicyViolation(violation.0))
+    }
+}
+
 impl From<Box<dyn StdError>> for Error {
     fn from(err: Box<dyn StdError>) -> Error {
-        Error {
-            kind: ErrorKind::Other(err),
-	}
+        Error::new(ErrorKind::Other(err))
     }
 }
 
@@ -78,10 +199,49 @@ impl fmt::Display for Error {
         match self.kind() {
             ErrorKind::FileExists => write!(f, "File already exists"),
             ErrorKind::DirectoryMissing => write!(f, "Destination path provided is not a valid directory"),
+            ErrorKind::InvalidFilename => write!(f, "Filename is not a single component within the destination directory"),
+            ErrorKind::InvalidUrl => write!(f, "URL is not a valid HTTP, HTTPS, file or data URL"),
             ErrorKind::PermissionDenied => write!(f, "Cannot create file: permission denied"),
             ErrorKind::InvalidResponse => write!(f, "Invalid response from the remote host"),
+            ErrorKind::HttpStatus(status) => write!(f, "Remote host responded with HTTP status {}", status),
+            ErrorKind::Connect => write!(f, "Could not connect to the remote host"),
+            ErrorKind::Redirect => write!(f, "Redirect was not followed"),
+            ErrorKind::MissingField(field) => write!(f, "Download is missing required field `{}`", field),
+            ErrorKind::InvalidConfig(reason) => write!(f, "Invalid download configuration: {}", reason),
+            ErrorKind::InvalidDigest => write!(f, "Expected digest provided is not valid"),
+            ErrorKind::InvalidMetalink => write!(f, "Metalink is not valid or does not list the download"),
+            ErrorKind::InvalidTorrent => write!(f, "Torrent is not valid, lists several files, or has no web seeds or peers"),
+            ErrorKind::ChecksumMismatch { expected, actual } => write!(f, "Checksum mismatch: expected {}, got {}", hex(expected), hex(actual)),
+            ErrorKind::ChecksumNotFound => write!(f, "Checksum manifest does not list the download"),
+            ErrorKind::AuthenticationFailed => write!(f, "Download failed to authenticate"),
+            ErrorKind::PieceMismatch { piece, offset, len } => write!(f, "Piece {} of the download, bytes {} to {}, does not match its checksum", piece, offset, offset + len),
+            ErrorKind::SignatureInvalid => write!(f, "Signature of the download is not valid"),
+            ErrorKind::PinMismatch => write!(f, "Certificate presented by the remote host does not match any pin"),
+            ErrorKind::Cancelled => write!(f, "Download was cancelled"),
+            ErrorKind::Timeout => write!(f, "Download timed out"),
+            ErrorKind::TooSlow { bytes_per_sec } => write!(f, "Download was slower than {} bytes per second", bytes_per_sec),
+            ErrorKind::TooManyRetries { retries, last } => write!(f, "Download failed after {} retries: {}", retries, last),
+            ErrorKind::TooLarge { limit } => write!(f, "Download is larger than the limit of {} bytes", limit),
+            ErrorKind::TruncatedBody { expected, actual } => write!(f, "Download ended after {} of {} bytes", actual, expected),
+            ErrorKind::PolicyViolation(reason) => write!(f, "Download was rejected by its policy: {}", reason),
+            ErrorKind::UnsafeArchiveEntry(path) => write!(f, "Archive entry `{}` would be extracted outside the destination directory", path.display()),
             ErrorKind::IO(err) => err.fmt(f),
             ErrorKind::Other(err) => err.fmt(f),
         }
     }
 }
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.kind() {
+            ErrorKind::TooManyRetries { last, .. } => Some(last.as_ref()),
+            ErrorKind::IO(err) => Some(err),
+            ErrorKind::Other(err) => Some(err.as_ref()),
+            _ => self.source.as_deref(),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,166 @@
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::error::Error as TDSTDError;
+use crate::result::DownloadResult;
+
+/// An event reported while a download is in progress, as yielded by
+/// [`AsyncDownload::download_events`](crate::AsyncDownload::download_events).
+#[derive(Debug)]
+pub enum DownloadEvent {
+    /// The response body has started streaming from `offset`, which is non-zero when resuming.
+    /// This is emitted again after each retry.
+    Started { offset: u64, total: Option<u64> },
+    /// A chunk of the download has been written.  `bytes` is the position of the download so far
+    /// and `total` is its length, if known.  `speed` is a smoothed transfer rate in bytes per
+    /// second, and `eta` is the estimated time remaining, if the length is known and the speed is
+    /// not yet zero.
+    Chunk { bytes: u64, total: Option<u64>, speed: f64, eta: Option<Duration> },
+    /// The download failed with a transient error and will be retried after `delay`.  `retry`
+    /// counts up from `1`.
+    Retrying { retry: u32, delay: Duration, error: TDSTDError },
+    /// An entry of an archive has been extracted to `path`, which is within the destination
+    /// directory, along with the `size` of its contents.
+    Extracted { path: PathBuf, size: u64 },
+    /// The download completed successfully.
+    Finished(DownloadResult),
+    /// The download failed.
+    Failed(TDSTDError),
+}
+
+/// The value returned by a progress callback, which decides whether the download carries on.  A
+/// callback returning `()` always carries on, while one returning `ControlFlow::Break(())` stops
+/// the download, which then fails with `Cancelled`.
+pub trait ProgressControl {
+    fn into_control_flow(self) -> ControlFlow<()>;
+}
+
+impl ProgressControl for () {
+    fn into_control_flow(self) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+impl ProgressControl for ControlFlow<()> {
+    fn into_control_flow(self) -> ControlFlow<()> {
+        self
+    }
+}
+
+impl DownloadEvent {
+    /// Returns a `Chunk` event, whose speed and ETA are filled in by a `Meter`.
+    pub(crate) fn chunk(bytes: u64, total: Option<u64>) -> Self {
+        DownloadEvent::Chunk { bytes, total, speed: 0.0, eta: None }
+    }
+}
+
+/// Holds back `Chunk` events which follow too soon after the last one reported, so that progress
+/// is reported at most once per interval or number of bytes, whichever comes first.
+pub(crate) struct ProgressFilter {
+    interval: Option<Duration>,
+    bytes: Option<u64>,
+    last: Option<(Instant, u64)>,
+    pending: Option<DownloadEvent>,
+}
+
+impl ProgressFilter {
+    pub(crate) fn new(interval: Option<Duration>, bytes: Option<u64>) -> Self {
+        Self {
+            interval,
+            bytes,
+            last: None,
+            pending: None,
+        }
+    }
+
+    /// Passes an event to `emit` unless it is a `Chunk` event which should be held back.  Any
+    /// other event first reports the last chunk held back, so events stay in order.
+    pub(crate) fn filter(&mut self, event: DownloadEvent, emit: &mut dyn FnMut(DownloadEvent)) {
+        let DownloadEvent::Chunk { bytes, .. } = event else {
+            self.flush(emit);
+            return emit(event);
+        };
+        if self.interval.is_none() && self.bytes.is_none() {
+            return emit(event);
+        }
+        let now = Instant::now();
+        let due = match self.last {
+            None => true,
+            Some((then, from)) => {
+                self.interval.is_some_and(|interval| now.duration_since(then) >= interval)
+                    || self.bytes.is_some_and(|min| bytes.saturating_sub(from) >= min)
+            }
+        };
+        if due {
+            self.last = Some((now, bytes));
+            self.pending = None;
+            emit(event);
+        } else {
+            self.pending = Some(event);
+        }
+    }
+
+    /// Reports the last chunk held back, if any.
+    pub(crate) fn flush(&mut self, emit: &mut dyn FnMut(DownloadEvent)) {
+        if let Some(event) = self.pending.take() {
+            emit(event);
+        }
+    }
+}
+
+/// Measures the speed of a download as an exponentially weighted moving average over samples
+/// taken at least `SAMPLE_INTERVAL` apart, so that it neither jumps around with every chunk nor
+/// takes too long to follow a change in speed.
+pub(crate) struct Meter {
+    speed: f64,
+    last: Option<(Instant, u64)>,
+}
+
+impl Meter {
+    const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// The time constant of the average, in seconds.  A sample this old has about a third of the
+    /// weight of the latest one.
+    const SMOOTHING: f64 = 3.0;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            speed: 0.0,
+            last: None,
+        }
+    }
+
+    /// Updates the speed from `Started` and `Chunk` events, filling it and the ETA into the
+    /// latter.
+    pub(crate) fn measure(&mut self, event: &mut DownloadEvent) {
+        let now = Instant::now();
+        match event {
+            DownloadEvent::Started { offset, .. } => self.last = Some((now, *offset)),
+            DownloadEvent::Chunk { bytes, total, speed, eta } => {
+                match self.last {
+                    Some((then, from)) => {
+                        let elapsed = now.duration_since(then);
+                        if elapsed >= Self::SAMPLE_INTERVAL {
+                            let secs = elapsed.as_secs_f64();
+                            let sample = bytes.saturating_sub(from) as f64 / secs;
+                            // The first sample is taken as it is, rather than averaged with zero.
+                            let weight = if self.speed > 0.0 { 1.0 - (-secs / Self::SMOOTHING).exp() } else { 1.0 };
+                            self.speed += weight * (sample - self.speed);
+                            self.last = Some((now, *bytes));
+                        }
+                    }
+                    None => self.last = Some((now, *bytes)),
+                }
+                *speed = self.speed;
+                *eta = match *total {
+                    Some(total) if self.speed > 0.0 => {
+                        Some(Duration::from_secs_f64(total.saturating_sub(*bytes) as f64 / self.speed))
+                    }
+                    _ => None,
+                };
+            }
+            _ => (),
+        }
+    }
+}
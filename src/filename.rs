@@ -0,0 +1,165 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+use percent_encoding::percent_decode_str;
+use reqwest::header::CONTENT_DISPOSITION;
+
+use crate::result::ResponseMetadata;
+
+/// The filename used when neither the response nor the URL suggest one.
+const FALLBACK: &str = "download";
+
+/// Derives a filename for the download from its response, preferring the `Content-Disposition`
+/// header and falling back to the last segment of the final URL.
+pub(crate) fn from_response(response: &ResponseMetadata) -> String {
+    response.headers
+        .get(CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(from_content_disposition)
+        .and_then(|name| sanitize(&name))
+        .or_else(|| from_url(&response.url))
+        .unwrap_or_else(|| String::from(FALLBACK))
+}
+
+/// Returns the filename from a `Content-Disposition` header, preferring the RFC 6266 `filename*`
+/// parameter over `filename`.
+fn from_content_disposition(value: &str) -> Option<String> {
+    let mut filename = None;
+    for param in split_params(value).into_iter().skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // `<charset>'<language>'<percent-encoded value>`
+                let mut parts = value.splitn(3, '\'');
+                let charset = parts.next()?;
+                let encoded = parts.nth(1)?;
+                let decoded = percent_decode_str(encoded);
+                let decoded = if charset.eq_ignore_ascii_case("utf-8") {
+                    decoded.decode_utf8().ok()?.into_owned()
+                } else {
+                    decoded.decode_utf8_lossy().into_owned()
+                };
+                return Some(decoded);
+            }
+            "filename" => filename = Some(unquote(value)),
+            _ => (),
+        }
+    }
+    filename
+}
+
+/// Splits a header value on `;`, ignoring any inside quoted strings.
+fn split_params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    params.push(&value[start..]);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(quoted) => {
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                unquoted.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+            }
+            unquoted
+        }
+        None => String::from(value),
+    }
+}
+
+/// Returns the last non-empty segment of the URL path, percent-decoded.
+pub(crate) fn from_url(url: &reqwest::Url) -> Option<String> {
+    let segment = url.path_segments()?.rfind(|s| !s.is_empty())?;
+    sanitize(&percent_decode_str(segment).decode_utf8_lossy())
+}
+
+/// Reduces a filename suggested by the server to a single path component: anything before the
+/// last path separator and any control characters are removed, and names which would refer to
+/// the directory itself or its parent are rejected.
+pub(crate) fn sanitize(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        None
+    } else {
+        Some(String::from(name))
+    }
+}
+
+/// Splits the path a download is written to into its directory, which is the current directory
+/// if it has none, and its filename, or returns `None` if it does not end in a filename.
+pub(crate) fn split_path(path: &Path) -> Option<(PathBuf, OsString)> {
+    // A trailing separator or `.` refers to a directory, although `file_name` ignores it.
+    if let [.., b'/' | b'\\'] | [.., b'/' | b'\\', b'.'] = path.as_os_str().as_encoded_bytes() {
+        return None;
+    }
+    let fname = path.file_name()?;
+    let dst_path = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Some((dst_path, fname.to_os_string()))
+}
+
+/// Returns `true` if the filename is a single normal path component, so that joining it to the
+/// destination directory cannot refer to a path outside of it.
+pub(crate) fn is_valid(name: &OsStr) -> bool {
+    let mut components = Path::new(name).components();
+    !name.as_encoded_bytes().iter().any(|b| matches!(b, b'/' | b'\\' | b'\0'))
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+/// Makes a filename valid on Windows as well: reserved characters are replaced with `_`,
+/// trailing dots and spaces are removed, and device names such as `CON` or `LPT1` are prefixed
+/// with `_`.  A name which is not valid UTF-8 has its invalid bytes replaced as well, since
+/// Windows cannot store them.
+pub(crate) fn portable(name: &OsStr) -> OsString {
+    let name = name.to_string_lossy();
+    let name: String = name.chars()
+        .map(|c| if matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') { '_' } else { c })
+        .collect();
+    let name = name.trim_end_matches(['.', ' ']);
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if is_reserved(stem) {
+        OsString::from(format!("_{}", name))
+    } else {
+        OsString::from(name)
+    }
+}
+
+fn is_reserved(stem: &str) -> bool {
+    let stem = stem.to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => matches!(stem.as_bytes(), [b'C', b'O', b'M', b'1'..=b'9'] | [b'L', b'P', b'T', b'1'..=b'9']),
+    }
+}
+
+/// Returns the filename with `suffix` appended, such as `.part`.
+pub(crate) fn with_suffix(name: &OsStr, suffix: &str) -> OsString {
+    let mut name = name.to_os_string();
+    name.push(suffix);
+    name
+}
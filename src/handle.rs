@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::DownloadEvent;
+
+/// How long a paused download keeps its connection open before dropping it, after which it is
+/// re-requested with a range when resumed.
+pub(crate) const PAUSE_KEEP_CONNECTION: Duration = Duration::from_secs(30);
+
+/// A handle to a download started with [`AsyncDownload::start`](crate::AsyncDownload::start),
+/// which can pause and resume it and report its progress.  Cloning a DownloadHandle returns a
+/// handle to the same download, and handles can be sent to other tasks.
+#[derive(Clone, Debug)]
+pub struct DownloadHandle {
+    control: Arc<Control>,
+}
+
+/// The state shared between a download and its handles.
+#[derive(Debug)]
+pub(crate) struct Control {
+    paused: watch::Sender<bool>,
+    pauses: AtomicU64,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Progress {
+    bytes: u64,
+    total: Option<u64>,
+    speed: f64,
+    eta: Option<Duration>,
+}
+
+impl DownloadHandle {
+    pub(crate) fn new(control: Arc<Control>) -> Self {
+        Self { control }
+    }
+
+    /// Pause the download.  Its connection is kept open for a while, and if it is paused for
+    /// longer, the connection is dropped and the rest of the download is requested with a range
+    /// once it is resumed.  If the server does not support ranges, the download then starts
+    /// again.
+    pub fn pause(&self) {
+        self.control.pauses.fetch_add(1, Ordering::SeqCst);
+        self.control.paused.send_replace(true);
+    }
+
+    /// Resume the download after [`pause`](DownloadHandle::pause).
+    pub fn resume(&self) {
+        self.control.paused.send_replace(false);
+    }
+
+    /// Returns `true` if the download is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.control.paused.borrow()
+    }
+
+    /// Returns the position of the download and its total length, if known, in bytes.
+    pub fn progress(&self) -> (u64, Option<u64>) {
+        let progress = self.control.progress.lock().unwrap();
+        (progress.bytes, progress.total)
+    }
+
+    /// Returns the smoothed transfer rate of the download, in bytes per second.
+    pub fn speed(&self) -> f64 {
+        self.control.progress.lock().unwrap().speed
+    }
+
+    /// Returns the estimated time remaining, if the length of the download is known and it is
+    /// making progress.
+    pub fn eta(&self) -> Option<Duration> {
+        self.control.progress.lock().unwrap().eta
+    }
+}
+
+impl Control {
+    pub(crate) fn new() -> Self {
+        Self {
+            paused: watch::Sender::new(false),
+            pauses: AtomicU64::new(0),
+            progress: Mutex::new(Progress::default()),
+        }
+    }
+
+    /// Returns how many times the download has been paused, to tell whether a failed attempt
+    /// overlapped with a pause.
+    pub(crate) fn pauses(&self) -> u64 {
+        self.pauses.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the download is not paused.
+    pub(crate) async fn unpaused(&self) {
+        let _ = self.paused.subscribe().wait_for(|paused| !paused).await;
+    }
+
+    /// Waits until the download is not paused, giving up after `timeout`.  Returns `false` if it
+    /// is still paused.
+    pub(crate) async fn unpaused_within(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.unpaused()).await.is_ok()
+    }
+
+    /// Updates the progress reported by the handles from an event.
+    pub(crate) fn record(&self, event: &DownloadEvent) {
+        let mut progress = self.progress.lock().unwrap();
+        match *event {
+            DownloadEvent::Started { offset, total } => {
+                progress.bytes = offset;
+                progress.total = total;
+            }
+            DownloadEvent::Chunk { bytes, total, speed, eta } => *progress = Progress { bytes, total, speed, eta },
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::error::Error as TDSTDError;
+use crate::{check_digest, Inspect};
+
+/// The digests of a Subresource Integrity string, of the strongest algorithm it lists.
+#[derive(Clone, Debug)]
+pub(crate) struct Integrity {
+    algorithm: Algorithm,
+    digests: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Integrity {
+    /// Parses a Subresource Integrity string, such as `sha384-<base64>`, which may list several
+    /// digests separated by whitespace, each optionally followed by `?` and options.  Digests of
+    /// other algorithms are ignored, and `None` is returned if none are left.
+    pub(crate) fn parse(sri: &str) -> Option<Self> {
+        let mut integrity: Option<Self> = None;
+        for item in sri.split_ascii_whitespace() {
+            let item = item.split('?').next().unwrap_or_default();
+            let Some((algorithm, digest)) = item.split_once('-') else {
+                continue;
+            };
+            let (algorithm, len) = match algorithm {
+                "sha256" => (Algorithm::Sha256, 32),
+                "sha384" => (Algorithm::Sha384, 48),
+                "sha512" => (Algorithm::Sha512, 64),
+                _ => continue,
+            };
+            let digest = BASE64.decode(digest).ok().filter(|digest| digest.len() == len)?;
+            match integrity {
+                Some(ref mut integrity) if integrity.algorithm == algorithm => integrity.digests.push(digest),
+                Some(ref integrity) if integrity.algorithm > algorithm => (),
+                _ => integrity = Some(Self { algorithm, digests: vec![digest] }),
+            }
+        }
+        integrity
+    }
+
+    pub(crate) fn hasher(&self) -> IntegrityHasher {
+        let hasher = match self.algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha384 => Hasher::Sha384(Sha384::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        };
+        IntegrityHasher(hasher)
+    }
+
+    /// Checks the digest of a completed download against those listed, any of which may match.
+    pub(crate) fn check(&self, hasher: IntegrityHasher) -> Result<(), TDSTDError> {
+        let actual = match hasher.0 {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha384(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        };
+        let expected = self.digests.iter().find(|digest| **digest == actual).unwrap_or(&self.digests[0]);
+        check_digest(expected, &actual)
+    }
+}
+
+/// Hashes every chunk of the download with the algorithm of an [`Integrity`].
+pub(crate) struct IntegrityHasher(Hasher);
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Inspect for IntegrityHasher {
+    fn update(&mut self, chunk: &[u8]) {
+        match self.0 {
+            Hasher::Sha256(ref mut hasher) => hasher.update(chunk),
+            Hasher::Sha384(ref mut hasher) => hasher.update(chunk),
+            Hasher::Sha512(ref mut hasher) => hasher.update(chunk),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self.0 {
+            Hasher::Sha256(ref mut hasher) => hasher.reset(),
+            Hasher::Sha384(ref mut hasher) => hasher.reset(),
+            Hasher::Sha512(ref mut hasher) => hasher.reset(),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use reqwest::Url;
+
+use crate::backend::BackendError;
+
+pub use reqwest::Request;
+
+/// The future returned by [`Interceptor::intercept`].
+pub type Intercepted<'a> = Pin<Box<dyn Future<Output = Result<(), BackendError>> + 'a>>;
+
+/// A hook which inspects and changes every request before it is sent, such as to sign it, add
+/// a trace header, or rewrite its URL to a preferred mirror.  Interceptors are added with
+/// [`AsyncDownloadBuilder::interceptor`](crate::AsyncDownloadBuilder::interceptor) and run in
+/// the order they were added, after credentials have been added to the request.
+///
+/// Every request the download makes is intercepted, including retries, range requests and each
+/// hop of a redirect, which is passed the URL that redirected to it.  A URL an interceptor
+/// rewrites must be allowed by the policy.  An error returned by an interceptor fails the
+/// download without retrying it.
+///
+/// Any `Fn(&mut Request, Option<&Url>) -> Result<(), BackendError>` is an interceptor which
+/// does not need to wait.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use reqwest::Url;
+/// use tokio_dl_stream_to_disk::AsyncDownload;
+/// use tokio_dl_stream_to_disk::backend::BackendError;
+/// use tokio_dl_stream_to_disk::intercept::Request;
+///
+/// # fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+/// let download = AsyncDownload::builder()
+///     .url("https://example.com/release.tar.gz")
+///     .dst_dir("/tmp")
+///     .interceptor(|request: &mut Request, _redirected_from: Option<&Url>| -> Result<(), BackendError> {
+///         request.headers_mut().insert("x-request-id", "7f3a".parse()?);
+///         Ok(())
+///     })
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Interceptor {
+    /// Inspects or changes `request` before it is sent.  `redirected_from` is the URL which
+    /// redirected to it, if it is a hop of a redirect.
+    fn intercept<'a>(&'a self, request: &'a mut Request, redirected_from: Option<&'a Url>) -> Intercepted<'a>;
+}
+
+impl<F: Fn(&mut Request, Option<&Url>) -> Result<(), BackendError>> Interceptor for F {
+    fn intercept<'a>(&'a self, request: &'a mut Request, redirected_from: Option<&'a Url>) -> Intercepted<'a> {
+        Box::pin(std::future::ready(self(request, redirected_from)))
+    }
+}
+
+/// The interceptors of a download, in order.
+#[derive(Default)]
+pub(crate) struct Interceptors(pub(crate) Vec<Box<dyn Interceptor>>);
+
+impl Interceptors {
+    /// Passes `request` through every interceptor.
+    pub(crate) async fn apply(&self, request: &mut Request, redirected_from: Option<&Url>) -> Result<(), BackendError> {
+        for interceptor in &self.0 {
+            interceptor.intercept(request, redirected_from).await?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Interceptors({})", self.0.len())
+    }
+}
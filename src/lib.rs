@@ -8,38 +8,159 @@
 //!
 //! #[tokio::main]
 //! async fn main() {
-//!     if AsyncDownload::new("https://bit.ly/3yWXSOW", &Path::new("/tmp"), "5mb_test.bin").download(&None).await.is_ok() {
+//!     let mut download = AsyncDownload::new("https://bit.ly/3yWXSOW", Path::new("/tmp"), "5mb_test.bin").unwrap();
+//!     if download.download(&None).await.is_ok() {
 //!         println!("File downloaded successfully!");
 //!     }
 //! }
 //! ```
 
+pub mod auth;
+pub mod backend;
+#[cfg(feature="bao")]
+pub mod bao;
+#[cfg(feature="blocking")]
+pub mod blocking;
+pub mod builder;
+mod byteranges;
+mod cache;
+#[cfg(feature="object-store")]
+mod cloud;
+mod conditional;
+#[cfg(feature="content-digest")]
+mod content_digest;
+#[cfg(feature="decrypt")]
+pub mod decrypt;
+#[cfg(any(feature="compress", feature="decompress"))]
+pub mod compression;
+mod date;
 pub mod error;
+pub mod event;
+mod filename;
+pub mod handle;
+#[cfg(feature="sri")]
+mod integrity;
+pub mod intercept;
+mod local;
+pub mod manager;
+#[cfg(feature="metalink")]
+mod metalink;
+pub mod metrics;
+mod mirrors;
+mod netrc;
+mod offload;
+#[cfg(feature="pinning")]
+mod pinning;
+#[cfg(feature="digest")]
+mod pieces;
+pub mod policy;
+pub mod probe;
+mod provenance;
+mod redirect;
+mod resolve;
+pub mod retry;
+pub mod result;
+mod segmented;
+#[cfg(feature="sftp")]
+mod sftp;
+#[cfg(any(feature="minisign", feature="openpgp"))]
+mod signature;
+#[cfg(feature="sha256sum")]
+mod sidecar;
+mod supplied;
+#[cfg(feature="state")]
+mod state;
+mod template;
+pub mod throttle;
+#[cfg(feature="torrent")]
+mod peers;
+#[cfg(feature="torrent")]
+mod torrent;
+#[cfg(feature="tracing")]
+mod trace;
+pub mod transform;
+#[cfg(any(feature="tar", feature="zip"))]
+mod unpack;
+#[cfg(all(feature="io-uring", target_os="linux"))]
+mod uring;
+mod url;
+mod verify;
 
-use std::convert::TryInto;
 use std::error::Error;
-use std::io::{Error as IOError, ErrorKind};
+use std::ffi::{OsStr, OsString};
+use std::future::Future;
+use std::io::Error as IOError;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures_util::stream::Stream;
 use futures_util::StreamExt;
 
+#[cfg(feature="digest")]
+use digest::Digest;
 #[cfg(feature="sha256sum")]
-use sha2::{Sha256, Digest};
-use tokio_util::io::StreamReader;
+use sha2::Sha256;
+use tokio::io::AsyncWrite;
 
+use crate::builder::Config;
+use crate::conditional::Validators;
+#[cfg(feature="content-digest")]
+use crate::content_digest::HeaderDigest;
+#[cfg(feature="digest")]
+use crate::pieces::PieceVerifier;
+#[cfg(feature="sri")]
+use crate::integrity::{Integrity, IntegrityHasher};
+#[cfg(feature="state")]
+use crate::state::DownloadState;
+use crate::event::{Meter, ProgressFilter};
+use crate::handle::{Control, PAUSE_KEEP_CONNECTION};
+use crate::offload::Offloaded;
+use crate::redirect::Redirects;
+use crate::throttle::SpeedCheck;
 use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
 
+pub use crate::auth::AuthProvider;
+pub use crate::builder::{AsyncDownloadBuilder, CleanupPolicy, OverwriteBehavior, SourceMetadata, SyncPolicy};
+#[cfg(any(feature="compress", feature="decompress"))]
+pub use crate::compression::CompressionFormat;
+pub use crate::event::{DownloadEvent, ProgressControl};
+pub use crate::handle::DownloadHandle;
+pub use crate::intercept::Interceptor;
+pub use crate::manager::DownloadManager;
+pub use crate::policy::Policy;
+pub use crate::probe::{Probe, ResumeSupport};
+pub use crate::result::{CorruptAttempt, DownloadResult, ResponseMetadata};
+pub use crate::retry::Backoff;
+pub use crate::throttle::{Budget, RateLimiter};
+pub use crate::transform::Transform;
+pub use crate::url::IntoUrl;
+pub use reqwest::tls::Version as TlsVersion;
+pub use reqwest::{Identity, Proxy};
+pub use tokio_util::sync::CancellationToken;
+#[cfg(feature="digest")]
+pub use digest;
+
 type S = dyn Stream<Item = Result<Bytes, IOError>> + Unpin;
 
+/// A callback taking the position of the current download and its total length, if known.
+pub type ProgressCallback = Box<dyn Fn(u64, Option<u64>)>;
+
 /// The AsyncDownload struct allows you to stream the contents of a download to the disk.
 pub struct AsyncDownload {
     url: String,
     dst_path: PathBuf,
-    fname: String,
+    fname: OsString,
     length: Option<u64>,
-    response_stream: Option<Box<S>>
+    response_stream: Option<Box<S>>,
+    response: Option<ResponseMetadata>,
+    accept_ranges: bool,
+    corrupt_attempts: Vec<CorruptAttempt>,
+    #[cfg(feature="state")]
+    state: Option<DownloadState>,
+    config: Config,
 }
 
 impl AsyncDownload {
@@ -47,37 +168,133 @@ impl AsyncDownload {
     ///
     /// # Arguments
     ///
-    /// * `url` - A string type containing the URL you want to download the contents of
-    /// * `dst_path` - A PathBuf type containing the destination path
-    /// * `fname` - A string type containing the filename of the download
-    pub fn new(url: &str, dst_path: &Path, fname: &str) -> Self {
+    /// * `url` - The URL you want to download the contents of, as a string or a `reqwest::Url`.
+    ///   It must be an absolute HTTP or HTTPS URL, or a `file` or `data` URL, or `InvalidUrl` is
+    ///   returned
+    /// * `dst_path` - The destination directory
+    /// * `fname` - The filename of the download, which need not be UTF-8, or an empty string to
+    ///   derive it from the response
+    pub fn new<U: IntoUrl, P: AsRef<Path>, F: AsRef<OsStr>>(url: U, dst_path: P, fname: F) -> Result<Self, TDSTDError> {
+        Ok(Self::with_url(url.into_url()?.to_string(), dst_path.as_ref().to_path_buf(), fname.as_ref().to_os_string()))
+    }
+
+    /// Returns an AsyncDownload like [`new`](AsyncDownload::new) with a URL which has already
+    /// been checked, or none if the body is supplied.
+    pub(crate) fn with_url(url: String, dst_path: PathBuf, fname: OsString) -> Self {
         Self {
-            url: String::from(url),
-            dst_path: PathBuf::from(dst_path),
-            fname: String::from(fname),
+            url,
+            dst_path,
+            fname,
             length: None,
-            response_stream: None
+            response_stream: None,
+            response: None,
+            accept_ranges: false,
+            corrupt_attempts: Vec::new(),
+            #[cfg(feature="state")]
+            state: None,
+            config: Config {
+                redirects: Some(Redirects::default()),
+                ..Config::default()
+            },
         }
     }
 
+    /// Returns an AsyncDownload struct like [`new`](AsyncDownload::new), which will write the
+    /// download to `path` rather than to a filename within a destination directory.  A path
+    /// without a directory is written to the current directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL you want to download the contents of, as for [`new`](AsyncDownload::new)
+    /// * `path` - The path of the file the download is written to, which must end in a filename,
+    ///   or `InvalidFilename` is returned
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// let mut download = AsyncDownload::to_path("https://example.com/file.bin", "/data/out/file.bin")?;
+    /// download.download(&None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_path<U: IntoUrl, P: AsRef<Path>>(url: U, path: P) -> Result<Self, TDSTDError> {
+        let (dst_path, fname) = filename::split_path(path.as_ref())
+            .ok_or_else(|| TDSTDError::new(TDSTDErrorKind::InvalidFilename))?;
+        Self::new(url, dst_path, fname)
+    }
+
+    /// Returns an AsyncDownload struct like [`new`](AsyncDownload::new), which will make its
+    /// request with the provided `reqwest::Client` rather than building a new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - A `reqwest::Client` to reuse for the request
+    /// * `url` - The URL you want to download the contents of, as for [`new`](AsyncDownload::new)
+    /// * `dst_path` - The destination directory
+    /// * `fname` - The filename of the download
+    pub fn with_client<U: IntoUrl, P: AsRef<Path>, F: AsRef<OsStr>>(client: reqwest::Client, url: U, dst_path: P, fname: F) -> Result<Self, TDSTDError> {
+        let mut download = Self::new(url, dst_path, fname)?;
+        download.config.client = Some(client);
+        download.config.content_decoded = true;
+        download.config.redirects = None;
+        Ok(download)
+    }
+
+    /// Returns an `AsyncDownloadBuilder`, which allows further options to be set on the download.
+    pub fn builder() -> AsyncDownloadBuilder {
+        AsyncDownloadBuilder::new()
+    }
+
     /// Returns the length of the download in bytes.  This should be called after calling [`get`]
     /// or [`download`].
     pub fn length(&self) -> Option<u64> {
        self.length 
     }
 
+    /// Returns the metadata of the most recent response, such as its status, headers and final
+    /// URL.  This is available after calling [`get`], e.g. to choose a filename from the
+    /// content type before calling [`download`].
+    pub fn metadata(&self) -> Option<&ResponseMetadata> {
+        self.response.as_ref()
+    }
+
+    /// Change the filename of the download within the destination directory, e.g. once its
+    /// [`metadata`] is known.  This must be called before the download starts.
+    pub fn set_filename<F: AsRef<OsStr>>(&mut self, fname: F) {
+        self.fname = fname.as_ref().to_os_string();
+    }
+
     /// Get the download URL, but do not download it.  If successful, returns an `AsyncDownload`
     /// object with a response stream, which you can then call [`download`] on.  After this, the
     /// length of the download should also be known and you can call [`length`] on it.
     pub async fn get(mut self) -> Result<AsyncDownload, Box<dyn Error>> {
-        self.get_non_consumable().await?;
+        self.get_non_consumable().await.map_err(|failure| failure.error)?;
         Ok(self)
     }
 
-    async fn get_non_consumable(&mut self) -> Result<(), Box<dyn Error>> {
-        let response = reqwest::get(self.url.clone())
-            .await?;
-        let content_length = response.headers().get("content-length").map_or(None, 
+    async fn get_non_consumable(&mut self) -> Result<(), Failure> {
+        self.get_conditional(&Validators::default()).await?;
+        Ok(())
+    }
+
+    /// Makes the initial request with any of the given validators, racing the mirrors if there
+    /// are any, and returns `true` without setting the response stream if the server responds
+    /// with `304 Not Modified`.
+    async fn get_conditional(&mut self, validators: &Validators) -> Result<bool, Failure> {
+        self.resolve_sources().await?;
+        let (response, stream) = if self.config.mirrors.is_empty() {
+            self.open(validators.apply(self.request())).await?
+        } else {
+            self.race_mirrors(validators).await?
+        };
+        let Some(stream) = stream else {
+            self.response = Some(response);
+            return Ok(true);
+        };
+        let content_length = response.headers.get("content-length").and_then(
             |l| {
                 match l.to_str() {
                     Err(_) => None,
@@ -86,92 +303,1822 @@ impl AsyncDownload {
                     }
                 }
             });
-        self.response_stream = Some(Box::new(response
-            .error_for_status()?
-            .bytes_stream()
-            .map(|result| result.map_err(|e| IOError::new(ErrorKind::Other, e)))));
+        let accept_ranges = response.headers.get("accept-ranges")
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"bytes"));
+        // A slice is only downloaded if the server sent exactly it, as the whole file would
+        // otherwise be written in its place.
+        let (content_length, accept_ranges) = match self.config.range {
+            Some(_) if response.status != reqwest::StatusCode::PARTIAL_CONTENT => {
+                return Err(Failure::fatal(TDSTDErrorKind::InvalidResponse));
+            }
+            Some(_) => (self.partial_length(&response.headers, 0)?, true),
+            None => (content_length, accept_ranges),
+        };
+        if self.fname.is_empty() {
+            self.fname = self.derived_fname(&response);
+        }
+        self.response = Some(response);
+        self.response_stream = Some(stream);
         self.length = content_length;
-        Ok(())
+        self.accept_ranges = accept_ranges;
+        Ok(false)
+    }
+
+    /// Derives the filename of the download from its response, with the extension changed if
+    /// it is decompressed or compressed as it is written.
+    fn derived_fname(&self, response: &ResponseMetadata) -> OsString {
+        let fname = filename::from_response(response);
+        #[cfg(feature="decompress")]
+        let fname = match self.config.decompress {
+            Some(format) => format.strip_extension(&fname),
+            None => fname,
+        };
+        #[cfg(feature="compress")]
+        let fname = match self.config.store_compressed {
+            Some(format) => format!("{}.{}", fname, format.extension()),
+            None => fname,
+        };
+        OsString::from(fname)
     }
 
-    /// Initiate the download and return a result.  Specify an optional callback.
+    fn request(&self) -> reqwest::RequestBuilder {
+        self.request_to(&self.url)
+    }
+
+    /// Builds a request with the configured options, to `url` rather than the download URL.
+    fn request_to(&self, url: &str) -> reqwest::RequestBuilder {
+        let request = self.request_with(self.config.method.clone(), url);
+        match self.config.range {
+            Some((start, end)) => request.header(reqwest::header::RANGE, format!("bytes={}-{}", start, end - 1)),
+            None => request,
+        }
+    }
+
+    /// Builds a request for the bytes of the download from `start` up to `end`, or up to its
+    /// end.  The offsets are into the slice set with [`range`](AsyncDownloadBuilder::range), if
+    /// any, rather than the remote file.
+    fn request_bytes(&self, start: u64, end: Option<u64>) -> reqwest::RequestBuilder {
+        let (offset, limit) = match self.config.range {
+            Some((range_start, range_end)) => (range_start, Some(range_end)),
+            None => (0, None),
+        };
+        let range = match end.map(|end| end + offset).or(limit) {
+            Some(end) => format!("bytes={}-{}", start + offset, end - 1),
+            None => format!("bytes={}-", start + offset),
+        };
+        self.request_with(self.config.method.clone(), &self.url).header(reqwest::header::RANGE, range)
+    }
+
+    /// Checks that the `Content-Range` of a `206 Partial Content` response starts at `pos`, and
+    /// returns the length of the download it gives.  If only a slice of the remote file is
+    /// downloaded, `pos` is an offset into the slice, the range must end within it, and the
+    /// length is that of the slice, cut short if the file ends before it.  Otherwise the length
+    /// is that of the file, if known.
+    fn partial_length(&self, headers: &reqwest::header::HeaderMap, pos: u64) -> Result<Option<u64>, Failure> {
+        let invalid = || Failure::fatal(TDSTDErrorKind::InvalidResponse);
+        let (start, last, total) = headers.get(reqwest::header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_range)
+            .ok_or_else(invalid)?;
+        let Some((range_start, range_end)) = self.config.range else {
+            return if start == pos { Ok(total) } else { Err(invalid()) };
+        };
+        if start != range_start + pos || last >= range_end || total.is_some_and(|total| total <= last) {
+            return Err(invalid());
+        }
+        Ok(Some(total.map_or(last + 1, |total| total.min(range_end)) - range_start))
+    }
+
+    /// Builds a request with the configured options, but with `method` rather than the
+    /// configured method.
+    fn request_with(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let client = self.config.client.clone().unwrap_or_else(default_client);
+        // The client refuses to build a request for a URL without a host, and would move the
+        // user of an `sftp` URL into an `Authorization` header, although neither is sent by it.
+        let request = match reqwest::Url::parse(url) {
+            Ok(url) if matches!(url.scheme(), "file" | "data" | "sftp" | "scp" | "magnet") => {
+                reqwest::RequestBuilder::from_parts(client, reqwest::Request::new(method, url))
+            }
+            _ => client.request(method, url),
+        };
+        let mut request = request.headers(self.config.headers.clone());
+        if let Some(ref body) = self.config.body {
+            request = request.body(body.clone());
+        }
+        if let Some(timeout) = self.config.timeout {
+            request = request.timeout(timeout);
+        }
+        if let Some(version) = self.config.version {
+            request = request.version(version);
+        }
+        request
+    }
+
+    /// Initiate the download and return a result summarizing it.  Specify an optional callback.
     ///
     /// Arguments:
     /// * `cb` - An optional callback for reporting information about the download asynchronously.
-    /// The callback takes the position of the current download, in bytes.
-    pub async fn download(&mut self, cb: &Option<Box<dyn Fn(u64) -> ()>>) -> Result<(), TDSTDError> {
-        if self.response_stream.is_none() {
-            self.get_non_consumable().await.map_err(|_| TDSTDError::new(TDSTDErrorKind::InvalidResponse))?;
-        }
-        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    ///   The callback takes the position of the current download, in bytes.
+    pub async fn download(&mut self, cb: &Option<Box<dyn Fn(u64)>>) -> Result<DownloadResult, TDSTDError> {
+        self.download_with_events(&mut position_only(cb)).await
+    }
 
-        let fname = self.dst_path.join(self.fname.clone());
-        if fname.is_file() {
-            return Err(TDSTDError::new(TDSTDErrorKind::FileExists));
-        }
+    /// Initiate the download and return a result summarizing it.  Specify an optional callback
+    /// which also receives the total length of the download, so it can compute a percentage.
+    ///
+    /// Arguments:
+    /// * `cb` - An optional callback for reporting information about the download asynchronously.
+    ///   The callback takes the position of the current download and the total length of the
+    ///   download if it is known, in bytes.
+    pub async fn download_with_progress(&mut self, cb: &Option<ProgressCallback>) -> Result<DownloadResult, TDSTDError> {
+        let mut progress = |event| {
+            if let (Some(cb), DownloadEvent::Chunk { bytes, total, .. }) = (cb, event) {
+                cb(bytes, total);
+            }
+        };
+        self.download_with_events(&mut progress).await
+    }
 
-        if self.dst_path.is_dir() {
-            let mut http_async_reader = StreamReader::new(self.response_stream.take().unwrap());
+    /// Initiate the download and return a result summarizing it, reporting its progress to a
+    /// callback which may update captured state.
+    ///
+    /// Arguments:
+    /// * `cb` - A callback for reporting information about the download.  The callback takes the
+    ///   position of the current download and the total length of the download if it is known,
+    ///   in bytes.  It may return `ControlFlow::Break(())` to stop the download, which then fails
+    ///   with `Cancelled`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::ops::ControlFlow;
+    ///
+    /// # async fn run(download: &mut tokio_dl_stream_to_disk::AsyncDownload) {
+    /// let quota = 100 * 1024 * 1024;
+    /// let result = download.download_with_callback(|bytes, _total| {
+    ///     if bytes > quota { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    /// }).await;
+    /// # }
+    /// ```
+    pub async fn download_with_callback<R: ProgressControl>(&mut self, mut cb: impl FnMut(u64, Option<u64>) -> R) -> Result<DownloadResult, TDSTDError> {
+        let (parent, abort) = self.abortable();
+        let mut progress = |event| {
+            if let DownloadEvent::Chunk { bytes, total, .. } = event {
+                if cb(bytes, total).into_control_flow().is_break() {
+                    abort.cancel();
+                }
+            }
+        };
+        let result = self.download_with_events(&mut progress).await;
+        self.config.cancellation_token = parent;
+        result
+    }
 
-            let mut dest = tokio::fs::File::create(fname).await?;
-            let mut buf = [0; 8 * 1024];
-            let mut num_bytes_total = 0;
-            loop {
-                let num_bytes = http_async_reader.read(&mut buf).await?;
-                if let Some(ref cb) = cb {
-                    num_bytes_total += num_bytes;
-                    cb(num_bytes_total.try_into().unwrap());
+    /// Initiate the download and return a result summarizing it, reporting its progress to an
+    /// async callback.  The download carries on while the future returned by the callback runs,
+    /// and any progress made in the meantime is reported by the next call, so the callback is
+    /// never called with stale positions.  The final position is always reported.
+    ///
+    /// Arguments:
+    /// * `cb` - A callback for reporting information about the download.  The callback takes the
+    ///   position of the current download and the total length of the download if it is known,
+    ///   in bytes, and returns a future which is awaited before it is called again.  The future
+    ///   may resolve to `ControlFlow::Break(())` to stop the download, which then fails with
+    ///   `Cancelled`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # async fn run(download: &mut tokio_dl_stream_to_disk::AsyncDownload, tx: tokio::sync::mpsc::Sender<u64>) {
+    /// let result = download.download_with_async_callback(|bytes, _total| {
+    ///     let tx = tx.clone();
+    ///     async move {
+    ///         let _ = tx.send(bytes).await;
+    ///     }
+    /// }).await;
+    /// # }
+    /// ```
+    pub async fn download_with_async_callback<F, Fut>(&mut self, mut cb: F) -> Result<DownloadResult, TDSTDError>
+    where
+        F: FnMut(u64, Option<u64>) -> Fut,
+        Fut: Future,
+        Fut::Output: ProgressControl,
+    {
+        let (parent, abort) = self.abortable();
+        let (tx, mut rx) = tokio::sync::watch::channel(None);
+        let download = async {
+            let tx = tx;
+            let mut progress = |event| {
+                if let DownloadEvent::Chunk { bytes, total, .. } = event {
+                    tx.send_replace(Some((bytes, total)));
                 }
-                if num_bytes > 0 {
-                    dest.write(&mut buf[0..num_bytes]).await?;
-                } else {
-                    break;
+            };
+            self.download_with_events(&mut progress).await
+        };
+        let report = async {
+            while rx.changed().await.is_ok() {
+                let progress = *rx.borrow_and_update();
+                if let Some((bytes, total)) = progress {
+                    if cb(bytes, total).await.into_control_flow().is_break() {
+                        abort.cancel();
+                    }
                 }
             }
-            Ok(())
+        };
+        let (result, ()) = tokio::join!(download, report);
+        self.config.cancellation_token = parent;
+        result
+    }
+
+    /// Replaces the cancellation token with a child of it, which a callback can cancel to stop the
+    /// download, returning the original token to be restored afterwards along with the child.
+    fn abortable(&mut self) -> (Option<CancellationToken>, CancellationToken) {
+        let parent = self.config.cancellation_token.take();
+        let abort = parent.as_ref().map_or_else(CancellationToken::new, CancellationToken::child_token);
+        self.config.cancellation_token = Some(abort.clone());
+        (parent, abort)
+    }
+
+    /// Initiate the download, returning a stream of events reporting its progress.  The download
+    /// only makes progress while the stream is polled, and the stream ends after yielding either
+    /// `DownloadEvent::Finished` or `DownloadEvent::Failed`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::path::Path;
+    /// use futures_util::StreamExt;
+    /// use tokio_dl_stream_to_disk::{AsyncDownload, DownloadEvent};
+    ///
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let mut download = AsyncDownload::new("https://bit.ly/3yWXSOW", Path::new("/tmp"), "5mb_test.bin")?;
+    /// let mut events = download.download_events();
+    /// while let Some(event) = events.next().await {
+    ///     if let DownloadEvent::Chunk { bytes, total: Some(total), speed, .. } = event {
+    ///         println!("{}% at {:.0} bytes/s", bytes * 100 / total, speed);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn download_events(&mut self) -> impl Stream<Item = DownloadEvent> + Unpin + '_ {
+        use futures_util::{future, stream, FutureExt};
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let download = async move {
+            let mut send = |event| {
+                let _ = tx.send(event);
+            };
+            let result = self.download_with_events(&mut send).await;
+            send(match result {
+                Ok(result) => DownloadEvent::Finished(result),
+                Err(err) => DownloadEvent::Failed(err),
+            });
+        };
+        stream::select(
+            stream::poll_fn(move |cx| rx.poll_recv(cx)),
+            Box::pin(download).into_stream().filter_map(|()| future::ready(None)),
+        )
+    }
+
+    /// Initiate the download, returning a handle which can pause and resume it and report its
+    /// progress, along with the download itself.  The download only makes progress while the
+    /// returned future is polled, and the handle can be sent to other tasks to control it.
+    /// Time spent paused counts towards the total timeout.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let mut download = AsyncDownload::new("https://bit.ly/3yWXSOW", Path::new("/tmp"), "5mb_test.bin")?;
+    /// let (handle, download) = download.start();
+    /// tokio::spawn(async move {
+    ///     handle.pause();
+    ///     tokio::time::sleep(Duration::from_secs(5)).await;
+    ///     handle.resume();
+    /// });
+    /// let result = download.await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn start(&mut self) -> (DownloadHandle, impl Future<Output = Result<DownloadResult, TDSTDError>> + '_) {
+        let control = std::sync::Arc::new(Control::new());
+        self.config.control = Some(control.clone());
+        let handle = DownloadHandle::new(control.clone());
+        let download = async move {
+            let result = self.download_with_events(&mut |event| control.record(&event)).await;
+            self.config.control = None;
+            result
+        };
+        (handle, download)
+    }
+
+    /// Resume an interrupted download and return a result summarizing it.  Specify an optional
+    /// callback.
+    ///
+    /// If a partial file already exists at the destination (or, for an atomic download, its `.part`
+    /// file exists), a `Range` request is made for the remaining bytes and they are appended to it.
+    /// If the server does not honor the range, the file is truncated and downloaded from the start.
+    /// If no partial file exists, this behaves like [`download`].  Any response stream obtained
+    /// with [`get`] is discarded, since it was not requested with a range.
+    ///
+    /// Arguments:
+    /// * `cb` - An optional callback for reporting information about the download asynchronously.
+    ///   The callback takes the position of the current download, in bytes, including the bytes
+    ///   that were already on disk.
+    pub async fn resume(&mut self, cb: &Option<Box<dyn Fn(u64)>>) -> Result<DownloadResult, TDSTDError> {
+        let started = Instant::now();
+        if self.config.decompresses() {
+            return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig("a decompressed download cannot be resumed")));
+        }
+        if !self.config.transforms.is_empty() {
+            return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig("a transformed download cannot be resumed")));
+        }
+        self.check_dst_dir().await?;
+        self.derive_fname().await?;
+
+        let fname = self.dst_path.join(&self.fname);
+        let partial = if self.config.atomic { self.part_path() } else { fname.clone() };
+        // A segmented download is picked up from the pieces saved as written instead.
+        #[cfg(feature="state")]
+        let segmented = self.load_state(&partial).await?;
+        #[cfg(not(feature="state"))]
+        let segmented = false;
+        let offset = if partial.is_file() && !segmented {
+            tokio::fs::metadata(&partial).await?.len()
         } else {
-            Err(TDSTDError::new(TDSTDErrorKind::DirectoryMissing))
+            0
+        };
+        let bytes_written = self.write_download(Target::File(&fname), offset, &mut position_only(cb), &mut ()).await?;
+        Ok(self.result(started, bytes_written, false))
+    }
+
+    /// Initiate the download, streaming it into `writer` rather than a file, and return a result
+    /// summarizing it.  Specify an optional callback.  The destination directory, filename and
+    /// overwrite behavior are not used, and neither are atomic or segmented downloads.  Transient
+    /// failures are retried by requesting the remaining bytes with a `Range` header; if the server
+    /// does not honor it, the download fails, since the bytes already written cannot be taken
+    /// back.  If an expected checksum was set on the builder, it is verified once everything has
+    /// been written.
+    ///
+    /// Arguments:
+    /// * `writer` - The writer to stream the download into, e.g. a socket, a compressor or a
+    ///   `Vec<u8>`.  It is flushed once the download completes.
+    /// * `cb` - An optional callback for reporting information about the download asynchronously.
+    ///   The callback takes the position of the current download, in bytes.
+    pub async fn download_to_writer<W: AsyncWrite + Unpin>(&mut self, writer: &mut W, cb: &Option<Box<dyn Fn(u64)>>) -> Result<DownloadResult, TDSTDError> {
+        let started = Instant::now();
+        let bytes_written = self.write_download(Target::Writer(writer), 0, &mut position_only(cb), &mut ()).await?;
+        Ok(DownloadResult {
+            path: None,
+            ..self.result(started, bytes_written, false)
+        })
+    }
+
+    /// Initiate the download and return its contents, for small payloads which do not need to be
+    /// written to disk.  Specify an optional callback.  This uses
+    /// [`download_to_writer`](AsyncDownload::download_to_writer), so the same retry and checksum
+    /// options apply.
+    ///
+    /// Arguments:
+    /// * `max_size` - The maximum size of the download in bytes.  If the download is known to be
+    ///   larger, or turns out to be once that many bytes have been received, it fails with
+    ///   `ErrorKind::TooLarge`.
+    /// * `cb` - An optional callback for reporting information about the download asynchronously.
+    ///   The callback takes the position of the current download, in bytes.
+    pub async fn download_to_memory(&mut self, max_size: u64, cb: &Option<Box<dyn Fn(u64)>>) -> Result<Bytes, TDSTDError> {
+        let too_large = || TDSTDError::new(TDSTDErrorKind::TooLarge { limit: max_size });
+        if self.length.is_some_and(|length| length > max_size) {
+            return Err(too_large());
+        }
+        let mut buffer = Limited {
+            buf: BytesMut::new(),
+            limit: max_size,
+        };
+        match self.download_to_writer(&mut buffer, cb).await {
+            Ok(_) => Ok(buffer.buf.freeze()),
+            Err(err) => match err.kind() {
+                TDSTDErrorKind::IO(io) if io.kind() == std::io::ErrorKind::FileTooLarge => Err(too_large()),
+                _ => Err(err),
+            },
         }
     }
 
     #[cfg(feature="sha256sum")]
     /// Initiate the download and return a result with the sha256sum of the download contents.
-    /// Specify an optional callback.
+    /// Specify an optional callback.  If an expected sha256sum was set on the builder, it is also
+    /// verified.  If the download is skipped because the file already exists, the sha256sum of the
+    /// existing file is returned.
+    ///
+    /// Arguments:
+    /// * `cb` - An optional callback for reporting information about the download asynchronously.
+    ///   The callback takes the position of the current download, in bytes.
+    pub async fn download_and_return_sha256sum(&mut self, cb: &Option<Box<dyn Fn(u64)>>) -> Result<Vec<u8>, TDSTDError> {
+        self.download_and_return_hash::<Sha256>(cb).await
+    }
+
+    #[cfg(feature="digest")]
+    /// Initiate the download and return a result with the digest of the download contents, using
+    /// any hash function implementing `digest::Digest`.  Specify an optional callback.  If an
+    /// expected checksum was set on the builder, it is also verified.  If the download is skipped
+    /// because the file already exists, the digest of the existing file is returned.
     ///
     /// Arguments:
     /// * `cb` - An optional callback for reporting information about the download asynchronously.
-    /// The callback takes the position of the current download, in bytes.
-    pub async fn download_and_return_sha256sum(&mut self, cb: &Option<Box<dyn Fn(u64) -> ()>>) -> Result<Vec<u8>, TDSTDError> {
-        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    ///   The callback takes the position of the current download, in bytes.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "sha256sum")]
+    /// # async fn run(download: &mut tokio_dl_stream_to_disk::AsyncDownload) -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let sha512sum = download.download_and_return_hash::<sha2::Sha512>(&None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_and_return_hash<D: Digest>(&mut self, cb: &Option<Box<dyn Fn(u64)>>) -> Result<Vec<u8>, TDSTDError> {
+        let mut hasher = Hashing(D::new());
+        match self.destination().await? {
+            Some(fname) => {
+                self.write_download(Target::File(&fname), 0, &mut position_only(cb), &mut hasher).await?;
+            }
+            None => inspect_file(&self.dst_path.join(&self.fname), &mut hasher).await?,
+        }
+        Ok(hasher.0.finalize().to_vec())
+    }
 
-        let fname = self.dst_path.join(self.fname.clone());
-        if fname.is_file() {
-            return Err(TDSTDError::new(TDSTDErrorKind::FileExists));
+    /// Returns the path the download is written to.  If the download was renamed because of
+    /// `OverwriteBehavior::RenameWithSuffix`, this is the renamed path.
+    pub fn path(&self) -> PathBuf {
+        self.dst_path.join(&self.fname)
+    }
+
+    /// Checks that the destination directory exists, creating it and any temporary directory if
+    /// `create_dirs` was set.
+    async fn check_dst_dir(&self) -> Result<(), TDSTDError> {
+        if self.config.create_dirs {
+            tokio::fs::create_dir_all(&self.dst_path).await?;
+            if let (true, Some(temp_dir)) = (self.config.atomic, &self.config.temp_dir) {
+                tokio::fs::create_dir_all(temp_dir).await?;
+            }
+        } else if !self.dst_path.is_dir() {
+            return Err(TDSTDError::new(TDSTDErrorKind::DirectoryMissing));
+        }
+        Ok(())
+    }
+
+    /// If no filename was given, makes the request so that one can be derived from the response.
+    async fn derive_fname(&mut self) -> Result<(), TDSTDError> {
+        if self.fname.is_empty() && self.response_stream.is_none() {
+            self.get_non_consumable().await?;
+        }
+        self.check_fname()
+    }
+
+    /// Makes the filename portable if configured, and checks that it stays within the
+    /// destination directory.
+    pub(crate) fn check_fname(&mut self) -> Result<(), TDSTDError> {
+        if self.config.portable_filenames {
+            self.fname = filename::portable(&self.fname);
+        }
+        if !filename::is_valid(&self.fname) {
+            return Err(TDSTDError::new(TDSTDErrorKind::InvalidFilename));
+        }
+        Ok(())
+    }
+
+    /// Checks the destination directory and applies the overwrite behavior, returning the path to
+    /// write the download to, or `None` if it should be skipped.
+    async fn destination(&mut self) -> Result<Option<PathBuf>, TDSTDError> {
+        self.check_dst_dir().await?;
+        self.derive_fname().await?;
+
+        // A templated filename is only known once the download completes.
+        let fname = self.dst_path.join(&self.fname);
+        if !fname.exists() || self.config.filename_template.is_some() {
+            return Ok(Some(fname));
+        }
+        match self.config.overwrite {
+            OverwriteBehavior::Error => Err(TDSTDError::new(TDSTDErrorKind::FileExists)),
+            OverwriteBehavior::Overwrite => Ok(Some(fname)),
+            OverwriteBehavior::Skip => Ok(None),
+            OverwriteBehavior::SkipIfValid => match self.verify_existing().await? {
+                true => Ok(None),
+                false => Ok(Some(fname)),
+            },
+            OverwriteBehavior::RenameWithSuffix => {
+                let mut suffix = 1;
+                loop {
+                    let candidate = filename::with_suffix(&self.fname, &format!(".{}", suffix));
+                    if !self.dst_path.join(&candidate).exists() {
+                        self.fname = candidate;
+                        return Ok(Some(self.dst_path.join(&self.fname)));
+                    }
+                    suffix += 1;
+                }
+            }
+        }
+    }
+
+    /// Initiate the download, reporting its progress to `events`.
+    async fn download_with_events(&mut self, events: &mut dyn FnMut(DownloadEvent)) -> Result<DownloadResult, TDSTDError> {
+        let started = Instant::now();
+        if self.check_not_modified().await? {
+            return Ok(DownloadResult {
+                not_modified: true,
+                ..self.result(started, 0, false)
+            });
+        }
+        match self.destination().await? {
+            Some(fname) => {
+                let bytes_written = self.write_download(Target::File(&fname), 0, events, &mut ()).await?;
+                self.store_validators().await?;
+                Ok(self.result(started, bytes_written, false))
+            }
+            None => Ok(self.result(started, 0, true)),
+        }
+    }
+
+    fn result(&mut self, started: Instant, bytes_written: u64, skipped: bool) -> DownloadResult {
+        DownloadResult {
+            path: Some(self.path()),
+            bytes_written,
+            elapsed: started.elapsed(),
+            skipped,
+            not_modified: false,
+            response: self.response.clone().map(Box::new),
+            corrupt_attempts: std::mem::take(&mut self.corrupt_attempts),
         }
+    }
 
-        if self.dst_path.is_dir() {
-            let mut http_async_reader = StreamReader::new(self.response_stream.take().unwrap());
+    /// Returns the path of the `.part` file used by atomic downloads.
+    fn part_path(&self) -> PathBuf {
+        let part = filename::with_suffix(&self.fname, ".part");
+        match self.config.temp_dir {
+            Some(ref temp_dir) => temp_dir.join(part),
+            None => self.dst_path.join(part),
+        }
+    }
 
-            let mut dest = tokio::fs::File::create(fname).await?;
-            let mut buf = [0; 8 * 1024];
-            let mut num_bytes_total = 0;
-            let mut hasher = Sha256::new();
+    /// Writes the download to `target`, going through the `.part` file if the download is atomic
+    /// and written to a file, and returns the number of bytes written.  A file which fails
+    /// verification is removed or quarantined, and downloaded again from the start if
+    /// `retry_on_mismatch` allows.
+    async fn write_download(
+        &mut self,
+        target: Target<'_>,
+        offset: u64,
+        events: &mut dyn FnMut(DownloadEvent),
+        inspect: &mut impl Inspect,
+    ) -> Result<u64, TDSTDError> {
+        // Positions restart from the offset of each response, so count the bytes between them.
+        let mut bytes_written = 0;
+        let mut last = 0;
+        let mut meter = Meter::new();
+        let mut filter = ProgressFilter::new(self.config.progress_interval, self.config.progress_bytes);
+        // The partial file is only removed once writing to it has started, so that an existing
+        // file is left alone if the request fails.
+        let partial = match target {
+            Target::File(_) if self.config.cleanup != CleanupPolicy::RemoveOnError => None,
+            Target::File(_) if self.config.atomic => Some(self.part_path()),
+            Target::File(fname) => Some(fname.to_path_buf()),
+            Target::Writer(_) => None,
+        };
+        let mut cleanup = RemoveOnDrop(None);
+        let started = Instant::now();
+        #[cfg(feature="tracing")]
+        let span = trace::download_span(&self.url, target.path());
+        let metrics = self.config.metrics.clone();
+        let mut filtered = |mut event: DownloadEvent| {
+            meter.measure(&mut event);
+            #[cfg(feature="tracing")]
+            trace::record(&event);
+            match event {
+                DownloadEvent::Started { offset, .. } => {
+                    last = offset;
+                    cleanup.0.clone_from(&partial);
+                }
+                DownloadEvent::Chunk { bytes, .. } => {
+                    bytes_written += bytes - last;
+                    if let Some(ref metrics) = metrics {
+                        metrics.0.bytes_downloaded(bytes - last);
+                    }
+                    last = bytes;
+                }
+                DownloadEvent::Retrying { ref error, .. } => {
+                    if let Some(ref metrics) = metrics {
+                        metrics.0.retried(error);
+                    }
+                }
+                _ => (),
+            }
+            filter.filter(event, events);
+        };
+        let sync = self.config.sync;
+        #[cfg(feature="state")]
+        let is_file = matches!(target, Target::File(_));
+        self.corrupt_attempts.clear();
+        let mut renamed = None;
+        let result = async {
+            let Target::File(fname) = target else {
+                return self.transfer_verified(target, offset, &mut filtered, inspect).await;
+            };
+            let mut offset = offset;
             loop {
-                let num_bytes = http_async_reader.read(&mut buf).await?;
-                if let Some(ref cb) = cb {
-                    num_bytes_total += num_bytes;
-                    cb(num_bytes_total.try_into().unwrap());
-                }
-                if num_bytes > 0 {
-                    dest.write(&mut buf[0..num_bytes]).await?;
-                    hasher.update(&buf[0..num_bytes]);
+                let written = if self.config.atomic { self.part_path() } else { fname.to_path_buf() };
+                let result = async {
+                    let mut fingerprint = Fingerprint::new(&self.config);
+                    if offset > 0 && fingerprint.is_enabled() {
+                        inspect_file(&written, &mut fingerprint).await?;
+                    }
+                    let offload = self.config.offload_hashing && fingerprint.is_enabled();
+                    let mut fingerprint = Offloaded::new(fingerprint, offload, self.config.max_buffered);
+                    self.transfer_verified(Target::File(&written), offset, &mut filtered, &mut (&mut fingerprint, &mut *inspect)).await?;
+                    let sha256 = fingerprint.finish().await.sha256();
+                    // A file cannot be renamed onto another filesystem, so a `.part` file in the
+                    // temporary directory is moved next to the destination first.
+                    let staged = self.dst_path.join(filename::with_suffix(&self.fname, ".part"));
+                    let written = if written.parent() == staged.parent() {
+                        written.clone()
+                    } else {
+                        move_file(&written, &staged).await?;
+                        staged
+                    };
+                    self.apply_remote_time(&written).await?;
+                    if self.config.source_metadata == SourceMetadata::ExtendedAttributes {
+                        self.source(sha256.as_deref()).set_xattrs(&written).await?;
+                    }
+                    #[cfg(target_os="macos")]
+                    if let Some(ref agent) = self.config.quarantine_agent {
+                        self.source(None).set_quarantine(&written, agent).await?;
+                    }
+                    #[cfg(unix)]
+                    set_permissions(&written, &self.config).await?;
+                    sync_file(&written, sync).await?;
+                    let fname = match self.config.filename_template {
+                        Some(ref template) => {
+                            let (name, keep) = self.expand_template(template, sha256.as_deref())?;
+                            let fname = self.dst_path.join(&name);
+                            renamed = Some(name);
+                            if !keep {
+                                tokio::fs::remove_file(&written).await?;
+                                return Ok(());
+                            }
+                            fname
+                        }
+                        None => fname.to_path_buf(),
+                    };
+                    if written != fname {
+                        tokio::fs::rename(&written, &fname).await?;
+                    }
+                    if self.config.source_metadata == SourceMetadata::Sidecar {
+                        self.source(sha256.as_deref()).write_sidecar(&fname).await?;
+                    }
+                    sync_parent(&fname, sync).await?;
+                    Ok::<_, TDSTDError>(())
+                }
+                .await;
+                let error = match result {
+                    Err(error) if is_corrupt(&error) => error,
+                    result => return result,
+                };
+                let quarantined = discard_corrupt(&written, fname, self.config.quarantine).await;
+                if self.corrupt_attempts.len() as u32 >= self.config.mismatch_retries {
+                    return Err(error);
+                }
+                self.corrupt_attempts.push(CorruptAttempt { error: error.to_string(), quarantined });
+                offset = 0;
+            }
+        };
+        #[cfg(feature="tracing")]
+        let result = tracing::Instrument::instrument(result, span.clone());
+        let result = result.await;
+        #[cfg(feature="tracing")]
+        span.in_scope(|| trace::finished(&result, bytes_written, started.elapsed()));
+        if let Some(ref metrics) = self.config.metrics {
+            match result {
+                Ok(()) => metrics.0.download_completed(bytes_written, started.elapsed()),
+                Err(ref error) => metrics.0.download_failed(error, started.elapsed()),
+            }
+        }
+        filter.flush(events);
+        result?;
+        cleanup.0 = None;
+        #[cfg(feature="state")]
+        if is_file {
+            self.remove_state().await?;
+        }
+        if let Some(fname) = renamed {
+            self.fname = fname;
+        }
+        Ok(bytes_written)
+    }
+
+    /// Writes the download with `transfer`, verifying it against the expected checksums if any
+    /// were set.  Any bytes already on disk before `offset` are included in the checksums, but are
+    /// not passed to `inspect`.
+    async fn transfer_verified(
+        &mut self,
+        mut target: Target<'_>,
+        offset: u64,
+        events: &mut dyn FnMut(DownloadEvent),
+        inspect: &mut impl Inspect,
+    ) -> Result<(), TDSTDError> {
+        self.resolve_sources().await.map_err(|failure| failure.error)?;
+        #[cfg(any(feature="minisign", feature="openpgp"))]
+        let signature = match target.path() {
+            Some(_) => self.fetch_signature().await?,
+            None if self.config.signing_key.is_some() => return Err(TDSTDError::new(TDSTDErrorKind::InvalidConfig(
+                "a signature can only be verified for a download to a file",
+            ))),
+            None => None,
+        };
+        #[cfg(feature="sha256sum")]
+        let expected_sha256 = match self.config.expected_sha256 {
+            Some(expected) => Some(expected),
+            None => self.manifest_sha256().await?,
+        };
+        let mut verifiers = Verifiers {
+            #[cfg(feature="digest")]
+            pieces: self.config.piece_hashes.clone().map(PieceVerifier::new),
+            #[cfg(feature="sri")]
+            integrity: self.config.expected_integrity.clone().map(|integrity| {
+                let hasher = integrity.hasher();
+                (integrity, hasher)
+            }),
+            #[cfg(feature="sha256sum")]
+            sha256: expected_sha256.map(|expected| (expected, Hashing(Sha256::new()))),
+            // The announced digest covers the whole response as it is sent, which the bytes seen
+            // do not add up to if the download was resumed, decoded or hashed after transforming
+            // it.
+            #[cfg(feature="content-digest")]
+            header_digest: HeaderDigest::new(
+                !self.config.ignore_content_digest
+                    && offset == 0
+                    && !self.config.content_decoded
+                    && (self.config.transforms.is_empty() || !self.config.hash_transformed),
+            ),
+        };
+        verifiers.inspect_existing(&target, offset).await?;
+        let mut verifiers = Offloaded::new(verifiers, self.config.offload_hashing, self.config.max_buffered);
+        self.transfer(&mut target, offset, events, &mut (&mut verifiers, inspect)).await?;
+        verifiers.finish().await.check()?;
+        #[cfg(any(feature="minisign", feature="openpgp"))]
+        if let (Some(signature), Some(fname)) = (signature, target.path()) {
+            signature.check(fname).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the download to `target`, starting at `offset`, retrying transient failures according
+    /// to the configured retry policy, until it completes, times out or is cancelled.
+    async fn transfer(
+        &mut self,
+        target: &mut Target<'_>,
+        offset: u64,
+        events: &mut dyn FnMut(DownloadEvent),
+        inspect: &mut impl Inspect,
+    ) -> Result<(), TDSTDError> {
+        let token = self.config.cancellation_token.clone();
+        let remove_on_cancel = self.config.cleanup != CleanupPolicy::Keep;
+        let total_timeout = self.config.total_timeout;
+        let fname = target.path().map(Path::to_path_buf);
+        let download = async {
+            if let Target::File(fname) = target {
+                if self.config.segments > 1
+                    && offset == 0
+                    && !self.config.decompresses()
+                    && self.config.transforms.is_empty()
+                    && self.transfer_segmented(fname, events).await?
+                {
+                    inspect.reset();
+                    if let Some(ref response) = self.response {
+                        inspect.response(response);
+                    }
+                    inspect_file(fname, inspect).await?;
+                    return Ok(());
+                }
+            }
+            self.transfer_with_retries(target, offset, events, inspect).await
+        };
+        let download = async {
+            match total_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, download).await
+                    .unwrap_or_else(|_| Err(TDSTDError::new(TDSTDErrorKind::Timeout))),
+                None => download.await,
+            }
+        };
+        tokio::select! {
+            result = download => result,
+            () = cancelled(&token) => {
+                if let (Some(fname), true) = (fname, remove_on_cancel) {
+                    let _ = tokio::fs::remove_file(fname).await;
+                }
+                Err(TDSTDError::new(TDSTDErrorKind::Cancelled))
+            }
+        }
+    }
+
+    async fn transfer_with_retries(
+        &mut self,
+        target: &mut Target<'_>,
+        offset: u64,
+        events: &mut dyn FnMut(DownloadEvent),
+        inspect: &mut impl Inspect,
+    ) -> Result<(), TDSTDError> {
+        let mut pos = offset;
+        let mut retry = 0;
+        loop {
+            let control = self.config.control.clone();
+            let pauses = control.as_ref().map(|control| control.pauses());
+            if let Some(ref control) = control {
+                control.unpaused().await;
+            }
+            let result = self.attempt(target, &mut pos, events, inspect).await;
+            #[cfg(feature="state")]
+            if let (Err(_), Target::File(_)) = (&result, &target) {
+                let _ = self.save_state(pos, None).await;
+            }
+            match result {
+                Ok(()) => return Ok(()),
+                // The connection may have been dropped while the download was paused, which does
+                // not count as a retry.
+                Err(failure) if failure.transient && control.as_ref().map(|control| control.pauses()) != pauses => (),
+                Err(failure) => match failure.retry_delay(&self.config, retry) {
+                    Some(delay) => {
+                        retry += 1;
+                        events(DownloadEvent::Retrying { retry, delay, error: failure.error });
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => return Err(failure.exhausted(retry)),
+                },
+            }
+        }
+    }
+
+    /// Makes a single attempt at writing the download to `target` from `pos`, which is advanced as
+    /// bytes are written.  If `pos` is non-zero, the remaining bytes are requested with a `Range`
+    /// header and appended to the target.
+    async fn attempt(
+        &mut self,
+        target: &mut Target<'_>,
+        pos: &mut u64,
+        events: &mut dyn FnMut(DownloadEvent),
+        inspect: &mut impl Inspect,
+    ) -> Result<(), Failure> {
+        use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE};
+        use reqwest::StatusCode;
+
+        // The offset into a compressed download does not match the length of the decompressed
+        // file, so it is downloaded again from the start.
+        if self.config.decompresses() && matches!(target, Target::File(_)) {
+            *pos = 0;
+        }
+        if *pos == 0 {
+            if self.response_stream.is_none() {
+                self.get_non_consumable().await?;
+            }
+            inspect.reset();
+            if let Some(ref response) = self.response {
+                inspect.response(response);
+            }
+            self.config.transforms.reset();
+            #[cfg(feature="state")]
+            if let Target::File(_) = target {
+                self.discard_state();
+                self.save_state(0, None).await?;
+            }
+            let stream = self.response_stream.take().unwrap();
+            return target.write(stream, pos, self.length, &mut self.config, events, inspect).await;
+        }
+
+        self.response_stream = None;
+        let request = self.request_bytes(*pos, None);
+        // The server sends the whole download instead if it is no longer the one saved.
+        #[cfg(feature="state")]
+        let request = match self.if_range() {
+            Some(if_range) => request.header(reqwest::header::IF_RANGE, if_range),
+            None => request,
+        };
+        let (response, redirects) = self.send(request).await?;
+        self.response = Some(ResponseMetadata::new(&response, redirects));
+        let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok());
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                self.length = self.partial_length(response.headers(), *pos)?;
+                #[cfg(feature="state")]
+                if let Target::File(_) = target {
+                    self.save_state(*pos, None).await?;
+                }
+                target.write(into_stream(response), pos, self.length, &mut self.config, events, inspect).await
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                // The file may already be complete, in which case the server reports the total
+                // length as `bytes */<length>`.
+                let total = header(CONTENT_RANGE)
+                    .and_then(|r| r.strip_prefix("bytes */"))
+                    .and_then(|t| t.parse::<u64>().ok());
+                let total = match self.config.range {
+                    Some((start, end)) => total.map(|total| total.min(end).saturating_sub(start)),
+                    None => total,
+                };
+                if total == Some(*pos) {
+                    self.length = total;
+                    Ok(())
+                } else {
+                    Err(Failure::fatal(TDSTDErrorKind::InvalidResponse))
+                }
+            }
+            status if status.is_success() => {
+                // The server ignored the range, so start again from the beginning, which is only
+                // possible if the target is a file and the whole remote file is downloaded.
+                if matches!(target, Target::Writer(_)) || self.config.range.is_some() {
+                    return Err(Failure::fatal(TDSTDErrorKind::InvalidResponse));
+                }
+                self.length = header(CONTENT_LENGTH).and_then(|l| l.parse::<u64>().ok());
+                *pos = 0;
+                inspect.reset();
+                if let Some(ref response) = self.response {
+                    inspect.response(response);
+                }
+                self.config.transforms.reset();
+                #[cfg(feature="state")]
+                {
+                    self.discard_state();
+                    self.save_state(0, None).await?;
+                }
+                target.write(into_stream(response), pos, self.length, &mut self.config, events, inspect).await
+            }
+            _ => Err(Failure::from_status(response)),
+        }
+    }
+}
+
+/// Adapts a callback which only takes the position of the download to the events reported by
+/// `transfer`.
+fn position_only(cb: &Option<Box<dyn Fn(u64)>>) -> impl FnMut(DownloadEvent) + '_ {
+    move |event| {
+        if let (Some(cb), DownloadEvent::Chunk { bytes, .. }) = (cb, event) {
+            cb(bytes);
+        }
+    }
+}
+
+/// Resolves when the token is cancelled, or never if there is no token.
+async fn cancelled(token: &Option<CancellationToken>) {
+    match token {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Where the download is written: a file, or a writer supplied by the caller.
+enum Target<'a> {
+    File(&'a Path),
+    Writer(&'a mut (dyn AsyncWrite + Unpin)),
+}
+
+impl Target<'_> {
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Target::File(fname) => Some(fname),
+            Target::Writer(_) => None,
+        }
+    }
+
+    /// Writes the response body with `write_stream`, appending to a file if `pos` is non-zero and
+    /// otherwise creating or truncating it.  A file is written through a buffer of the configured
+    /// size.
+    async fn write(
+        &mut self,
+        stream: Box<S>,
+        pos: &mut u64,
+        total: Option<u64>,
+        config: &mut Config,
+        events: &mut dyn FnMut(DownloadEvent),
+        inspect: &mut impl Inspect,
+    ) -> Result<(), Failure> {
+        match self {
+            Target::File(fname) => {
+                let file = if *pos == 0 {
+                    tokio::fs::File::create(fname).await?
                 } else {
-                    break;
+                    tokio::fs::OpenOptions::new().append(true).open(fname).await?
+                };
+                // The length of a compressed or transformed body is not the length of the file.
+                if let (true, true, Some(total)) = (config.preallocate, config.transforms.is_empty() && !config.decompresses(), total) {
+                    preallocate(&file, total).await?;
+                }
+                let capacity = config.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE).min(config.max_buffered.unwrap_or(usize::MAX));
+                let mut dest = tokio::io::BufWriter::with_capacity(capacity, file);
+                #[cfg(feature="decompress")]
+                if let Some(format) = config.decompress {
+                    use tokio::io::AsyncWriteExt;
+
+                    let mut dest = format.decoder(dest);
+                    write_stream(stream, &mut dest, pos, total, config, events, inspect).await?;
+                    return Ok(dest.shutdown().await?);
+                }
+                write_stream(stream, &mut dest, pos, total, config, events, inspect).await
+            }
+            Target::Writer(writer) => write_stream(stream, *writer, pos, total, config, events, inspect).await,
+        }
+    }
+}
+
+/// An in-memory buffer for `download_to_memory`, which refuses writes beyond `limit` bytes.
+struct Limited {
+    buf: BytesMut,
+    limit: u64,
+}
+
+impl AsyncWrite for Limited {
+    fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize, IOError>> {
+        if (self.buf.len() + data.len()) as u64 > self.limit {
+            return Poll::Ready(Err(IOError::from(std::io::ErrorKind::FileTooLarge)));
+        }
+        self.buf.extend_from_slice(data);
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IOError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IOError>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Removes a partially written file when dropped, unless it has been disarmed by clearing the path.
+/// This covers downloads which fail as well as those which are dropped before they finish.
+struct RemoveOnDrop(Option<PathBuf>);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        if let Some(ref fname) = self.0 {
+            let _ = std::fs::remove_file(fname);
+        }
+    }
+}
+
+/// Receives every chunk of the download as it is written to disk, e.g. to hash it.
+trait Inspect {
+    fn update(&mut self, chunk: &[u8]);
+
+    /// Like `update`, for a chunk which can be shared rather than copied if it is inspected on
+    /// another thread.
+    async fn update_shared(&mut self, chunk: &Bytes) {
+        self.update(chunk);
+    }
+
+    /// Called when the download (re)starts from the beginning, discarding anything seen so far.
+    fn reset(&mut self);
+
+    /// Called after `reset` with the response the download starts again from.
+    fn response(&mut self, _response: &ResponseMetadata) {}
+
+    /// Returns an error if what was seen so far cannot be part of a valid download, so that it
+    /// fails without waiting for the rest.
+    fn verify(&mut self) -> Result<(), TDSTDErrorKind> {
+        Ok(())
+    }
+}
+
+impl Inspect for () {
+    fn update(&mut self, _chunk: &[u8]) {}
+
+    fn reset(&mut self) {}
+}
+
+impl<I: Inspect + ?Sized> Inspect for &mut I {
+    fn update(&mut self, chunk: &[u8]) {
+        (**self).update(chunk);
+    }
+
+    async fn update_shared(&mut self, chunk: &Bytes) {
+        (**self).update_shared(chunk).await;
+    }
+
+    fn reset(&mut self) {
+        (**self).reset();
+    }
+
+    fn response(&mut self, response: &ResponseMetadata) {
+        (**self).response(response);
+    }
+
+    fn verify(&mut self) -> Result<(), TDSTDErrorKind> {
+        (**self).verify()
+    }
+}
+
+impl<A: Inspect, B: Inspect> Inspect for (A, B) {
+    fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+        self.1.update(chunk);
+    }
+
+    async fn update_shared(&mut self, chunk: &Bytes) {
+        self.0.update_shared(chunk).await;
+        self.1.update_shared(chunk).await;
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+        self.1.reset();
+    }
+
+    fn response(&mut self, response: &ResponseMetadata) {
+        self.0.response(response);
+        self.1.response(response);
+    }
+
+    fn verify(&mut self) -> Result<(), TDSTDErrorKind> {
+        self.0.verify()?;
+        self.1.verify()
+    }
+}
+
+impl<I: Inspect> Inspect for Option<I> {
+    fn update(&mut self, chunk: &[u8]) {
+        if let Some(inspect) = self {
+            inspect.update(chunk);
+        }
+    }
+
+    async fn update_shared(&mut self, chunk: &Bytes) {
+        if let Some(inspect) = self {
+            inspect.update_shared(chunk).await;
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some(inspect) = self {
+            inspect.reset();
+        }
+    }
+
+    fn response(&mut self, response: &ResponseMetadata) {
+        if let Some(inspect) = self {
+            inspect.response(response);
+        }
+    }
+
+    fn verify(&mut self) -> Result<(), TDSTDErrorKind> {
+        match self {
+            Some(inspect) => inspect.verify(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature="digest")]
+/// Hashes every chunk of the download.
+struct Hashing<D>(D);
+
+#[cfg(feature="digest")]
+impl<D: Digest> Inspect for Hashing<D> {
+    fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    fn reset(&mut self) {
+        self.0 = D::new();
+    }
+}
+
+/// The hashers a download is checked against once it has been written, each with what it is
+/// expected to match.
+struct Verifiers {
+    #[cfg(feature="digest")]
+    pieces: Option<PieceVerifier>,
+    #[cfg(feature="sri")]
+    integrity: Option<(Integrity, IntegrityHasher)>,
+    #[cfg(feature="sha256sum")]
+    sha256: Option<([u8; 32], Hashing<Sha256>)>,
+    #[cfg(feature="content-digest")]
+    header_digest: HeaderDigest,
+}
+
+impl Verifiers {
+    /// Feeds the bytes already on disk before `offset` to the hashers, so that any pieces among
+    /// them are checked before any more are downloaded.
+    async fn inspect_existing(&mut self, target: &Target<'_>, offset: u64) -> Result<(), TDSTDError> {
+        #[cfg(not(any(feature="digest", feature="sri")))]
+        let _ = (target, offset);
+        #[cfg(any(feature="digest", feature="sri"))]
+        if let (Target::File(fname), true) = (target, offset > 0) {
+            inspect_file(fname, self).await?;
+            self.verify().map_err(TDSTDError::new)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the download against every hasher that expects something of it.
+    fn check(self) -> Result<(), TDSTDError> {
+        #[cfg(feature="digest")]
+        if let Some(pieces) = self.pieces {
+            pieces.check().map_err(TDSTDError::new)?;
+        }
+        #[cfg(feature="sha256sum")]
+        if let Some((expected, hasher)) = self.sha256 {
+            check_digest(&expected, &hasher.0.finalize())?;
+        }
+        #[cfg(feature="sri")]
+        if let Some((integrity, hasher)) = self.integrity {
+            integrity.check(hasher)?;
+        }
+        #[cfg(feature="content-digest")]
+        self.header_digest.check()?;
+        Ok(())
+    }
+}
+
+impl Inspect for Verifiers {
+    fn update(&mut self, _chunk: &[u8]) {
+        #[cfg(feature="digest")]
+        self.pieces.update(_chunk);
+        #[cfg(feature="sri")]
+        if let Some((_, ref mut hasher)) = self.integrity {
+            hasher.update(_chunk);
+        }
+        #[cfg(feature="sha256sum")]
+        if let Some((_, ref mut hasher)) = self.sha256 {
+            hasher.update(_chunk);
+        }
+        #[cfg(feature="content-digest")]
+        self.header_digest.update(_chunk);
+    }
+
+    fn reset(&mut self) {
+        #[cfg(feature="digest")]
+        self.pieces.reset();
+        #[cfg(feature="sri")]
+        if let Some((_, ref mut hasher)) = self.integrity {
+            hasher.reset();
+        }
+        #[cfg(feature="sha256sum")]
+        if let Some((_, ref mut hasher)) = self.sha256 {
+            hasher.reset();
+        }
+        #[cfg(feature="content-digest")]
+        self.header_digest.reset();
+    }
+
+    fn response(&mut self, _response: &ResponseMetadata) {
+        #[cfg(feature="content-digest")]
+        self.header_digest.response(_response);
+    }
+
+    fn verify(&mut self) -> Result<(), TDSTDErrorKind> {
+        #[cfg(feature="digest")]
+        self.pieces.verify()?;
+        Ok(())
+    }
+}
+
+/// Hashes the download for a filename template which names it by its contents, or to record
+/// its checksum with its source.
+struct Fingerprint {
+    #[cfg(feature="sha256sum")]
+    sha256: Option<Sha256>,
+}
+
+impl Fingerprint {
+    fn new(config: &Config) -> Self {
+        #[cfg(not(feature="sha256sum"))]
+        let _ = config;
+        Self {
+            #[cfg(feature="sha256sum")]
+            sha256: (config.source_metadata != SourceMetadata::None
+                || config.filename_template.as_ref().is_some_and(|template| template.needs_sha256()))
+                .then(Sha256::new),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        #[cfg(feature="sha256sum")]
+        return self.sha256.is_some();
+        #[cfg(not(feature="sha256sum"))]
+        return false;
+    }
+
+    /// Returns the hex sha256sum of everything seen since the last reset, if enabled.
+    fn sha256(self) -> Option<String> {
+        #[cfg(feature="sha256sum")]
+        return self.sha256
+            .map(|sha256| sha256.finalize().iter().map(|b| format!("{:02x}", b)).collect());
+        #[cfg(not(feature="sha256sum"))]
+        return None;
+    }
+}
+
+impl Inspect for Fingerprint {
+    fn update(&mut self, _chunk: &[u8]) {
+        #[cfg(feature="sha256sum")]
+        if let Some(ref mut sha256) = self.sha256 {
+            sha256.update(_chunk);
+        }
+    }
+
+    fn reset(&mut self) {
+        #[cfg(feature="sha256sum")]
+        if let Some(ref mut sha256) = self.sha256 {
+            *sha256 = Sha256::new();
+        }
+    }
+}
+
+/// Feeds the contents of an existing file to `inspect`.
+async fn inspect_file(fname: &Path, inspect: &mut impl Inspect) -> Result<(), IOError> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(fname).await?;
+    let mut buf = BytesMut::new();
+    loop {
+        buf.reserve(DEFAULT_BUFFER_SIZE);
+        if file.read_buf(&mut buf).await? == 0 {
+            return Ok(());
+        }
+        inspect.update_shared(&buf.split().freeze()).await;
+    }
+}
+
+#[cfg(target_os="linux")]
+/// Reserves disk space for the first `len` bytes of `file` without changing its size.  Filesystems
+/// which do not support this are left alone.
+async fn preallocate(file: &tokio::fs::File, len: u64) -> Result<(), IOError> {
+    use std::os::fd::AsRawFd;
+
+    let len = libc::off_t::try_from(len).map_err(IOError::other)?;
+    let file = file.try_clone().await?.into_std().await;
+    tokio::task::spawn_blocking(move || {
+        // SAFETY: the descriptor is owned by `file`, which outlives the call.
+        let ret = unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) };
+        let err = IOError::last_os_error();
+        match err.raw_os_error() {
+            _ if ret == 0 => Ok(()),
+            Some(libc::EOPNOTSUPP) => Ok(()),
+            _ => Err(err),
+        }
+    })
+    .await
+    .map_err(IOError::other)?
+}
+
+#[cfg(not(target_os="linux"))]
+/// Checks that there is room for the first `len` bytes of `file` by growing it to `len` and then
+/// truncating it back to its size.  Filesystems which allocate the space to grow a file, rather
+/// than leaving a hole, fail straight away if there is not enough.
+async fn preallocate(file: &tokio::fs::File, len: u64) -> Result<(), IOError> {
+    let size = file.metadata().await?.len();
+    if len > size {
+        file.set_len(len).await?;
+        file.set_len(size).await?;
+    }
+    Ok(())
+}
+
+/// Syncs a completed download to disk according to the sync policy.
+async fn sync_file(fname: &Path, sync: SyncPolicy) -> Result<(), IOError> {
+    match sync {
+        SyncPolicy::None => Ok(()),
+        SyncPolicy::Data => tokio::fs::File::open(fname).await?.sync_data().await,
+        SyncPolicy::Full => tokio::fs::File::open(fname).await?.sync_all().await,
+    }
+}
+
+#[cfg(unix)]
+/// Applies the configured owner and permissions to the written file at `fname`.
+async fn set_permissions(fname: &Path, config: &Config) -> Result<(), IOError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if config.uid.is_some() || config.gid.is_some() {
+        std::os::unix::fs::chown(fname, config.uid, config.gid)?;
+    }
+    if let Some(mode) = config.mode {
+        tokio::fs::set_permissions(fname, std::fs::Permissions::from_mode(mode & 0o7777)).await?;
+    }
+    Ok(())
+}
+
+/// Moves the file at `from` to `to`, or if they are on different filesystems, copies it and
+/// syncs the copy before removing the original.
+async fn move_file(from: &Path, to: &Path) -> Result<(), IOError> {
+    match tokio::fs::rename(from, to).await {
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => (),
+        result => return result,
+    }
+    tokio::fs::copy(from, to).await?;
+    tokio::fs::File::open(to).await?.sync_all().await?;
+    tokio::fs::remove_file(from).await
+}
+
+/// Syncs the directory containing a download, so that a newly created or renamed entry is
+/// durable.  Directories cannot be opened for syncing on other platforms, so this only applies to
+/// Unix.
+async fn sync_parent(fname: &Path, sync: SyncPolicy) -> Result<(), IOError> {
+    match (sync, fname.parent()) {
+        (SyncPolicy::Full, Some(dir)) if cfg!(unix) => tokio::fs::File::open(dir).await?.sync_all().await,
+        _ => Ok(()),
+    }
+}
+
+#[cfg(any(feature="sha256sum", feature="content-digest", feature="sri"))]
+/// Compares the digest of a completed download with the expected one.
+fn check_digest(expected: &[u8], actual: &[u8]) -> Result<(), TDSTDError> {
+    if expected == actual {
+        return Ok(());
+    }
+    Err(TDSTDError::new(TDSTDErrorKind::ChecksumMismatch {
+        expected: expected.to_vec(),
+        actual: actual.to_vec(),
+    }))
+}
+
+/// Returns `true` if the download failed verification, in which case what was written cannot be
+/// trusted.
+fn is_corrupt(error: &TDSTDError) -> bool {
+    matches!(
+        error.kind(),
+        TDSTDErrorKind::ChecksumMismatch { .. }
+            | TDSTDErrorKind::PieceMismatch { .. }
+            | TDSTDErrorKind::AuthenticationFailed
+            | TDSTDErrorKind::SignatureInvalid
+    )
+}
+
+/// Gets rid of a download at `written` which failed verification, whatever the cleanup policy.
+/// With `quarantine`, it is moved next to `fname` as `<fname>.corrupt-<timestamp>`, and where it
+/// was moved to is returned, and otherwise, or if it cannot be moved, it is removed.
+async fn discard_corrupt(written: &Path, fname: &Path, quarantine: bool) -> Option<PathBuf> {
+    if quarantine {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let name = format!("{}.corrupt-{}", fname.display(), timestamp);
+        let mut candidate = PathBuf::from(&name);
+        let mut suffix = 1;
+        while candidate.exists() {
+            candidate = PathBuf::from(format!("{}.{}", name, suffix));
+            suffix += 1;
+        }
+        if tokio::fs::rename(written, &candidate).await.is_ok() {
+            return Some(candidate);
+        }
+    }
+    let _ = tokio::fs::remove_file(written).await;
+    None
+}
+
+/// An error from a single attempt at the download, and whether it is worth retrying.
+struct Failure {
+    error: TDSTDError,
+    transient: bool,
+    retry_after: Option<Duration>,
+}
+
+impl Failure {
+    fn fatal(kind: TDSTDErrorKind) -> Self {
+        Failure {
+            error: TDSTDError::new(kind),
+            transient: false,
+            retry_after: None,
+        }
+    }
+
+    fn transient(error: TDSTDError) -> Self {
+        Failure {
+            error,
+            transient: true,
+            retry_after: None,
+        }
+    }
+
+    /// Returns the failure for an unsuccessful response, along with how long the server asked
+    /// for the client to wait before retrying, if it did.
+    fn from_status(response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after = retry::retry_after(&response);
+        let failure = match response.error_for_status() {
+            Err(err) => Failure::from(err),
+            Ok(_) => Failure::fatal(TDSTDErrorKind::HttpStatus(status)),
+        };
+        Failure { retry_after, ..failure }
+    }
+
+    /// Returns how long to wait before retrying after `retry` retries, or `None` if the failure
+    /// should not be retried.  A `Retry-After` sent by the server is used instead of the backoff,
+    /// unless it is longer than the configured maximum.
+    fn retry_delay(&self, config: &Config, retry: u32) -> Option<Duration> {
+        if !self.transient || retry >= config.retries {
+            return None;
+        }
+        match self.retry_after {
+            Some(delay) if delay > config.max_retry_after.unwrap_or(retry::DEFAULT_MAX_RETRY_AFTER) => None,
+            Some(delay) => Some(delay),
+            None => Some(config.backoff.delay(retry)),
+        }
+    }
+
+    /// Returns the error to report once no more retries will be made, after `retries` of them.
+    /// If a transient failure used up the retries, it is wrapped in `TooManyRetries`.
+    fn exhausted(self, retries: u32) -> TDSTDError {
+        if self.transient && retries > 0 {
+            TDSTDError::new(TDSTDErrorKind::TooManyRetries { retries, last: Box::new(self.error) })
+        } else {
+            self.error
+        }
+    }
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(err: reqwest::Error) -> Self {
+        let transient = err.is_timeout()
+            || err.is_connect()
+            || err.is_request()
+            || err.is_body()
+            || err.status().is_some_and(|s| s.is_server_error() || s == reqwest::StatusCode::TOO_MANY_REQUESTS);
+        let error = TDSTDError::from(err);
+        // A policy violation on connecting is reported as a connect error, but will not go away.
+        let transient = transient && !matches!(error.kind(), TDSTDErrorKind::PolicyViolation(_));
+        Failure {
+            error,
+            transient,
+            retry_after: None,
+        }
+    }
+}
+
+impl From<Failure> for TDSTDError {
+    fn from(failure: Failure) -> Self {
+        failure.error
+    }
+}
+
+impl From<IOError> for Failure {
+    fn from(err: IOError) -> Self {
+        Failure {
+            error: err.into(),
+            transient: false,
+            retry_after: None,
+        }
+    }
+}
+
+/// Returns the client used when none is configured.  Redirects are followed by `send` rather than
+/// the client.
+fn default_client() -> reqwest::Client {
+    builder::content_decoding(reqwest::Client::builder(), false)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("the default client could not be built")
+}
+
+impl AsyncDownload {
+    /// Sends the initial request for a download, returning its response and body, or no body if
+    /// the server responded with `304 Not Modified`.
+    async fn open(&self, request: reqwest::RequestBuilder) -> Result<(ResponseMetadata, Option<Box<S>>), Failure> {
+        let (response, redirects) = self.send(request).await?;
+        let parts = ResponseMetadata::new(&response, redirects);
+        if parts.status == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok((parts, None));
+        }
+        if !response.status().is_success() {
+            return Err(Failure::from_status(response));
+        }
+        Ok((parts, Some(into_stream(response))))
+    }
+}
+
+fn into_stream(response: reqwest::Response) -> Box<S> {
+    Box::new(response
+        .bytes_stream()
+        .map(|result| result.map_err(IOError::other)))
+}
+
+/// Parses a `Content-Range` header of the form `bytes <start>-<end>/<total>`, returning the start
+/// offset, the offset of the last byte and the total length, if known.
+fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let total = match total {
+        "*" => None,
+        t => Some(t.parse::<u64>().ok()?),
+    };
+    let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+    (start <= end).then_some((start, end, total))
+}
+
+/// Reads from the network, failing if nothing arrives within the stall timeout.  Both read errors
+/// and stalls are transient failures.
+async fn with_stall_timeout<T>(config: &Config, read: impl Future<Output = Result<T, IOError>>) -> Result<T, Failure> {
+    let result = match config.stall_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, read).await
+            .unwrap_or_else(|_| Err(IOError::from(std::io::ErrorKind::TimedOut))),
+        None => read.await,
+    };
+    result.map_err(|err| Failure::transient(if err.kind() == std::io::ErrorKind::TimedOut {
+        TDSTDError::new(TDSTDErrorKind::Timeout)
+    } else {
+        err.into()
+    }))
+}
+
+/// Reads the next chunk of the response body within the stall timeout, also failing the attempt
+/// once `speed` finds that too little has arrived.
+async fn read_body(stream: &mut S, config: &Config, speed: &mut Option<SpeedCheck>) -> Result<Option<Bytes>, Failure> {
+    let Some(speed) = speed else {
+        return with_stall_timeout(config, async { stream.next().await.transpose() }).await;
+    };
+    loop {
+        let started = Instant::now();
+        let read = with_stall_timeout(config, async { stream.next().await.transpose() });
+        match tokio::time::timeout(speed.remaining(), read).await {
+            Ok(chunk) => {
+                let chunk = chunk?;
+                let len = chunk.as_ref().map_or(0, Bytes::len);
+                speed.record(started.elapsed(), len as u64).map_err(Failure::transient)?;
+                return Ok(chunk);
+            }
+            Err(_) => speed.record(started.elapsed(), 0).map_err(Failure::transient)?,
+        }
+    }
+}
+
+/// The size of the buffered writer in front of a file, unless [`buffer_size`](AsyncDownloadBuilder::buffer_size) is set.
+const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Streams the response body into `dest`, advancing `pos` and reporting it along with the total
+/// length as chunks are written, and flushes it once the body ends.  Errors reading from the
+/// network are considered transient, as is a body which ends before the total length, which
+/// fails with `TruncatedBody`.  Whatever was written is flushed when the body fails as well, so
+/// that a retry resumes after it.
+async fn write_stream(
+    stream: Box<S>,
+    dest: &mut (impl AsyncWrite + Unpin + ?Sized),
+    pos: &mut u64,
+    total: Option<u64>,
+    config: &mut Config,
+    events: &mut dyn FnMut(DownloadEvent),
+    inspect: &mut impl Inspect,
+) -> Result<(), Failure> {
+    use tokio::io::AsyncWriteExt;
+
+    events(DownloadEvent::Started { offset: *pos, total });
+    if let Err(failure) = copy_stream(stream, dest, pos, total, config, events, inspect).await {
+        dest.flush().await?;
+        return Err(failure);
+    }
+    match total {
+        Some(total) if *pos != total => {
+            dest.flush().await?;
+            return Err(Failure {
+                error: TDSTDError::new(TDSTDErrorKind::TruncatedBody { expected: total, actual: *pos }),
+                transient: *pos < total,
+                retry_after: None,
+            });
+        }
+        _ => (),
+    }
+    // The stages only learn that the body has ended once it is known to be complete.
+    if !config.transforms.is_empty() {
+        let output = config.transforms.finish().await?;
+        if config.hash_transformed {
+            inspect.update_shared(&output).await;
+        }
+        dest.write_all(&output).await?;
+    }
+    dest.flush().await?;
+    Ok(())
+}
+
+/// Writes each chunk of the response body into `dest` as it arrives, until the body ends,
+/// advancing `pos` and reporting it along with the total length.  With
+/// [`max_buffered`](AsyncDownloadBuilder::max_buffered), the body is read ahead of the writer
+/// instead, through a queue of at most that many bytes.
+async fn copy_stream(
+    mut stream: Box<S>,
+    dest: &mut (impl AsyncWrite + Unpin + ?Sized),
+    pos: &mut u64,
+    total: Option<u64>,
+    config: &mut Config,
+    events: &mut dyn FnMut(DownloadEvent),
+    inspect: &mut impl Inspect,
+) -> Result<(), Failure> {
+    use std::sync::Arc;
+
+    use futures_util::future::{self, Either};
+    use tokio::sync::Semaphore;
+
+    // The writer needs the stages while the reader needs the rest of the configuration.
+    let mut transforms = std::mem::take(&mut config.transforms);
+    let result = {
+        let config = &*config;
+        let mut writer = Writer { dest, pos, total, transforms: &mut transforms, config, events, inspect };
+        let mut speed = config.minimum_speed.map(SpeedCheck::new);
+        match config.max_buffered {
+            None => async {
+                while let Some(chunk) = read_chunk(&mut stream, config, &mut speed).await? {
+                    writer.write(chunk).await?;
                 }
+                Ok(())
             }
-            Ok(hasher.finalize().to_vec())
+            .await,
+            Some(max_buffered) => {
+                let (queue, mut chunks) = tokio::sync::mpsc::unbounded_channel();
+                let budget = Arc::new(Semaphore::new(max_buffered));
+                // The queue is closed once the reader is done with it.
+                let read = std::pin::pin!(async move {
+                    while let Some(chunk) = read_chunk(&mut stream, config, &mut speed).await? {
+                        // A chunk larger than a permit count fills the budget, which is larger
+                        // still.
+                        let permits = u32::try_from(chunk.len().min(max_buffered)).unwrap_or(u32::MAX);
+                        let permit = Arc::clone(&budget).acquire_many_owned(permits).await.ok();
+                        if queue.send((chunk, permit)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                });
+                let write = std::pin::pin!(async {
+                    while let Some((chunk, _permit)) = chunks.recv().await {
+                        writer.write(chunk).await?;
+                    }
+                    Ok(())
+                });
+                // Whatever was read before the body failed is still written, so that a retry
+                // resumes after it, but if writing fails there is no point reading any more.
+                match future::select(read, write).await {
+                    Either::Left((read, write)) => write.await.and(read),
+                    Either::Right((write, _)) => write,
+                }
+            }
+        }
+    };
+    config.transforms = transforms;
+    result
+}
+
+/// Reads the next chunk of the response body, once the download is not paused and within the
+/// rate limit, or returns `None` once the body ends.
+async fn read_chunk(stream: &mut Box<S>, config: &Config, speed: &mut Option<SpeedCheck>) -> Result<Option<Bytes>, Failure> {
+    loop {
+        if let Some(ref control) = config.control {
+            if !control.unpaused_within(PAUSE_KEEP_CONNECTION).await {
+                return Err(Failure::transient(TDSTDError::new(TDSTDErrorKind::Timeout)));
+            }
+        }
+        let chunk = read_body(stream, config, speed).await?;
+        match chunk {
+            Some(chunk) if chunk.is_empty() => (),
+            Some(chunk) => {
+                throttle::throttle(config, chunk.len() as u64).await;
+                return Ok(Some(chunk));
+            }
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Passes the chunks of the response body through the transforms and `inspect` into `dest`.
+struct Writer<'a, W: ?Sized, I> {
+    dest: &'a mut W,
+    pos: &'a mut u64,
+    total: Option<u64>,
+    transforms: &'a mut transform::Transforms,
+    config: &'a Config,
+    events: &'a mut dyn FnMut(DownloadEvent),
+    inspect: &'a mut I,
+}
+
+impl<W: AsyncWrite + Unpin + ?Sized, I: Inspect> Writer<'_, W, I> {
+    async fn write(&mut self, chunk: Bytes) -> Result<(), Failure> {
+        use tokio::io::AsyncWriteExt;
+
+        let len = chunk.len() as u64;
+        if self.transforms.is_empty() {
+            self.inspect.update_shared(&chunk).await;
+            self.dest.write_all(&chunk).await?;
         } else {
-            Err(TDSTDError::new(TDSTDErrorKind::DirectoryMissing))
+            if !self.config.hash_transformed {
+                self.inspect.update_shared(&chunk).await;
+            }
+            let output = self.transforms.apply(chunk).await?;
+            if self.config.hash_transformed {
+                self.inspect.update_shared(&output).await;
+            }
+            self.dest.write_all(&output).await?;
         }
+        self.inspect.verify().map_err(Failure::fatal)?;
+        *self.pos += len;
+        (self.events)(DownloadEvent::chunk(*self.pos, self.total));
+        Ok(())
     }
 }
@@ -0,0 +1,188 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::stream;
+use percent_encoding::percent_decode_str;
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LAST_MODIFIED, RANGE};
+use reqwest::{Method, StatusCode, Url};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::backend::{self, Body, Response};
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::{date, Failure};
+
+/// How much of a local file is read at a time.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Answers a request for a `file` or `data` URL as a server would, including a `Range` of the
+/// form downloads request, so that such URLs are written, resumed and verified like any other.
+/// A file which does not exist is `404 Not Found`.
+pub(crate) async fn execute(request: &reqwest::Request) -> Result<reqwest::Response, Failure> {
+    let url = request.url();
+    let range = range(request);
+    let head = request.method() == Method::HEAD;
+    let response = match url.scheme() {
+        "file" => file(url, range, head).await?,
+        _ => data(url, range, head)?,
+    };
+    backend::into_response(url.clone(), response)
+}
+
+async fn file(url: &Url, range: Option<(u64, Option<u64>)>, head: bool) -> Result<Response<Body>, Failure> {
+    let path = url.to_file_path().map_err(|()| Failure::fatal(TDSTDErrorKind::InvalidUrl))?;
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == IOErrorKind::NotFound => return Ok(status(StatusCode::NOT_FOUND)),
+        Err(err) if err.kind() == IOErrorKind::PermissionDenied => return Ok(status(StatusCode::FORBIDDEN)),
+        Err(err) => return Err(err.into()),
+    };
+    let metadata = file.metadata().await?;
+    if metadata.is_dir() {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+    let (mut builder, range) = partial(range, metadata.len());
+    if let Ok(modified) = metadata.modified() {
+        builder = builder.header(LAST_MODIFIED, date::format_http_date(modified));
+    }
+    let body = match head {
+        true => Box::pin(stream::empty()),
+        false => read(file, range).await?,
+    };
+    builder.body(body).map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))
+}
+
+/// Returns a body which reads `range` of `file`.
+pub(crate) async fn read(mut file: tokio::fs::File, range: Range<u64>) -> Result<Body, IOError> {
+    if range.is_empty() {
+        return Ok(Box::pin(stream::empty()));
+    }
+    file.seek(std::io::SeekFrom::Start(range.start)).await?;
+    let file = file.take(range.end - range.start);
+    Ok(Box::pin(stream::unfold((file, BytesMut::new()), |(mut file, mut buf)| async move {
+        buf.reserve(CHUNK_LEN);
+        match file.read_buf(&mut buf).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(buf.split().freeze()), (file, buf))),
+            Err(err) => Some((Err(err.into()), (file, buf))),
+        }
+    })))
+}
+
+/// Decodes a `data` URL, such as `data:text/plain;base64,SGVsbG8=`.
+fn data(url: &Url, range: Option<(u64, Option<u64>)>, head: bool) -> Result<Response<Body>, Failure> {
+    let invalid = || Failure::fatal(TDSTDErrorKind::InvalidUrl);
+    let url = url.as_str().strip_prefix("data:").ok_or_else(invalid)?;
+    let url = url.split('#').next().unwrap_or_default();
+    let (mediatype, data) = url.split_once(',').ok_or_else(invalid)?;
+    let mediatype = mediatype.trim();
+    let data: Vec<u8> = percent_decode_str(data).collect();
+    let (mediatype, data) = match mediatype.strip_suffix(";base64") {
+        Some(mediatype) => (mediatype, decode_base64(&data).ok_or_else(invalid)?),
+        None => (mediatype, data),
+    };
+    let content_type = match mediatype {
+        "" => String::from("text/plain;charset=US-ASCII"),
+        m if m.starts_with(';') => format!("text/plain{}", m),
+        m => String::from(m),
+    };
+    let (builder, range) = partial(range, data.len() as u64);
+    let chunk = Bytes::from(data).slice(range.start as usize..range.end as usize);
+    let body: Body = match head || chunk.is_empty() {
+        true => Box::pin(stream::empty()),
+        false => Box::pin(stream::iter([Ok(chunk)])),
+    };
+    builder.header(CONTENT_TYPE, content_type)
+        .body(body)
+        .map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))
+}
+
+/// Returns a response with `status` and no body.
+pub(crate) fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Box::pin(stream::empty()) as Body);
+    *response.status_mut() = status;
+    response
+}
+
+/// Returns the head of a response to a request for `len` bytes, and the bytes its body holds.
+pub(crate) fn partial(range: Option<(u64, Option<u64>)>, len: u64) -> (http::response::Builder, Range<u64>) {
+    let builder = Response::builder().header(ACCEPT_RANGES, "bytes");
+    match range {
+        None => (builder.header(CONTENT_LENGTH, len), 0..len),
+        Some((start, _)) if start >= len => {
+            let builder = builder.status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(CONTENT_RANGE, format!("bytes */{}", len));
+            (builder, 0..0)
+        }
+        Some((start, end)) => {
+            let end = end.map_or(len, |end| end.saturating_add(1).min(len));
+            let builder = builder.status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end - 1, len))
+                .header(CONTENT_LENGTH, end - start);
+            (builder, start..end)
+        }
+    }
+}
+
+/// Returns the range of bytes `request` asks for, if it has a `Range` header downloads make.
+pub(crate) fn range(request: &reqwest::Request) -> Option<(u64, Option<u64>)> {
+    request.headers()
+        .get(RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_range)
+}
+
+/// Parses a `Range` of the form `bytes=<start>-` or `bytes=<start>-<end>`.
+fn parse_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse::<u64>().ok()?;
+    let end = match end {
+        "" => None,
+        end => Some(end.parse::<u64>().ok()?).filter(|&end| end >= start),
+    };
+    Some((start, end))
+}
+
+/// Decodes standard base64, ignoring whitespace and allowing the padding to be left out.
+pub(crate) fn decode_base64(input: &[u8]) -> Option<Vec<u8>> {
+    let input: Vec<u8> = input.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+    let input = input.strip_suffix(b"==").or_else(|| input.strip_suffix(b"=")).unwrap_or(&input);
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(input.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for &b in input {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6 | u32::from(value)) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((acc >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Encodes `input` as standard base64, with padding.
+pub(crate) fn encode_base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let acc = chunk.iter().fold(0u32, |acc, &b| acc << 8 | u32::from(b)) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(char::from(ALPHABET[(acc >> (18 - 6 * i) & 0x3f) as usize])),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,776 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::stream::{FuturesUnordered, Stream, StreamExt};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::metrics::{Metrics, SharedMetrics};
+use crate::throttle::Budget;
+use crate::{AsyncDownload, DownloadEvent, DownloadResult, ProgressCallback};
+
+/// The DownloadManager struct allows you to run many downloads concurrently, sharing one
+/// `reqwest::Client` between them.
+///
+/// Downloads wait in a queue until there is room to start them.  Those of a higher
+/// [`Priority`] start first, and those of the same priority in the [`QueueOrder`] they were
+/// added in.  With [`per_host`](DownloadManager::per_host), a download whose host already has
+/// as many downloads running is passed over for the next one.  A download which has not
+/// started yet can be given another priority, or cancelled, through a [`QueueHandle`], which
+/// can also [shut the manager down](QueueHandle::shutdown) while it runs.
+///
+/// The manager can share a bandwidth [`Budget`] between all of its downloads, so that each
+/// download which is running gets a fair share of it.  Groups of downloads are given budgets of
+/// their own with [`AsyncDownloadBuilder::budget`](crate::AsyncDownloadBuilder::budget).
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::path::Path;
+/// use tokio_dl_stream_to_disk::{AsyncDownload, DownloadManager};
+///
+/// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+/// let mut manager = DownloadManager::new(4);
+/// for i in 0..10 {
+///     let fname = format!("{}.bin", i);
+///     manager.add(AsyncDownload::new(&format!("https://example.com/{}", fname), Path::new("/tmp"), &fname)?);
+/// }
+/// let results = manager.run(&Some(Box::new(|bytes, total| {
+///     println!("{} of {:?} bytes", bytes, total);
+/// }))).await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DownloadManager {
+    client: reqwest::Client,
+    /// Whether `client` was provided, rather than one which leaves redirects and decoding to the
+    /// downloads.
+    provided_client: bool,
+    concurrency: usize,
+    per_host: Option<usize>,
+    order: QueueOrder,
+    metrics: Option<SharedMetrics>,
+    budget: Option<Budget>,
+    downloads: Vec<AsyncDownload>,
+    /// The result and final progress of each download which has completed, which later runs
+    /// return rather than downloading it again.
+    completed: Vec<Option<(DownloadResult, ItemProgress)>>,
+    shared: Arc<Shared>,
+}
+
+/// How soon a queued download starts, relative to the other downloads of a [`DownloadManager`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Start after every other download.
+    Low,
+    /// The priority of downloads added with [`add`](DownloadManager::add).
+    #[default]
+    Normal,
+    /// Start before every other download.
+    High,
+}
+
+/// The order in which queued downloads of the same [`Priority`] start.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueueOrder {
+    /// The download which was added first starts first.
+    #[default]
+    Fifo,
+    /// The download which was added last starts first.
+    Lifo,
+}
+
+/// How [`QueueHandle::shutdown`] stops the downloads of a [`DownloadManager`] which are
+/// running.  Either way, no more downloads start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shutdown {
+    /// Let the downloads which are running finish, and cancel those which are still running
+    /// after `timeout`.
+    Graceful { timeout: Duration },
+    /// Cancel the downloads which are running right away.
+    Abort,
+}
+
+/// A handle to the queue of a [`DownloadManager`], which can reprioritize or cancel downloads
+/// before they start, including while [`run`](DownloadManager::run) is in progress.  Downloads
+/// are identified by the index [`add`](DownloadManager::add) returned.  Cloning a QueueHandle
+/// returns a handle to the same queue, and handles can be sent to other tasks.
+#[derive(Clone, Debug)]
+pub struct QueueHandle {
+    shared: Arc<Shared>,
+}
+
+/// What [`DownloadManager::run_events`] yields.
+#[derive(Debug)]
+pub enum BatchEvent {
+    /// The progress of the batch has changed.
+    Progress(BatchProgress),
+    /// Every download has ended, with the result of each in the order they were added.
+    Finished(Vec<Result<DownloadResult, TDSTDError>>),
+}
+
+/// A snapshot of the progress of every download of a [`DownloadManager`], which is enough to
+/// render a single bar for the whole batch along with one for each download.
+#[derive(Clone, Debug, Default)]
+pub struct BatchProgress {
+    items: Vec<ItemProgress>,
+}
+
+/// The progress of one download of a batch.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ItemProgress {
+    bytes: u64,
+    total: Option<u64>,
+    speed: f64,
+    state: ItemState,
+}
+
+/// Where a download of a batch is up to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ItemState {
+    /// Waiting in the queue to start.
+    #[default]
+    Pending,
+    /// Downloading.
+    Running,
+    /// Finished successfully.
+    Completed,
+    /// Failed.
+    Failed,
+    /// Cancelled, before it started or when the manager was shut down.
+    Cancelled,
+}
+
+impl BatchProgress {
+    /// Returns the number of bytes downloaded so far across the downloads which have not failed
+    /// or been cancelled.
+    pub fn bytes(&self) -> u64 {
+        self.counted().map(|item| item.bytes).sum()
+    }
+
+    /// Returns the total length of the downloads which have not failed or been cancelled, once
+    /// it is known for every one of them.
+    pub fn total(&self) -> Option<u64> {
+        self.counted().map(|item| item.total).sum()
+    }
+
+    /// Returns the combined transfer rate of the running downloads, in bytes per second.
+    pub fn speed(&self) -> f64 {
+        self.items.iter().map(|item| item.speed).sum()
+    }
+
+    /// Returns the number of downloads which finished successfully.
+    pub fn completed(&self) -> usize {
+        self.count(ItemState::Completed)
+    }
+
+    /// Returns the number of downloads which failed.
+    pub fn failed(&self) -> usize {
+        self.count(ItemState::Failed)
+    }
+
+    /// Returns the number of downloads which were cancelled, before they started or when the
+    /// manager was shut down.
+    pub fn cancelled(&self) -> usize {
+        self.count(ItemState::Cancelled)
+    }
+
+    /// Returns the number of downloads which are running.
+    pub fn running(&self) -> usize {
+        self.count(ItemState::Running)
+    }
+
+    /// Returns the number of downloads which are waiting to start.
+    pub fn pending(&self) -> usize {
+        self.count(ItemState::Pending)
+    }
+
+    /// Returns the progress of each download, in the order they were added.
+    pub fn items(&self) -> &[ItemProgress] {
+        &self.items
+    }
+
+    fn counted(&self) -> impl Iterator<Item = &ItemProgress> {
+        self.items.iter().filter(|item| !matches!(item.state, ItemState::Failed | ItemState::Cancelled))
+    }
+
+    fn count(&self, state: ItemState) -> usize {
+        self.items.iter().filter(|item| item.state == state).count()
+    }
+}
+
+impl ItemProgress {
+    /// Returns the position of the download, in bytes.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Returns the length of the download, if known.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Returns the smoothed transfer rate of the download while it runs, in bytes per second.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Returns where the download is up to.
+    pub fn state(&self) -> ItemState {
+        self.state
+    }
+}
+
+/// What a [`DownloadManager`] shares with its [`QueueHandle`]s.
+#[derive(Debug, Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    /// The number of downloads which are running.
+    running: watch::Sender<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    entries: Vec<Entry>,
+    /// Whether the manager has been shut down, so that no more downloads start until the run
+    /// ends.
+    shut_down: bool,
+    /// Cancelled to stop the downloads which are running when the manager is shut down, and
+    /// replaced once the run ends.
+    abort: CancellationToken,
+}
+
+/// The place of a download in the queue.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    priority: Priority,
+    state: State,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Queued,
+    Started,
+    Completed,
+    Cancelled,
+}
+
+impl DownloadManager {
+    /// Returns a DownloadManager which runs at most `concurrency` downloads at a time.
+    pub fn new(concurrency: usize) -> Self {
+        Self { provided_client: false, ..Self::with_client(crate::default_client(), concurrency) }
+    }
+
+    /// Returns a DownloadManager like [`new`](DownloadManager::new), which shares the provided
+    /// `reqwest::Client` between its downloads.  Like
+    /// [`AsyncDownload::with_client`](crate::AsyncDownload::with_client), the downloads given it
+    /// leave redirects and decoding to it.
+    pub fn with_client(client: reqwest::Client, concurrency: usize) -> Self {
+        Self {
+            client,
+            provided_client: true,
+            concurrency: concurrency.max(1),
+            per_host: None,
+            order: QueueOrder::default(),
+            metrics: None,
+            budget: None,
+            downloads: Vec::new(),
+            completed: Vec::new(),
+            shared: Arc::default(),
+        }
+    }
+
+    /// Run at most `limit` downloads from the same host at a time, so that no server is sent
+    /// more than its share of connections.  Downloads of URLs without a host, such as `file`
+    /// URLs, are not limited.  Defaults to the concurrency of the manager.
+    pub fn per_host(mut self, limit: usize) -> Self {
+        self.per_host = Some(limit.max(1));
+        self
+    }
+
+    /// Set the order in which downloads of the same priority start.  Defaults to
+    /// [`QueueOrder::Fifo`].
+    pub fn order(mut self, order: QueueOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Record the downloads added after this in `metrics`, unless they were given their own.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(SharedMetrics(metrics));
+        self
+    }
+
+    /// Share `budget` between the downloads added after this, on top of any budgets they were
+    /// given of their own.  Its rate can be changed while they run with
+    /// [`Budget::set_bytes_per_sec`].
+    pub fn budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Add a download to the manager, returning its index in the results of
+    /// [`run`](DownloadManager::run).  Downloads which were not given their own client will use
+    /// the manager's.
+    pub fn add(&mut self, download: AsyncDownload) -> usize {
+        self.add_with_priority(download, Priority::Normal)
+    }
+
+    /// Add a download to the manager like [`add`](DownloadManager::add), with `priority`.
+    pub fn add_with_priority(&mut self, mut download: AsyncDownload, priority: Priority) -> usize {
+        if download.config.client.is_none() {
+            download.config.client = Some(self.client.clone());
+            if self.provided_client {
+                download.config.content_decoded = true;
+                download.config.redirects = None;
+            }
+        }
+        if download.config.metrics.is_none() {
+            download.config.metrics.clone_from(&self.metrics);
+        }
+        if let Some(ref budget) = self.budget {
+            download.config.budgets.push(budget.share());
+        }
+        self.downloads.push(download);
+        self.completed.push(None);
+        self.shared.queue.lock().unwrap().entries.push(Entry { priority, state: State::Queued });
+        self.downloads.len() - 1
+    }
+
+    /// Returns the downloads added to the manager, e.g. to find their paths and lengths after
+    /// [`run`](DownloadManager::run).
+    pub fn downloads(&self) -> &[AsyncDownload] {
+        &self.downloads
+    }
+
+    /// Returns a handle to the queue of the manager.
+    pub fn queue(&self) -> QueueHandle {
+        QueueHandle { shared: Arc::clone(&self.shared) }
+    }
+
+    /// Run all of the downloads, returning the result of each in the order they were added.
+    /// A download which was cancelled before it started, or which was stopped by a
+    /// [`shutdown`](QueueHandle::shutdown), fails with `Cancelled`, and one which completed in an
+    /// earlier run is not downloaded again.  Specify an optional callback.
+    ///
+    /// Arguments:
+    /// * `cb` - An optional callback for reporting the aggregate progress of the downloads.  The
+    ///   callback takes the number of bytes downloaded across all downloads, and their total
+    ///   length once it is known for every download which has not failed or been cancelled.
+    pub async fn run(&mut self, cb: &Option<ProgressCallback>) -> Vec<Result<DownloadResult, TDSTDError>> {
+        self.run_with(&mut |batch| {
+            if let Some(cb) = cb {
+                cb(batch.bytes(), batch.total());
+            }
+        })
+        .await
+    }
+
+    /// Run all of the downloads like [`run`](DownloadManager::run), returning a stream of the
+    /// progress of the whole batch.  A snapshot is yielded whenever any download makes progress,
+    /// starts or ends, and the stream ends after yielding the result of each download.  The
+    /// downloads only make progress while the stream is polled.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use futures_util::StreamExt;
+    /// use tokio_dl_stream_to_disk::DownloadManager;
+    /// use tokio_dl_stream_to_disk::manager::BatchEvent;
+    ///
+    /// # async fn run(manager: &mut DownloadManager) {
+    /// let mut events = manager.run_events();
+    /// while let Some(event) = events.next().await {
+    ///     match event {
+    ///         BatchEvent::Progress(batch) => println!(
+    ///             "{} of {:?} bytes, {} done, {} failed, {} waiting",
+    ///             batch.bytes(), batch.total(), batch.completed(), batch.failed(), batch.pending(),
+    ///         ),
+    ///         BatchEvent::Finished(results) => println!("{} downloads finished", results.len()),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn run_events(&mut self) -> impl Stream<Item = BatchEvent> + Unpin + '_ {
+        use futures_util::{future, stream, FutureExt};
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let run = async move {
+            let results = self.run_with(&mut |batch| {
+                let _ = tx.send(BatchEvent::Progress(batch.clone()));
+            })
+            .await;
+            let _ = tx.send(BatchEvent::Finished(results));
+        };
+        stream::select(
+            stream::poll_fn(move |cx| rx.poll_recv(cx)),
+            Box::pin(run).into_stream().filter_map(|()| future::ready(None)),
+        )
+    }
+
+    /// Runs the downloads, passing `observe` the progress of the batch whenever it changes.
+    async fn run_with(&mut self, observe: &mut dyn FnMut(&BatchProgress)) -> Vec<Result<DownloadResult, TDSTDError>> {
+        // Downloads which were stopped by a shutdown, or failed, in an earlier run start again.
+        for entry in self.shared.queue.lock().unwrap().entries.iter_mut().filter(|entry| entry.state == State::Started) {
+            entry.state = State::Queued;
+        }
+        let hosts: Vec<Option<String>> = self.downloads.iter()
+            .map(|download| reqwest::Url::parse(&download.url).ok()?.host_str().map(str::to_owned))
+            .collect();
+        let batch = RefCell::new(BatchProgress {
+            items: self.completed.iter().map(|completed| completed.as_ref().map(|&(_, item)| item).unwrap_or_default()).collect(),
+        });
+        let observe = RefCell::new(observe);
+        let shared = &self.shared;
+        let report = |i: Option<usize>, change: &mut dyn FnMut(&mut ItemProgress)| {
+            let mut batch = batch.borrow_mut();
+            if let Some(i) = i {
+                change(&mut batch.items[i]);
+            }
+            // Downloads may have been cancelled through a QueueHandle since the last report, or
+            // the manager shut down so that none of those queued start.  The lock is released
+            // before `observe`, which may use one.
+            let cancelled: Vec<bool> = {
+                let queue = shared.queue.lock().unwrap();
+                queue.entries.iter().map(|entry| queue.shut_down || entry.state == State::Cancelled).collect()
+            };
+            for (item, cancelled) in batch.items.iter_mut().zip(cancelled) {
+                if cancelled && item.state == ItemState::Pending {
+                    item.state = ItemState::Cancelled;
+                }
+            }
+            (observe.borrow_mut())(&batch);
+        };
+
+        let mut downloads: Vec<Option<&mut AsyncDownload>> = self.downloads.iter_mut().map(Some).collect();
+        let mut results: Vec<Option<Result<DownloadResult, TDSTDError>>> = self.completed.iter()
+            .map(|completed| completed.as_ref().map(|(result, _)| Ok(result.clone())))
+            .collect();
+        let _quiesce = Quiesce(shared);
+        let abort = shared.queue.lock().unwrap().abort.clone();
+        let mut running = FuturesUnordered::new();
+        let mut per_host: HashMap<&str, usize> = HashMap::new();
+        loop {
+            while running.len() < self.concurrency {
+                let limit = self.per_host.unwrap_or(usize::MAX);
+                let eligible = |i: usize| hosts[i].as_deref().is_none_or(|host| per_host.get(host).is_none_or(|&n| n < limit));
+                let Some(i) = next(&mut shared.queue.lock().unwrap(), self.order, eligible) else {
+                    break;
+                };
+                if let Some(ref host) = hosts[i] {
+                    *per_host.entry(host).or_default() += 1;
+                }
+                report(Some(i), &mut |item| item.state = ItemState::Running);
+                let (download, report, abort) = (downloads[i].take().unwrap(), &report, &abort);
+                running.push(async move {
+                    let mut events = |event| match event {
+                        DownloadEvent::Started { offset, total } => report(Some(i), &mut |item| {
+                            item.bytes = offset;
+                            item.total = total;
+                        }),
+                        DownloadEvent::Chunk { bytes, total, speed, .. } => report(Some(i), &mut |item| {
+                            item.bytes = bytes;
+                            item.total = total;
+                            item.speed = speed;
+                        }),
+                        _ => (),
+                    };
+                    // The download is stopped through a child of its own token, if it was given
+                    // one, so that the manager does not cancel that token itself.
+                    let (parent, cancel) = download.abortable();
+                    let result = {
+                        let mut attempt = pin!(download.download_with_events(&mut events));
+                        tokio::select! {
+                            result = &mut attempt => result,
+                            () = abort.cancelled() => {
+                                cancel.cancel();
+                                attempt.await
+                            }
+                        }
+                    };
+                    download.config.cancellation_token = parent;
+                    (i, result)
+                });
+                shared.running.send_replace(running.len());
+            }
+            let Some((i, result)) = running.next().await else {
+                break;
+            };
+            shared.running.send_replace(running.len());
+            if let Some(ref host) = hosts[i] {
+                per_host.entry(host).and_modify(|n| *n -= 1);
+            }
+            report(Some(i), &mut |item| {
+                item.speed = 0.0;
+                item.state = match result {
+                    Ok(_) => {
+                        // A download which was skipped or not modified may not have reported its
+                        // length, which is then what it wrote.
+                        let total = item.total.unwrap_or(item.bytes);
+                        item.bytes = total;
+                        item.total = Some(total);
+                        ItemState::Completed
+                    }
+                    Err(ref err) if matches!(err.kind(), TDSTDErrorKind::Cancelled) => ItemState::Cancelled,
+                    Err(_) => ItemState::Failed,
+                };
+            });
+            if let Ok(ref result) = result {
+                shared.queue.lock().unwrap().entries[i].state = State::Completed;
+                self.completed[i] = Some((result.clone(), batch.borrow().items[i]));
+            }
+            results[i] = Some(result);
+        }
+        // Downloads which never started were cancelled.
+        for (item, result) in batch.borrow_mut().items.iter_mut().zip(&results) {
+            if result.is_none() {
+                item.state = ItemState::Cancelled;
+            }
+        }
+        report(None, &mut |_| ());
+        results.into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(TDSTDError::new(TDSTDErrorKind::Cancelled))))
+            .collect()
+    }
+}
+
+/// Resets the number of running downloads once a run ends, including if it is dropped along
+/// with the downloads which were running, and lifts any shutdown so that a later run starts
+/// afresh.  Both are done under the lock, so that a shutdown which is still waiting for the run
+/// cannot cancel the downloads of the next.
+struct Quiesce<'a>(&'a Shared);
+
+impl Drop for Quiesce<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.0.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        queue.shut_down = false;
+        queue.abort = CancellationToken::new();
+        self.0.running.send_replace(0);
+    }
+}
+
+/// Takes the queued download which is to start next out of the queue, of those for which
+/// `eligible` returns `true`.  None start once the manager has been shut down.
+fn next(queue: &mut Queue, order: QueueOrder, eligible: impl Fn(usize) -> bool) -> Option<usize> {
+    if queue.shut_down {
+        return None;
+    }
+    let queued = queue.entries.iter()
+        .enumerate()
+        .filter(|&(i, entry)| entry.state == State::Queued && eligible(i));
+    let (i, _) = match order {
+        QueueOrder::Fifo => queued.max_by_key(|&(i, entry)| (entry.priority, Reverse(i))),
+        QueueOrder::Lifo => queued.max_by_key(|&(i, entry)| (entry.priority, i)),
+    }?;
+    queue.entries[i].state = State::Started;
+    Some(i)
+}
+
+impl QueueHandle {
+    /// Give the download at `index` another priority.  Returns `false` if it has already
+    /// started or been cancelled.
+    pub fn set_priority(&self, index: usize, priority: Priority) -> bool {
+        match self.shared.queue.lock().unwrap().entries.get_mut(index) {
+            Some(entry) if entry.state == State::Queued => {
+                entry.priority = priority;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Take the download at `index` out of the queue, so that it never starts and fails with
+    /// `Cancelled`.  Returns `false` if it has already started.
+    pub fn cancel(&self, index: usize) -> bool {
+        match self.shared.queue.lock().unwrap().entries.get_mut(index) {
+            Some(entry) if matches!(entry.state, State::Queued | State::Cancelled) => {
+                entry.state = State::Cancelled;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Shut the manager down, such as when a service is asked to stop.  No more downloads start,
+    /// and the ones which are running are stopped according to `mode`.  Resolves once no
+    /// download is running.  The downloads which did not finish fail with `Cancelled`.
+    ///
+    /// The shutdown lasts until the run in progress ends, or the next one if none is, and the
+    /// manager can then be run again.  A later run starts the downloads which were still queued
+    /// along with those which were stopped, and returns the results of those which completed
+    /// without downloading them again.  A download which was stopped keeps its partial
+    /// file, unless its [`CleanupPolicy`](crate::CleanupPolicy) removes it, and its saved state,
+    /// so that it picks up where it left off with [`resume`](crate::AsyncDownload::resume).  The
+    /// downloads only stop while [`run`](DownloadManager::run) is polled, so the manager should
+    /// be shut down alongside it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use tokio_dl_stream_to_disk::DownloadManager;
+    /// use tokio_dl_stream_to_disk::manager::Shutdown;
+    ///
+    /// # async fn run(mut manager: DownloadManager) {
+    /// let queue = manager.queue();
+    /// let mut run = std::pin::pin!(manager.run(&None));
+    /// let results = tokio::select! {
+    ///     results = &mut run => results,
+    ///     _ = tokio::signal::ctrl_c() => {
+    ///         let shutdown = queue.shutdown(Shutdown::Graceful { timeout: Duration::from_secs(30) });
+    ///         tokio::join!(run, shutdown).0
+    ///     }
+    /// };
+    /// # }
+    /// ```
+    pub async fn shutdown(&self, mode: Shutdown) {
+        self.shared.queue.lock().unwrap().shut_down = true;
+        let mut running = self.shared.running.subscribe();
+        if let Shutdown::Graceful { timeout } = mode {
+            if tokio::time::timeout(timeout, running.wait_for(|&n| n == 0)).await.is_ok() {
+                return;
+            }
+        }
+        {
+            // If the run has ended since, its token has been replaced by one for the next run,
+            // which is left alone.
+            let queue = self.shared.queue.lock().unwrap();
+            if *running.borrow() > 0 {
+                queue.abort.cancel();
+            }
+        }
+        let _ = running.wait_for(|&n| n == 0).await;
+    }
+
+    /// Returns the priority of the download at `index`, unless it has already started or been
+    /// cancelled.
+    pub fn priority(&self, index: usize) -> Option<Priority> {
+        self.shared.queue.lock().unwrap().entries
+            .get(index)
+            .filter(|entry| entry.state == State::Queued)
+            .map(|entry| entry.priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serves `/redirect`, which redirects to `/file`, and five bytes for any other path, except
+    /// that the first request for `/hang` is sent two of them and then left waiting.  Returns the
+    /// URL of the server and the paths it was sent, in order.
+    async fn serve() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&requests);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let log = Arc::clone(&log);
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let path = String::from_utf8_lossy(&request).split(' ').nth(1).unwrap_or_default().to_owned();
+                    let hung = {
+                        let mut log = log.lock().unwrap();
+                        log.push(path.clone());
+                        path == "/hang" && log.iter().filter(|logged| **logged == path).count() == 1
+                    };
+                    let response = match path.as_str() {
+                        "/redirect" => "HTTP/1.1 302 Found\r\nLocation: /file\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        _ if hung => "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhe",
+                        _ => "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+                    };
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    if hung {
+                        std::future::pending::<()>().await;
+                    }
+                });
+            }
+        });
+        (base, requests)
+    }
+
+    /// Returns an empty directory for the test `name` to download to.
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tdstd-manager-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn records_redirects() {
+        let (base, requests) = serve().await;
+        let dir = dir("redirects");
+        let mut manager = DownloadManager::new(1);
+        manager.add(AsyncDownload::new(format!("{}/redirect", base), &dir, "file").unwrap());
+        let results = manager.run(&None).await;
+
+        let result = results[0].as_ref().unwrap();
+        assert_eq!(result.redirects(), [reqwest::Url::parse(&format!("{}/redirect", base)).unwrap()]);
+        assert_eq!(*requests.lock().unwrap(), ["/redirect", "/file"]);
+        assert_eq!(std::fs::read(dir.join("file")).unwrap(), b"hello");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn runs_again_after_shutdown() {
+        let (base, requests) = serve().await;
+        let dir = dir("shutdown");
+        let mut manager = DownloadManager::new(1);
+        for name in ["first", "hang", "last"] {
+            let download = AsyncDownload::builder()
+                .url(format!("{}/{}", base, name))
+                .dst_dir(&dir)
+                .filename(name)
+                .overwrite(true)
+                .build()
+                .unwrap();
+            manager.add(download);
+        }
+
+        // Shut down once the first download has completed and the second has started.
+        let queue = manager.queue();
+        let stopping = tokio::sync::Notify::new();
+        let mut observe = |batch: &BatchProgress| {
+            if batch.completed() == 1 && batch.items()[1].bytes() > 0 {
+                stopping.notify_one();
+            }
+        };
+        let run = manager.run_with(&mut observe);
+        let shutdown = async {
+            stopping.notified().await;
+            queue.shutdown(Shutdown::Abort).await;
+        };
+        let (results, ()) = tokio::join!(run, shutdown);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1].as_ref().unwrap_err().kind(), TDSTDErrorKind::Cancelled));
+        assert!(matches!(results[2].as_ref().unwrap_err().kind(), TDSTDErrorKind::Cancelled));
+
+        let results = manager.run(&None).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(*requests.lock().unwrap(), ["/first", "/hang", "/hang", "/last"]);
+        for name in ["first", "hang", "last"] {
+            assert_eq!(std::fs::read(dir.join(name)).unwrap(), b"hello");
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
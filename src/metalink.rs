@@ -0,0 +1,397 @@
+#[cfg(feature="sha256sum")]
+use std::sync::Arc;
+
+use reqwest::header::LINK;
+use reqwest::{Method, Url};
+#[cfg(feature="sha256sum")]
+use sha2::Sha256;
+
+#[cfg(feature="sha256sum")]
+use crate::builder::parse_hex;
+use crate::error::ErrorKind as TDSTDErrorKind;
+#[cfg(feature="sha256sum")]
+use crate::pieces::PieceHashes;
+use crate::{filename, AsyncDownload, Failure};
+
+/// The media type of a Metalink document, as linked to from a `Link` header.
+const METALINK_TYPE: &str = "application/metalink4+xml";
+
+/// The priority of a URL which is not given one, which is the lowest.
+const LOWEST_PRIORITY: u32 = 999999;
+
+/// Where the Metalink describing a download is found.
+#[derive(Clone, Debug)]
+pub(crate) enum MetalinkSource {
+    /// A Metalink document at a URL.
+    Url(String),
+    /// The `Link` and `Digest` headers of the download URL, as in RFC 6249.
+    Links,
+}
+
+/// What a Metalink lists for a file.
+#[derive(Debug, Default)]
+struct Described {
+    name: Option<String>,
+    /// URLs with their priority, where a lower number is preferred.
+    urls: Vec<(u32, String)>,
+    #[cfg(feature="sha256sum")]
+    sha256: Option<[u8; 32]>,
+    /// The length of each piece and their sha256sums.
+    #[cfg(feature="sha256sum")]
+    pieces: Option<(u64, Vec<Vec<u8>>)>,
+}
+
+impl Described {
+    /// Adds what `other` lists to what is not known yet.
+    fn merge(&mut self, other: Described) {
+        self.name = self.name.take().or(other.name);
+        self.urls.extend(other.urls);
+        #[cfg(feature="sha256sum")]
+        {
+            self.sha256 = self.sha256.or(other.sha256);
+            self.pieces = self.pieces.take().or(other.pieces);
+        }
+    }
+}
+
+impl AsyncDownload {
+    /// Fetches the Metalink the download is described by, if one was set and has not been
+    /// fetched yet, and applies the URLs, filename and hashes it lists for the download.
+    pub(crate) async fn resolve_metalink(&mut self) -> Result<(), Failure> {
+        let Some(source) = self.config.metalink.take() else {
+            return Ok(());
+        };
+        let (described, replaces_url) = match source {
+            MetalinkSource::Url(ref url) => (self.fetch_metalink(url).await?, *url == self.url),
+            MetalinkSource::Links => (self.linked_metalink().await?, false),
+        };
+        self.apply_metalink(described, replaces_url)
+    }
+
+    /// Fetches the Metalink document at `url` and returns what it lists for the download.
+    async fn fetch_metalink(&self, url: &str) -> Result<Described, Failure> {
+        let (response, _) = self.send(self.request_with(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(Failure::from_status(response));
+        }
+        let base = response.url().clone();
+        let document = response.text().await?;
+        let invalid = || Failure::fatal(TDSTDErrorKind::InvalidMetalink);
+        let files = parse(&document, &base).ok_or_else(invalid)?;
+        // The file may be listed under the name on the server or the name it is saved as.
+        let names: Vec<String> = [
+            reqwest::Url::parse(&self.url).ok().filter(|_| self.url != url).and_then(|url| filename::from_url(&url)),
+            self.fname.to_str().and_then(filename::sanitize),
+        ].into_iter().flatten().collect();
+        select(files, &names).ok_or_else(invalid)
+    }
+
+    /// Asks the download URL for the mirrors, digest and Metalink it links to, as in RFC 6249.
+    /// A server which does not answer a `HEAD` request links to none.
+    async fn linked_metalink(&self) -> Result<Described, Failure> {
+        let (response, _) = self.send(self.request_with(Method::HEAD, &self.url)).await?;
+        let mut described = Described::default();
+        if !response.status().is_success() {
+            return Ok(described);
+        }
+        let base = response.url().clone();
+        let mut document = None;
+        for value in response.headers().get_all(LINK).iter().filter_map(|v| v.to_str().ok()) {
+            for (target, params) in parse_links(value) {
+                let Ok(url) = base.join(&target) else {
+                    continue;
+                };
+                let param = |name: &str| params.iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v.as_str());
+                let rel = |rel: &str| param("rel").is_some_and(|rels| rels.split_ascii_whitespace().any(|r| r.eq_ignore_ascii_case(rel)));
+                if rel("duplicate") {
+                    let priority = param("pri").and_then(|p| p.parse().ok()).unwrap_or(LOWEST_PRIORITY);
+                    described.urls.push((priority, url.to_string()));
+                } else if rel("describedby") && param("type").is_some_and(|t| t.eq_ignore_ascii_case(METALINK_TYPE)) {
+                    document.get_or_insert(url);
+                }
+            }
+        }
+        #[cfg(feature="sha256sum")]
+        {
+            described.sha256 = response.headers().get("digest")
+                .and_then(|v| v.to_str().ok())
+                .and_then(digest_sha256);
+        }
+        if let Some(url) = document {
+            described.merge(self.fetch_metalink(url.as_str()).await?);
+        }
+        Ok(described)
+    }
+
+    /// Makes the URLs a Metalink lists the download URL and its mirrors, in order of priority,
+    /// and expects the hashes it lists unless others were set.  If the Metalink was fetched from
+    /// the download URL, it is replaced, and the Metalink must list a URL which can be
+    /// downloaded from.
+    fn apply_metalink(&mut self, described: Described, replaces_url: bool) -> Result<(), Failure> {
+        let mut urls = described.urls;
+        urls.sort_by_key(|&(priority, _)| priority);
+        if !self.use_sources(urls.into_iter().map(|(_, url)| url), replaces_url) {
+            return Err(Failure::fatal(TDSTDErrorKind::InvalidMetalink));
+        }
+        if self.fname.is_empty() {
+            if let Some(name) = described.name.as_deref().and_then(filename::sanitize) {
+                self.fname = name.into();
+            }
+        }
+        #[cfg(feature="sha256sum")]
+        {
+            if self.config.expected_sha256.is_none() {
+                self.config.expected_sha256 = described.sha256;
+            }
+            if let (None, Some((piece_len, hashes))) = (&self.config.piece_hashes, described.pieces) {
+                self.config.piece_hashes = Some(Arc::new(PieceHashes::new::<Sha256>(piece_len, hashes)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the files a Metalink document lists, in either the format of RFC 5854 or the older
+/// version 3, or `None` if it is not a Metalink.  Relative URLs are resolved against `base`.
+fn parse(document: &str, base: &Url) -> Option<Vec<Described>> {
+    let root = parse_xml(document)?;
+    if root.name != "metalink" {
+        return None;
+    }
+    // Version 3 puts the files in a `files` element, and the hashes and URLs of each in
+    // `verification` and `resources` elements.
+    let files = root.children("file").chain(root.children("files").flat_map(|files| files.children("file")));
+    let described = files.map(|file| {
+        let verification = file.children("verification");
+        let resources = file.children("resources");
+        let mut urls = Vec::new();
+        for url in file.children("url").chain(resources.flat_map(|r| r.children("url"))) {
+            let priority = match (url.attr("priority"), url.attr("preference")) {
+                (Some(priority), _) => priority.parse().unwrap_or(LOWEST_PRIORITY),
+                // A version 3 preference runs from 0 to 100, where a higher number is preferred.
+                (None, Some(preference)) => 101 - preference.parse::<u32>().unwrap_or(0).min(100),
+                (None, None) => LOWEST_PRIORITY,
+            };
+            if let Ok(url) = base.join(url.text.trim()) {
+                urls.push((priority, url.to_string()));
+            }
+        }
+        #[cfg(feature="sha256sum")]
+        let (sha256, pieces) = {
+            let is_sha256 = |element: &Element| element.attr("type").is_some_and(|t| t.replace('-', "").eq_ignore_ascii_case("sha256"));
+            let sha256 = file.children("hash")
+                .chain(verification.clone().flat_map(|v| v.children("hash")))
+                .find(|hash| is_sha256(hash))
+                .and_then(|hash| parse_hex(hash.text.trim())?.try_into().ok());
+            let pieces = file.children("pieces")
+                .chain(verification.flat_map(|v| v.children("pieces")))
+                .filter(|pieces| is_sha256(pieces))
+                .find_map(|pieces| {
+                    let piece_len = pieces.attr("length")?.parse::<u64>().ok().filter(|&len| len > 0)?;
+                    let hashes = pieces.children("hash")
+                        .map(|hash| parse_hex(hash.text.trim()).filter(|hash| hash.len() == 32))
+                        .collect::<Option<Vec<_>>>()?;
+                    Some((piece_len, hashes))
+                });
+            (sha256, pieces)
+        };
+        #[cfg(not(feature="sha256sum"))]
+        let _ = verification;
+        Described {
+            name: file.attr("name").map(String::from),
+            urls,
+            #[cfg(feature="sha256sum")]
+            sha256,
+            #[cfg(feature="sha256sum")]
+            pieces,
+        }
+    });
+    Some(described.collect())
+}
+
+/// Returns the file of a Metalink which is the download: the only one, or otherwise the one
+/// named after any of `names`.
+fn select(mut files: Vec<Described>, names: &[String]) -> Option<Described> {
+    if files.len() == 1 {
+        return files.pop();
+    }
+    files.into_iter().find(|file| file.name.as_deref()
+        .and_then(filename::sanitize)
+        .is_some_and(|name| names.contains(&name)))
+}
+
+/// Returns the sha256sum in a `Digest` header, such as `SHA-256=<base64 digest>`.
+#[cfg(feature="sha256sum")]
+fn digest_sha256(value: &str) -> Option<[u8; 32]> {
+    value.split(',')
+        .filter_map(|digest| digest.trim().split_once('='))
+        .find(|(algorithm, _)| algorithm.eq_ignore_ascii_case("sha-256"))
+        .and_then(|(_, digest)| crate::local::decode_base64(digest.as_bytes()))
+        .and_then(|digest| digest.try_into().ok())
+}
+
+/// Parses the value of a `Link` header into the target of each link and its parameters.
+fn parse_links(value: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut links = Vec::new();
+    let mut rest = value.trim_start();
+    while let Some(link) = rest.strip_prefix('<') {
+        let Some((target, mut params)) = link.split_once('>') else {
+            break;
+        };
+        let mut parsed = Vec::new();
+        while let Some(param) = params.trim_start().strip_prefix(';') {
+            let param = param.trim_start();
+            let end = param.find(['=', ';', ',']).unwrap_or(param.len());
+            let name = param[..end].trim();
+            let (value, remaining) = match param[end..].strip_prefix('=') {
+                Some(value) => {
+                    let value = value.trim_start();
+                    match value.strip_prefix('"') {
+                        Some(quoted) => {
+                            let end = quoted.find('"').unwrap_or(quoted.len());
+                            (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+                        }
+                        None => {
+                            let end = value.find([';', ',']).unwrap_or(value.len());
+                            (value[..end].trim_end(), &value[end..])
+                        }
+                    }
+                }
+                None => ("", &param[end..]),
+            };
+            parsed.push((String::from(name), String::from(value)));
+            params = remaining;
+        }
+        links.push((String::from(target), parsed));
+        rest = params.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    links
+}
+
+/// An element of an XML document, named without its namespace prefix.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + Clone + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// Parses the root element of an XML document, or returns `None` if it is not well-formed.
+/// Only the predefined and numeric entities are expanded, since a document type declaration is
+/// skipped rather than read.
+fn parse_xml(document: &str) -> Option<Element> {
+    let mut stack = vec![Element::default()];
+    let mut rest = document;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            stack.last_mut()?.text.push_str(&unescape(rest)?);
+            break;
+        };
+        stack.last_mut()?.text.push_str(&unescape(&rest[..start])?);
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = &comment[comment.find("-->")? + 3..];
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>")?;
+            stack.last_mut()?.text.push_str(&cdata[..end]);
+            rest = &cdata[end + 3..];
+        } else if rest.starts_with("<?") {
+            rest = &rest[rest.find("?>")? + 2..];
+        } else if rest.starts_with("<!") {
+            rest = &rest[rest.find('>')? + 1..];
+        } else if let Some(end_tag) = rest.strip_prefix("</") {
+            let end = end_tag.find('>')?;
+            let element = stack.pop()?;
+            if local_name(end_tag[..end].trim()) != element.name || stack.is_empty() {
+                return None;
+            }
+            stack.last_mut()?.children.push(element);
+            rest = &end_tag[end + 1..];
+        } else {
+            let (element, closed, remaining) = parse_tag(&rest[1..])?;
+            match closed {
+                true => stack.last_mut()?.children.push(element),
+                false => stack.push(element),
+            }
+            rest = remaining;
+        }
+    }
+    let mut document = stack.pop().filter(|_| stack.is_empty())?;
+    match document.children.len() {
+        1 => document.children.pop(),
+        _ => None,
+    }
+}
+
+/// Parses a start tag after its `<`, returning the element, whether the tag also closed it, and
+/// what follows the tag.
+fn parse_tag(tag: &str) -> Option<(Element, bool, &str)> {
+    let end = tag.find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')?;
+    let mut element = Element {
+        name: String::from(local_name(&tag[..end])),
+        ..Element::default()
+    };
+    let mut rest = &tag[end..];
+    loop {
+        rest = rest.trim_start();
+        if let Some(rest) = rest.strip_prefix("/>") {
+            return Some((element, true, rest));
+        }
+        if let Some(rest) = rest.strip_prefix('>') {
+            return Some((element, false, rest));
+        }
+        let (name, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let (value, remaining) = value[1..].split_once(quote)?;
+        element.attrs.push((String::from(local_name(name.trim())), unescape(value)?));
+        rest = remaining;
+    }
+}
+
+/// Returns an XML name without its namespace prefix.
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Expands the entity and character references in XML text.
+fn unescape(text: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let (entity, remaining) = rest[start + 1..].split_once(';')?;
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => entity.strip_prefix('#')?.parse().ok()?,
+                };
+                char::from_u32(code)?
+            }
+        };
+        unescaped.push(c);
+        rest = remaining;
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}
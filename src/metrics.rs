@@ -0,0 +1,71 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Error as TDSTDError;
+
+/// Receives measurements of downloads, e.g. to export them as Prometheus counters and
+/// histograms.  It is set for a single download with
+/// [`AsyncDownloadBuilder::metrics`](crate::AsyncDownloadBuilder::metrics), or for every download
+/// of a [`DownloadManager`](crate::DownloadManager) with
+/// [`DownloadManager::metrics`](crate::DownloadManager::metrics), and every method does nothing
+/// by default.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::time::Duration;
+/// use tokio_dl_stream_to_disk::error::{Error, ErrorKind};
+/// use tokio_dl_stream_to_disk::metrics::Metrics;
+///
+/// #[derive(Default)]
+/// struct Counters {
+///     bytes: AtomicU64,
+///     timeouts: AtomicU64,
+/// }
+///
+/// impl Metrics for Counters {
+///     fn bytes_downloaded(&self, bytes: u64) {
+///         self.bytes.fetch_add(bytes, Ordering::Relaxed);
+///     }
+///
+///     fn download_failed(&self, error: &Error, _elapsed: Duration) {
+///         if let ErrorKind::Timeout = error.kind() {
+///             self.timeouts.fetch_add(1, Ordering::Relaxed);
+///         }
+///     }
+/// }
+/// ```
+pub trait Metrics {
+    /// Called as the response body is received, with the number of bytes since the last call.
+    fn bytes_downloaded(&self, bytes: u64) {
+        let _ = bytes;
+    }
+
+    /// Called before a download is retried after a transient failure, with the error it failed
+    /// with.
+    fn retried(&self, error: &TDSTDError) {
+        let _ = error;
+    }
+
+    /// Called once a download has been written, with the bytes written and how long it took.
+    fn download_completed(&self, bytes_written: u64, elapsed: Duration) {
+        let _ = (bytes_written, elapsed);
+    }
+
+    /// Called once a download has failed, with the error it failed with and how long it took.
+    fn download_failed(&self, error: &TDSTDError, elapsed: Duration) {
+        let _ = (error, elapsed);
+    }
+}
+
+/// The metrics a download is recorded in, if any.
+#[derive(Clone)]
+pub(crate) struct SharedMetrics(pub(crate) Arc<dyn Metrics>);
+
+impl fmt::Debug for SharedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Metrics")
+    }
+}
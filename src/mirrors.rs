@@ -0,0 +1,98 @@
+use futures_util::future::select_ok;
+use futures_util::stream::{self, StreamExt};
+
+use crate::conditional::Validators;
+use crate::result::ResponseMetadata;
+#[cfg(any(feature="metalink", feature="torrent"))]
+use crate::IntoUrl;
+use crate::{AsyncDownload, Failure, S};
+
+/// How many bytes each mirror delivers in a race, unless configured otherwise.
+const DEFAULT_RACE_BYTES: u64 = 256 * 1024;
+
+/// How many of the URLs a description such as a Metalink lists are raced, in order of
+/// priority, so that a list of hundreds of mirrors does not open hundreds of connections.
+#[cfg(any(feature="metalink", feature="torrent"))]
+const RACED_URLS: usize = 4;
+
+impl AsyncDownload {
+    /// Fetches what the download is described by, such as a Metalink or a torrent, if it has not
+    /// been fetched yet, so that the URLs, filename and hashes it lists apply from the start.
+    pub(crate) async fn resolve_sources(&mut self) -> Result<(), Failure> {
+        #[cfg(feature="metalink")]
+        self.resolve_metalink().await?;
+        #[cfg(feature="torrent")]
+        self.resolve_torrent().await?;
+        Ok(())
+    }
+
+    /// Makes `urls`, in order of preference, the download URL and its mirrors, after the
+    /// download URL unless `replaces_url`, skipping any which are not valid or not allowed by
+    /// the policy.  Returns `false` if there is then no URL to download from.
+    #[cfg(any(feature="metalink", feature="torrent"))]
+    pub(crate) fn use_sources(&mut self, listed: impl IntoIterator<Item = String>, replaces_url: bool) -> bool {
+        let mut urls: Vec<String> = match replaces_url {
+            true => Vec::new(),
+            false => vec![self.url.clone()],
+        };
+        for url in listed {
+            let allowed = url.as_str().into_url().is_ok_and(|url| {
+                self.config.policy.as_ref().is_none_or(|policy| policy.check_url(&url).is_ok())
+            });
+            if allowed && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        urls.truncate(RACED_URLS);
+        let Some((url, mirrors)) = urls.split_first() else {
+            return false;
+        };
+        self.url.clone_from(url);
+        for mirror in mirrors {
+            if !self.config.mirrors.contains(mirror) {
+                self.config.mirrors.push(mirror.clone());
+            }
+        }
+        true
+    }
+
+    /// Requests the download from its URL and every mirror at once, reading the first bytes of
+    /// each response, and returns the response of whichever finishes first.  The other requests
+    /// are dropped, and the download URL is switched to the winner so that any range requests go
+    /// to it.  The bytes read during the race are put back at the front of the returned stream.
+    pub(crate) async fn race_mirrors(&mut self, validators: &Validators) -> Result<(ResponseMetadata, Option<Box<S>>), Failure> {
+        if !self.config.mirrors.contains(&self.url) {
+            self.config.mirrors.insert(0, self.url.clone());
+        }
+        let race_bytes = self.config.race_bytes.unwrap_or(DEFAULT_RACE_BYTES);
+        let download = &*self;
+        let candidates = download.config.mirrors.iter().map(|url| {
+            let request = validators.apply(download.request_to(url));
+            Box::pin(async move {
+                let (response, stream) = download.open(request).await?;
+                let Some(mut stream) = stream else {
+                    return Ok((url, response, None));
+                };
+                let mut prefix = Vec::new();
+                let mut read = 0;
+                while read < race_bytes {
+                    match stream.next().await {
+                        Some(Ok(chunk)) => {
+                            read += chunk.len() as u64;
+                            prefix.push(Ok(chunk));
+                        }
+                        Some(Err(err)) => {
+                            return Err(Failure::transient(err.into()))
+                        }
+                        None => break,
+                    }
+                }
+                let stream: Box<S> = Box::new(stream::iter(prefix).chain(stream));
+                Ok((url, response, Some(stream)))
+            })
+        });
+        let ((url, response, stream), _) = select_ok(candidates).await?;
+        self.url = url.clone();
+        Ok((response, stream))
+    }
+}
@@ -0,0 +1,130 @@
+use std::env;
+use std::io::ErrorKind as IOErrorKind;
+use std::path::PathBuf;
+
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+
+use crate::{local, Failure};
+
+/// The `.netrc` file credentials are looked up in.
+#[derive(Clone, Debug)]
+pub(crate) struct Netrc {
+    path: PathBuf,
+    /// Whether the file must exist, as when its path was given rather than found in the home
+    /// directory.
+    required: bool,
+}
+
+impl Netrc {
+    /// Returns the file named by `NETRC`, or `~/.netrc`, which need not exist.
+    pub(crate) fn default_file() -> Option<Self> {
+        let path = match env::var_os("NETRC").filter(|path| !path.is_empty()) {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(env::var_os("HOME").filter(|home| !home.is_empty())?).join(".netrc"),
+        };
+        Some(Self { path, required: false })
+    }
+
+    pub(crate) fn file(path: PathBuf) -> Self {
+        Self { path, required: true }
+    }
+
+    /// Adds Basic authentication to an `http` or `https` request with the login and password
+    /// the file lists for its host, or its `default`, unless the request already has an
+    /// `Authorization` header.  The file is read for each request, as it is small, so that it
+    /// may change during a long download.
+    pub(crate) async fn authorize(&self, request: &mut reqwest::Request) -> Result<(), Failure> {
+        let url = request.url();
+        if !matches!(url.scheme(), "http" | "https") || request.headers().contains_key(AUTHORIZATION) {
+            return Ok(());
+        }
+        let Some(host) = url.host_str() else {
+            return Ok(());
+        };
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == IOErrorKind::NotFound && !self.required => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        if let Some((login, password)) = lookup(&contents, host) {
+            let credentials = local::encode_base64(format!("{}:{}", login, password).as_bytes());
+            if let Ok(mut value) = HeaderValue::from_str(&format!("Basic {}", credentials)) {
+                value.set_sensitive(true);
+                request.headers_mut().insert(AUTHORIZATION, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the login and password a `.netrc` file lists for `host`, or for its `default` if it
+/// does not list the host, as curl and wget find them.  Macros defined with `macdef` are
+/// skipped, and a value may be quoted to hold spaces.
+fn lookup(contents: &str, host: &str) -> Option<(String, String)> {
+    // The machine of each entry, or `None` for the default, with its login and password.
+    let mut entries: Vec<(Option<String>, String, String)> = Vec::new();
+    let mut tokens = tokenize(contents).into_iter();
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "machine" => entries.push((Some(tokens.next().unwrap_or_default()), String::new(), String::new())),
+            "default" => entries.push((None, String::new(), String::new())),
+            "login" | "password" | "account" => {
+                let value = tokens.next().unwrap_or_default();
+                match (token.as_str(), entries.last_mut()) {
+                    ("login", Some(entry)) => entry.1 = value,
+                    ("password", Some(entry)) => entry.2 = value,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    let (_, login, password) = entries.iter()
+        .find(|(machine, _, _)| machine.as_deref().is_some_and(|machine| machine.eq_ignore_ascii_case(host)))
+        .or_else(|| entries.iter().find(|(machine, _, _)| machine.is_none()))?;
+    Some((login.clone(), password.clone()))
+}
+
+/// Splits a `.netrc` file into its tokens, leaving out the definitions of macros, which run
+/// from `macdef` to the next empty line.
+fn tokenize(contents: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+    for line in contents.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        let mut chars = line.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let Some(c) = chars.next() else {
+                break;
+            };
+            if c == '#' && tokens.last().is_none_or(|last: &String| last != "password") {
+                break;
+            }
+            let mut token = String::new();
+            if c == '"' {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => token.extend(chars.next()),
+                        c => token.push(c),
+                    }
+                }
+            } else {
+                token.push(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    token.push(c);
+                }
+            }
+            tokens.push(token);
+        }
+        if tokens.len() >= 2 && tokens[tokens.len() - 2] == "macdef" {
+            tokens.truncate(tokens.len() - 2);
+            in_macro = true;
+        }
+    }
+    tokens
+}
@@ -0,0 +1,137 @@
+use std::sync::{mpsc, Arc, Mutex, PoisonError, TryLockError};
+
+use bytes::Bytes;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::result::ResponseMetadata;
+use crate::Inspect;
+
+/// How many bytes may be queued for the worker before the download waits for it to catch up,
+/// unless [`max_buffered`](crate::AsyncDownloadBuilder::max_buffered) is lower.
+const QUEUE_LEN: usize = 4 * 1024 * 1024;
+
+enum Message {
+    /// A chunk, with the permit it holds in the queue, if any.
+    Chunk(Bytes, Option<OwnedSemaphorePermit>),
+    Reset,
+    Response(ResponseMetadata),
+}
+
+/// Runs an inspector, such as the hashers a download is verified with, either inline or, with
+/// [`offload_hashing`](crate::AsyncDownloadBuilder::offload_hashing), on a blocking thread.  A
+/// worker hashes each chunk while the next is written and read, and the download waits for it
+/// once its queue is full.
+pub(crate) enum Offloaded<I> {
+    Inline(I),
+    Worker(Worker<I>),
+}
+
+pub(crate) struct Worker<I> {
+    inspect: Arc<Mutex<I>>,
+    queue: mpsc::Sender<Message>,
+    permits: Arc<Semaphore>,
+    queue_len: usize,
+    handle: JoinHandle<()>,
+}
+
+impl<I: Inspect + Send + 'static> Offloaded<I> {
+    pub(crate) fn new(inspect: I, offload: bool, max_buffered: Option<usize>) -> Self {
+        if !offload {
+            return Offloaded::Inline(inspect);
+        }
+        let inspect = Arc::new(Mutex::new(inspect));
+        let (queue, messages) = mpsc::channel();
+        let worker = Arc::clone(&inspect);
+        let handle = tokio::task::spawn_blocking(move || {
+            for message in messages {
+                let mut inspect = worker.lock().unwrap_or_else(PoisonError::into_inner);
+                match message {
+                    Message::Chunk(chunk, _permit) => inspect.update(&chunk),
+                    Message::Reset => inspect.reset(),
+                    Message::Response(response) => inspect.response(&response),
+                }
+            }
+        });
+        let queue_len = max_buffered.map_or(QUEUE_LEN, |max| max.min(QUEUE_LEN));
+        Offloaded::Worker(Worker {
+            inspect,
+            queue,
+            permits: Arc::new(Semaphore::new(queue_len)),
+            queue_len,
+            handle,
+        })
+    }
+
+    /// Waits for everything queued to be inspected, and returns the inspector.
+    pub(crate) async fn finish(self) -> I {
+        match self {
+            Offloaded::Inline(inspect) => inspect,
+            Offloaded::Worker(worker) => {
+                drop(worker.queue);
+                if let Err(err) = worker.handle.await {
+                    if err.is_panic() {
+                        std::panic::resume_unwind(err.into_panic());
+                    }
+                }
+                Arc::into_inner(worker.inspect)
+                    .expect("the worker has exited")
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner)
+            }
+        }
+    }
+}
+
+impl<I: Inspect> Inspect for Offloaded<I> {
+    fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Offloaded::Inline(inspect) => inspect.update(chunk),
+            Offloaded::Worker(worker) => {
+                let _ = worker.queue.send(Message::Chunk(Bytes::copy_from_slice(chunk), None));
+            }
+        }
+    }
+
+    async fn update_shared(&mut self, chunk: &Bytes) {
+        match self {
+            Offloaded::Inline(inspect) => inspect.update(chunk),
+            Offloaded::Worker(worker) => {
+                let permits = chunk.len().min(worker.queue_len) as u32;
+                let permit = Arc::clone(&worker.permits).acquire_many_owned(permits).await.ok();
+                let _ = worker.queue.send(Message::Chunk(chunk.clone(), permit));
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Offloaded::Inline(inspect) => inspect.reset(),
+            Offloaded::Worker(worker) => {
+                let _ = worker.queue.send(Message::Reset);
+            }
+        }
+    }
+
+    fn response(&mut self, response: &ResponseMetadata) {
+        match self {
+            Offloaded::Inline(inspect) => inspect.response(response),
+            Offloaded::Worker(worker) => {
+                let _ = worker.queue.send(Message::Response(response.clone()));
+            }
+        }
+    }
+
+    /// A worker only verifies what it has inspected so far, and is skipped while it is busy.
+    fn verify(&mut self) -> Result<(), TDSTDErrorKind> {
+        match self {
+            Offloaded::Inline(inspect) => inspect.verify(),
+            Offloaded::Worker(worker) => match worker.inspect.try_lock() {
+                Ok(mut inspect) => inspect.verify(),
+                Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().verify(),
+                Err(TryLockError::WouldBlock) => Ok(()),
+            },
+        }
+    }
+}
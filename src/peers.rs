@@ -0,0 +1,709 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, Bytes};
+use futures_util::stream::{self, FuturesUnordered, StreamExt};
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use reqwest::{Method, Url};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Notify;
+
+use crate::backend::{self, Body};
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::torrent::{Info, Value, MAX_TORRENT_LEN};
+use crate::{into_stream, local, policy, read_body, AsyncDownload, Failure};
+
+/// The protocol named in the handshake of BEP 3.
+const PROTOCOL: &[u8] = b"BitTorrent protocol";
+
+/// The bit of the reserved bytes of the handshake which offers the extension protocol of BEP 10.
+const EXTENSIONS: u8 = 0x10;
+
+/// The id peers are asked to send `ut_metadata` messages of BEP 9 with.
+const UT_METADATA: u8 = 1;
+
+/// How much of a piece is asked for in each request, which is the most peers are required to
+/// send, and how much of the info dictionary each metadata message carries.
+const BLOCK_LEN: usize = 16 * 1024;
+
+/// How many blocks are asked for at once, so that a peer is not waiting for the next request.
+const PIPELINE: usize = 16;
+
+/// The longest message accepted from a peer: a bitfield of a torrent as long as any which is
+/// accepted, or a block with its header.
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+/// The longest tracker response which is read.
+const MAX_ANNOUNCE_LEN: usize = 1024 * 1024;
+
+/// How long to wait for a peer to accept a connection, and for the messages it sends at first.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const GREETING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a peer may send nothing before it is given up on.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for each answer from a UDP tracker, which is asked again once before it is
+/// given up on.
+const UDP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many peers are connected to at once when none of those connected have a piece.
+const CONNECTING: usize = 8;
+
+/// How many peers are asked for a piece before the read fails.
+const PIECE_ATTEMPTS: usize = 4;
+
+/// The port announced to trackers.  Peers are never accepted, since nothing is uploaded.
+const PORT: u16 = 6881;
+
+/// The peers of a torrent, which answer requests for its magnet link as a server would,
+/// including a `Range` of the form downloads request.  Pieces are fetched from peers over the
+/// wire protocol of BEP 3 and checked against their SHA-1 hashes, one piece at a time for each
+/// request, so that segments are fetched from peers of their own.  Nothing is uploaded.
+pub(crate) struct Swarm {
+    info_hash: [u8; 20],
+    peer_id: [u8; 20],
+    trackers: Vec<String>,
+    /// Whether only peers with publicly routable addresses may be connected to.
+    public_only: bool,
+    /// What the torrent lists for its file, once it is known.
+    info: OnceLock<Info>,
+    peers: Mutex<Peers>,
+    connections: Arc<Connections>,
+}
+
+#[derive(Default)]
+struct Peers {
+    /// The addresses which have not been connected to yet.
+    untried: VecDeque<SocketAddr>,
+    /// Every address which has been heard of, so that none is connected to twice.
+    known: HashSet<SocketAddr>,
+    /// The connections which are not fetching a piece.
+    idle: Vec<Connection>,
+    announced: bool,
+}
+
+/// Counts the connections which are open or being opened, so that a read which needs a piece
+/// every peer with it is busy with waits for one to be given back, rather than failing.
+#[derive(Default)]
+struct Connections {
+    open: AtomicUsize,
+    changed: Notify,
+}
+
+/// One of the [`Connections`], which is counted until it is dropped.
+struct Counted(Arc<Connections>);
+
+impl Counted {
+    fn new(connections: &Arc<Connections>) -> Self {
+        connections.open.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(connections))
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+        self.0.changed.notify_waiters();
+    }
+}
+
+impl Swarm {
+    pub(crate) fn new(info_hash: [u8; 20], trackers: Vec<String>, public_only: bool) -> Self {
+        let random = || RandomState::new().build_hasher().finish().to_le_bytes();
+        let mut peer_id = *b"-TD0100-000000000000";
+        peer_id[8..16].copy_from_slice(&random());
+        peer_id[16..].copy_from_slice(&random()[..4]);
+        Self {
+            info_hash,
+            peer_id,
+            trackers,
+            public_only,
+            info: OnceLock::new(),
+            peers: Mutex::default(),
+            connections: Arc::default(),
+        }
+    }
+
+    /// Returns the magnet link which requests are made for.
+    pub(crate) fn url(&self) -> String {
+        let hash: String = self.info_hash.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("magnet:?xt=urn:btih:{}", hash)
+    }
+
+    pub(crate) fn info(&self) -> Option<&Info> {
+        self.info.get()
+    }
+
+    pub(crate) fn set_info(&self, info: Info) {
+        let _ = self.info.set(info);
+    }
+
+    /// Adds peers to connect to, skipping those already known and, if only public addresses
+    /// are allowed, those which are not.
+    pub(crate) fn add_peers(&self, addrs: impl IntoIterator<Item = SocketAddr>) {
+        let mut peers = self.peers.lock().unwrap();
+        for addr in addrs {
+            if addr.port() == 0 || (self.public_only && !policy::is_public(addr.ip())) {
+                continue;
+            }
+            if peers.known.insert(addr) {
+                peers.untried.push_back(addr);
+            }
+        }
+    }
+
+    /// Keeps `connection` for the next piece which its peer has.
+    fn give_back(&self, connection: Connection) {
+        self.peers.lock().unwrap().idle.push(connection);
+        self.connections.changed.notify_waiters();
+    }
+
+    /// Returns `true` if there is no peer left to connect to or reuse.
+    fn is_exhausted(&self) -> bool {
+        let peers = self.peers.lock().unwrap();
+        peers.untried.is_empty() && peers.idle.is_empty()
+    }
+
+    /// Answers `request` with the bytes of the file it asks for.
+    fn execute(self: &Arc<Self>, request: &reqwest::Request) -> Result<reqwest::Response, Failure> {
+        let info = self.info().ok_or_else(|| Failure::fatal(TDSTDErrorKind::InvalidTorrent))?;
+        let (builder, range) = local::partial(local::range(request), info.len);
+        let body: Body = match request.method() == Method::HEAD || range.is_empty() {
+            true => Box::pin(stream::empty()),
+            false => Box::pin(Arc::clone(self).read(range)),
+        };
+        let response = builder.body(body).map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))?;
+        backend::into_response(request.url().clone(), response)
+    }
+
+    /// Returns a stream of the bytes in `range`, fetched a piece at a time.  The connection is
+    /// kept from one piece to the next, and given back once the range has been read or the
+    /// stream is dropped.
+    fn read(self: Arc<Self>, range: Range<u64>) -> impl futures_util::Stream<Item = Result<Bytes, backend::BackendError>> + Send {
+        let held = Held { swarm: self, connection: None };
+        stream::unfold((held, range), |(mut held, range)| async move {
+            if range.is_empty() {
+                return None;
+            }
+            let piece_len = held.swarm.info().map_or(1, |info| info.piece_len);
+            let index = range.start / piece_len;
+            match held.swarm.piece(index, &mut held.connection).await {
+                Ok(piece) => {
+                    let start = (range.start - index * piece_len) as usize;
+                    let end = (range.end - index * piece_len).min(piece.len() as u64) as usize;
+                    let chunk = piece.slice(start..end);
+                    let range = range.start + chunk.len() as u64..range.end;
+                    Some((Ok(chunk), (held, range)))
+                }
+                Err(err) => Some((Err(err.into()), (held, range.end..range.end))),
+            }
+        })
+    }
+
+    /// Fetches and checks the piece at `index`, from the peer of `connection` if it has the
+    /// piece, and otherwise from another, which is left in `connection` for the next piece.  A
+    /// peer which fails to send the piece, or sends one which does not match its hash, is
+    /// disconnected.
+    async fn piece(&self, index: u64, connection: &mut Option<Connection>) -> Result<Bytes, IOError> {
+        let info = self.info().ok_or_else(|| IOError::other("the torrent is not known"))?;
+        let hash = info.pieces.get(index as usize).ok_or_else(|| IOError::other("piece out of range"))?;
+        let len = info.piece_len.min(info.len - index * info.piece_len) as usize;
+        for _ in 0..PIECE_ATTEMPTS {
+            let mut peer = match connection.take() {
+                Some(peer) if peer.has(index as usize) => peer,
+                other => {
+                    if let Some(peer) = other {
+                        self.give_back(peer);
+                    }
+                    self.peer_with(Some(index as usize)).await?
+                }
+            };
+            if let Ok(piece) = peer.fetch_piece(index as u32, len, hash).await {
+                *connection = Some(peer);
+                return Ok(piece);
+            }
+        }
+        Err(IOError::other("no peer sent a valid piece"))
+    }
+
+    /// Fetches the info dictionary from the peers, as in BEP 9, checking it against the info
+    /// hash.
+    pub(crate) async fn metadata(&self) -> Result<Vec<u8>, IOError> {
+        loop {
+            let mut peer = self.peer_with(None).await?;
+            if let Ok(info) = peer.fetch_metadata(&self.info_hash).await {
+                self.give_back(peer);
+                return Ok(info);
+            }
+        }
+    }
+
+    /// Returns a connection to a peer which has the piece at `index`, or which can send the info
+    /// dictionary if no piece is given, reusing an idle one if there is one.  Otherwise new
+    /// addresses are connected to, [`CONNECTING`] at a time, and the other peers which accept
+    /// are kept for later.  Once there are none left, this waits for a busy connection to be
+    /// given back, and fails if there is none.
+    async fn peer_with(&self, index: Option<usize>) -> Result<Connection, IOError> {
+        let wanted = |peer: &Connection| match index {
+            Some(index) => peer.has(index),
+            None => peer.metadata.is_some(),
+        };
+        loop {
+            let changed = self.connections.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            let (addrs, busy) = {
+                let mut peers = self.peers.lock().unwrap();
+                if let Some(i) = peers.idle.iter().position(wanted) {
+                    return Ok(peers.idle.swap_remove(i));
+                }
+                let n = peers.untried.len().min(CONNECTING);
+                let busy = self.connections.open.load(Ordering::SeqCst) > peers.idle.len();
+                // They are counted at once, so that no other read fails while they are opened.
+                let addrs: Vec<_> = peers.untried.drain(..n)
+                    .map(|addr| (addr, Counted::new(&self.connections)))
+                    .collect();
+                (addrs, busy)
+            };
+            if addrs.is_empty() {
+                if !busy {
+                    return Err(IOError::new(IOErrorKind::NotFound, "no peer has what was asked for"));
+                }
+                changed.await;
+                continue;
+            }
+            let mut pending: HashSet<SocketAddr> = addrs.iter().map(|(addr, _)| *addr).collect();
+            let mut connecting: FuturesUnordered<_> = addrs.into_iter()
+                .map(|(addr, counted)| async move { (addr, Connection::open(addr, &self.info_hash, &self.peer_id, counted).await) })
+                .collect();
+            let mut found = None;
+            while let Some((addr, result)) = connecting.next().await {
+                pending.remove(&addr);
+                match result {
+                    Ok(peer) if wanted(&peer) => {
+                        found = Some(peer);
+                        break;
+                    }
+                    Ok(peer) => self.give_back(peer),
+                    Err(_) => (),
+                }
+            }
+            // Those which had not answered yet are connected to again later.
+            self.peers.lock().unwrap().untried.extend(pending);
+            if let Some(peer) = found {
+                return Ok(peer);
+            }
+        }
+    }
+}
+
+/// The connection a read keeps between pieces, which is given back to the swarm when the read
+/// is done with it.  A connection is only left here between pieces, so one in the middle of
+/// fetching a piece is closed instead.
+struct Held {
+    swarm: Arc<Swarm>,
+    connection: Option<Connection>,
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.swarm.give_back(connection);
+        }
+    }
+}
+
+impl fmt::Debug for Swarm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Swarm").field("url", &self.url()).finish()
+    }
+}
+
+/// A message from a peer, of those which are acted on.
+enum Message {
+    Choke,
+    Unchoke,
+    Have(u32),
+    Bitfield(Bytes),
+    Piece { index: u32, begin: u32, block: Bytes },
+    /// A message of the extension protocol, with its extended id.
+    Extended(u8, Bytes),
+    Other,
+}
+
+/// A connection to a peer, after the handshake.
+struct Connection {
+    stream: BufReader<TcpStream>,
+    /// The pieces the peer has, the first in the highest bit of the first byte.
+    have: Vec<u8>,
+    choked: bool,
+    interested: bool,
+    /// The id the peer gave `ut_metadata`, and the length of the info dictionary, if it can send
+    /// it.
+    metadata: Option<(u8, usize)>,
+    _counted: Counted,
+}
+
+impl Connection {
+    /// Connects to the peer at `addr` and exchanges handshakes, then reads the messages it sends
+    /// at first, such as which pieces it has.  `counted` counts the connection while it is open.
+    async fn open(addr: SocketAddr, info_hash: &[u8; 20], peer_id: &[u8; 20], counted: Counted) -> Result<Self, IOError> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await
+            .map_err(|_| IOError::from(IOErrorKind::TimedOut))??;
+        let mut connection = Self {
+            stream: BufReader::new(stream),
+            have: Vec::new(),
+            choked: true,
+            interested: false,
+            metadata: None,
+            _counted: counted,
+        };
+        let mut handshake = Vec::with_capacity(68);
+        handshake.push(PROTOCOL.len() as u8);
+        handshake.extend_from_slice(PROTOCOL);
+        handshake.extend_from_slice(&[0, 0, 0, 0, 0, EXTENSIONS, 0, 0]);
+        handshake.extend_from_slice(info_hash);
+        handshake.extend_from_slice(peer_id);
+        connection.stream.get_mut().write_all(&handshake).await?;
+        let mut answer = [0; 68];
+        tokio::time::timeout(CONNECT_TIMEOUT, connection.stream.read_exact(&mut answer)).await
+            .map_err(|_| IOError::from(IOErrorKind::TimedOut))??;
+        if answer[0] as usize != PROTOCOL.len() || &answer[1..20] != PROTOCOL || &answer[28..48] != info_hash {
+            return Err(malformed("the peer sent another handshake"));
+        }
+        let extended = answer[25] & EXTENSIONS != 0;
+        if extended {
+            connection.send(20, &[&[0], b"d1:md11:ut_metadatai1eee"]).await?;
+        }
+        let deadline = Instant::now() + GREETING_TIMEOUT;
+        while connection.have.is_empty() || (extended && connection.metadata.is_none()) {
+            match tokio::time::timeout_at(deadline.into(), connection.receive()).await {
+                Ok(message) => drop(message?),
+                Err(_) => break,
+            }
+        }
+        Ok(connection)
+    }
+
+    fn has(&self, index: usize) -> bool {
+        self.have.get(index / 8).is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    /// Sends a message with `id` and a payload made of `parts`.
+    async fn send(&mut self, id: u8, parts: &[&[u8]]) -> Result<(), IOError> {
+        let len = 1 + parts.iter().map(|part| part.len()).sum::<usize>();
+        let mut message = Vec::with_capacity(4 + len);
+        message.extend_from_slice(&(len as u32).to_be_bytes());
+        message.push(id);
+        for part in parts {
+            message.extend_from_slice(part);
+        }
+        self.stream.get_mut().write_all(&message).await
+    }
+
+    /// Reads the next message, other than a keep-alive, and updates what is known of the peer.
+    async fn receive(&mut self) -> Result<Message, IOError> {
+        let data = loop {
+            let len = tokio::time::timeout(READ_TIMEOUT, self.stream.read_u32()).await
+                .map_err(|_| IOError::from(IOErrorKind::TimedOut))?? as usize;
+            if len > MAX_MESSAGE_LEN {
+                return Err(malformed("the peer sent a message which is too long"));
+            }
+            if len > 0 {
+                let mut data = vec![0; len];
+                tokio::time::timeout(READ_TIMEOUT, self.stream.read_exact(&mut data)).await
+                    .map_err(|_| IOError::from(IOErrorKind::TimedOut))??;
+                break Bytes::from(data);
+            }
+        };
+        let (id, mut payload) = (data[0], data.slice(1..));
+        let message = match id {
+            0 => Message::Choke,
+            1 => Message::Unchoke,
+            4 if payload.len() == 4 => Message::Have(payload.get_u32()),
+            5 => Message::Bitfield(payload),
+            7 if payload.len() >= 8 => Message::Piece { index: payload.get_u32(), begin: payload.get_u32(), block: payload },
+            20 if !payload.is_empty() => Message::Extended(payload[0], payload.slice(1..)),
+            _ => Message::Other,
+        };
+        match message {
+            Message::Choke => self.choked = true,
+            Message::Unchoke => self.choked = false,
+            Message::Have(index) => {
+                let byte = index as usize / 8;
+                if byte < MAX_MESSAGE_LEN {
+                    if self.have.len() <= byte {
+                        self.have.resize(byte + 1, 0);
+                    }
+                    self.have[byte] |= 0x80 >> (index % 8);
+                }
+            }
+            Message::Bitfield(ref bits) => self.have = bits.to_vec(),
+            Message::Extended(0, ref handshake) => {
+                if let Some((handshake, _)) = Value::parse(handshake, 0) {
+                    let id = handshake.get(b"m").and_then(|m| m.get(b"ut_metadata")).and_then(Value::int);
+                    let len = handshake.get(b"metadata_size").and_then(Value::int);
+                    if let (Some(id @ 1..=255), Some(len @ 1..)) = (id, len) {
+                        self.metadata = Some((id as u8, len as usize));
+                    }
+                }
+            }
+            _ => (),
+        }
+        Ok(message)
+    }
+
+    /// Fetches the piece at `index`, which is `len` bytes long, and checks it against `hash`.
+    async fn fetch_piece(&mut self, index: u32, len: usize, hash: &[u8]) -> Result<Bytes, IOError> {
+        if !self.interested {
+            self.send(2, &[]).await?;
+            self.interested = true;
+        }
+        let blocks = len.div_ceil(BLOCK_LEN);
+        let block_len = |block: usize| BLOCK_LEN.min(len - block * BLOCK_LEN);
+        let mut piece = vec![0; len];
+        let mut received = vec![false; blocks];
+        let (mut left, mut next, mut requested) = (blocks, 0, 0);
+        while left > 0 {
+            while !self.choked && requested < PIPELINE && next < blocks {
+                if !received[next] {
+                    let begin = (next * BLOCK_LEN) as u32;
+                    let request_len = block_len(next) as u32;
+                    self.send(6, &[&index.to_be_bytes(), &begin.to_be_bytes(), &request_len.to_be_bytes()]).await?;
+                    requested += 1;
+                }
+                next += 1;
+            }
+            match self.receive().await? {
+                Message::Piece { index: i, begin, block } if i == index => {
+                    let block_index = begin as usize / BLOCK_LEN;
+                    if !(begin as usize).is_multiple_of(BLOCK_LEN) || block_index >= blocks || block.len() != block_len(block_index) {
+                        return Err(malformed("the peer sent a block which was not asked for"));
+                    }
+                    if !received[block_index] {
+                        piece[begin as usize..begin as usize + block.len()].copy_from_slice(&block);
+                        received[block_index] = true;
+                        left -= 1;
+                    }
+                    requested = requested.saturating_sub(1);
+                }
+                // The peer drops the requests it has not answered when it chokes, so those are
+                // asked for again once it unchokes.
+                Message::Choke => (next, requested) = (0, 0),
+                _ => (),
+            }
+        }
+        if Sha1::digest(&piece).as_slice() != hash {
+            return Err(malformed("the peer sent a piece which does not match its hash"));
+        }
+        Ok(Bytes::from(piece))
+    }
+
+    /// Fetches the info dictionary with `ut_metadata`, as in BEP 9, and checks it against
+    /// `info_hash`.
+    async fn fetch_metadata(&mut self, info_hash: &[u8; 20]) -> Result<Vec<u8>, IOError> {
+        let (id, len) = self.metadata.ok_or_else(|| malformed("the peer cannot send the info dictionary"))?;
+        if len > MAX_TORRENT_LEN {
+            return Err(malformed("the info dictionary is too long"));
+        }
+        let mut info = Vec::with_capacity(len);
+        for piece in 0..len.div_ceil(BLOCK_LEN) {
+            let request = format!("d8:msg_typei0e5:piecei{}ee", piece);
+            self.send(20, &[&[id], request.as_bytes()]).await?;
+            loop {
+                let Message::Extended(UT_METADATA, message) = self.receive().await? else {
+                    continue;
+                };
+                let (header, data) = Value::parse(&message, 0).ok_or_else(|| malformed("the peer sent a malformed message"))?;
+                if header.get(b"piece").and_then(Value::int) != Some(piece as u64) {
+                    continue;
+                }
+                if header.get(b"msg_type").and_then(Value::int) != Some(1) || data.len() != BLOCK_LEN.min(len - info.len()) {
+                    return Err(malformed("the peer did not send the info dictionary"));
+                }
+                info.extend_from_slice(data);
+                break;
+            }
+        }
+        if Sha1::digest(&info).as_slice() != info_hash {
+            return Err(malformed("the info dictionary does not match the info hash"));
+        }
+        Ok(info)
+    }
+}
+
+impl AsyncDownload {
+    /// Answers a request for the magnet link of the download's swarm, asking its trackers for
+    /// more peers first if every one has been tried.
+    pub(crate) async fn execute_swarm(&self, request: &reqwest::Request) -> Result<reqwest::Response, Failure> {
+        let swarm = self.config.swarm.as_ref().ok_or_else(|| Failure::fatal(TDSTDErrorKind::InvalidTorrent))?;
+        if swarm.is_exhausted() {
+            self.announce(swarm).await;
+        }
+        swarm.execute(request)
+    }
+
+    /// Asks every tracker of `swarm` for peers, adding those they list.  Trackers which cannot
+    /// be reached, or are not allowed by the policy, are skipped.
+    pub(crate) async fn announce(&self, swarm: &Swarm) {
+        let event = match std::mem::replace(&mut swarm.peers.lock().unwrap().announced, true) {
+            false => "&event=started",
+            true => "",
+        };
+        // Trackers may not list seeds to a peer which is not missing anything.
+        let left = swarm.info().map_or(1, |info| info.len);
+        let announces = swarm.trackers.iter().map(|tracker| async move {
+            let url = Url::parse(tracker).ok()?;
+            if let Some(ref policy) = self.config.policy {
+                policy.check_url(&url).ok()?;
+            }
+            match url.scheme() {
+                "http" | "https" => self.announce_http(url, swarm, left, event).await.ok(),
+                "udp" => announce_udp(&url, swarm, left, event.is_empty()).await.ok(),
+                _ => None,
+            }
+        });
+        for peers in futures_util::future::join_all(announces).await.into_iter().flatten() {
+            swarm.add_peers(peers);
+        }
+    }
+
+    /// Announces to an HTTP tracker, as in BEP 3, and returns the peers it lists, in the compact
+    /// form of BEP 23 or BEP 7 or as dictionaries.
+    async fn announce_http(&self, mut url: Url, swarm: &Swarm, left: u64, event: &str) -> Result<Vec<SocketAddr>, Failure> {
+        let query = format!(
+            "{}info_hash={}&peer_id={}&port={}&uploaded=0&downloaded=0&left={}&compact=1&numwant=50{}",
+            url.query().map_or(String::new(), |query| format!("{}&", query)),
+            percent_encode(&swarm.info_hash, NON_ALPHANUMERIC),
+            percent_encode(&swarm.peer_id, NON_ALPHANUMERIC),
+            PORT,
+            left,
+            event,
+        );
+        url.set_query(Some(&query));
+        let (response, _) = self.send(self.request_with(Method::GET, url.as_str())).await?;
+        if !response.status().is_success() {
+            return Err(Failure::from_status(response));
+        }
+        let invalid = || Failure::fatal(TDSTDErrorKind::InvalidResponse);
+        let mut stream = into_stream(response);
+        let mut data = Vec::new();
+        while let Some(chunk) = read_body(&mut stream, &self.config, &mut None).await? {
+            if data.len() + chunk.len() > MAX_ANNOUNCE_LEN {
+                return Err(invalid());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let (response, _) = Value::parse(&data, 0).ok_or_else(invalid)?;
+        let mut peers = match response.get(b"peers") {
+            Some(Value::Bytes(compact)) => compact_peers(compact, 4),
+            Some(Value::List(peers)) => peers.iter()
+                .filter_map(|peer| {
+                    let ip: IpAddr = std::str::from_utf8(peer.get(b"ip")?.bytes()?).ok()?.parse().ok()?;
+                    let port = u16::try_from(peer.get(b"port")?.int()?).ok()?;
+                    Some(SocketAddr::new(ip, port))
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let Some(compact) = response.get(b"peers6").and_then(Value::bytes) {
+            peers.extend(compact_peers(compact, 16));
+        }
+        Ok(peers)
+    }
+}
+
+/// Announces to a UDP tracker, as in BEP 15, and returns the peers it lists.  If only public
+/// addresses are allowed, the tracker's address must be one.
+async fn announce_udp(url: &Url, swarm: &Swarm, left: u64, again: bool) -> Result<Vec<SocketAddr>, IOError> {
+    let host = url.host_str().ok_or_else(|| malformed("the tracker has no host"))?;
+    let port = url.port().ok_or_else(|| malformed("the tracker has no port"))?;
+    let addr = tokio::net::lookup_host((host.trim_start_matches('[').trim_end_matches(']'), port)).await?
+        .find(|addr| !swarm.public_only || policy::is_public(addr.ip()))
+        .ok_or_else(|| malformed("the tracker has no address which may be connected to"))?;
+    let socket = match addr {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?,
+    };
+    socket.connect(addr).await?;
+    let transaction = || (RandomState::new().build_hasher().finish() as u32).to_be_bytes();
+
+    let id = transaction();
+    let mut connect = 0x41727101980u64.to_be_bytes().to_vec();
+    connect.extend_from_slice(&0u32.to_be_bytes());
+    connect.extend_from_slice(&id);
+    let answer = exchange(&socket, &connect, 0, id).await?;
+    let connection_id = answer.get(..8).ok_or_else(|| malformed("the tracker sent a malformed answer"))?;
+
+    let id = transaction();
+    let mut announce = connection_id.to_vec();
+    announce.extend_from_slice(&1u32.to_be_bytes());
+    announce.extend_from_slice(&id);
+    announce.extend_from_slice(&swarm.info_hash);
+    announce.extend_from_slice(&swarm.peer_id);
+    announce.extend_from_slice(&0u64.to_be_bytes());
+    announce.extend_from_slice(&left.to_be_bytes());
+    announce.extend_from_slice(&0u64.to_be_bytes());
+    // The event is `started` the first time, and none after.
+    announce.extend_from_slice(&if again { 0u32 } else { 2u32 }.to_be_bytes());
+    announce.extend_from_slice(&[0; 4]);
+    announce.extend_from_slice(&transaction());
+    announce.extend_from_slice(&(-1i32).to_be_bytes());
+    announce.extend_from_slice(&PORT.to_be_bytes());
+    let answer = exchange(&socket, &announce, 1, id).await?;
+    let peers = answer.get(12..).ok_or_else(|| malformed("the tracker sent a malformed answer"))?;
+    Ok(compact_peers(peers, if addr.is_ipv4() { 4 } else { 16 }))
+}
+
+/// Sends `packet` to a UDP tracker and returns the rest of its answer for `action`, after the
+/// action and `transaction` id.  The packet is sent again once if no answer arrives.
+async fn exchange(socket: &UdpSocket, packet: &[u8], action: u32, transaction: [u8; 4]) -> Result<Vec<u8>, IOError> {
+    let mut buf = vec![0; 64 * 1024];
+    for _ in 0..2 {
+        socket.send(packet).await?;
+        let deadline = Instant::now() + UDP_TIMEOUT;
+        while let Ok(received) = tokio::time::timeout_at(deadline.into(), socket.recv(&mut buf)).await {
+            let answer = &buf[..received?];
+            if answer.len() < 8 || answer[4..8] != transaction {
+                continue;
+            }
+            let answered = u32::from_be_bytes([answer[0], answer[1], answer[2], answer[3]]);
+            if answered == 3 {
+                return Err(IOError::other(String::from_utf8_lossy(&answer[8..]).into_owned()));
+            }
+            if answered != action {
+                return Err(malformed("the tracker sent a malformed answer"));
+            }
+            return Ok(answer[8..].to_vec());
+        }
+    }
+    Err(IOError::from(IOErrorKind::TimedOut))
+}
+
+/// Parses peers in the compact form, an address `ip_len` bytes long followed by a port.
+fn compact_peers(data: &[u8], ip_len: usize) -> Vec<SocketAddr> {
+    data.chunks_exact(ip_len + 2)
+        .map(|peer| {
+            let ip = match ip_len {
+                4 => IpAddr::from(<[u8; 4]>::try_from(&peer[..4]).unwrap()),
+                _ => IpAddr::from(<[u8; 16]>::try_from(&peer[..16]).unwrap()),
+            };
+            SocketAddr::new(ip, u16::from_be_bytes([peer[ip_len], peer[ip_len + 1]]))
+        })
+        .collect()
+}
+
+fn malformed(message: &str) -> IOError {
+    IOError::new(IOErrorKind::InvalidData, message)
+}
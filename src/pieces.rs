@@ -0,0 +1,118 @@
+use std::fmt;
+use std::sync::Arc;
+
+use digest::DynDigest;
+
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::Inspect;
+
+/// The expected hashes of the fixed-size pieces of a download.
+pub(crate) struct PieceHashes {
+    piece_len: u64,
+    hashes: Vec<Vec<u8>>,
+    new_hasher: fn() -> Box<dyn DynDigest + Send>,
+}
+
+impl PieceHashes {
+    pub(crate) fn new<D: DynDigest + Default + Send + 'static>(piece_len: u64, hashes: Vec<Vec<u8>>) -> Self {
+        fn new_hasher<D: DynDigest + Default + Send + 'static>() -> Box<dyn DynDigest + Send> {
+            Box::new(D::default())
+        }
+        Self {
+            piece_len,
+            hashes,
+            new_hasher: new_hasher::<D>,
+        }
+    }
+
+    /// Returns the error for the piece at `index`, covering as much of it as was downloaded.
+    fn mismatch(&self, index: usize, len: u64) -> TDSTDErrorKind {
+        TDSTDErrorKind::PieceMismatch {
+            piece: index,
+            offset: index as u64 * self.piece_len,
+            len,
+        }
+    }
+}
+
+impl fmt::Debug for PieceHashes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PieceHashes")
+            .field("piece_len", &self.piece_len)
+            .field("pieces", &self.hashes.len())
+            .finish()
+    }
+}
+
+/// Hashes every piece of the download as it is completed, remembering the first one which does
+/// not match.
+pub(crate) struct PieceVerifier {
+    pieces: Arc<PieceHashes>,
+    hasher: Box<dyn DynDigest + Send>,
+    index: usize,
+    filled: u64,
+    /// The index and length of the first piece which did not match.
+    mismatch: Option<(usize, u64)>,
+}
+
+impl PieceVerifier {
+    pub(crate) fn new(pieces: Arc<PieceHashes>) -> Self {
+        Self {
+            hasher: (pieces.new_hasher)(),
+            pieces,
+            index: 0,
+            filled: 0,
+            mismatch: None,
+        }
+    }
+
+    /// Checks the piece being hashed, which is complete, and moves on to the next.
+    fn finish_piece(&mut self) {
+        let actual = self.hasher.finalize_reset();
+        let matches = self.pieces.hashes.get(self.index).is_some_and(|expected| **expected == *actual);
+        if !matches && self.mismatch.is_none() {
+            self.mismatch = Some((self.index, self.filled));
+        }
+        self.index += 1;
+        self.filled = 0;
+    }
+
+    /// Checks the last piece once the download is complete, and that no pieces are missing.
+    pub(crate) fn check(mut self) -> Result<(), TDSTDErrorKind> {
+        if self.filled > 0 {
+            self.finish_piece();
+        }
+        if self.mismatch.is_none() && self.index < self.pieces.hashes.len() {
+            return Err(self.pieces.mismatch(self.index, 0));
+        }
+        self.verify()
+    }
+}
+
+impl Inspect for PieceVerifier {
+    fn update(&mut self, mut chunk: &[u8]) {
+        while !chunk.is_empty() {
+            let take = chunk.len().min((self.pieces.piece_len - self.filled) as usize);
+            self.hasher.update(&chunk[..take]);
+            self.filled += take as u64;
+            chunk = &chunk[take..];
+            if self.filled == self.pieces.piece_len {
+                self.finish_piece();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.hasher.reset();
+        self.index = 0;
+        self.filled = 0;
+        self.mismatch = None;
+    }
+
+    fn verify(&mut self) -> Result<(), TDSTDErrorKind> {
+        match self.mismatch {
+            Some((index, len)) => Err(self.pieces.mismatch(index, len)),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use reqwest::tls::TlsInfo;
+use sha2::{Digest, Sha256};
+
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::{AsyncDownload, Failure};
+
+/// A sha256 digest which the certificate presented by the server must match.
+#[derive(Clone, Debug)]
+pub(crate) enum Pin {
+    /// The digest of the whole DER-encoded certificate.
+    Certificate([u8; 32]),
+    /// The digest of the DER-encoded `SubjectPublicKeyInfo` of the certificate, which stays the
+    /// same when a certificate is renewed with the same key.
+    Spki([u8; 32]),
+}
+
+impl AsyncDownload {
+    /// Checks the certificate of a response against the configured pins, if there are any.
+    pub(crate) fn check_pins(&self, response: &reqwest::Response) -> Result<(), Failure> {
+        if self.config.pins.is_empty() || matches(&self.config.pins, response) {
+            Ok(())
+        } else {
+            Err(Failure::fatal(TDSTDErrorKind::PinMismatch))
+        }
+    }
+}
+
+/// Returns `true` if the certificate of a response matches any of the pins.  A response without
+/// a certificate, e.g. because it was not made over TLS, matches none of them.
+fn matches(pins: &[Pin], response: &reqwest::Response) -> bool {
+    let Some(cert) = response.extensions().get::<TlsInfo>().and_then(TlsInfo::peer_certificate) else {
+        return false;
+    };
+    let cert_digest: [u8; 32] = Sha256::digest(cert).into();
+    let spki_digest: Option<[u8; 32]> = spki(cert).map(|spki| Sha256::digest(spki).into());
+    pins.iter().any(|pin| match pin {
+        Pin::Certificate(digest) => *digest == cert_digest,
+        Pin::Spki(digest) => Some(*digest) == spki_digest,
+    })
+}
+
+/// Returns the DER encoding of the `SubjectPublicKeyInfo` of a DER-encoded X.509 certificate.
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = read_tlv(cert, SEQUENCE)?;
+    let (_, mut tbs, _) = read_tlv(cert, SEQUENCE)?;
+    // The version is an optional explicitly tagged field.
+    if tbs.first() == Some(&0xa0) {
+        tbs = read_tlv(tbs, 0xa0)?.2;
+    }
+    // The serial number, signature algorithm, issuer, validity and subject.
+    for tag in [INTEGER, SEQUENCE, SEQUENCE, SEQUENCE, SEQUENCE] {
+        tbs = read_tlv(tbs, tag)?.2;
+    }
+    let (spki, _, _) = read_tlv(tbs, SEQUENCE)?;
+    Some(spki)
+}
+
+const INTEGER: u8 = 0x02;
+const SEQUENCE: u8 = 0x30;
+
+/// Reads a DER element with the given tag from the start of `input`, returning the whole
+/// element, its contents and the rest of the input.
+fn read_tlv(input: &[u8], tag: u8) -> Option<(&[u8], &[u8], &[u8])> {
+    let (&first, rest) = input.split_first()?;
+    let (&len, mut rest) = rest.split_first()?;
+    if first != tag {
+        return None;
+    }
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let octets = (len & 0x7f) as usize;
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let (len, after) = rest.split_at(octets);
+        rest = after;
+        len.iter().fold(0, |acc, &b| (acc << 8) | b as usize)
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header = input.len() - rest.len();
+    Some((&input[..header + len], &rest[..len], &rest[len..]))
+}
@@ -0,0 +1,175 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use reqwest::Url;
+
+/// Restrictions on what a download may connect to, for services which download URLs supplied by
+/// their users.  The policy is checked for the download URL and any mirrors when the download
+/// is built, and again for every redirect and every address a host name resolves to, so a
+/// redirect or DNS record cannot lead the download somewhere it was not allowed to go.  A
+/// violation fails the download with `PolicyViolation` and is never retried.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use tokio_dl_stream_to_disk::{AsyncDownload, Policy};
+///
+/// # fn run(user_url: &str) -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+/// let download = AsyncDownload::builder()
+///     .url(user_url)
+///     .dst_dir("/tmp")
+///     .policy(Policy::strict())
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    https_only: bool,
+    same_host_redirects: bool,
+    public_addresses_only: bool,
+}
+
+impl Policy {
+    /// Returns a policy which allows everything, to enable restrictions on individually.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a policy which enables every restriction.
+    pub fn strict() -> Self {
+        Self {
+            https_only: true,
+            same_host_redirects: true,
+            public_addresses_only: true,
+        }
+    }
+
+    /// Reject URLs, including redirects, which do not use `https`.
+    pub fn https_only(mut self, enabled: bool) -> Self {
+        self.https_only = enabled;
+        self
+    }
+
+    /// Reject redirects to a host other than the one the request was made to.
+    pub fn same_host_redirects(mut self, enabled: bool) -> Self {
+        self.same_host_redirects = enabled;
+        self
+    }
+
+    /// Reject connections to loopback, private, link-local and other addresses which are not
+    /// publicly routable, whether they appear in a URL or a host name resolves to them.  `file`
+    /// URLs are rejected as well, as are `sftp` and `scp` URLs, whose addresses are not checked.
+    /// IPv6 addresses which embed an IPv4 address, such as NAT64 and 6to4 addresses, are checked
+    /// by the address they embed.
+    ///
+    /// When the download goes through a proxy, the proxy resolves host names, so only addresses
+    /// which appear in URLs are checked and the addresses host names resolve to are not.
+    pub fn public_addresses_only(mut self, enabled: bool) -> Self {
+        self.public_addresses_only = enabled;
+        self
+    }
+
+    /// Checks the scheme and, if it is an IP address, the host of a URL.
+    pub(crate) fn check_url(&self, url: &Url) -> Result<(), Violation> {
+        if self.https_only && url.scheme() != "https" {
+            return Err(Violation("URL does not use https"));
+        }
+        if self.public_addresses_only {
+            match url.scheme() {
+                "file" => return Err(Violation("URL refers to a local file")),
+                // The ssh client resolves the host itself, so its addresses cannot be checked.
+                "sftp" | "scp" => return Err(Violation("URL is read over SSH")),
+                _ => (),
+            }
+            let ip = url.host_str()
+                .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+                .and_then(|host| host.parse::<IpAddr>().ok());
+            if ip.is_some_and(|ip| !is_public(ip)) {
+                return Err(Violation("URL refers to an address which is not public"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the URL a response redirects to.
+    pub(crate) fn check_redirect(&self, from: &Url, to: &Url) -> Result<(), Violation> {
+        if self.same_host_redirects && to.host_str() != from.host_str() {
+            return Err(Violation("redirect to a different host"));
+        }
+        self.check_url(to)
+    }
+
+    /// Returns `true` if the addresses host names resolve to must be checked.
+    pub(crate) fn checks_addresses(&self) -> bool {
+        self.public_addresses_only
+    }
+}
+
+/// The reason a URL or connection was rejected by a [`Policy`].
+#[derive(Debug)]
+pub(crate) struct Violation(pub(crate) &'static str);
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl StdError for Violation {}
+
+/// Returns `true` if the address is publicly routable.
+pub(crate) fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+/// Returns the IPv4 address embedded in an IPv6 address which reaches it through a translator
+/// or tunnel: IPv4-mapped `::ffff:0:0/96`, IPv4-compatible `::/96`, NAT64 `64:ff9b::/96` and
+/// 6to4 `2002::/16` addresses.
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let s = ip.segments();
+    let v4 = |high: u16, low: u16| Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)));
+    match s {
+        [0, 0, 0, 0, 0, 0 | 0xffff, high, low] => v4(high, low),
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => v4(high, low),
+        [0x2002, high, low, ..] => v4(high, low),
+        _ => None,
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", shared address space for carrier-grade NAT, and reserved.
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let s = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // Deprecated site-local addresses, fec0::/10.
+        || (s[0] & 0xffc0) == 0xfec0
+        // Discard prefix, 100::/64.
+        || s[..4] == [0x100, 0, 0, 0]
+        // Documentation prefix, 2001:db8::/32.
+        || (s[0] == 0x2001 && s[1] == 0xdb8))
+}
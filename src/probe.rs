@@ -0,0 +1,194 @@
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{Method, StatusCode};
+
+use crate::error::Error as TDSTDError;
+use crate::result::ResponseMetadata;
+use crate::{parse_content_range, AsyncDownload};
+
+/// What the server reports about a download before it is fetched, as returned by
+/// [`AsyncDownload::probe`].
+#[derive(Clone, Debug)]
+pub struct Probe {
+    length: Option<u64>,
+    resumable: bool,
+    metadata: ResponseMetadata,
+}
+
+impl Probe {
+    /// Returns the length of the download in bytes, if the server reported it.
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Returns `true` if the server supports byte ranges, so an interrupted download can be
+    /// resumed or split into segments.
+    pub fn resumable(&self) -> bool {
+        self.resumable
+    }
+
+    /// Returns the `Content-Type` of the download.
+    pub fn content_type(&self) -> Option<&str> {
+        self.metadata.content_type()
+    }
+
+    /// Returns the `Last-Modified` date of the download, as sent by the server.
+    pub fn last_modified(&self) -> Option<&str> {
+        self.metadata.last_modified()
+    }
+
+    /// Returns the metadata of the response the probe was answered with.
+    pub fn metadata(&self) -> &ResponseMetadata {
+        &self.metadata
+    }
+}
+
+/// How far the server supports resuming a download, as returned by
+/// [`AsyncDownload::supports_resume`].
+#[derive(Clone, Debug)]
+pub struct ResumeSupport {
+    length: Option<u64>,
+    accepts_ranges: bool,
+    ranges: bool,
+    strong_etag: bool,
+    head: bool,
+}
+
+impl ResumeSupport {
+    /// Returns the length of the download in bytes, if the server reported it.
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Returns `true` if the server advertised support for byte ranges with
+    /// `Accept-Ranges: bytes`, whether or not it honored the range requested.
+    pub fn accepts_ranges(&self) -> bool {
+        self.accepts_ranges
+    }
+
+    /// Returns `true` if the server answered a range request with `206 Partial Content` and the
+    /// range requested, whatever it advertised.
+    pub fn ranges(&self) -> bool {
+        self.ranges
+    }
+
+    /// Returns `true` if the server sent a strong `ETag`, which makes sure a resumed download
+    /// continues the same file with `If-Range`.
+    pub fn strong_etag(&self) -> bool {
+        self.strong_etag
+    }
+
+    /// Returns `true` if the server answered a `HEAD` request successfully, with the same length
+    /// and `ETag`, if both have one, as a `GET`, so that [`probe`](AsyncDownload::probe) can rely on it.
+    pub fn head(&self) -> bool {
+        self.head
+    }
+
+    /// Returns `true` if an interrupted download can be resumed with
+    /// [`resume`](AsyncDownload::resume) rather than started again.
+    pub fn can_resume(&self) -> bool {
+        self.ranges
+    }
+
+    /// Returns `true` if the download can be split into
+    /// [`segments`](crate::AsyncDownloadBuilder::segments), which needs ranges and a known length.
+    pub fn can_segment(&self) -> bool {
+        self.ranges && self.length.is_some()
+    }
+}
+
+impl AsyncDownload {
+    /// Ask the server about the download without downloading it.  A `HEAD` request is made, and
+    /// if the server does not answer it successfully, a `GET` for the first byte is made instead.
+    /// The configured headers are sent, but the configured method is not used.
+    pub async fn probe(&self) -> Result<Probe, TDSTDError> {
+        if let Ok((response, redirects)) = self.send(self.request_with(Method::HEAD, &self.url)).await {
+            if response.status().is_success() {
+                let metadata = ResponseMetadata::new(&response, redirects);
+                return Ok(Probe {
+                    length: metadata.content_length(),
+                    resumable: metadata.accepts_ranges(),
+                    metadata,
+                });
+            }
+        }
+
+        let (response, redirects) = self.send(self.request_with(Method::GET, &self.url).header(RANGE, "bytes=0-0")).await?;
+        let response = response.error_for_status()?;
+        let metadata = ResponseMetadata::new(&response, redirects);
+        let probe = if response.status() == StatusCode::PARTIAL_CONTENT {
+            Probe {
+                length: metadata.headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_content_range)
+                    .and_then(|(_, _, total)| total),
+                resumable: true,
+                metadata,
+            }
+        } else {
+            Probe {
+                length: metadata.content_length(),
+                resumable: metadata.accepts_ranges(),
+                metadata,
+            }
+        };
+        Ok(probe)
+    }
+
+    /// Check how far the server supports resuming the download, without downloading it, to
+    /// choose between resuming, segmented and plain downloads.  A `HEAD` request is made, and a
+    /// `GET` for the first byte, since servers do not always honor ranges they advertise, or
+    /// answer `HEAD` the way they answer `GET`.  The configured headers are sent, but the
+    /// configured method is not used.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// let support = AsyncDownload::new("https://example.com/disk.img", "/tmp", "")?
+    ///     .supports_resume()
+    ///     .await?;
+    /// let segments = if support.can_segment() { 4 } else { 1 };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn supports_resume(&self) -> Result<ResumeSupport, TDSTDError> {
+        let head = match self.send(self.request_with(Method::HEAD, &self.url)).await {
+            Ok((response, redirects)) if response.status().is_success() => Some(ResponseMetadata::new(&response, redirects)),
+            _ => None,
+        };
+
+        let (response, redirects) = self.send(self.request_with(Method::GET, &self.url).header(RANGE, "bytes=0-0")).await?;
+        let response = response.error_for_status()?;
+        let metadata = ResponseMetadata::new(&response, redirects);
+        let range = match response.status() {
+            StatusCode::PARTIAL_CONTENT => metadata.headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_content_range),
+            _ => None,
+        };
+        let ranges = range.is_some_and(|(start, _, _)| start == 0);
+        let length = match range {
+            Some((_, _, total)) => total,
+            None => metadata.content_length(),
+        };
+        let etag = metadata.etag()
+            .or_else(|| head.as_ref().and_then(ResponseMetadata::etag));
+        Ok(ResumeSupport {
+            length,
+            accepts_ranges: metadata.accepts_ranges() || head.as_ref().is_some_and(ResponseMetadata::accepts_ranges),
+            ranges,
+            strong_etag: etag.is_some_and(|etag| !etag.starts_with("W/")),
+            head: head.as_ref().is_some_and(|head| {
+                let same_etag = match (head.etag(), metadata.etag()) {
+                    (Some(head_etag), Some(etag)) => head_etag == etag,
+                    _ => true,
+                };
+                head.content_length() == length && same_etag
+            }),
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::{date, filename, AsyncDownload};
+
+/// Where a completed download came from, recorded alongside it with
+/// [`source_metadata`](crate::AsyncDownloadBuilder::source_metadata).
+pub(crate) struct Source {
+    url: String,
+    #[cfg(target_os="macos")]
+    requested: String,
+    content_type: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    downloaded: String,
+    sha256: Option<String>,
+}
+
+impl Source {
+    /// Returns the fields which are known, with their names in the sidecar and as extended
+    /// attributes.
+    fn fields(&self) -> Vec<(&'static str, &'static str, &str)> {
+        [
+            ("url", "user.xdg.origin.url", Some(&self.url)),
+            ("content_type", "user.mime_type", self.content_type.as_ref()),
+            ("etag", "user.etag", self.etag.as_ref()),
+            ("last_modified", "user.last_modified", self.last_modified.as_ref()),
+            ("downloaded", "user.downloaded", Some(&self.downloaded)),
+            ("sha256", "user.checksum.sha256", self.sha256.as_ref()),
+        ].into_iter()
+            .filter_map(|(key, name, value)| Some((key, name, value?.as_str())))
+            .collect()
+    }
+
+    /// Sets the extended attributes of the file at `path`.
+    pub(crate) async fn set_xattrs(&self, path: &Path) -> Result<(), IOError> {
+        for (_, name, value) in self.fields() {
+            #[cfg(target_os="macos")]
+            let name = name.strip_prefix("user.").unwrap_or(name);
+            set_xattr(path, name, value.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os="macos")]
+    /// Sets the Gatekeeper quarantine attribute of the file at `path`, as downloaded by `agent`,
+    /// and the URLs it was downloaded from.
+    pub(crate) async fn set_quarantine(&self, path: &Path, agent: &str) -> Result<(), IOError> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        // The flags mark the file as downloaded and not yet approved by the user.
+        let quarantine = format!("0081;{:08x};{};", timestamp, agent.replace(';', "_"));
+        set_xattr(path, "com.apple.quarantine", quarantine.as_bytes())?;
+        let mut urls = vec![self.url.as_str()];
+        if self.requested != self.url {
+            urls.push(&self.requested);
+        }
+        set_xattr(path, "com.apple.metadata:kMDItemWhereFroms", &plist_strings(&urls))
+    }
+
+    /// Writes the `.meta.json` sidecar of the download at `fname`.
+    pub(crate) async fn write_sidecar(&self, fname: &Path) -> Result<(), IOError> {
+        let entries: Vec<String> = self.fields()
+            .into_iter()
+            .map(|(key, _, value)| format!("  \"{}\": \"{}\"", key, escape_json(value)))
+            .collect();
+        tokio::fs::write(sidecar_path(fname), format!("{{\n{}\n}}\n", entries.join(",\n"))).await
+    }
+}
+
+impl AsyncDownload {
+    /// Returns the source of the download from its last response, with its hex sha256sum if it
+    /// was hashed.
+    pub(crate) fn source(&self, sha256: Option<&str>) -> Source {
+        let header = |name| self.response.as_ref()
+            .and_then(|response| response.headers.get(name))
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        Source {
+            url: self.response.as_ref().map_or_else(|| self.url.clone(), |response| response.url.to_string()),
+            #[cfg(target_os="macos")]
+            requested: self.url.clone(),
+            content_type: header(reqwest::header::CONTENT_TYPE),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+            downloaded: date::format_http_date(SystemTime::now()),
+            sha256: sha256.map(String::from),
+        }
+    }
+}
+
+fn sidecar_path(fname: &Path) -> PathBuf {
+    let name = fname.file_name().unwrap_or_default();
+    fname.with_file_name(filename::with_suffix(name, ".meta.json"))
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(target_os="macos")]
+/// Encodes an array of ASCII strings, such as URLs, as a binary property list.
+fn plist_strings(strings: &[&str]) -> Vec<u8> {
+    // Every object is referred to by a single byte and located by an eight byte offset.
+    let mut plist = b"bplist00".to_vec();
+    let mut offsets = vec![plist.len() as u64];
+    plist.push(0xa0 | strings.len().min(15) as u8);
+    if strings.len() >= 15 {
+        plist.extend(plist_int(strings.len()));
+    }
+    plist.extend((1..=strings.len()).map(|i| i as u8));
+    for string in strings {
+        offsets.push(plist.len() as u64);
+        plist.push(0x50 | string.len().min(15) as u8);
+        if string.len() >= 15 {
+            plist.extend(plist_int(string.len()));
+        }
+        plist.extend(string.bytes());
+    }
+    let offset_table = plist.len() as u64;
+    for offset in &offsets {
+        plist.extend(offset.to_be_bytes());
+    }
+    plist.extend([0; 6]);
+    plist.extend([8, 1]);
+    plist.extend((offsets.len() as u64).to_be_bytes());
+    plist.extend(0u64.to_be_bytes());
+    plist.extend(offset_table.to_be_bytes());
+    plist
+}
+
+#[cfg(target_os="macos")]
+/// Encodes the length of a long array or string in a binary property list.
+fn plist_int(n: usize) -> Vec<u8> {
+    let mut int = vec![0x13];
+    int.extend((n as u64).to_be_bytes());
+    int
+}
+
+#[cfg(any(target_os="linux", target_os="macos"))]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<(), IOError> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name)?;
+    // SAFETY: both strings are NUL-terminated, and the value is valid for its length.
+    #[cfg(target_os="linux")]
+    let ret = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+    #[cfg(target_os="macos")]
+    let ret = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0, 0) };
+    match ret {
+        0 => Ok(()),
+        _ => Err(IOError::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os="linux", target_os="macos")))]
+fn set_xattr(_path: &Path, _name: &str, _value: &[u8]) -> Result<(), IOError> {
+    Err(IOError::from(std::io::ErrorKind::Unsupported))
+}
@@ -0,0 +1,170 @@
+use reqwest::header::{
+    HeaderMap, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, LOCATION,
+    PROXY_AUTHORIZATION, TRANSFER_ENCODING, WWW_AUTHENTICATE,
+};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::auth::SharedAuth;
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::{AsyncDownload, Failure};
+
+/// How redirects are followed, when the download follows them itself rather than leaving them
+/// to a pre-built client.
+#[derive(Clone, Debug)]
+pub(crate) struct Redirects {
+    pub(crate) limit: usize,
+    pub(crate) same_host: bool,
+    pub(crate) deny_downgrade: bool,
+}
+
+impl Default for Redirects {
+    fn default() -> Self {
+        Self {
+            limit: 10,
+            same_host: false,
+            deny_downgrade: false,
+        }
+    }
+}
+
+impl AsyncDownload {
+    /// Sends a request, following any redirects according to the configuration and policy, and
+    /// returns the final response along with the URLs which redirected to it, in order.
+    pub(crate) async fn send(&self, request: reqwest::RequestBuilder) -> Result<(reqwest::Response, Vec<Url>), Failure> {
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        let mut chain = Vec::new();
+        let Some(ref redirects) = self.config.redirects else {
+            let response = self.send_authorized(&client, request, None).await?;
+            return Ok((response, chain));
+        };
+        loop {
+            let next = request.try_clone();
+            let response = self.send_authorized(&client, request, chain.last()).await?;
+            let Some(location) = location(&response) else {
+                return Ok((response, chain));
+            };
+            let (Some(mut next), Ok(url)) = (next, response.url().join(location)) else {
+                return Err(Failure::fatal(TDSTDErrorKind::Redirect));
+            };
+            let from = response.url();
+            if chain.len() >= redirects.limit
+                || !matches!(url.scheme(), "http" | "https")
+                || redirects.same_host && url.host_str() != from.host_str()
+                || redirects.deny_downgrade && from.scheme() == "https" && url.scheme() != "https"
+            {
+                return Err(Failure::fatal(TDSTDErrorKind::Redirect));
+            }
+            if let Some(ref policy) = self.config.policy {
+                policy.check_redirect(from, &url)
+                    .map_err(|violation| Failure::fatal(TDSTDErrorKind::PolicyViolation(violation.0)))?;
+            }
+
+            let status = response.status();
+            if status == StatusCode::SEE_OTHER && next.method() != Method::HEAD
+                || matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND) && next.method() == Method::POST
+            {
+                *next.method_mut() = Method::GET;
+                *next.body_mut() = None;
+                remove_content_headers(next.headers_mut());
+            }
+            if url.host_str() != from.host_str() || url.port_or_known_default() != from.port_or_known_default() {
+                for name in [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, WWW_AUTHENTICATE] {
+                    next.headers_mut().remove(name);
+                }
+            }
+            #[cfg(feature="tracing")]
+            crate::trace::redirect(&response, &url);
+            chain.push(from.clone());
+            *next.url_mut() = url;
+            request = next;
+        }
+    }
+
+    /// Sends a single request with the configured credentials, passing it through the
+    /// interceptors, which are told the URL it was `redirected_from`, if any.  If the credentials
+    /// are rejected with `401 Unauthorized`, the auth provider is asked to renew them and the
+    /// request is sent once more.
+    async fn send_authorized(&self, client: &reqwest::Client, mut request: reqwest::Request, redirected_from: Option<&Url>) -> Result<reqwest::Response, Failure> {
+        let retry = match self.config.auth {
+            Some(_) => request.try_clone(),
+            None => None,
+        };
+        self.authorize(&mut request, false).await?;
+        self.intercept(&mut request, redirected_from).await?;
+        let response = self.send_once(client, request).await?;
+        match retry {
+            Some(mut retry) if response.status() == StatusCode::UNAUTHORIZED => {
+                self.authorize(&mut retry, true).await?;
+                self.intercept(&mut retry, redirected_from).await?;
+                self.send_once(client, retry).await
+            }
+            _ => Ok(response),
+        }
+    }
+
+    /// Passes `request` through the interceptors, checking any URL they rewrite it to.
+    async fn intercept(&self, request: &mut reqwest::Request, redirected_from: Option<&Url>) -> Result<(), Failure> {
+        if self.config.interceptors.0.is_empty() {
+            return Ok(());
+        }
+        let url = request.url().clone();
+        self.config.interceptors.apply(request, redirected_from).await
+            .map_err(|err| Failure::fatal(TDSTDErrorKind::Other(err)))?;
+        if *request.url() != url {
+            self.check_policy(request.url())?;
+        }
+        Ok(())
+    }
+
+    fn check_policy(&self, url: &Url) -> Result<(), Failure> {
+        match self.config.policy {
+            Some(ref policy) => policy.check_url(url)
+                .map_err(|violation| Failure::fatal(TDSTDErrorKind::PolicyViolation(violation.0))),
+            None => Ok(()),
+        }
+    }
+
+    async fn send_once(&self, client: &reqwest::Client, request: reqwest::Request) -> Result<reqwest::Response, Failure> {
+        #[cfg(feature="tracing")]
+        crate::trace::request(&request);
+        let response = self.execute(client, request).await?;
+        #[cfg(feature="pinning")]
+        self.check_pins(&response)?;
+        Ok(response)
+    }
+
+    /// Adds the credentials configured for the host of `request`, if it has none, or those of
+    /// the auth provider, which `rejected` asks to renew them.  Credentials are added to each
+    /// request rather than carried across redirects, so that another host is only sent its own.
+    async fn authorize(&self, request: &mut reqwest::Request, rejected: bool) -> Result<(), Failure> {
+        if let Some(SharedAuth(ref provider)) = self.config.auth {
+            let credentials = provider.credentials(request.url(), rejected).await
+                .map_err(|err| Failure::transient(TDSTDError::new(TDSTDErrorKind::Other(err))))?;
+            credentials.apply(request);
+            self.check_policy(request.url())?;
+        }
+        if let Some(ref netrc) = self.config.netrc {
+            netrc.authorize(request).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the `Location` of a redirect response, or `None` if the response is not a redirect.
+fn location(response: &reqwest::Response) -> Option<&str> {
+    match response.status() {
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT => response.headers().get(LOCATION)?.to_str().ok(),
+        _ => None,
+    }
+}
+
+fn remove_content_headers(headers: &mut HeaderMap) {
+    for name in [CONTENT_TYPE, CONTENT_LENGTH, CONTENT_ENCODING, TRANSFER_ENCODING] {
+        headers.remove(name);
+    }
+}
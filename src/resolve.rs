@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::policy::{is_public, Violation};
+
+/// Resolves host names for the client, from the static overrides if there is one for the host,
+/// and otherwise with the custom resolver if one is set, or the system resolver.  If the policy
+/// requires it, resolving fails if any of the addresses is not public.  Addresses of the
+/// preferred family, if any, are tried first.
+#[derive(Clone, Default)]
+pub(crate) struct Resolver {
+    pub(crate) custom: Option<Arc<dyn Resolve>>,
+    pub(crate) overrides: HashMap<String, Vec<IpAddr>>,
+    pub(crate) public_only: bool,
+    pub(crate) prefer_ipv6: Option<bool>,
+}
+
+impl Resolver {
+    /// Returns `true` if the resolver behaves differently from the default one.
+    pub(crate) fn is_needed(&self) -> bool {
+        self.custom.is_some() || !self.overrides.is_empty() || self.public_only || self.prefer_ipv6.is_some()
+    }
+}
+
+impl std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Resolver")
+            .field("custom", &self.custom.is_some())
+            .field("overrides", &self.overrides)
+            .field("public_only", &self.public_only)
+            .field("prefer_ipv6", &self.prefer_ipv6)
+            .finish()
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let mut addrs: Vec<SocketAddr> = match resolver.overrides.get(&name.as_str().to_ascii_lowercase()) {
+                Some(ips) => ips.iter().map(|ip| SocketAddr::new(*ip, 0)).collect(),
+                None => match resolver.custom {
+                    Some(ref custom) => custom.resolve(name).await?.collect(),
+                    None => tokio::net::lookup_host((name.as_str(), 0)).await?.collect(),
+                },
+            };
+            if resolver.public_only && addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(Box::new(Violation("host name resolves to an address which is not public")) as Box<_>);
+            }
+            if let Some(prefer_ipv6) = resolver.prefer_ipv6 {
+                addrs.sort_by_key(|addr| addr.is_ipv6() != prefer_ipv6);
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
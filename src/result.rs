@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use reqwest::{StatusCode, Url, Version};
+
+/// The metadata of the most recent response received for a download, as returned by
+/// [`AsyncDownload::metadata`](crate::AsyncDownload::metadata) and
+/// [`DownloadResult::metadata`].
+#[derive(Clone, Debug)]
+pub struct ResponseMetadata {
+    pub(crate) status: StatusCode,
+    pub(crate) version: Version,
+    pub(crate) url: Url,
+    pub(crate) headers: HeaderMap,
+    pub(crate) redirects: Vec<Url>,
+}
+
+impl ResponseMetadata {
+    pub(crate) fn new(response: &reqwest::Response, redirects: Vec<Url>) -> Self {
+        Self {
+            status: response.status(),
+            version: response.version(),
+            url: response.url().clone(),
+            headers: response.headers().clone(),
+            redirects,
+        }
+    }
+
+    fn header(&self, name: reqwest::header::HeaderName) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Returns the HTTP status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the HTTP version the response was received over.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the final URL of the response, after any redirects.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns the headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the URLs which redirected to the response, in the order they were requested,
+    /// starting with the URL of the request.  The final URL is not included, and is returned by
+    /// [`url`](ResponseMetadata::url).  This is empty if there were no redirects, or if they were
+    /// followed by a pre-built client.
+    pub fn redirects(&self) -> &[Url] {
+        &self.redirects
+    }
+
+    /// Returns the `Content-Type` of the response, e.g. to choose a file extension.
+    pub fn content_type(&self) -> Option<&str> {
+        self.header(CONTENT_TYPE)
+    }
+
+    /// Returns the `Content-Length` of the response.  For a range request, this is the length of
+    /// the range rather than of the whole download.
+    pub fn content_length(&self) -> Option<u64> {
+        self.header(CONTENT_LENGTH).and_then(|l| l.parse::<u64>().ok())
+    }
+
+    /// Returns the `Last-Modified` date of the response, as sent by the server.
+    pub fn last_modified(&self) -> Option<&str> {
+        self.header(LAST_MODIFIED)
+    }
+
+    /// Returns the `ETag` of the response.
+    pub fn etag(&self) -> Option<&str> {
+        self.header(ETAG)
+    }
+
+    /// Returns `true` if the server advertised support for byte ranges with
+    /// `Accept-Ranges: bytes`.
+    pub fn accepts_ranges(&self) -> bool {
+        self.header(ACCEPT_RANGES).is_some_and(|v| v.eq_ignore_ascii_case("bytes"))
+    }
+}
+
+/// A summary of a completed download.
+#[derive(Clone, Debug)]
+pub struct DownloadResult {
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) bytes_written: u64,
+    pub(crate) elapsed: Duration,
+    pub(crate) skipped: bool,
+    pub(crate) not_modified: bool,
+    pub(crate) response: Option<Box<ResponseMetadata>>,
+    pub(crate) corrupt_attempts: Vec<CorruptAttempt>,
+}
+
+impl DownloadResult {
+    /// Returns the path the download was written to, or `None` if it was streamed into a writer
+    /// with [`download_to_writer`](crate::AsyncDownload::download_to_writer).
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the number of bytes written by this call.  When resuming, this does not include
+    /// the bytes which were already on disk.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns how long the download took, including any retries.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the average speed of the download, in bytes per second.
+    pub fn average_speed(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_written as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns `true` if nothing was downloaded because the file already existed and
+    /// `OverwriteBehavior::Skip` was set, or `OverwriteBehavior::SkipIfValid` and it matched.
+    pub fn skipped(&self) -> bool {
+        self.skipped
+    }
+
+    /// Returns `true` if nothing was downloaded because the server responded to a conditional
+    /// request with `304 Not Modified`.
+    pub fn not_modified(&self) -> bool {
+        self.not_modified
+    }
+
+    /// Returns the metadata of the last response.
+    pub fn metadata(&self) -> Option<&ResponseMetadata> {
+        self.response.as_deref()
+    }
+
+    /// Returns the final URL of the download, after any redirects.
+    pub fn url(&self) -> Option<&Url> {
+        self.response.as_ref().map(|r| &r.url)
+    }
+
+    /// Returns the URLs which redirected to the last response, as for
+    /// [`ResponseMetadata::redirects`].
+    pub fn redirects(&self) -> &[Url] {
+        self.response.as_ref().map_or(&[], |r| &r.redirects)
+    }
+
+    /// Returns the HTTP version the last response was received over.
+    pub fn version(&self) -> Option<Version> {
+        self.response.as_ref().map(|r| r.version)
+    }
+
+    /// Returns the HTTP status of the last response.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.response.as_ref().map(|r| r.status)
+    }
+
+    /// Returns the headers of the last response.
+    pub fn headers(&self) -> Option<&HeaderMap> {
+        self.response.as_ref().map(|r| &r.headers)
+    }
+
+    /// Returns the attempts at the download which failed verification before it was downloaded
+    /// again, with [`retry_on_mismatch`](crate::AsyncDownloadBuilder::retry_on_mismatch), in the
+    /// order they were made.
+    pub fn corrupt_attempts(&self) -> &[CorruptAttempt] {
+        &self.corrupt_attempts
+    }
+}
+
+/// An attempt at a download which was written but failed verification, as returned by
+/// [`DownloadResult::corrupt_attempts`].
+#[derive(Clone, Debug)]
+pub struct CorruptAttempt {
+    pub(crate) error: String,
+    pub(crate) quarantined: Option<PathBuf>,
+}
+
+impl CorruptAttempt {
+    /// Returns the message of the error the attempt failed with, such as a checksum mismatch.
+    pub fn error(&self) -> &str {
+        &self.error
+    }
+
+    /// Returns the path the corrupt file was moved to, if it was quarantined with
+    /// [`quarantine_corrupt`](crate::AsyncDownloadBuilder::quarantine_corrupt) rather than
+    /// removed.
+    pub fn quarantined(&self) -> Option<&Path> {
+        self.quarantined.as_deref()
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+
+use crate::date;
+
+/// The policy used to decide how long to wait between retries of a failed download.
+#[derive(Clone, Debug)]
+pub enum Backoff {
+    /// Wait the same duration before every retry.
+    Fixed(Duration),
+    /// Double the wait before every retry, starting at `initial` and capped at `max`.
+    Exponential { initial: Duration, max: Duration },
+    /// Like `Exponential`, but wait a random duration between zero and the exponential delay
+    /// ("full jitter"), so many clients retrying at once do not do so in lockstep.
+    ExponentialWithJitter { initial: Duration, max: Duration },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::Exponential {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    /// Returns the duration to wait before the given retry, where the first retry is `0`.
+    pub fn delay(&self, retry: u32) -> Duration {
+        match self {
+            Backoff::Fixed(delay) => *delay,
+            Backoff::Exponential { initial, max } => exponential(*initial, *max, retry),
+            Backoff::ExponentialWithJitter { initial, max } => {
+                let delay = exponential(*initial, *max, retry);
+                let random = RandomState::new().build_hasher().finish();
+                delay.mul_f64(random as f64 / u64::MAX as f64)
+            }
+        }
+    }
+}
+
+fn exponential(initial: Duration, max: Duration, retry: u32) -> Duration {
+    initial
+        .checked_mul(2u32.saturating_pow(retry))
+        .map_or(max, |delay| delay.min(max))
+}
+
+/// The longest `Retry-After` which is waited for, unless configured otherwise.
+pub(crate) const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Returns how long a `429 Too Many Requests` or `503 Service Unavailable` response asks the
+/// client to wait before retrying, from its `Retry-After` header.
+pub(crate) fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    if !matches!(response.status(), StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) {
+        return None;
+    }
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = date::parse_http_date(value)?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
@@ -0,0 +1,500 @@
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::Error as IOError;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+#[cfg(feature="state")]
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures_util::stream::{self, TryStreamExt};
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+
+use crate::byteranges::{self, Parts};
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::throttle::{throttle, SpeedCheck};
+use crate::{into_stream, preallocate, read_body, AsyncDownload, DownloadEvent, Failure, S};
+
+#[cfg(feature="state")]
+/// How often the pieces written are saved to the state file, if enabled.
+const STATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The length of the smallest pieces completion is tracked in, and the most pieces a download is
+/// split into, so that the map of them saved in the state stays small.
+const MIN_PIECE_LEN: u64 = 16 * 1024;
+const MAX_PIECES: u64 = 64 * 1024;
+
+/// The most ranges asked for in one request, as servers refuse requests for too many.
+const MAX_RANGES: usize = 32;
+
+/// A byte range of a segmented download, from `start` up to `end`, which has been written up to
+/// `pos`.  It starts at the start of a piece.
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    start: u64,
+    end: u64,
+    pos: u64,
+}
+
+/// Which pieces of a segmented download have been written, so that it can be resumed with any
+/// number of segments.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature="state", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct PieceMap {
+    piece_len: u64,
+    total: u64,
+    /// A bit for each piece, the first in the lowest bit of the first byte, which is saved as
+    /// hex.
+    #[cfg_attr(feature="state", serde(with = "hex_bits"))]
+    written: Vec<u8>,
+}
+
+impl PieceMap {
+    fn new(total: u64) -> Self {
+        let piece_len = (total / MAX_PIECES).next_power_of_two().max(MIN_PIECE_LEN);
+        Self {
+            piece_len,
+            total,
+            written: vec![0; total.div_ceil(piece_len).div_ceil(8) as usize],
+        }
+    }
+
+    #[cfg(feature="state")]
+    /// Returns `true` if the map is for a download of `total` bytes.
+    pub(crate) fn fits(&self, total: u64) -> bool {
+        self.total == total
+            && self.piece_len > 0
+            && self.written.len() as u64 == total.div_ceil(self.piece_len).div_ceil(8)
+    }
+
+    fn pieces(&self) -> u64 {
+        self.total.div_ceil(self.piece_len)
+    }
+
+    fn is_written(&self, piece: u64) -> bool {
+        self.written[(piece / 8) as usize] & (1 << (piece % 8)) != 0
+    }
+
+    /// Marks the pieces which lie entirely within `start..end` as written.
+    fn mark(&mut self, start: u64, end: u64) {
+        let last = if end == self.total { self.pieces() } else { end / self.piece_len };
+        for piece in start.div_ceil(self.piece_len)..last {
+            self.written[(piece / 8) as usize] |= 1 << (piece % 8);
+        }
+    }
+
+    /// Returns the number of bytes in the pieces which have been written.
+    fn written_len(&self) -> u64 {
+        (0..self.pieces())
+            .filter(|&piece| self.is_written(piece))
+            .map(|piece| ((piece + 1) * self.piece_len).min(self.total) - piece * self.piece_len)
+            .sum()
+    }
+
+    /// Splits the pieces which have not been written into segments of about the same length, so
+    /// that they can be fetched over `connections` connections.  There may be more segments
+    /// than connections if the pieces left are not contiguous.
+    fn split(&self, connections: u64) -> Vec<Segment> {
+        let left = (0..self.pieces()).filter(|&piece| !self.is_written(piece)).count() as u64;
+        let per_segment = left.div_ceil(connections).max(1);
+        let mut segments = Vec::new();
+        let mut piece = 0;
+        while piece < self.pieces() {
+            if self.is_written(piece) {
+                piece += 1;
+                continue;
+            }
+            let first = piece;
+            while piece < self.pieces() && !self.is_written(piece) && piece - first < per_segment {
+                piece += 1;
+            }
+            let start = first * self.piece_len;
+            segments.push(Segment { start, end: (piece * self.piece_len).min(self.total), pos: start });
+        }
+        segments
+    }
+}
+
+#[cfg(feature="state")]
+mod hex_bits {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bits: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&bits.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        crate::builder::parse_hex(&hex).ok_or_else(|| D::Error::custom("invalid hex"))
+    }
+}
+
+/// Progress shared between the segments of a download, which run concurrently on one task.
+struct Progress<'a> {
+    bytes: Cell<u64>,
+    total: u64,
+    segments: RefCell<Vec<Segment>>,
+    pieces: RefCell<PieceMap>,
+    events: RefCell<&'a mut dyn FnMut(DownloadEvent)>,
+    #[cfg(feature="state")]
+    saved: Cell<Option<Instant>>,
+}
+
+impl Progress<'_> {
+    fn emit(&self, event: DownloadEvent) {
+        (self.events.borrow_mut())(event);
+    }
+
+    fn advance(&self, index: usize, bytes: u64) {
+        let mut segments = self.segments.borrow_mut();
+        let segment = &mut segments[index];
+        let mut pieces = self.pieces.borrow_mut();
+        // Only the piece being written and any after it can have been completed.
+        let from = segment.pos - (segment.pos - segment.start) % pieces.piece_len;
+        segment.pos += bytes;
+        pieces.mark(from, segment.pos);
+        drop((segments, pieces));
+        self.bytes.set(self.bytes.get() + bytes);
+        self.emit(DownloadEvent::chunk(self.bytes.get(), Some(self.total)));
+    }
+
+    #[cfg(feature="state")]
+    /// Saves the pieces which have been written if they have not been saved for a while, unless
+    /// another segment is saving them already.
+    async fn checkpoint(&self, download: &AsyncDownload) -> Result<(), std::io::Error> {
+        match self.saved.get() {
+            Some(saved) if saved.elapsed() >= STATE_INTERVAL => (),
+            _ => return Ok(()),
+        }
+        self.saved.set(None);
+        let pieces = self.pieces.borrow().clone();
+        let result = download.save_state(self.bytes.get(), Some(&pieces)).await;
+        self.saved.set(Some(Instant::now()));
+        result
+    }
+}
+
+impl AsyncDownload {
+    /// Writes the download to `fname` over several connections, each fetching its own byte range
+    /// and writing it at the matching offset of a file which is the full length from the start.
+    /// If the state of the download is saved for the same download, only the pieces it does not
+    /// record as written are fetched.
+    /// Returns `Ok(false)` without writing anything if the server does not support ranges, the
+    /// length is unknown or the initial request fails, so the caller can fall back to a single
+    /// connection.
+    pub(crate) async fn transfer_segmented(
+        &mut self,
+        fname: &Path,
+        events: &mut dyn FnMut(DownloadEvent),
+    ) -> Result<bool, TDSTDError> {
+        let connections = self.config.segments as u64;
+        if self.response_stream.is_none() && self.get_non_consumable().await.is_err() {
+            return Ok(false);
+        }
+        let total = match self.length {
+            Some(total) if self.accept_ranges && total >= connections => total,
+            _ => return Ok(false),
+        };
+
+        #[cfg(feature="state")]
+        let saved = self.saved_pieces(total);
+        #[cfg(not(feature="state"))]
+        let saved = None;
+        let (pieces, resumed) = match saved {
+            Some(saved) => (saved, true),
+            None => (PieceMap::new(total), false),
+        };
+        // The pieces picked up from a saved state are written into the file as it is.
+        let file = match resumed {
+            true => tokio::fs::OpenOptions::new().write(true).open(fname).await?,
+            false => tokio::fs::File::create(fname).await?,
+        };
+        if self.config.preallocate && !resumed {
+            preallocate(&file, total).await?;
+        }
+        file.set_len(total).await?;
+        let file = Output::new(file.into_std().await).await;
+        let segments = pieces.split(connections);
+        let bytes = pieces.written_len();
+        events(DownloadEvent::Started { offset: bytes, total: Some(total) });
+        #[cfg(feature="state")]
+        self.save_state(bytes, Some(&pieces)).await?;
+
+        // The response we already have covers the first segment, unless it was already started,
+        // and the rest are requested with ranges.
+        let mut first = self.response_stream.take().filter(|_| segments.first().is_some_and(|segment| segment.start == 0));
+        let groups = group(segments.len(), connections as usize, first.is_some());
+        let progress = Progress {
+            bytes: Cell::new(bytes),
+            total,
+            segments: RefCell::new(segments),
+            pieces: RefCell::new(pieces),
+            events: RefCell::new(events),
+            #[cfg(feature="state")]
+            saved: Cell::new(Some(Instant::now())),
+        };
+        let result = stream::iter(groups.into_iter().map(Ok))
+            .try_for_each_concurrent(connections as usize, |group| {
+                let stream = if group.start == 0 { first.take() } else { None };
+                self.fetch_group(&file, group, stream, &progress)
+            })
+            .await;
+        #[cfg(feature="state")]
+        if result.is_err() {
+            let pieces = progress.pieces.borrow().clone();
+            let _ = self.save_state(progress.bytes.get(), Some(&pieces)).await;
+        }
+        result?;
+        Ok(true)
+    }
+
+    /// Fetches the rest of the segments in `group` with a single request for all of their
+    /// ranges, writing each part of the `multipart/byteranges` response at its offset in `file`.
+    /// The request is made again for the ranges which are left on transient failures, and if
+    /// the server does not answer with a multipart response, the segments are fetched one at a
+    /// time instead.
+    async fn fetch_group(
+        &self,
+        file: &Output,
+        group: Range<usize>,
+        stream: Option<Box<S>>,
+        progress: &Progress<'_>,
+    ) -> Result<(), TDSTDError> {
+        if group.len() == 1 {
+            return self.fetch_segment(file, group.start, stream, progress).await;
+        }
+        let unfinished = |&i: &usize| {
+            let segment = progress.segments.borrow()[i];
+            segment.pos < segment.end
+        };
+        let mut retry = 0;
+        loop {
+            let pending: Vec<usize> = group.clone().filter(unfinished).collect();
+            let result = match pending.len() {
+                0 => return Ok(()),
+                1 => return self.fetch_segment(file, pending[0], None, progress).await,
+                _ => self.fetch_ranges(file, &pending, progress).await,
+            };
+            match result {
+                Ok(true) => return Ok(()),
+                Ok(false) => {
+                    for i in pending {
+                        self.fetch_segment(file, i, None, progress).await?;
+                    }
+                    return Ok(());
+                }
+                Err(failure) => match failure.retry_delay(&self.config, retry) {
+                    Some(delay) => {
+                        retry += 1;
+                        progress.emit(DownloadEvent::Retrying { retry, delay, error: failure.error });
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => return Err(failure.exhausted(retry)),
+                },
+            }
+        }
+    }
+
+    /// Requests the rest of each segment in `pending` at once, and writes each part of the
+    /// response into `file`.  Returns `Ok(false)` without writing anything if the server sent
+    /// anything but a `multipart/byteranges` response, such as the whole file or the ranges
+    /// merged into one.  A part which does not continue one of the segments is an
+    /// `InvalidResponse`, and one which was left out is a transient failure.
+    async fn fetch_ranges(&self, file: &Output, pending: &[usize], progress: &Progress<'_>) -> Result<bool, Failure> {
+        let ranges: Vec<(u64, u64)> = pending.iter()
+            .map(|&i| progress.segments.borrow()[i])
+            .map(|segment| (segment.pos, segment.end))
+            .collect();
+        let offset = self.config.range.map_or(0, |(start, _)| start);
+        let request = self.request_with(self.config.method.clone(), &self.url)
+            .header(RANGE, byteranges::header(&ranges, offset));
+        let (response, _) = self.send(request).await?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return match response.status().is_success() {
+                true => Ok(false),
+                false => Err(Failure::from_status(response)),
+            };
+        }
+        let Some(boundary) = byteranges::boundary(response.headers()) else {
+            return Ok(false);
+        };
+        let mut parts = Parts::new(into_stream(response), &boundary);
+        while let Some((start, last)) = parts.next_part(&self.config).await? {
+            let (start, end) = match (start.checked_sub(offset), last.saturating_add(1).checked_sub(offset)) {
+                (Some(start), Some(end)) => (start, end),
+                _ => return Err(Failure::fatal(TDSTDErrorKind::InvalidResponse)),
+            };
+            let index = pending.iter().copied().find(|&i| {
+                let segment = progress.segments.borrow()[i];
+                segment.pos == start && end <= segment.end
+            });
+            let Some(index) = index else {
+                return Err(Failure::fatal(TDSTDErrorKind::InvalidResponse));
+            };
+            let mut pos = start;
+            write_range(&mut parts, file, &mut pos, end, index, self, progress).await?;
+        }
+        match pending.iter().map(|&i| progress.segments.borrow()[i]).find(|segment| segment.pos < segment.end) {
+            Some(segment) => Err(Failure::transient(TDSTDError::new(TDSTDErrorKind::TruncatedBody {
+                expected: segment.end,
+                actual: segment.pos,
+            }))),
+            None => Ok(true),
+        }
+    }
+
+    /// Fetches the rest of the segment at `index` and writes it at the same offset in `file`,
+    /// retrying from the last byte written on transient failures.
+    async fn fetch_segment(
+        &self,
+        file: &Output,
+        index: usize,
+        mut stream: Option<Box<S>>,
+        progress: &Progress<'_>,
+    ) -> Result<(), TDSTDError> {
+        let Segment { mut pos, end, .. } = progress.segments.borrow()[index];
+        let mut retry = 0;
+        loop {
+            let result = async {
+                let mut stream = match stream.take() {
+                    Some(stream) => stream,
+                    None => self.request_range(pos, end).await?,
+                };
+                write_range(&mut stream, file, &mut pos, end, index, self, progress).await
+            }
+            .await;
+            match result {
+                Ok(()) => return Ok(()),
+                Err(failure) => match failure.retry_delay(&self.config, retry) {
+                    Some(delay) => {
+                        retry += 1;
+                        progress.emit(DownloadEvent::Retrying { retry, delay, error: failure.error });
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => return Err(failure.exhausted(retry)),
+                },
+            }
+        }
+    }
+
+    /// Requests the bytes from `start` up to `end`, returning the response stream if the server
+    /// answered with exactly that range.
+    async fn request_range(&self, start: u64, end: u64) -> Result<Box<S>, Failure> {
+        let (response, _) = self.send(self.request_bytes(start, Some(end))).await?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(Failure::from_status(response));
+        }
+        self.partial_length(response.headers(), start)?;
+        Ok(into_stream(response))
+    }
+}
+
+/// Splits `count` segments into runs of consecutive ones which are each fetched with a single
+/// request, so that there are about as many requests as `connections`.  If the initial
+/// response covers the first segment, it is fetched on its own.
+fn group(count: usize, mut connections: usize, first: bool) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    if first && count > 0 {
+        groups.push(0..1);
+        start = 1;
+        connections = connections.saturating_sub(1);
+    }
+    let per_group = (count - start).div_ceil(connections.max(1)).clamp(1, MAX_RANGES);
+    groups.extend((start..count).step_by(per_group).map(|from| from..(from + per_group).min(count)));
+    groups
+}
+
+/// Writes the stream into `file` at `pos` until `end` is reached, ignoring anything the stream
+/// yields past `end`, as the segment at `index`.  A stream which ends early is a transient
+/// failure.
+async fn write_range(
+    stream: &mut S,
+    file: &Output,
+    pos: &mut u64,
+    end: u64,
+    index: usize,
+    download: &AsyncDownload,
+    progress: &Progress<'_>,
+) -> Result<(), Failure> {
+    let config = &download.config;
+    let mut speed = config.minimum_speed.map(SpeedCheck::new);
+    while *pos < end {
+        if let Some(ref control) = config.control {
+            control.unpaused().await;
+        }
+        let chunk = read_body(stream, config, &mut speed).await?;
+        let chunk = match chunk {
+            Some(chunk) => chunk,
+            None => {
+                return Err(Failure::transient(TDSTDError::new(TDSTDErrorKind::TruncatedBody {
+                    expected: end,
+                    actual: *pos,
+                })))
+            }
+        };
+        let len = chunk.len().min((end - *pos) as usize);
+        throttle(config, len as u64).await;
+        file.write_at(*pos, chunk.slice(..len)).await?;
+        *pos += len as u64;
+        progress.advance(index, len as u64);
+        #[cfg(feature="state")]
+        progress.checkpoint(download).await?;
+    }
+    Ok(())
+}
+
+/// The file a segmented download is written to, by each of its segments at the same time.  With
+/// the `io-uring` feature on Linux, writes go through io_uring where it is available.
+struct Output {
+    file: Arc<File>,
+    #[cfg(all(feature="io-uring", target_os="linux"))]
+    uring: Option<crate::uring::Writer>,
+}
+
+impl Output {
+    async fn new(file: File) -> Self {
+        Self {
+            #[cfg(all(feature="io-uring", target_os="linux"))]
+            uring: crate::uring::Writer::new(&file).await.ok(),
+            file: Arc::new(file),
+        }
+    }
+
+    /// Writes all of `data` at `offset` in the file.
+    async fn write_at(&self, offset: u64, data: Bytes) -> Result<(), IOError> {
+        #[cfg(all(feature="io-uring", target_os="linux"))]
+        if let Some(ref uring) = self.uring {
+            return uring.write_at(offset, data).await;
+        }
+        write_at(&self.file, offset, data).await
+    }
+}
+
+/// Writes all of `data` at `offset` in `file`, which the other segments write to at the same
+/// time, so there is no file position to move.
+async fn write_at(file: &Arc<File>, offset: u64, data: Bytes) -> Result<(), IOError> {
+    let file = Arc::clone(file);
+    tokio::task::spawn_blocking(move || {
+        #[cfg(unix)]
+        return std::os::unix::fs::FileExt::write_all_at(&*file, &data, offset);
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::FileExt;
+
+            let mut written = 0;
+            while written < data.len() {
+                match file.seek_write(&data[written..], offset + written as u64)? {
+                    0 => return Err(IOError::from(std::io::ErrorKind::WriteZero)),
+                    n => written += n,
+                }
+            }
+            Ok(())
+        }
+    })
+    .await
+    .map_err(IOError::other)?
+}
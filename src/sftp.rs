@@ -0,0 +1,320 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use std::process::Stdio;
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::stream;
+use percent_encoding::percent_decode_str;
+use reqwest::header::LAST_MODIFIED;
+use reqwest::{Method, StatusCode, Url};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::backend::{self, Body, Response};
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::{date, local, Failure};
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_FSTAT: u8 = 8;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FXF_READ: u32 = 0x1;
+
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x1;
+const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x2;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x4;
+const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x8;
+
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+
+/// The version of the SFTP protocol spoken, which is the one OpenSSH implements.
+const SFTP_VERSION: u32 = 3;
+
+/// How much is asked for in each read, which is just under the most OpenSSH's server sends.
+const READ_LEN: u32 = 255 * 1024;
+
+/// The longest packet accepted from the server.
+const MAX_PACKET_LEN: usize = 1024 * 1024;
+
+/// Answers a request for an `sftp` or `scp` URL as a server would, including a `Range` of the
+/// form downloads request, by reading the file over the SFTP subsystem of the system's `ssh`
+/// client.  The client's own configuration, keys, agent and known hosts are used, and it is run
+/// in batch mode, so that a connection which would prompt for a password or an unknown host key
+/// fails rather than waiting for input.  A file which does not exist is `404 Not Found`.
+pub(crate) async fn execute(request: &reqwest::Request) -> Result<reqwest::Response, Failure> {
+    let url = request.url();
+    let opened = open(url, local::range(request), request.method() == Method::HEAD);
+    let response = match request.timeout() {
+        Some(&timeout) => tokio::time::timeout(timeout, opened).await
+            .map_err(|_| Failure::transient(TDSTDError::new(TDSTDErrorKind::Timeout)))??,
+        None => opened.await?,
+    };
+    backend::into_response(url.clone(), response)
+}
+
+async fn open(url: &Url, range: Option<(u64, Option<u64>)>, head: bool) -> Result<Response<Body>, Failure> {
+    let mut session = Session::connect(url).await?;
+    let transient = |err: IOError| Failure::transient(err.into());
+    let handle = match session.open(&remote_path(url)).await.map_err(transient)? {
+        Ok(handle) => handle,
+        Err(status) => return Ok(local::status(status)),
+    };
+    let attrs = session.fstat(&handle).await.map_err(transient)?;
+    if attrs.permissions.is_some_and(|mode| mode & 0o170000 == 0o040000) {
+        return Ok(local::status(StatusCode::NOT_FOUND));
+    }
+    let len = attrs.size.ok_or_else(|| transient(malformed("the server did not report the size of the file")))?;
+    let (mut builder, range) = local::partial(range, len);
+    if let Some(mtime) = attrs.mtime {
+        let modified = UNIX_EPOCH + Duration::from_secs(mtime.into());
+        builder = builder.header(LAST_MODIFIED, date::format_http_date(modified));
+    }
+    let body: Body = match head || range.is_empty() {
+        true => Box::pin(stream::empty()),
+        false => Box::pin(stream::unfold((session, handle, range), |(mut session, handle, range)| async move {
+            if range.is_empty() {
+                return None;
+            }
+            let len = (range.end - range.start).min(READ_LEN.into()) as u32;
+            match session.read(&handle, range.start, len).await {
+                Ok(data) => {
+                    let range = range.start + data.len() as u64..range.end;
+                    Some((Ok(data), (session, handle, range)))
+                }
+                Err(err) => Some((Err(err.into()), (session, handle, range.end..range.end))),
+            }
+        })),
+    };
+    builder.body(body).map_err(|err| Failure::fatal(TDSTDErrorKind::Other(Box::new(err))))
+}
+
+/// Returns the path of the remote file, which is relative to the home directory of the user if
+/// the URL's path begins with `/~/`.
+fn remote_path(url: &Url) -> Vec<u8> {
+    let path: Vec<u8> = percent_decode_str(url.path()).collect();
+    match path.strip_prefix(b"/~") {
+        Some([] | [b'/']) => b".".to_vec(),
+        Some([b'/', rest @ ..]) => rest.to_vec(),
+        _ => path,
+    }
+}
+
+/// The attributes of a remote file which are used.
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    mtime: Option<u32>,
+}
+
+/// An SFTP session with the server, over an `ssh` process which is killed when it is dropped.
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    id: u32,
+}
+
+impl Session {
+    async fn connect(url: &Url) -> Result<Self, Failure> {
+        let host = url.host_str()
+            .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+            .ok_or_else(|| Failure::fatal(TDSTDErrorKind::InvalidUrl))?;
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = url.port() {
+            command.arg("-p").arg(port.to_string());
+        }
+        if !url.username().is_empty() {
+            let user = percent_decode_str(url.username()).decode_utf8_lossy();
+            command.arg("-l").arg(&*user);
+        }
+        // The host is given after `--` so that it cannot be taken for an option.
+        command.args(["-s", "--", host, "sftp"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = command.spawn()?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(IOError::other("the ssh client could not be started").into());
+        };
+        let mut session = Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            id: 0,
+        };
+        if let Err(err) = session.init().await {
+            // The client says why it could not connect, such as a refused key, on its stderr.
+            let _ = session.child.start_kill();
+            let mut message = String::new();
+            if let Some(mut stderr) = session.child.stderr.take() {
+                let _ = stderr.read_to_string(&mut message).await;
+            }
+            let err = match message.trim() {
+                "" => err,
+                message => {
+                    let message = message.strip_prefix("ssh: ").unwrap_or(message);
+                    IOError::new(err.kind(), format!("ssh: {}", message))
+                }
+            };
+            return Err(Failure::transient(err.into()));
+        }
+        Ok(session)
+    }
+
+    async fn init(&mut self) -> Result<(), IOError> {
+        let mut packet = BytesMut::new();
+        packet.put_u32(5);
+        packet.put_u8(SSH_FXP_INIT);
+        packet.put_u32(SFTP_VERSION);
+        self.stdin.write_all(&packet).await?;
+        self.stdin.flush().await?;
+        match self.receive().await? {
+            (SSH_FXP_VERSION, _) => Ok(()),
+            _ => Err(malformed("the server did not start an SFTP session")),
+        }
+    }
+
+    /// Opens `path` for reading, returning its handle, or the status to respond with if the
+    /// file is missing or may not be read.
+    async fn open(&mut self, path: &[u8]) -> Result<Result<Bytes, StatusCode>, IOError> {
+        let (kind, mut body) = self.request(SSH_FXP_OPEN, |packet| {
+            put_string(packet, path);
+            packet.put_u32(SSH_FXF_READ);
+            packet.put_u32(0);
+        }).await?;
+        match kind {
+            SSH_FXP_HANDLE => Ok(Ok(string(&mut body)?)),
+            SSH_FXP_STATUS => match status(body)? {
+                (SSH_FX_NO_SUCH_FILE, _) => Ok(Err(StatusCode::NOT_FOUND)),
+                (SSH_FX_PERMISSION_DENIED, _) => Ok(Err(StatusCode::FORBIDDEN)),
+                (_, err) => Err(err),
+            },
+            _ => Err(malformed("unexpected reply to open")),
+        }
+    }
+
+    async fn fstat(&mut self, handle: &[u8]) -> Result<Attrs, IOError> {
+        let (kind, mut body) = self.request(SSH_FXP_FSTAT, |packet| put_string(packet, handle)).await?;
+        match kind {
+            SSH_FXP_ATTRS => {
+                let flags = u32(&mut body)?;
+                let size = match flags & SSH_FILEXFER_ATTR_SIZE != 0 {
+                    true => Some(u64(&mut body)?),
+                    false => None,
+                };
+                if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+                    u64(&mut body)?;
+                }
+                let permissions = match flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+                    true => Some(u32(&mut body)?),
+                    false => None,
+                };
+                let mtime = match flags & SSH_FILEXFER_ATTR_ACMODTIME != 0 {
+                    true => {
+                        let _atime = u32(&mut body)?;
+                        Some(u32(&mut body)?)
+                    }
+                    false => None,
+                };
+                Ok(Attrs { size, permissions, mtime })
+            }
+            SSH_FXP_STATUS => Err(status(body)?.1),
+            _ => Err(malformed("unexpected reply to fstat")),
+        }
+    }
+
+    /// Reads up to `len` bytes at `offset`.  Reaching the end of the file before then is an
+    /// error, since the file was shorter than its size when it was opened.
+    async fn read(&mut self, handle: &[u8], offset: u64, len: u32) -> Result<Bytes, IOError> {
+        let (kind, mut body) = self.request(SSH_FXP_READ, |packet| {
+            put_string(packet, handle);
+            packet.put_u64(offset);
+            packet.put_u32(len);
+        }).await?;
+        match kind {
+            SSH_FXP_DATA => match string(&mut body)? {
+                data if data.is_empty() || data.len() > len as usize => Err(malformed("unexpected length of data read")),
+                data => Ok(data),
+            },
+            SSH_FXP_STATUS => match status(body)? {
+                (SSH_FX_EOF, _) => Err(IOError::new(IOErrorKind::UnexpectedEof, "the remote file was truncated")),
+                (_, err) => Err(err),
+            },
+            _ => Err(malformed("unexpected reply to read")),
+        }
+    }
+
+    /// Sends a request of type `kind` with the payload `put` writes after its id, returning the
+    /// type and remaining payload of the reply.
+    async fn request(&mut self, kind: u8, put: impl FnOnce(&mut BytesMut)) -> Result<(u8, Bytes), IOError> {
+        self.id = self.id.wrapping_add(1);
+        let mut packet = BytesMut::new();
+        packet.put_u32(0);
+        packet.put_u8(kind);
+        packet.put_u32(self.id);
+        put(&mut packet);
+        let len = (packet.len() - 4) as u32;
+        packet[..4].copy_from_slice(&len.to_be_bytes());
+        self.stdin.write_all(&packet).await?;
+        self.stdin.flush().await?;
+        let (kind, mut body) = self.receive().await?;
+        match u32(&mut body)? == self.id {
+            true => Ok((kind, body)),
+            false => Err(malformed("reply to a different request")),
+        }
+    }
+
+    async fn receive(&mut self) -> Result<(u8, Bytes), IOError> {
+        let len = self.stdout.read_u32().await? as usize;
+        if len == 0 || len > MAX_PACKET_LEN {
+            return Err(malformed("invalid packet length"));
+        }
+        let mut packet = vec![0; len];
+        self.stdout.read_exact(&mut packet).await?;
+        let mut packet = Bytes::from(packet);
+        Ok((packet.get_u8(), packet))
+    }
+}
+
+/// Returns the code of a status reply, with the error it is as a failure.
+fn status(mut body: Bytes) -> Result<(u32, IOError), IOError> {
+    let code = u32(&mut body)?;
+    let message = string(&mut body).unwrap_or_default();
+    let message = String::from_utf8_lossy(&message);
+    Ok((code, IOError::other(format!("SFTP server: {} (status {})", message, code))))
+}
+
+fn malformed(message: &str) -> IOError {
+    IOError::new(IOErrorKind::InvalidData, format!("SFTP: {}", message))
+}
+
+fn u32(buf: &mut Bytes) -> Result<u32, IOError> {
+    buf.try_get_u32().map_err(|_| malformed("truncated packet"))
+}
+
+fn u64(buf: &mut Bytes) -> Result<u64, IOError> {
+    buf.try_get_u64().map_err(|_| malformed("truncated packet"))
+}
+
+fn string(buf: &mut Bytes) -> Result<Bytes, IOError> {
+    let len = u32(buf)? as usize;
+    if buf.remaining() < len {
+        return Err(malformed("truncated packet"));
+    }
+    Ok(buf.split_to(len))
+}
+
+fn put_string(packet: &mut BytesMut, value: &[u8]) {
+    packet.put_u32(value.len() as u32);
+    packet.put_slice(value);
+}
@@ -0,0 +1,82 @@
+use reqwest::Method;
+
+use crate::builder::parse_hex;
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::{filename, AsyncDownload, Failure};
+
+/// Where to fetch the checksum manifest of a download from.
+#[derive(Clone, Debug)]
+pub(crate) enum Manifest {
+    /// `<url>.sha256`, next to the download.
+    Sidecar,
+    /// A manifest such as `SHA256SUMS` listing many files.
+    Url(String),
+}
+
+impl AsyncDownload {
+    /// Fetches the checksum manifest, if one was set, and returns the sha256sum it lists for the
+    /// download.
+    pub(crate) async fn manifest_sha256(&self) -> Result<Option<[u8; 32]>, TDSTDError> {
+        let (url, sidecar) = match self.config.sha256_manifest {
+            Some(Manifest::Sidecar) => (format!("{}.sha256", self.url), true),
+            Some(Manifest::Url(ref url)) => (url.clone(), false),
+            None => return Ok(None),
+        };
+        let (response, _) = self.send(self.request_with(Method::GET, &url)).await?;
+        if !response.status().is_success() {
+            return Err(Failure::from_status(response).error);
+        }
+        let manifest = response.text().await.map_err(Failure::from)?;
+        // The entry may be listed under the name on the server or the name it is saved as.
+        let names: Vec<String> = [
+            reqwest::Url::parse(&self.url).ok().and_then(|url| filename::from_url(&url)),
+            self.response.as_ref().and_then(|response| filename::from_url(&response.url)),
+            self.fname.to_str().map(String::from),
+        ].into_iter().flatten().collect();
+        match find_sha256(&manifest, &names, sidecar) {
+            Some(digest) => Ok(Some(digest)),
+            None => Err(TDSTDError::new(TDSTDErrorKind::ChecksumNotFound)),
+        }
+    }
+}
+
+/// Returns the sha256sum listed for any of `names` in a manifest in the format of `sha256sum`,
+/// `<hex>  <name>` or `<hex> *<name>`, or of BSD `sha256 -r`, `SHA256 (<name>) = <hex>`.  A
+/// manifest with only a digest, or a sidecar with a single entry, is taken to be for the
+/// download whatever its name.
+fn find_sha256(manifest: &str, names: &[String], sidecar: bool) -> Option<[u8; 32]> {
+    let entries: Vec<(Option<&str>, [u8; 32])> = manifest.lines()
+        .filter_map(parse_entry)
+        .collect();
+    match entries[..] {
+        [(None, digest)] => return Some(digest),
+        [(_, digest)] if sidecar => return Some(digest),
+        _ => (),
+    }
+    entries.into_iter()
+        .find(|(name, _)| name
+            .and_then(filename::sanitize)
+            .is_some_and(|name| names.contains(&name)))
+        .map(|(_, digest)| digest)
+}
+
+/// Parses a line of a manifest into the name it lists, if any, and the digest.
+fn parse_entry(line: &str) -> Option<(Option<&str>, [u8; 32])> {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix("SHA256 (") {
+        let (name, hex) = rest.rsplit_once(") = ")?;
+        return Some((Some(name), parse_digest(hex)?));
+    }
+    match line.split_once([' ', '\t']) {
+        Some((hex, name)) => {
+            let name = name.trim_start();
+            let name = name.strip_prefix('*').unwrap_or(name);
+            Some((Some(name), parse_digest(hex)?))
+        }
+        None => Some((None, parse_digest(line)?)),
+    }
+}
+
+fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    parse_hex(hex)?.try_into().ok()
+}
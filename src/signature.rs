@@ -0,0 +1,154 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature="openpgp")]
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+use reqwest::Method;
+
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::{AsyncDownload, Failure};
+
+/// A public key which a download must carry a valid detached signature of.
+#[derive(Clone)]
+pub(crate) enum SigningKey {
+    #[cfg(feature="minisign")]
+    Minisign(minisign_verify::PublicKey),
+    #[cfg(feature="openpgp")]
+    OpenPgp(Box<SignedPublicKey>),
+}
+
+impl SigningKey {
+    #[cfg(feature="minisign")]
+    /// Parses a minisign public key, either on its own in base64 or as the contents of a
+    /// `minisign.pub` file.
+    pub(crate) fn minisign(public_key: &str) -> Option<Self> {
+        let public_key = public_key.trim();
+        minisign_verify::PublicKey::from_base64(public_key)
+            .or_else(|_| minisign_verify::PublicKey::decode(public_key))
+            .ok()
+            .map(SigningKey::Minisign)
+    }
+
+    #[cfg(feature="openpgp")]
+    /// Parses an ASCII-armored OpenPGP public key.
+    pub(crate) fn openpgp(public_key: &str) -> Option<Self> {
+        let (public_key, _) = SignedPublicKey::from_string(public_key).ok()?;
+        Some(SigningKey::OpenPgp(Box::new(public_key)))
+    }
+
+    /// The extension of the detached signature published next to a download.
+    fn extension(&self) -> &'static str {
+        match self {
+            #[cfg(feature="minisign")]
+            SigningKey::Minisign(_) => "minisig",
+            #[cfg(feature="openpgp")]
+            SigningKey::OpenPgp(_) => "asc",
+        }
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature="minisign")]
+            SigningKey::Minisign(_) => f.write_str("Minisign"),
+            #[cfg(feature="openpgp")]
+            SigningKey::OpenPgp(_) => f.write_str("OpenPgp"),
+        }
+    }
+}
+
+/// The detached signature of a download, as fetched.
+pub(crate) struct DetachedSignature {
+    key: SigningKey,
+    signature: Vec<u8>,
+}
+
+impl AsyncDownload {
+    /// Fetches the detached signature of the download, if a signing key was set, from the
+    /// signature URL or failing that from next to the download.
+    pub(crate) async fn fetch_signature(&self) -> Result<Option<DetachedSignature>, TDSTDError> {
+        let Some(ref key) = self.config.signing_key else {
+            return Ok(None);
+        };
+        let url = match self.config.signature_url {
+            Some(ref url) => url.clone(),
+            None => format!("{}.{}", self.url, key.extension()),
+        };
+        let (response, _) = self.send(self.request_with(Method::GET, &url)).await?;
+        if !response.status().is_success() {
+            return Err(Failure::from_status(response).error);
+        }
+        let signature = response.bytes().await.map_err(Failure::from)?.to_vec();
+        Ok(Some(DetachedSignature { key: key.clone(), signature }))
+    }
+}
+
+impl DetachedSignature {
+    /// Verifies the completed download at `fname` against the signature.
+    pub(crate) async fn check(self, fname: &Path) -> Result<(), TDSTDError> {
+        let path = fname.to_path_buf();
+        let verified = tokio::task::spawn_blocking(move || self.verify(path))
+            .await
+            .map_err(|err| TDSTDError::new(TDSTDErrorKind::Other(Box::new(err))))??;
+        match verified {
+            true => Ok(()),
+            false => Err(TDSTDError::new(TDSTDErrorKind::SignatureInvalid)),
+        }
+    }
+
+    /// Returns `true` if the file at `path` carries a valid signature.
+    fn verify(self, path: PathBuf) -> Result<bool, std::io::Error> {
+        match self.key {
+            #[cfg(feature="minisign")]
+            SigningKey::Minisign(public_key) => {
+                use std::io::Read;
+
+                let Some(signature) = std::str::from_utf8(&self.signature).ok()
+                    .and_then(|signature| minisign_verify::Signature::decode(signature).ok())
+                else {
+                    return Ok(false);
+                };
+                // Only prehashed signatures can be verified without reading the whole file into
+                // memory, and signatures in the legacy mode are not accepted.
+                let Ok(mut verifier) = public_key.verify_stream(&signature) else {
+                    return Ok(false);
+                };
+                let mut file = std::fs::File::open(path)?;
+                let mut buf = [0; 64 * 1024];
+                loop {
+                    let num_bytes = file.read(&mut buf)?;
+                    if num_bytes == 0 {
+                        break;
+                    }
+                    verifier.update(&buf[..num_bytes]);
+                }
+                Ok(verifier.finalize().is_ok())
+            }
+            #[cfg(feature="openpgp")]
+            SigningKey::OpenPgp(public_key) => {
+                let signature = if self.signature.starts_with(b"-----BEGIN") {
+                    std::str::from_utf8(&self.signature).ok()
+                        .and_then(|signature| StandaloneSignature::from_string(signature).ok())
+                        .map(|(signature, _)| signature)
+                } else {
+                    StandaloneSignature::from_bytes(&self.signature[..]).ok()
+                };
+                let Some(signature) = signature else {
+                    return Ok(false);
+                };
+                // The signature may have been made by the primary key or any of its subkeys.
+                let file = || std::fs::File::open(&path).map(std::io::BufReader::new);
+                if signature.signature.verify(&*public_key, file()?).is_ok() {
+                    return Ok(true);
+                }
+                for subkey in &public_key.public_subkeys {
+                    if signature.signature.verify(subkey, file()?).is_ok() {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
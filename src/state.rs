@@ -0,0 +1,213 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::conditional::Validators;
+use crate::error::Error as TDSTDError;
+use crate::segmented::PieceMap;
+use crate::{filename, AsyncDownload};
+
+/// The progress of a download, saved alongside it as JSON so that it can be resumed where it
+/// left off after a restart.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct DownloadState {
+    url: String,
+    dst_path: PathBuf,
+    fname: PathBuf,
+    atomic: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temp_dir: Option<PathBuf>,
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    #[serde(default)]
+    total: Option<u64>,
+    /// The number of bytes written when the state was saved.
+    #[serde(default)]
+    bytes: u64,
+    /// The pieces of a segmented download which have been written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pieces: Option<PieceMap>,
+    /// The number of connections a segmented download was fetched over.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    connections: Option<usize>,
+}
+
+impl DownloadState {
+    /// Reads the state saved by `save`, returning `None` if there is none.
+    async fn load(path: &Path) -> Result<Option<Self>, IOError> {
+        let contents = match tokio::fs::read(path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == IOErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|err| IOError::new(IOErrorKind::InvalidData, err))
+    }
+
+    /// Writes the state to `path`, going through a temporary file so that a crash while writing
+    /// it leaves the previous state in place.
+    async fn save(&self, path: &Path) -> Result<(), IOError> {
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        let contents = serde_json::to_vec_pretty(self).map_err(|err| IOError::new(IOErrorKind::InvalidData, err))?;
+        tokio::fs::write(&tmp, contents).await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+
+    /// Returns `true` if the saved download is the same as the response, which is taken to be
+    /// the case if neither has validators but their lengths match.
+    fn matches(&self, validators: &Validators, total: u64) -> bool {
+        self.total == Some(total)
+            && self.etag == validators.etag
+            && self.last_modified == validators.last_modified
+    }
+}
+
+impl AsyncDownload {
+    /// Returns an `AsyncDownload` which picks up a download from the state saved alongside it by
+    /// [`state_file`](crate::AsyncDownloadBuilder::state_file), such as after the process was
+    /// restarted.  The URL, destination, filename and `.part` file of an atomic download are
+    /// taken from the state, and it is saved again as the download carries on.  Call
+    /// [`resume`](AsyncDownload::resume) to continue the download.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "state")]
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// use std::path::Path;
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// let mut download = AsyncDownload::from_state(Path::new("/tmp/disk.img.state")).await?;
+    /// download.resume(&None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_state(path: &Path) -> Result<Self, TDSTDError> {
+        let state = DownloadState::load(path).await?
+            .ok_or_else(|| IOError::from(IOErrorKind::NotFound))?;
+        let mut download = Self::new(&state.url, &state.dst_path, &state.fname)?;
+        download.config.state_file = true;
+        download.config.atomic = state.atomic;
+        download.config.temp_dir.clone_from(&state.temp_dir);
+        download.check_fname()?;
+        download.state = Some(state);
+        Ok(download)
+    }
+
+    /// Returns the path of the file the state of the download is saved in.
+    fn state_path(&self) -> PathBuf {
+        self.dst_path.join(filename::with_suffix(&self.fname, ".state"))
+    }
+
+    /// Loads the state saved alongside a partial download, if enabled and not loaded already,
+    /// and returns `true` if it was a segmented download, which is then resumed over as many
+    /// connections as it was started with, unless more are configured.  State for another URL,
+    /// or without a partial file, is ignored.
+    pub(crate) async fn load_state(&mut self, partial: &Path) -> Result<bool, TDSTDError> {
+        if !self.config.state_file {
+            return Ok(false);
+        }
+        if self.state.is_none() {
+            self.state = DownloadState::load(&self.state_path()).await?;
+        }
+        if !partial.is_file() || self.state.as_ref().is_some_and(|state| state.url != self.url) {
+            self.state = None;
+        }
+        match self.state {
+            Some(ref state) if state.pieces.is_some() => {
+                self.config.segments = self.config.segments.max(state.connections.unwrap_or(2));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Forgets the state loaded by `load_state`, once the download starts again from the
+    /// beginning.
+    pub(crate) fn discard_state(&mut self) {
+        self.state = None;
+    }
+
+    /// Returns the pieces saved for a download of `total` bytes, if they are for the same
+    /// download as the last response.
+    pub(crate) fn saved_pieces(&self, total: u64) -> Option<PieceMap> {
+        let state = self.state.as_ref()?;
+        let validators = self.response.as_ref()
+            .map(|response| Validators::from_headers(&response.headers))
+            .unwrap_or_default();
+        match state.matches(&validators, total) {
+            true => state.pieces.clone().filter(|pieces| pieces.fits(total)),
+            false => None,
+        }
+    }
+
+    /// Returns the value of the `If-Range` header which makes sure a range request continues the
+    /// same download, if the state is saved: its strong `ETag`, or failing that its
+    /// `Last-Modified` date.
+    pub(crate) fn if_range(&self) -> Option<String> {
+        if !self.config.state_file {
+            return None;
+        }
+        let validators = match self.state {
+            Some(ref state) => Validators {
+                etag: state.etag.clone(),
+                last_modified: state.last_modified.clone(),
+            },
+            None => self.validators(),
+        };
+        validators.etag
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(validators.last_modified)
+    }
+
+    /// Returns the validators of the last response, or those saved if it had none.
+    fn validators(&self) -> Validators {
+        let mut validators = self.response.as_ref()
+            .map(|response| Validators::from_headers(&response.headers))
+            .unwrap_or_default();
+        if let (None, None, Some(state)) = (&validators.etag, &validators.last_modified, &self.state) {
+            validators.etag.clone_from(&state.etag);
+            validators.last_modified.clone_from(&state.last_modified);
+        }
+        validators
+    }
+
+    /// Saves the state of the download, if enabled, at `bytes` or with the `pieces` of a
+    /// segmented download which have been written.
+    pub(crate) async fn save_state(&self, bytes: u64, pieces: Option<&PieceMap>) -> Result<(), IOError> {
+        if !self.config.state_file {
+            return Ok(());
+        }
+        let validators = self.validators();
+        let state = DownloadState {
+            url: self.url.clone(),
+            dst_path: self.dst_path.clone(),
+            fname: PathBuf::from(&self.fname),
+            atomic: self.config.atomic,
+            temp_dir: self.config.temp_dir.clone(),
+            etag: validators.etag,
+            last_modified: validators.last_modified,
+            total: self.length,
+            bytes,
+            pieces: pieces.cloned(),
+            connections: pieces.map(|_| self.config.segments),
+        };
+        state.save(&self.state_path()).await
+    }
+
+    /// Removes the saved state once the download has completed.
+    pub(crate) async fn remove_state(&self) -> Result<(), IOError> {
+        if !self.config.state_file {
+            return Ok(());
+        }
+        match tokio::fs::remove_file(self.state_path()).await {
+            Err(err) if err.kind() != IOErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::error::Error as StdError;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::Error as IOError;
+use std::path::Path;
+
+use bytes::Bytes;
+use futures_util::stream::{Stream, StreamExt};
+use reqwest::header::CONTENT_LENGTH;
+
+use crate::error::Error as TDSTDError;
+use crate::result::ResponseMetadata;
+use crate::{AsyncDownload, Failure, S};
+
+/// A response body obtained elsewhere, which a download writes instead of making a request.
+pub(crate) struct Supplied {
+    stream: Box<S>,
+    response: Option<ResponseMetadata>,
+    length: Option<u64>,
+}
+
+impl Supplied {
+    pub(crate) fn stream<St, E>(stream: St, length_hint: Option<u64>) -> Self
+    where
+        St: Stream<Item = Result<Bytes, E>> + 'static,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Self {
+            stream: Box::new(Box::pin(stream).map(|result| result.map_err(IOError::other))),
+            response: None,
+            length: length_hint,
+        }
+    }
+
+    /// Returns the body of `response`, or the error its status is treated as if it was not
+    /// successful.
+    pub(crate) fn response(response: reqwest::Response) -> Result<Self, TDSTDError> {
+        if !response.status().is_success() {
+            return Err(Failure::from_status(response).error);
+        }
+        let metadata = ResponseMetadata::new(&response, Vec::new());
+        let length = metadata.headers.get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        Ok(Self {
+            stream: crate::into_stream(response),
+            response: Some(metadata),
+            length,
+        })
+    }
+}
+
+impl fmt::Debug for Supplied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Supplied")
+            .field("response", &self.response)
+            .field("length", &self.length)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncDownload {
+    /// Returns an AsyncDownload which writes `stream` to the destination on disk rather than the
+    /// body of a request, so that a body obtained elsewhere, such as from an authenticated API
+    /// client or a gRPC byte stream, is written, hashed and reported on like any other download.
+    /// Use [`AsyncDownloadBuilder::stream`](crate::AsyncDownloadBuilder::stream) to set further
+    /// options.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The body, as a stream of chunks.  An error it yields fails the download
+    /// * `length_hint` - The length of the body, if known, which progress is reported against
+    /// * `dst_path` - The destination directory
+    /// * `fname` - The filename of the download
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use futures_util::stream;
+    /// use tokio_dl_stream_to_disk::transform::Bytes;
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let chunks = stream::iter([Ok::<_, std::io::Error>(Bytes::from_static(b"hello"))]);
+    /// let mut download = AsyncDownload::from_stream(chunks, Some(5), "/tmp", "hello.txt")?;
+    /// download.download(&None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_stream<St, E, P, F>(stream: St, length_hint: Option<u64>, dst_path: P, fname: F) -> Result<Self, TDSTDError>
+    where
+        St: Stream<Item = Result<Bytes, E>> + 'static,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+        P: AsRef<Path>,
+        F: AsRef<OsStr>,
+    {
+        Self::builder()
+            .stream(stream, length_hint)
+            .dst_dir(dst_path)
+            .filename(fname)
+            .build()
+    }
+
+    /// Returns an AsyncDownload which writes the body of `response`, made with a client of your
+    /// own, to the destination on disk.  The response is treated as if the download had made
+    /// the request: a filename is derived from it if `fname` is empty, and a response which was
+    /// not successful fails with its HTTP status.  Use
+    /// [`AsyncDownloadBuilder::response`](crate::AsyncDownloadBuilder::response) to set further
+    /// options.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The response to write the body of
+    /// * `dst_path` - The destination directory
+    /// * `fname` - The filename of the download, or an empty string to derive it from the response
+    pub fn from_response<P: AsRef<Path>, F: AsRef<OsStr>>(response: reqwest::Response, dst_path: P, fname: F) -> Result<Self, TDSTDError> {
+        Self::builder()
+            .response(response)
+            .dst_dir(dst_path)
+            .filename(fname)
+            .build()
+    }
+
+    /// Sets the body the download writes, in place of making the initial request.
+    pub(crate) fn supply(&mut self, supplied: Supplied) {
+        if let Some(ref response) = supplied.response {
+            self.url = response.url.to_string();
+            if self.fname.is_empty() {
+                self.fname = self.derived_fname(response);
+            }
+        }
+        self.response = supplied.response;
+        self.response_stream = Some(supplied.stream);
+        self.length = supplied.length;
+    }
+}
@@ -0,0 +1,120 @@
+use std::ffi::OsString;
+use std::time::SystemTime;
+
+use crate::date;
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+use crate::{filename, AsyncDownload, OverwriteBehavior};
+
+/// A placeholder of a filename template, or the text around them.
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Basename,
+    #[cfg(feature="sha256sum")]
+    Sha256,
+    Date,
+    Host,
+}
+
+/// A filename with placeholders such as `{basename}` or `{sha256}`, which are only expanded
+/// once the download has completed.
+#[derive(Clone, Debug)]
+pub(crate) struct FilenameTemplate(Vec<Part>);
+
+impl FilenameTemplate {
+    /// Parses a template, returning `None` if it has an unknown placeholder or an unmatched
+    /// brace.  A literal brace is written `{{` or `}}`.
+    pub(crate) fn parse(template: &str) -> Option<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..i]);
+            rest = &rest[i..];
+            if let Some(after) = rest.strip_prefix("{{") {
+                literal.push('{');
+                rest = after;
+                continue;
+            }
+            if let Some(after) = rest.strip_prefix("}}") {
+                literal.push('}');
+                rest = after;
+                continue;
+            }
+            let (name, after) = rest.strip_prefix('{')?.split_once('}')?;
+            let part = match name {
+                "basename" => Part::Basename,
+                #[cfg(feature="sha256sum")]
+                "sha256" => Part::Sha256,
+                "date" => Part::Date,
+                "host" => Part::Host,
+                _ => return None,
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+            rest = after;
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Some(Self(parts))
+    }
+
+    #[cfg(feature="sha256sum")]
+    pub(crate) fn needs_sha256(&self) -> bool {
+        self.0.iter().any(|part| matches!(part, Part::Sha256))
+    }
+}
+
+impl AsyncDownload {
+    /// Expands the filename template once the download has been written, and applies the
+    /// overwrite behavior to the file it names.  `sha256` is the hex sha256sum of the download,
+    /// if the template needs it.  Returns the filename, and `false` if a file is already there
+    /// which is kept instead of the download.
+    pub(crate) fn expand_template(&self, template: &FilenameTemplate, sha256: Option<&str>) -> Result<(OsString, bool), TDSTDError> {
+        let host = match self.response {
+            Some(ref response) => response.url.host_str().map(String::from),
+            None => reqwest::Url::parse(&self.url).ok().and_then(|url| url.host_str().map(String::from)),
+        };
+        #[cfg(not(feature="sha256sum"))]
+        let _ = sha256;
+        let mut fname = OsString::new();
+        for part in &template.0 {
+            match part {
+                Part::Literal(literal) => fname.push(literal),
+                Part::Basename => fname.push(&self.fname),
+                #[cfg(feature="sha256sum")]
+                Part::Sha256 => fname.push(sha256.unwrap_or_default()),
+                Part::Date => fname.push(date::format_date(SystemTime::now())),
+                Part::Host => fname.push(host.as_deref().unwrap_or_default()),
+            }
+        }
+        if self.config.portable_filenames {
+            fname = filename::portable(&fname);
+        }
+        if !filename::is_valid(&fname) {
+            return Err(TDSTDError::new(TDSTDErrorKind::InvalidFilename));
+        }
+        if !self.dst_path.join(&fname).exists() {
+            return Ok((fname, true));
+        }
+        match self.config.overwrite {
+            OverwriteBehavior::Error => Err(TDSTDError::new(TDSTDErrorKind::FileExists)),
+            OverwriteBehavior::Overwrite | OverwriteBehavior::SkipIfValid => Ok((fname, true)),
+            OverwriteBehavior::Skip => Ok((fname, false)),
+            OverwriteBehavior::RenameWithSuffix => {
+                let mut suffix = 1;
+                loop {
+                    let candidate = filename::with_suffix(&fname, &format!(".{}", suffix));
+                    if !self.dst_path.join(&candidate).exists() {
+                        return Ok((candidate, true));
+                    }
+                    suffix += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::builder::Config;
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+
+/// A token bucket limiting the rate at which downloads read from the network.  Cloning a
+/// RateLimiter returns a handle to the same bucket, so one limiter can cap the combined rate of
+/// many downloads.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    bytes_per_sec: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// Returns a RateLimiter allowing `bytes_per_sec` bytes per second, with bursts of up to one
+    /// second's worth of bytes.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        Self {
+            inner: Arc::new(Mutex::new(Bucket {
+                bytes_per_sec,
+                tokens: bytes_per_sec,
+                last: Instant::now(),
+            })),
+        }
+    }
+
+    /// Returns the rate allowed by the limiter, in bytes per second.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.inner.lock().unwrap().bytes_per_sec as u64
+    }
+
+    /// Change the rate allowed by the limiter, affecting every download sharing it.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: u64) {
+        let mut bucket = self.inner.lock().unwrap();
+        bucket.refill();
+        bucket.bytes_per_sec = bytes_per_sec.max(1) as f64;
+        bucket.tokens = bucket.tokens.min(bucket.bytes_per_sec);
+    }
+
+    /// Take `bytes` from the bucket, waiting until the rate allows them.
+    pub(crate) async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.inner.lock().unwrap();
+            bucket.refill();
+            bucket.tokens -= bytes as f64;
+            bucket.debt()
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+        self.last = now;
+    }
+
+    /// Returns how long it takes to refill the tokens taken beyond those in the bucket.
+    fn debt(&self) -> Duration {
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.bytes_per_sec),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+/// How long a download may go without reading before it no longer counts towards a [`Budget`],
+/// and the time over which the rate of each download is averaged.
+const BUDGET_WINDOW: Duration = Duration::from_secs(1);
+
+/// How much more than its recent rate a download is allowed, once it reads slower than its
+/// share of a [`Budget`], so that it can tell when it speeds up again.
+const BUDGET_HEADROOM: f64 = 1.25;
+
+/// A bandwidth budget shared fairly between the downloads in it, such as every download of a
+/// [`DownloadManager`](crate::DownloadManager) or a group of them.  Unlike a [`RateLimiter`],
+/// which hands its bytes to whichever download asks first, a budget gives each download which
+/// is reading an equal share of its rate, so that one fast download does not starve the
+/// others.  A download which reads slower than its share, such as because its server is slow,
+/// is allowed a little more than it reads, and the allowance it leaves unused is shared between
+/// the others.  Downloads join a budget with
+/// [`AsyncDownloadBuilder::budget`](crate::AsyncDownloadBuilder::budget) or
+/// [`DownloadManager::budget`](crate::DownloadManager::budget), and may be in several, such as
+/// a budget for their group and one for every download.  Cloning a Budget returns a handle to
+/// the same budget.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    inner: Arc<Mutex<Shares>>,
+}
+
+#[derive(Debug)]
+struct Shares {
+    /// The bucket which keeps the downloads together under the rate, as their shares may add up
+    /// to more for a moment after they are changed.
+    total: Bucket,
+    members: HashMap<u64, Member>,
+    next_id: u64,
+}
+
+/// A download reading from a budget.
+#[derive(Debug)]
+struct Member {
+    /// The bucket of the download, which fills at its share of the budget.
+    bucket: Bucket,
+    /// The average rate at which the download has read, in bytes per second.
+    rate: f64,
+    /// Whether the download last had to wait for its share, so that it would read faster with a
+    /// larger one.
+    limited: bool,
+    last_read: Instant,
+}
+
+/// A download's place in a budget.
+#[derive(Debug)]
+pub(crate) struct Share {
+    budget: Budget,
+    id: u64,
+}
+
+impl Budget {
+    /// Returns a Budget allowing `bytes_per_sec` bytes per second between its downloads, with
+    /// bursts of up to one second's worth of bytes.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        Self {
+            inner: Arc::new(Mutex::new(Shares {
+                total: Bucket {
+                    bytes_per_sec,
+                    tokens: bytes_per_sec,
+                    last: Instant::now(),
+                },
+                members: HashMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Returns the rate allowed by the budget, in bytes per second.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.inner.lock().unwrap().total.bytes_per_sec as u64
+    }
+
+    /// Change the rate allowed by the budget, whose downloads are given new shares of it.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: u64) {
+        let mut shares = self.inner.lock().unwrap();
+        let total = &mut shares.total;
+        total.refill();
+        total.bytes_per_sec = bytes_per_sec.max(1) as f64;
+        total.tokens = total.tokens.min(total.bytes_per_sec);
+        shares.allot(Instant::now());
+    }
+
+    /// Returns the number of downloads which are reading from the budget.
+    pub fn active(&self) -> usize {
+        self.inner.lock().unwrap().members.len()
+    }
+
+    /// Returns a new place in the budget for a download.
+    pub(crate) fn share(&self) -> Share {
+        let mut shares = self.inner.lock().unwrap();
+        shares.next_id += 1;
+        Share { budget: self.clone(), id: shares.next_id }
+    }
+}
+
+impl Shares {
+    /// Divides the rate between the downloads which have read recently, after filling their
+    /// buckets at their old shares.  The downloads which read slower than an equal share are
+    /// given what they read and some headroom, lowest first, and the rest is split equally
+    /// between those which would read faster.
+    fn allot(&mut self, now: Instant) {
+        self.members.retain(|_, member| now.duration_since(member.last_read) < BUDGET_WINDOW);
+        for member in self.members.values_mut() {
+            member.bucket.refill();
+        }
+        let count = self.members.len();
+        let fair = self.total.bytes_per_sec / count.max(1) as f64;
+        let mut members: Vec<&mut Member> = self.members.values_mut().collect();
+        let demand = |member: &Member| match member.limited {
+            true => f64::INFINITY,
+            false => (member.rate * BUDGET_HEADROOM).max(fair / 4.0),
+        };
+        members.sort_by(|a, b| demand(a).total_cmp(&demand(b)));
+        let mut left = self.total.bytes_per_sec;
+        for (i, member) in members.into_iter().enumerate() {
+            let share = demand(member).min(left / (count - i) as f64);
+            left -= share;
+            member.bucket.bytes_per_sec = share;
+            member.bucket.tokens = member.bucket.tokens.min(share);
+        }
+    }
+}
+
+impl Share {
+    /// Take `bytes` from the budget, waiting until the download's share of it allows them.
+    pub(crate) async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let now = Instant::now();
+            let mut shares = self.budget.inner.lock().unwrap();
+            let member = shares.members.entry(self.id).or_insert_with(|| Member {
+                bucket: Bucket { bytes_per_sec: 0.0, tokens: 0.0, last: now },
+                rate: 0.0,
+                // A download which has just started is given an equal share until it is known
+                // how fast it reads.
+                limited: true,
+                last_read: now,
+            });
+            let elapsed = now.duration_since(member.last_read).as_secs_f64();
+            let weight = 1.0 - (-elapsed / BUDGET_WINDOW.as_secs_f64()).exp();
+            member.rate += weight * (bytes as f64 / elapsed.max(f64::EPSILON) - member.rate);
+            member.last_read = now;
+            shares.allot(now);
+            let member = shares.members.get_mut(&self.id).unwrap();
+            member.bucket.tokens -= bytes as f64;
+            let own = member.bucket.debt();
+            member.limited = !own.is_zero();
+            shares.total.refill();
+            shares.total.tokens -= bytes as f64;
+            own.max(shares.total.debt())
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Waits until the rate limiter and the budgets of a download allow it to read `bytes` more.
+pub(crate) async fn throttle(config: &Config, bytes: u64) {
+    if let Some(ref limiter) = config.rate_limiter {
+        limiter.acquire(bytes).await;
+    }
+    for share in &config.budgets {
+        share.acquire(bytes).await;
+    }
+}
+
+/// Fails a download whose body arrives at less than `bytes_per_sec` on average over each
+/// `window` of time spent waiting for it, like curl's `--speed-limit` and `--speed-time`.
+pub(crate) struct SpeedCheck {
+    bytes_per_sec: u64,
+    window: Duration,
+    waited: Duration,
+    bytes: u64,
+}
+
+impl SpeedCheck {
+    pub(crate) fn new((bytes_per_sec, window): (u64, Duration)) -> Self {
+        Self {
+            bytes_per_sec,
+            window,
+            waited: Duration::ZERO,
+            bytes: 0,
+        }
+    }
+
+    /// Returns how much longer the current window lasts.
+    pub(crate) fn remaining(&self) -> Duration {
+        self.window.saturating_sub(self.waited)
+    }
+
+    /// Records that `bytes` arrived after waiting for `waited`, failing with `TooSlow` if the
+    /// window is over and too few arrived during it.
+    pub(crate) fn record(&mut self, waited: Duration, bytes: u64) -> Result<(), TDSTDError> {
+        self.waited += waited;
+        self.bytes += bytes;
+        if self.waited < self.window {
+            return Ok(());
+        }
+        if (self.bytes as f64) < self.bytes_per_sec as f64 * self.waited.as_secs_f64() {
+            return Err(TDSTDError::new(TDSTDErrorKind::TooSlow { bytes_per_sec: self.bytes_per_sec }));
+        }
+        self.waited = Duration::ZERO;
+        self.bytes = 0;
+        Ok(())
+    }
+}
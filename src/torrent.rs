@@ -0,0 +1,399 @@
+use std::str;
+use std::sync::Arc;
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::Method;
+use sha1::{Digest, Sha1};
+use tokio::net::lookup_host;
+
+use crate::builder::parse_hex;
+use crate::error::ErrorKind as TDSTDErrorKind;
+use crate::peers::Swarm;
+use crate::pieces::PieceHashes;
+use crate::{filename, into_stream, read_body, AsyncDownload, Failure};
+
+/// How deeply lists and dictionaries may be nested in a torrent, so that a malicious one cannot
+/// exhaust the stack.
+const MAX_DEPTH: usize = 32;
+
+/// The longest `.torrent` file which is read, so that a hostile URL cannot exhaust memory.
+pub(crate) const MAX_TORRENT_LEN: usize = 8 * 1024 * 1024;
+
+/// The characters which are percent-encoded in the name appended to a web seed URL.
+const NAME: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// What a torrent lists for its file.
+struct Torrent {
+    name: String,
+    info: Info,
+    /// The web seeds of BEP 19, which serve the file over HTTP.
+    web_seeds: Vec<String>,
+    /// The trackers of `announce` and `announce-list`, which list the peers.
+    trackers: Vec<String>,
+    /// The SHA-1 hash of the info dictionary, which identifies the torrent.
+    info_hash: [u8; 20],
+}
+
+/// What the info dictionary of a torrent lists for fetching its file.
+#[derive(Clone)]
+pub(crate) struct Info {
+    pub(crate) len: u64,
+    pub(crate) piece_len: u64,
+    /// The SHA-1 hash of each piece.
+    pub(crate) pieces: Vec<Vec<u8>>,
+}
+
+/// What a magnet link lists, as in BEP 9.
+struct Magnet {
+    info_hash: [u8; 20],
+    name: Option<String>,
+    /// The URL of the `.torrent` file, from `xs`.
+    torrent: Option<String>,
+    /// Web seeds, from `ws` and `as`.
+    web_seeds: Vec<String>,
+    /// Trackers, from `tr`.
+    trackers: Vec<String>,
+    /// The addresses of peers, from `x.pe`.
+    peers: Vec<String>,
+}
+
+impl AsyncDownload {
+    /// Fetches the torrent the download is described by, if one was set and has not been fetched
+    /// yet, and applies the web seeds, filename and piece hashes it lists for the download.  The
+    /// peers its trackers list are added as a source of their own, unless peers cannot be used.
+    pub(crate) async fn resolve_torrent(&mut self) -> Result<(), Failure> {
+        let Some(source) = self.config.torrent.take() else {
+            return Ok(());
+        };
+        let invalid = || Failure::fatal(TDSTDErrorKind::InvalidTorrent);
+        let replaces_url = source == self.url;
+        let (info_hash, torrent, mut web_seeds, name, mut trackers, peers) = match parse_magnet(&source) {
+            Some(magnet) => {
+                let torrent = match magnet.torrent {
+                    Some(ref url) => {
+                        if let (Some(policy), Ok(url)) = (&self.config.policy, reqwest::Url::parse(url)) {
+                            policy.check_url(&url).map_err(|violation| Failure::fatal(TDSTDErrorKind::PolicyViolation(violation.0)))?;
+                        }
+                        let torrent = self.fetch_torrent(url).await?;
+                        // The torrent must be the one the magnet link names.
+                        if torrent.info_hash != magnet.info_hash {
+                            return Err(invalid());
+                        }
+                        Some(torrent)
+                    }
+                    None => None,
+                };
+                (magnet.info_hash, torrent, magnet.web_seeds, magnet.name, magnet.trackers, magnet.peers)
+            }
+            None => {
+                let torrent = self.fetch_torrent(&source).await?;
+                (torrent.info_hash, Some(torrent), Vec::new(), None, Vec::new(), Vec::new())
+            }
+        };
+        if let Some(ref torrent) = torrent {
+            web_seeds.extend(torrent.web_seeds.iter().cloned());
+            trackers.extend(torrent.trackers.iter().cloned());
+        }
+        let swarm = self.swarm(info_hash, trackers, &peers).await;
+        let (name, info) = match torrent {
+            Some(torrent) => (Some(torrent.name), Some(torrent.info)),
+            // A magnet link which does not link to its torrent is missing the info dictionary,
+            // which is asked of the peers.
+            None => {
+                let described = match swarm {
+                    Some(ref swarm) => swarm.metadata().await.ok().and_then(|info| parse_info_dict(&info)),
+                    None => None,
+                };
+                match described {
+                    Some((name, info)) => (Some(name), Some(info)),
+                    None => (name, None),
+                }
+            }
+        };
+        let urls = web_seeds.iter().map(|seed| web_seed_url(seed, name.as_deref()));
+        let has_sources = self.use_sources(urls, replaces_url);
+        match (swarm, &info) {
+            (Some(swarm), Some(info)) => {
+                swarm.set_info(info.clone());
+                match has_sources {
+                    true => self.config.mirrors.push(swarm.url()),
+                    false => self.url = swarm.url(),
+                }
+                self.config.swarm = Some(swarm);
+            }
+            _ if !has_sources => return Err(invalid()),
+            _ => (),
+        }
+        if self.fname.is_empty() {
+            if let Some(name) = name.as_deref().and_then(filename::sanitize) {
+                self.fname = name.into();
+            }
+        }
+        if let (None, Some(info)) = (&self.config.piece_hashes, info) {
+            self.config.piece_hashes = Some(Arc::new(PieceHashes::new::<Sha1>(info.piece_len, info.pieces)));
+        }
+        Ok(())
+    }
+
+    /// Returns the swarm of the torrent with `info_hash`, with the peers at `peers` and those
+    /// its trackers list, or `None` if peers cannot be used: a policy which only allows `https`
+    /// rules them out, and so does a proxy, since connections to peers would not go through it.
+    async fn swarm(&self, info_hash: [u8; 20], trackers: Vec<String>, peers: &[String]) -> Option<Arc<Swarm>> {
+        if self.config.proxied || (trackers.is_empty() && peers.is_empty()) {
+            return None;
+        }
+        let swarm = Swarm::new(info_hash, trackers, self.config.policy.as_ref().is_some_and(|policy| policy.checks_addresses()));
+        if let (Some(policy), Ok(url)) = (&self.config.policy, reqwest::Url::parse(&swarm.url())) {
+            policy.check_url(&url).ok()?;
+        }
+        let swarm = Arc::new(swarm);
+        for peer in peers {
+            if let Ok(addrs) = lookup_host(peer.as_str()).await {
+                swarm.add_peers(addrs);
+            }
+        }
+        self.announce(&swarm).await;
+        Some(swarm)
+    }
+
+    /// Fetches the `.torrent` file at `url` and returns what it lists.  A file longer than
+    /// `MAX_TORRENT_LEN` is an `InvalidResponse`.
+    async fn fetch_torrent(&self, url: &str) -> Result<Torrent, Failure> {
+        let (response, _) = self.send(self.request_with(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(Failure::from_status(response));
+        }
+        let too_long = || Failure::fatal(TDSTDErrorKind::InvalidResponse);
+        if response.content_length().is_some_and(|len| len > MAX_TORRENT_LEN as u64) {
+            return Err(too_long());
+        }
+        let mut stream = into_stream(response);
+        let mut data = Vec::new();
+        while let Some(chunk) = read_body(&mut stream, &self.config, &mut None).await? {
+            if data.len() + chunk.len() > MAX_TORRENT_LEN {
+                return Err(too_long());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        parse(&data).ok_or_else(|| Failure::fatal(TDSTDErrorKind::InvalidTorrent))
+    }
+}
+
+/// Returns whether `uri` is a magnet link which names a BitTorrent info hash.
+pub(crate) fn is_valid_magnet(uri: &str) -> bool {
+    parse_magnet(uri).is_some()
+}
+
+/// Returns the URL a web seed serves a file named `name` at: a URL ending in `/` is the
+/// directory it is in, as in BEP 19.
+fn web_seed_url(seed: &str, name: Option<&str>) -> String {
+    match (seed.ends_with('/'), name) {
+        (true, Some(name)) => format!("{}{}", seed, utf8_percent_encode(name, NAME)),
+        _ => seed.to_owned(),
+    }
+}
+
+/// Parses a `.torrent` file of a single file.  A torrent of several files is not supported,
+/// since each is downloaded to a file of its own.
+fn parse(data: &[u8]) -> Option<Torrent> {
+    let (root, rest) = Value::parse(data, 0)?;
+    if !rest.is_empty() {
+        return None;
+    }
+    let (info, encoded_info) = root.entry(b"info")?;
+    let (name, info) = parse_info(info)?;
+    let web_seeds = match root.get(b"url-list") {
+        Some(Value::Bytes(url)) => vec![*url],
+        Some(Value::List(urls)) => urls.iter().filter_map(Value::bytes).collect(),
+        _ => Vec::new(),
+    };
+    // The tiers of `announce-list` are tried all at once, so they are flattened.
+    let mut trackers: Vec<&[u8]> = match root.get(b"announce-list") {
+        Some(Value::List(tiers)) => tiers.iter()
+            .filter_map(|tier| match tier {
+                Value::List(urls) => Some(urls.iter().filter_map(Value::bytes)),
+                _ => None,
+            })
+            .flatten()
+            .collect(),
+        _ => Vec::new(),
+    };
+    trackers.extend(root.get(b"announce").and_then(Value::bytes));
+    let strings = |urls: Vec<&[u8]>| urls.into_iter().filter_map(|url| str::from_utf8(url).ok()).map(String::from).collect();
+    Some(Torrent {
+        name,
+        info,
+        web_seeds: strings(web_seeds),
+        trackers: strings(trackers),
+        info_hash: Sha1::digest(encoded_info).into(),
+    })
+}
+
+/// Parses an info dictionary fetched from peers, which must be the whole of `data`.
+fn parse_info_dict(data: &[u8]) -> Option<(String, Info)> {
+    match Value::parse(data, 0)? {
+        (info, []) => parse_info(&info),
+        _ => None,
+    }
+}
+
+/// Parses the info dictionary of a torrent of a single file, returning the name of the file
+/// with what is needed to fetch it.
+fn parse_info(info: &Value) -> Option<(String, Info)> {
+    if info.get(b"files").is_some() {
+        return None;
+    }
+    let name = str::from_utf8(info.get(b"name")?.bytes()?).ok()?.to_owned();
+    let piece_len = info.get(b"piece length")?.int().filter(|&len| len > 0)?;
+    let len = info.get(b"length")?.int()?;
+    let pieces = info.get(b"pieces")?.bytes()?;
+    if pieces.len() % 20 != 0 || (pieces.len() / 20) as u64 != len.div_ceil(piece_len) {
+        return None;
+    }
+    let pieces = pieces.chunks(20).map(<[u8]>::to_vec).collect();
+    Some((name, Info { len, piece_len, pieces }))
+}
+
+/// Parses a magnet link, which must name a BitTorrent info hash, in hex or base32.
+fn parse_magnet(uri: &str) -> Option<Magnet> {
+    let query = uri.strip_prefix("magnet:?")?;
+    let mut info_hash = None;
+    let mut name = None;
+    let mut torrent = None;
+    let mut web_seeds = Vec::new();
+    let mut trackers = Vec::new();
+    let mut peers = Vec::new();
+    for (key, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+        let value = percent_decode_str(value).decode_utf8_lossy().into_owned();
+        // Parameters may be numbered, such as `xt.1`, when there are several.
+        match key.split('.').next().unwrap_or_default() {
+            "xt" => {
+                if let Some(hash) = value.strip_prefix("urn:btih:") {
+                    info_hash = info_hash.or_else(|| parse_info_hash(hash));
+                }
+            }
+            "dn" => name = name.or(Some(value)),
+            "xs" => torrent = torrent.or(Some(value)),
+            "ws" | "as" => web_seeds.push(value),
+            "tr" => trackers.push(value),
+            "x" if key == "x.pe" || key.starts_with("x.pe.") => peers.push(value),
+            _ => (),
+        }
+    }
+    Some(Magnet {
+        info_hash: info_hash?,
+        name,
+        torrent,
+        web_seeds,
+        trackers,
+        peers,
+    })
+}
+
+/// Parses an info hash in hex, or in the base32 of RFC 4648 which older magnet links use.
+fn parse_info_hash(hash: &str) -> Option<[u8; 20]> {
+    match hash.len() {
+        40 => parse_hex(hash)?.try_into().ok(),
+        32 => {
+            let mut bytes = Vec::with_capacity(20);
+            let (mut buffer, mut bits) = (0u64, 0);
+            for c in hash.bytes() {
+                let digit = match c.to_ascii_uppercase() {
+                    c @ b'A'..=b'Z' => c - b'A',
+                    c @ b'2'..=b'7' => c - b'2' + 26,
+                    _ => return None,
+                };
+                buffer = buffer << 5 | u64::from(digit);
+                bits += 5;
+                if bits >= 8 {
+                    bits -= 8;
+                    bytes.push((buffer >> bits) as u8);
+                }
+            }
+            bytes.try_into().ok()
+        }
+        _ => None,
+    }
+}
+
+/// A bencoded value.
+pub(crate) enum Value<'a> {
+    Int(u64),
+    Bytes(&'a [u8]),
+    List(Vec<Value<'a>>),
+    /// Each key, with its value and the bytes the value was encoded as.
+    Dict(Vec<(&'a [u8], Value<'a>, &'a [u8])>),
+}
+
+impl<'a> Value<'a> {
+    /// Parses the value at the start of `input`, returning it with the rest of the input.
+    /// Negative integers are not valid, since a torrent has no use for them.
+    pub(crate) fn parse(input: &'a [u8], depth: usize) -> Option<(Value<'a>, &'a [u8])> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match *input.first()? {
+            b'i' => {
+                let end = input.iter().position(|&b| b == b'e')?;
+                let n = str::from_utf8(&input[1..end]).ok()?.parse().ok()?;
+                Some((Value::Int(n), &input[end + 1..]))
+            }
+            b'l' => {
+                let mut rest = &input[1..];
+                let mut items = Vec::new();
+                while *rest.first()? != b'e' {
+                    let (item, next) = Value::parse(rest, depth + 1)?;
+                    items.push(item);
+                    rest = next;
+                }
+                Some((Value::List(items), &rest[1..]))
+            }
+            b'd' => {
+                let mut rest = &input[1..];
+                let mut entries = Vec::new();
+                while *rest.first()? != b'e' {
+                    let (key, next) = parse_bytes(rest)?;
+                    let (value, next_entry) = Value::parse(next, depth + 1)?;
+                    entries.push((key, value, &next[..next.len() - next_entry.len()]));
+                    rest = next_entry;
+                }
+                Some((Value::Dict(entries), &rest[1..]))
+            }
+            _ => parse_bytes(input).map(|(bytes, rest)| (Value::Bytes(bytes), rest)),
+        }
+    }
+
+    /// Returns the value of `key` in a dictionary with the bytes it was encoded as.
+    fn entry(&self, key: &[u8]) -> Option<(&Value<'a>, &'a [u8])> {
+        match self {
+            Value::Dict(entries) => entries.iter().find(|(k, _, _)| *k == key).map(|&(_, ref value, encoded)| (value, encoded)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<&Value<'a>> {
+        self.entry(key).map(|(value, _)| value)
+    }
+
+    pub(crate) fn int(&self) -> Option<u64> {
+        match *self {
+            Value::Int(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a bencoded byte string, such as `4:spam`, at the start of `input`.
+fn parse_bytes(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let colon = input.iter().position(|&b| b == b':')?;
+    let len: usize = str::from_utf8(&input[..colon]).ok()?.parse().ok()?;
+    let rest = &input[colon + 1..];
+    (len <= rest.len()).then(|| rest.split_at(len))
+}
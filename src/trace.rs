@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::time::Duration;
+
+use reqwest::{Request, Response, Url};
+use tracing::Span;
+
+use crate::error::Error as TDSTDError;
+use crate::event::DownloadEvent;
+
+/// Returns the span a download is written within, with its URL and the file it is written to, if
+/// any.
+pub(crate) fn download_span(url: &str, destination: Option<&Path>) -> Span {
+    tracing::info_span!("download", url, destination = destination.map(|path| tracing::field::display(path.display())))
+}
+
+pub(crate) fn request(request: &Request) {
+    tracing::debug!(method = %request.method(), url = %request.url(), "sending request");
+}
+
+pub(crate) fn redirect(response: &Response, to: &Url) {
+    tracing::debug!(status = response.status().as_u16(), from = %response.url(), to = %to, "following redirect");
+}
+
+/// Records an event reported while the download is in progress.  Every chunk is recorded, even
+/// those held back from the progress callback.
+pub(crate) fn record(event: &DownloadEvent) {
+    match event {
+        DownloadEvent::Started { offset, total } => tracing::debug!(offset, total, "response body started"),
+        DownloadEvent::Chunk { bytes, total, speed, .. } => tracing::trace!(bytes, total, speed, "chunk written"),
+        DownloadEvent::Retrying { retry, delay, error } => tracing::warn!(retry, ?delay, %error, "retrying download"),
+        DownloadEvent::Extracted { path, size } => tracing::debug!(path = %path.display(), size, "entry extracted"),
+        DownloadEvent::Finished(_) | DownloadEvent::Failed(_) => (),
+    }
+}
+
+/// Records how the download ended, with the bytes written by this call and how long it took.
+pub(crate) fn finished(result: &Result<(), TDSTDError>, bytes_written: u64, elapsed: Duration) {
+    match result {
+        Ok(()) => tracing::info!(bytes_written, ?elapsed, "download finished"),
+        Err(error) => tracing::warn!(bytes_written, ?elapsed, %error, "download failed"),
+    }
+}
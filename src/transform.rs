@@ -0,0 +1,128 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::io::Error as IOError;
+use std::pin::Pin;
+
+use bytes::BytesMut;
+
+pub use bytes::Bytes;
+
+/// The future returned by the methods of a [`Transform`].
+pub type Transformed<'a> = Pin<Box<dyn Future<Output = Result<Bytes, IOError>> + 'a>>;
+
+/// A stage between the response body and wherever the download is written, such as a decryptor,
+/// a filter or a recompressor.  Stages are added with
+/// [`AsyncDownloadBuilder::transform`](crate::AsyncDownloadBuilder::transform) and run in the
+/// order they were added, each receiving the output of the one before.  An error returned by a
+/// stage fails the download without retrying it.  A stage which authenticates its input, such
+/// as a decryptor, should fail with an `std::io::Error` wrapping [`AuthenticationFailed`] when
+/// the input is not authentic, so that the download fails with `AuthenticationFailed` and the
+/// file it was written to is removed.
+///
+/// A stage keeps its state across retries which carry on from where the last attempt stopped, so
+/// it sees every byte of the body exactly once.  It is reset when the download starts again
+/// from the beginning instead.
+///
+/// Any `FnMut(Bytes) -> Result<Bytes, std::io::Error>` is a stage which keeps no state.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::future::ready;
+/// use tokio_dl_stream_to_disk::transform::{Bytes, Transform, Transformed};
+///
+/// /// Counts the bytes passing through, without changing them.
+/// struct Count(u64);
+///
+/// impl Transform for Count {
+///     fn transform(&mut self, chunk: Bytes) -> Transformed<'_> {
+///         self.0 += chunk.len() as u64;
+///         Box::pin(ready(Ok(chunk)))
+///     }
+///
+///     fn reset(&mut self) {
+///         self.0 = 0;
+///     }
+/// }
+/// ```
+pub trait Transform {
+    /// Transforms a chunk of the body, returning the bytes to pass on, which may be empty if
+    /// the stage needs more input first.
+    fn transform(&mut self, chunk: Bytes) -> Transformed<'_>;
+
+    /// Returns any bytes the stage still holds once the body has ended, or an error if the body
+    /// was not complete, e.g. because it was cut short.  By default there are none.
+    fn finish(&mut self) -> Transformed<'_> {
+        Box::pin(std::future::ready(Ok(Bytes::new())))
+    }
+
+    /// Discards any state, since the body is starting again from the beginning.
+    fn reset(&mut self) {}
+}
+
+impl<F: FnMut(Bytes) -> Result<Bytes, IOError>> Transform for F {
+    fn transform(&mut self, chunk: Bytes) -> Transformed<'_> {
+        Box::pin(std::future::ready(self(chunk)))
+    }
+}
+
+/// The error a [`Transform`] wraps in an `std::io::Error` when its input fails to authenticate.
+#[derive(Debug)]
+pub struct AuthenticationFailed;
+
+impl fmt::Display for AuthenticationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("input failed to authenticate")
+    }
+}
+
+impl StdError for AuthenticationFailed {}
+
+/// The stages a download passes through, in order.
+#[derive(Default)]
+pub(crate) struct Transforms(pub(crate) Vec<Box<dyn Transform>>);
+
+impl Transforms {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Passes a chunk of the body through every stage.
+    pub(crate) async fn apply(&mut self, mut chunk: Bytes) -> Result<Bytes, IOError> {
+        for stage in &mut self.0 {
+            chunk = stage.transform(chunk).await?;
+        }
+        Ok(chunk)
+    }
+
+    /// Finishes every stage once the body has ended, passing what each one still holds through
+    /// the stages after it before they are finished in turn.
+    pub(crate) async fn finish(&mut self) -> Result<Bytes, IOError> {
+        let mut carry = Bytes::new();
+        for stage in &mut self.0 {
+            if !carry.is_empty() {
+                carry = stage.transform(carry).await?;
+            }
+            let tail = stage.finish().await?;
+            if !tail.is_empty() {
+                let mut joined = BytesMut::from(carry);
+                joined.extend_from_slice(&tail);
+                carry = joined.freeze();
+            }
+        }
+        Ok(carry)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        for stage in &mut self.0 {
+            stage.reset();
+        }
+    }
+}
+
+impl fmt::Debug for Transforms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Transforms({})", self.0.len())
+    }
+}
@@ -0,0 +1,283 @@
+#[cfg(feature="tar")]
+use std::cell::RefCell;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+#[cfg(feature="zip")]
+use async_zip::error::ZipError;
+#[cfg(feature="zip")]
+use async_zip::tokio::read::fs::ZipFileReader;
+#[cfg(feature="tar")]
+use futures_util::StreamExt;
+#[cfg(feature="tar")]
+use tokio::io::AsyncRead;
+#[cfg(feature="tar")]
+use tokio_tar::Archive;
+
+use crate::error::{Error as TDSTDError, ErrorKind as TDSTDErrorKind};
+#[cfg(feature="zip")]
+use crate::RemoveOnDrop;
+use crate::{AsyncDownload, DownloadEvent, DownloadResult, Target};
+
+#[cfg(feature="tar")]
+/// The size of the pipe between the download and the extraction of the archive.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+impl AsyncDownload {
+    #[cfg(feature="tar")]
+    /// Initiate the download of a tar archive, extracting its entries into `dst_dir` as they
+    /// arrive rather than writing the archive to disk first, and return a result summarizing it.
+    /// If the download is set to [`decompress`](crate::AsyncDownloadBuilder::decompress), the
+    /// archive is decompressed first, e.g. for a `.tar.gz`.
+    ///
+    /// The download is streamed as with
+    /// [`download_to_writer`](AsyncDownload::download_to_writer), so the same retry and checksum
+    /// options apply, and `Chunk` events report the bytes downloaded.  An `Extracted` event is
+    /// reported once each entry has been written.  Entries with an absolute path or a `..`
+    /// component, and links pointing outside `dst_dir`, fail the download with
+    /// `UnsafeArchiveEntry`.  Entries already extracted are left in place if the download fails.
+    ///
+    /// Arguments:
+    /// * `dst_dir` - The directory to extract the archive into.  It is created if `create_dirs`
+    ///   was set, and must exist otherwise.
+    /// * `events` - A callback for reporting the progress of the download and extraction.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::path::Path;
+    /// use tokio_dl_stream_to_disk::{AsyncDownload, DownloadEvent};
+    ///
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// let mut download = AsyncDownload::builder()
+    ///     .url("https://example.com/release.tar")
+    ///     .dst_dir("/tmp")
+    ///     .build()?;
+    /// download.download_and_unpack_tar(Path::new("/tmp/release"), |event| {
+    ///     if let DownloadEvent::Extracted { path, size } = event {
+    ///         println!("{} ({} bytes)", path.display(), size);
+    ///     }
+    /// }).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_and_unpack_tar(
+        &mut self,
+        dst_dir: &Path,
+        events: impl FnMut(DownloadEvent),
+    ) -> Result<DownloadResult, TDSTDError> {
+        let started = Instant::now();
+        self.check_unpack_dir(dst_dir).await?;
+        let (mut tx, rx) = tokio::io::duplex(PIPE_CAPACITY);
+        #[cfg(feature="decompress")]
+        let rx: Box<dyn AsyncRead + Unpin> = match self.config.decompress {
+            Some(format) => format.reader(tokio::io::BufReader::new(rx)),
+            None => Box::new(rx),
+        };
+        // Both halves report events, but never while the other is.
+        let events = RefCell::new(events);
+        let mut download_events = |event| (events.borrow_mut())(event);
+        let mut unpack_events = |event| (events.borrow_mut())(event);
+        let download = async {
+            let bytes_written = self.write_download(Target::Writer(&mut tx), 0, &mut download_events, &mut ()).await;
+            // Closing the pipe lets the archive see the end of the download.
+            drop(tx);
+            bytes_written
+        };
+        let (bytes_written, ()) = tokio::try_join!(download, unpack_tar(rx, dst_dir, &mut unpack_events))?;
+        Ok(DownloadResult {
+            path: Some(dst_dir.to_path_buf()),
+            ..self.result(started, bytes_written, false)
+        })
+    }
+
+    #[cfg(feature="zip")]
+    /// Initiate the download of a ZIP archive, extracting its entries into `dst_dir` once it
+    /// completes, and return a result summarizing it.
+    ///
+    /// Since the entries of a ZIP archive are listed at its end, the archive is first written to
+    /// the `.part` file of the download, in its destination directory or temporary directory, and
+    /// removed once it has been extracted or the download fails.  This is written like any other
+    /// download, so the same retry, resume, segmented and checksum options apply, and `Chunk`
+    /// events report the bytes downloaded.  An `Extracted` event is reported once each entry
+    /// has been written, and entries whose contents do not match their CRC-32 fail the download.
+    /// Entries with an absolute path or a `..` component, and links pointing outside `dst_dir`,
+    /// fail the download with `UnsafeArchiveEntry`.  Entries already extracted are left in place
+    /// if the download fails.
+    ///
+    /// Arguments:
+    /// * `dst_dir` - The directory to extract the archive into.  It is created if `create_dirs`
+    ///   was set, and must exist otherwise.
+    /// * `events` - A callback for reporting the progress of the download and extraction.
+    pub async fn download_and_unpack_zip(
+        &mut self,
+        dst_dir: &Path,
+        mut events: impl FnMut(DownloadEvent),
+    ) -> Result<DownloadResult, TDSTDError> {
+        let started = Instant::now();
+        self.check_unpack_dir(dst_dir).await?;
+        self.check_dst_dir().await?;
+        self.derive_fname().await?;
+        let spool = self.part_path();
+        let _spool = RemoveOnDrop(Some(spool.clone()));
+        let bytes_written = self.write_download(Target::File(&spool), 0, &mut events, &mut ()).await?;
+        unpack_zip(&spool, dst_dir, &mut events).await?;
+        Ok(DownloadResult {
+            path: Some(dst_dir.to_path_buf()),
+            ..self.result(started, bytes_written, false)
+        })
+    }
+
+    /// Checks that the directory to unpack an archive into exists, creating it if `create_dirs`
+    /// was set.
+    async fn check_unpack_dir(&self, dst_dir: &Path) -> Result<(), TDSTDError> {
+        if self.config.create_dirs {
+            tokio::fs::create_dir_all(dst_dir).await?;
+        } else if !dst_dir.is_dir() {
+            return Err(TDSTDError::new(TDSTDErrorKind::DirectoryMissing));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature="tar")]
+/// Extracts each entry of the archive read from `src` into `dst_dir`, then reads to the end so
+/// that the download is not held up by any padding after the archive.
+async fn unpack_tar(
+    src: impl AsyncRead + Unpin,
+    dst_dir: &Path,
+    events: &mut dyn FnMut(DownloadEvent),
+) -> Result<(), TDSTDError> {
+    let mut archive = Archive::new(src);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let unsafe_entry = |path| TDSTDError::new(TDSTDErrorKind::UnsafeArchiveEntry(path));
+        if !is_contained(0, &path) {
+            return Err(unsafe_entry(path));
+        }
+        // Symbolic links are relative to the directory they are in, which may itself be reached
+        // through a link, and hard links to the root of the archive.
+        let depth = match entry.header().entry_type() {
+            kind if kind.is_symlink() => match real_depth(dst_dir, &path).await? {
+                Some(depth) => depth,
+                None => return Err(unsafe_entry(path)),
+            },
+            _ => 0,
+        };
+        if entry.link_name()?.is_some_and(|link| !is_contained(depth, &link)) {
+            return Err(unsafe_entry(path));
+        }
+        let size = entry.header().size()?;
+        if !entry.unpack_in(dst_dir).await? {
+            return Err(unsafe_entry(path));
+        }
+        events(DownloadEvent::Extracted { path: dst_dir.join(normalize(&path)), size });
+    }
+    drop(entries);
+    if let Ok(mut src) = archive.into_inner() {
+        tokio::io::copy(&mut src, &mut tokio::io::sink()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(feature="zip")]
+/// Extracts each entry of the ZIP archive at `archive` into `dst_dir`.
+async fn unpack_zip(
+    archive: &Path,
+    dst_dir: &Path,
+    events: &mut dyn FnMut(DownloadEvent),
+) -> Result<(), TDSTDError> {
+    use tokio_util::compat::TokioAsyncWriteCompatExt;
+
+    let reader = ZipFileReader::new(archive).await.map_err(zip_error)?;
+    for (index, entry) in reader.file().entries().iter().enumerate() {
+        let name = entry.filename().as_str().map_err(zip_error)?;
+        let path = normalize(Path::new(name));
+        let unsafe_entry = || TDSTDError::new(TDSTDErrorKind::UnsafeArchiveEntry(PathBuf::from(name)));
+        if !is_contained(0, Path::new(name)) || path.as_os_str().is_empty() {
+            return Err(unsafe_entry());
+        }
+        // Every entry is checked against links extracted earlier, not only links themselves.
+        let Some(depth) = real_depth(dst_dir, &path).await? else {
+            return Err(unsafe_entry());
+        };
+        let dst = dst_dir.join(&path);
+        let mode = entry.unix_permissions().map(u32::from);
+        let mut contents = reader.reader_with_entry(index).await.map_err(zip_error)?;
+        if entry.dir().map_err(zip_error)? {
+            tokio::fs::create_dir_all(&dst).await?;
+        } else if mode.is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            let mut link = Vec::new();
+            contents.read_to_end_checked(&mut link).await.map_err(zip_error)?;
+            let link = PathBuf::from(String::from_utf8(link).map_err(|_| unsafe_entry())?);
+            if !is_contained(depth, &link) {
+                return Err(unsafe_entry());
+            }
+            let _ = tokio::fs::remove_file(&dst).await;
+            #[cfg(unix)]
+            tokio::fs::symlink(link, &dst).await?;
+            #[cfg(not(unix))]
+            tokio::fs::write(&dst, link.as_os_str().as_encoded_bytes()).await?;
+        } else {
+            let mut file = tokio::fs::File::create(&dst).await?.compat_write();
+            futures_util::io::copy(&mut contents, &mut file).await?;
+            if contents.compute_hash() != entry.crc32() {
+                return Err(zip_error(ZipError::CRC32CheckError));
+            }
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+
+                tokio::fs::set_permissions(&dst, std::fs::Permissions::from_mode(mode & 0o777)).await?;
+            }
+        }
+        events(DownloadEvent::Extracted { path: dst, size: entry.uncompressed_size() });
+    }
+    Ok(())
+}
+
+#[cfg(feature="zip")]
+fn zip_error(err: ZipError) -> TDSTDError {
+    match err {
+        ZipError::UpstreamReadError(err) => err.into(),
+        err => TDSTDError::new(TDSTDErrorKind::Other(Box::new(err))),
+    }
+}
+
+/// Returns a path within an archive with only its normal components, e.g. without a leading or
+/// trailing `/` or any `.`.
+fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|component| matches!(component, Component::Normal(_))).collect()
+}
+
+/// Returns `true` if a relative path within an archive, starting from a directory `depth` levels
+/// below the root of the archive, stays within it.  `..` may only lead the path, since going up
+/// from a directory reached through a link would not undo the link.
+fn is_contained(mut depth: usize, path: &Path) -> bool {
+    let mut descended = false;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => {
+                depth += 1;
+                descended = true;
+            }
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 && !descended => depth -= 1,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Returns how many levels below `dst_dir` the directory an entry is extracted into really is,
+/// after following any links to it, creating it if it does not exist yet.  Returns `None` if it
+/// is not within `dst_dir`.
+async fn real_depth(dst_dir: &Path, path: &Path) -> Result<Option<usize>, TDSTDError> {
+    let parent = dst_dir.join(path.parent().unwrap_or(Path::new("")));
+    tokio::fs::create_dir_all(&parent).await?;
+    let root = tokio::fs::canonicalize(dst_dir).await?;
+    let parent = tokio::fs::canonicalize(parent).await?;
+    Ok(parent.strip_prefix(root).ok().map(|relative| relative.components().count()))
+}
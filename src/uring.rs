@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::Error as IOError;
+use std::rc::Rc;
+use std::thread;
+
+use bytes::Bytes;
+use tokio::sync::{mpsc, oneshot};
+
+/// How many writes may be waiting for the ring thread before the segments wait for it to catch
+/// up.
+const QUEUE_LEN: usize = 64;
+
+/// How many submission queue entries the ring has.
+const RING_ENTRIES: u32 = 256;
+
+struct Write {
+    offset: u64,
+    data: Bytes,
+    done: oneshot::Sender<Result<(), IOError>>,
+}
+
+/// Writes to a file at given offsets through io_uring, on a thread of its own running a
+/// `tokio-uring` runtime, since that cannot be driven from the runtime downloads run on.  Writes
+/// are sent to the thread over a bounded channel and submitted as they arrive, so that the
+/// segments of a download are written at once.  The thread ends once the writer is dropped.
+pub(crate) struct Writer {
+    queue: mpsc::Sender<Write>,
+}
+
+impl Writer {
+    /// Starts a thread writing to `file`.  Returns an error if io_uring is not available, such
+    /// as on older kernels or where it is blocked, so the caller can write another way.
+    pub(crate) async fn new(file: &File) -> Result<Self, IOError> {
+        let file = file.try_clone()?;
+        let (queue, mut writes) = mpsc::channel::<Write>(QUEUE_LEN);
+        let (ready, started) = oneshot::channel();
+        thread::Builder::new().name(String::from("tdstd-uring")).spawn(move || {
+            let runtime = match tokio_uring::Runtime::new(tokio_uring::builder().entries(RING_ENTRIES)) {
+                Ok(runtime) => runtime,
+                Err(err) => {
+                    let _ = ready.send(Err(err));
+                    return;
+                }
+            };
+            let _ = ready.send(Ok(()));
+            runtime.block_on(async move {
+                let file = Rc::new(tokio_uring::fs::File::from_std(file));
+                while let Some(write) = writes.recv().await {
+                    let file = Rc::clone(&file);
+                    tokio_uring::spawn(async move {
+                        let (result, _) = file.write_all_at(write.data, write.offset).await;
+                        let _ = write.done.send(result);
+                    });
+                }
+            });
+        })?;
+        started.await.map_err(IOError::other)??;
+        Ok(Self { queue })
+    }
+
+    /// Writes all of `data` at `offset` in the file.
+    pub(crate) async fn write_at(&self, offset: u64, data: Bytes) -> Result<(), IOError> {
+        let (done, written) = oneshot::channel();
+        self.queue.send(Write { offset, data, done }).await.map_err(IOError::other)?;
+        written.await.map_err(IOError::other)?
+    }
+}
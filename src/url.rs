@@ -0,0 +1,72 @@
+use reqwest::Url;
+
+use crate::error::Error as TDSTDError;
+
+/// A type which can be used as the URL of a download, checked up front so that an invalid URL
+/// fails when the download is created rather than when it is requested.  It is implemented for
+/// `reqwest::Url` and string types.
+///
+/// Besides HTTP and HTTPS, a `file` URL is read from the local filesystem, and a `data` URL is
+/// decoded, with the same progress, verification and atomic writes, e.g. for tests or offline
+/// mirrors.  With the `sftp` feature, an `sftp` or `scp` URL such as
+/// `sftp://user@host/path/to/file` is read over the SFTP subsystem of the system's `ssh` client,
+/// authenticating with its configuration, keys and agent, so that it resumes and is split into
+/// segments too.  A path beginning with `/~/` is relative to the user's home directory.  With
+/// the `object-store` feature, an `s3://bucket/key`, `gs://bucket/key` or `az://container/blob`
+/// URL is read from Amazon S3, Google Cloud Storage or Azure Blob Storage with the
+/// [`object_store`](https://docs.rs/object_store) crate, using the ambient credentials of each,
+/// such as `AWS_ACCESS_KEY_ID`, an AWS profile, application default credentials or a managed
+/// identity.
+pub trait IntoUrl {
+    /// Parses the URL, returning `InvalidUrl` if it is not an absolute HTTP or HTTPS URL with a
+    /// host, a `file` URL of a local path, a `data` URL, or, with the `sftp` feature, an `sftp` or
+    /// `scp` URL with a host and no password, or, with the `object-store` feature, an `s3`, `gs` or
+    /// `az` URL with a bucket or container.
+    fn into_url(self) -> Result<Url, TDSTDError>;
+}
+
+impl IntoUrl for Url {
+    fn into_url(self) -> Result<Url, TDSTDError> {
+        let valid = match self.scheme() {
+            "http" | "https" => self.has_host(),
+            "file" => self.to_file_path().is_ok(),
+            "data" => true,
+            // The ssh client is never given a password, so one in the URL would be ignored.
+            #[cfg(feature="sftp")]
+            "sftp" | "scp" => self.has_host() && self.password().is_none(),
+            #[cfg(feature="object-store")]
+            "s3" | "gs" | "az" => self.has_host(),
+            _ => false,
+        };
+        match valid {
+            true => Ok(self),
+            false => Err(TDSTDError::invalid_url(None)),
+        }
+    }
+}
+
+impl IntoUrl for &Url {
+    fn into_url(self) -> Result<Url, TDSTDError> {
+        self.clone().into_url()
+    }
+}
+
+impl IntoUrl for &str {
+    fn into_url(self) -> Result<Url, TDSTDError> {
+        Url::parse(self)
+            .map_err(|err| TDSTDError::invalid_url(Some(Box::new(err))))?
+            .into_url()
+    }
+}
+
+impl IntoUrl for String {
+    fn into_url(self) -> Result<Url, TDSTDError> {
+        self.as_str().into_url()
+    }
+}
+
+impl IntoUrl for &String {
+    fn into_url(self) -> Result<Url, TDSTDError> {
+        self.as_str().into_url()
+    }
+}
@@ -0,0 +1,134 @@
+#[cfg(feature="sha256sum")]
+use sha2::{Digest, Sha256};
+
+use crate::error::Error as TDSTDError;
+#[cfg(feature="content-digest")]
+use crate::content_digest::HeaderDigest;
+#[cfg(feature="sri")]
+use crate::integrity::Integrity;
+#[cfg(feature="digest")]
+use crate::pieces::PieceVerifier;
+#[cfg(feature="sha256sum")]
+use crate::Hashing;
+#[cfg(feature="content-digest")]
+use crate::Inspect;
+use crate::{inspect_file, AsyncDownload};
+
+impl AsyncDownload {
+    /// Check whether the file already at the destination is the download, without downloading
+    /// it.  The file is hashed against the expected checksums set on the builder, including a
+    /// sha256sum manifest, a Subresource Integrity string or piece hashes.  If none were set, a
+    /// probe is made instead, and the file must be as long as the server reports, and with the
+    /// `content-digest` feature match the `Repr-Digest` or `Content-Digest` it reports, if any.
+    /// Returns `false` if the file does not exist, does not match, or neither checksums nor a
+    /// length are known, and also if the download is decompressed or transformed as it is
+    /// written, unless the checksums refer to the transformed bytes with
+    /// [`hash_transformed`](crate::AsyncDownloadBuilder::hash_transformed).  The file is never
+    /// removed.
+    ///
+    /// This is used by `OverwriteBehavior::SkipIfValid` to skip the download when the file is
+    /// already up to date.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "sha256sum")]
+    /// # async fn run() -> Result<(), tokio_dl_stream_to_disk::error::Error> {
+    /// use tokio_dl_stream_to_disk::AsyncDownload;
+    ///
+    /// let mut download = AsyncDownload::builder()
+    ///     .url("https://example.com/release.tar")
+    ///     .dst_dir("/tmp")
+    ///     .expect_sha256_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+    ///     .build()?;
+    /// if !download.verify_existing().await? {
+    ///     download.download(&None).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn verify_existing(&mut self) -> Result<bool, TDSTDError> {
+        self.derive_fname().await?;
+        let path = self.path();
+        // The checksums and the length refer to the download as it is sent.
+        let rewritten = self.config.decompresses()
+            || !(self.config.transforms.is_empty() || self.config.hash_transformed);
+        if rewritten || !path.is_file() {
+            return Ok(false);
+        }
+        #[cfg(feature="sha256sum")]
+        let expected_sha256 = match self.config.expected_sha256 {
+            Some(expected) => Some(expected),
+            None => self.manifest_sha256().await?,
+        };
+        #[cfg(feature="sha256sum")]
+        let mut sha256 = expected_sha256.map(|_| Hashing(Sha256::new()));
+        #[cfg(feature="sri")]
+        let integrity = self.config.expected_integrity.clone();
+        #[cfg(feature="sri")]
+        let mut integrity_hasher = integrity.as_ref().map(Integrity::hasher);
+        #[cfg(feature="digest")]
+        let mut pieces = self.config.piece_hashes.clone().map(PieceVerifier::new);
+        let expected = [
+            #[cfg(feature="sha256sum")]
+            sha256.is_some(),
+            #[cfg(feature="sri")]
+            integrity.is_some(),
+            #[cfg(feature="digest")]
+            pieces.is_some(),
+        ].contains(&true);
+        #[cfg(feature="content-digest")]
+        let mut header_digest = HeaderDigest::new(
+            !expected && !self.config.ignore_content_digest && !self.config.content_decoded,
+        );
+        // Without any checksums of our own, fall back to what the server reports.
+        if !expected {
+            let probe = self.probe().await?;
+            if probe.length() != Some(tokio::fs::metadata(&path).await?.len()) {
+                return Ok(false);
+            }
+            #[cfg(feature="content-digest")]
+            header_digest.response(probe.metadata());
+            #[cfg(feature="content-digest")]
+            let announced = header_digest.is_announced();
+            #[cfg(not(feature="content-digest"))]
+            let announced = false;
+            if !announced {
+                return Ok(true);
+            }
+        }
+        let inspect = &mut ();
+        #[cfg(feature="sha256sum")]
+        let inspect = &mut (&mut sha256, inspect);
+        #[cfg(feature="sri")]
+        let inspect = &mut (&mut integrity_hasher, inspect);
+        #[cfg(feature="digest")]
+        let inspect = &mut (&mut pieces, inspect);
+        #[cfg(feature="content-digest")]
+        let inspect = &mut (&mut header_digest, inspect);
+        inspect_file(&path, inspect).await?;
+        #[cfg(feature="sha256sum")]
+        if let (Some(expected), Some(hasher)) = (expected_sha256, sha256) {
+            if hasher.0.finalize()[..] != expected {
+                return Ok(false);
+            }
+        }
+        #[cfg(feature="sri")]
+        if let (Some(integrity), Some(hasher)) = (integrity, integrity_hasher) {
+            if integrity.check(hasher).is_err() {
+                return Ok(false);
+            }
+        }
+        #[cfg(feature="digest")]
+        if let Some(pieces) = pieces {
+            if pieces.check().is_err() {
+                return Ok(false);
+            }
+        }
+        #[cfg(feature="content-digest")]
+        if header_digest.check().is_err() {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}